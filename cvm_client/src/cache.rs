@@ -0,0 +1,317 @@
+use crate::delta::sha256_hex;
+use crate::errors::CvmError::{CacheUnavailable, InsufficientDiskSpace};
+use crate::errors::{map_io_error, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// Identifies the cached build of one version of an app for one target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheKey {
+    pub app_id: String,
+    pub version: String,
+    pub architecture: String,
+}
+
+/// A cached build, as recorded in the manifest next to it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CacheEntry {
+    pub app_id: String,
+    pub version: String,
+    pub architecture: String,
+    pub file_name: String,
+    pub sha256: String,
+    pub size: u64,
+    pub downloaded_at: DateTime<Utc>,
+    /// When the build last passed its startup checks.
+    #[serde(default)]
+    pub known_good_at: Option<DateTime<Utc>>,
+}
+
+impl CacheEntry {
+    pub fn key(&self) -> CacheKey {
+        CacheKey {
+            app_id: self.app_id.clone(),
+            version: self.version.clone(),
+            architecture: self.architecture.clone(),
+        }
+    }
+}
+
+/// Which builds are removed from the cache after an update.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// How many of the most recently downloaded versions are kept for each architecture.
+    pub keep_versions: usize,
+    /// Older versions are removed while the cache holds more than this.
+    pub max_bytes: Option<u64>,
+}
+
+/// Cache of downloaded builds, laid out as `<root>/<app_id>/<version>/<architecture>/` with the
+/// build and a `manifest.json` describing it in each directory.
+///
+/// Builds are written next to their final path as `<file_name>.partial` and renamed once they are
+/// verified. The manifest is written last, so a directory without one holds no usable build.
+#[derive(Debug, Clone)]
+pub struct ArtifactCache {
+    root: PathBuf,
+    /// When the cache was opened. Partial downloads written since then may still be resumed.
+    opened_at: SystemTime,
+}
+
+impl ArtifactCache {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        ArtifactCache {
+            root: root.into(),
+            opened_at: SystemTime::now(),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn entry_dir(&self, key: &CacheKey) -> Result<PathBuf> {
+        let mut dir = self.root.clone();
+        for component in [&key.app_id, &key.version, &key.architecture] {
+            dir.push(path_component(component)?);
+        }
+        Ok(dir)
+    }
+
+    /// Creates the directory of the entry and returns the path the build is to be written to.
+    pub fn prepare(&self, key: &CacheKey, file_name: &str) -> Result<PathBuf> {
+        let dir = self.entry_dir(key)?;
+        std::fs::create_dir_all(&dir).map_err(map_io_error)?;
+        Ok(dir.join(path_component(file_name)?))
+    }
+
+    /// Records the build at `path`, which was verified to have the digest, in the manifest.
+    pub fn commit(&self, key: &CacheKey, path: &Path, sha256: &str) -> Result<CacheEntry> {
+        let file_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| CacheUnavailable {
+                message: format!("Invalid build path {}", path.display()),
+            })?;
+        let entry = CacheEntry {
+            app_id: key.app_id.clone(),
+            version: key.version.clone(),
+            architecture: key.architecture.clone(),
+            file_name: file_name.to_string(),
+            sha256: sha256.to_ascii_lowercase(),
+            size: std::fs::metadata(path).map_err(map_io_error)?.len(),
+            downloaded_at: Utc::now(),
+            known_good_at: None,
+        };
+        self.write_manifest(&entry)?;
+        Ok(entry)
+    }
+
+    /// Records that the build passed its startup checks, which makes it the last known good build
+    /// until another one does.
+    pub fn mark_known_good(&self, key: &CacheKey) -> Result<CacheEntry> {
+        let mut entry = self.entry(key).ok_or_else(|| CacheUnavailable {
+            message: format!("Version {} is not cached", key.version),
+        })?;
+        entry.known_good_at = Some(Utc::now());
+        self.write_manifest(&entry)?;
+        Ok(entry)
+    }
+
+    fn write_manifest(&self, entry: &CacheEntry) -> Result<()> {
+        let manifest_path = self.entry_dir(&entry.key())?.join(MANIFEST_FILE_NAME);
+        let staging_path = manifest_path.with_extension("json.partial");
+        let manifest = serde_json::to_vec_pretty(entry).map_err(|err| CacheUnavailable {
+            message: err.to_string(),
+        })?;
+        std::fs::write(&staging_path, manifest).map_err(map_io_error)?;
+        std::fs::rename(&staging_path, &manifest_path).map_err(map_io_error)
+    }
+
+    /// Reads the manifest of an entry. Returns `None` when there is no usable manifest.
+    pub fn entry(&self, key: &CacheKey) -> Option<CacheEntry> {
+        let manifest = std::fs::read(self.entry_dir(key).ok()?.join(MANIFEST_FILE_NAME)).ok()?;
+        serde_json::from_slice(&manifest).ok()
+    }
+
+    /// Returns the path of the cached build when its content still matches the digest recorded in
+    /// the manifest, and `expected_sha256` when it is given.
+    pub fn lookup(&self, key: &CacheKey, expected_sha256: Option<&str>) -> Option<PathBuf> {
+        let entry = self.entry(key)?;
+        if expected_sha256.is_some_and(|expected| !expected.eq_ignore_ascii_case(&entry.sha256)) {
+            println!(
+                "Cached build of {} is not the build the server offers, downloading it again",
+                key.version
+            );
+            return None;
+        }
+        let path = self.entry_dir(key).ok()?.join(path_component(&entry.file_name).ok()?);
+        let content = std::fs::read(&path).ok()?;
+        if sha256_hex(&content) != entry.sha256 {
+            println!("Cached build of {} is corrupt, downloading it again", key.version);
+            return None;
+        }
+        Some(path)
+    }
+
+    /// Lists the cached builds of an app, most recently downloaded first.
+    pub fn entries(&self, app_id: &str) -> Vec<CacheEntry> {
+        let mut entries: Vec<CacheEntry> = self
+            .entry_dirs(app_id)
+            .into_iter()
+            .filter_map(|dir| std::fs::read(dir.join(MANIFEST_FILE_NAME)).ok())
+            .filter_map(|manifest| serde_json::from_slice::<CacheEntry>(&manifest).ok())
+            .filter(|entry| entry.app_id == app_id)
+            .collect();
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.downloaded_at));
+        entries
+    }
+
+    /// The build of the app for the architecture that most recently passed its startup checks.
+    pub fn last_known_good(&self, app_id: &str, architecture: &str) -> Option<CacheEntry> {
+        self.entries(app_id)
+            .into_iter()
+            .filter(|entry| entry.architecture == architecture && entry.known_good_at.is_some())
+            .max_by_key(|entry| entry.known_good_at)
+    }
+
+    /// Removes the builds of the app the policy doesn't keep, along with directories left behind by
+    /// downloads that never completed. The running build and the last known good build are always
+    /// kept, and so are partial downloads written since the cache was opened, which the next
+    /// attempt resumes. Returns the removed entries.
+    pub fn enforce_retention(
+        &self,
+        policy: RetentionPolicy,
+        running: &CacheKey,
+    ) -> Result<Vec<CacheEntry>> {
+        let entries = self.entries(&running.app_id);
+        let last_known_good = self
+            .last_known_good(&running.app_id, &running.architecture)
+            .map(|entry| entry.key());
+        let protected = |entry: &CacheEntry| {
+            entry.key() == *running || Some(entry.key()) == last_known_good
+        };
+
+        let mut kept_per_architecture: HashMap<&str, usize> = HashMap::new();
+        let mut kept: Vec<&CacheEntry> = entries
+            .iter()
+            .filter(|entry| {
+                let kept = kept_per_architecture.entry(&entry.architecture).or_default();
+                *kept += 1;
+                *kept <= policy.keep_versions || protected(entry)
+            })
+            .collect();
+        if let Some(max_bytes) = policy.max_bytes {
+            let mut total: u64 = kept.iter().map(|entry| entry.size).sum();
+            // Drop the oldest builds first until the cache fits.
+            while total > max_bytes {
+                let Some(position) = kept.iter().rposition(|entry| !protected(entry)) else {
+                    break;
+                };
+                total -= kept.remove(position).size;
+            }
+        }
+
+        let running_dir = self.entry_dir(running)?;
+        for dir in self.entry_dirs(&running.app_id) {
+            if dir != running_dir
+                && !dir.join(MANIFEST_FILE_NAME).is_file()
+                && !self.holds_resumable_download(&dir)
+            {
+                remove_entry_dir(&dir)?;
+            }
+        }
+        let mut removed = Vec::new();
+        for entry in &entries {
+            if !kept.contains(&entry) {
+                remove_entry_dir(&self.entry_dir(&entry.key())?)?;
+                println!("Removed {} ({} bytes) from the build cache", entry.version, entry.size);
+                removed.push(entry.clone());
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Whether the directory holds a partial download written since the cache was opened.
+    fn holds_resumable_download(&self, dir: &Path) -> bool {
+        std::fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().extension().is_some_and(|extension| extension == "partial"))
+            .filter_map(|entry| entry.metadata().and_then(|metadata| metadata.modified()).ok())
+            .any(|modified| modified >= self.opened_at)
+    }
+
+    /// Fails with [`InsufficientDiskSpace`] unless `required` bytes are free on the cache's volume.
+    pub fn ensure_free_space(&self, required: u64) -> Result<()> {
+        std::fs::create_dir_all(&self.root).map_err(map_io_error)?;
+        ensure_free_space(&self.root, required)
+    }
+
+    /// The `<app_id>/<version>/<architecture>` directories of the app.
+    fn entry_dirs(&self, app_id: &str) -> Vec<PathBuf> {
+        let Ok(app_dir) = path_component(app_id).map(|app_id| self.root.join(app_id)) else {
+            return Vec::new();
+        };
+        sub_dirs(&app_dir).iter().flat_map(|version_dir| sub_dirs(version_dir)).collect()
+    }
+}
+
+/// Fails with [`InsufficientDiskSpace`] unless `required` bytes are free on the volume holding the
+/// path.
+pub fn ensure_free_space(path: &Path, required: u64) -> Result<()> {
+    let available = free_space(path)?;
+    if available < required {
+        return Err(InsufficientDiskSpace {
+            path: path.display().to_string(),
+            available,
+            required,
+        });
+    }
+    Ok(())
+}
+
+/// Bytes available to unprivileged users on the volume holding the path.
+pub fn free_space(path: &Path) -> Result<u64> {
+    let stat = nix::sys::statvfs::statvfs(path).map_err(|err| map_io_error(err.into()))?;
+    // The field types differ between platforms.
+    #[allow(clippy::useless_conversion)]
+    let (blocks, block_size) = (u64::from(stat.blocks_available()), u64::from(stat.fragment_size()));
+    Ok(blocks.saturating_mul(block_size))
+}
+
+fn sub_dirs(dir: &Path) -> Vec<PathBuf> {
+    std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect()
+}
+
+/// Removes the directory of an entry and its version directory once that is empty.
+fn remove_entry_dir(dir: &Path) -> Result<()> {
+    std::fs::remove_dir_all(dir).map_err(map_io_error)?;
+    if let Some(version_dir) = dir.parent() {
+        let _ = std::fs::remove_dir(version_dir);
+    }
+    Ok(())
+}
+
+/// Rejects names that would escape the cache directory, as versions and file names come from the
+/// server.
+fn path_component(name: &str) -> Result<&str> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\', '\0']) {
+        return Err(CacheUnavailable {
+            message: format!("Invalid cache path component {:?}", name),
+        });
+    }
+    Ok(name)
+}
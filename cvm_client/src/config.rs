@@ -1,10 +1,33 @@
-use crate::config::ConfigError::{ArchitectureNotSupported, OSNotSupported};
+use crate::config::ConfigError::{
+    ArchitectureNotSupported, InvalidAppEnv, InvalidLabels, InvalidMaintenanceWindow, InvalidNumber,
+    InvalidHealthCheck, InvalidListenAddresses, InvalidRestartPolicy, OSNotSupported, UnknownGroup,
+    UnknownUser,
+};
+use crate::health::{HealthCheck, HealthProbe};
+use crate::launch::{find_group, find_user, LaunchConfig};
+use crate::output::OutputConfig;
+use crate::supervisor::{RestartMode, RestartPolicy};
+use crate::update_policy::MaintenanceWindow;
+use std::collections::BTreeMap;
 use std::fmt::Formatter;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 
 #[derive(Debug)]
 pub enum ConfigError {
     OSNotSupported,
     ArchitectureNotSupported,
+    InvalidMaintenanceWindow { value: String },
+    InvalidLabels { value: String },
+    InvalidNumber { key: String, value: String },
+    InvalidRestartPolicy { value: String },
+    InvalidHealthCheck { value: String },
+    InvalidListenAddresses { value: String },
+    InvalidAppEnv { value: String },
+    UnknownUser { value: String },
+    UnknownGroup { value: String },
 }
 
 impl std::fmt::Display for ConfigError {
@@ -16,10 +39,49 @@ impl std::fmt::Display for ConfigError {
             ArchitectureNotSupported => {
                 write!(
                     f,
-                    "Architecture is not supported for {}",
+                    "Architecture is not supported for {}, set CVM_TARGET to the target triple",
                     std::env::consts::ARCH
                 )
             }
+            InvalidMaintenanceWindow { value } => {
+                write!(
+                    f,
+                    "Invalid maintenance window {}, expected HH:MM-HH:MM in UTC",
+                    value
+                )
+            }
+            InvalidLabels { value } => {
+                write!(f, "Invalid labels {}, expected key=value,key=value", value)
+            }
+            InvalidNumber { key, value } => {
+                write!(f, "Invalid {} {}, expected a number", key, value)
+            }
+            InvalidRestartPolicy { value } => {
+                write!(
+                    f,
+                    "Invalid restart policy {}, expected always, on-failure or never",
+                    value
+                )
+            }
+            InvalidHealthCheck { value } => {
+                write!(
+                    f,
+                    "Invalid health check {}, expected an http(s) url, tcp:<host>:<port>, command:<command> or stdout:<regex>",
+                    value
+                )
+            }
+            InvalidListenAddresses { value } => {
+                write!(
+                    f,
+                    "Invalid listen addresses {}, expected <ip>:<port>,<ip>:<port>",
+                    value
+                )
+            }
+            InvalidAppEnv { value } => {
+                write!(f, "Invalid app environment {}, expected KEY=value,KEY=value", value)
+            }
+            UnknownUser { value } => write!(f, "Unknown user {}", value),
+            UnknownGroup { value } => write!(f, "Unknown group {}", value),
         }
     }
 }
@@ -31,13 +93,93 @@ pub const DEFAULT_CLIENT_ID: &str = "f944fc73-e1ca-442a-88bb-642d42a38a6c";
 pub const DEFAULT_APP_ID: &str = "50b473ee-35b3-4252-8998-6be4d4130d3a";
 pub const DEFAULT_ARCHITECTURE: &str = "x86_64-unknown-linux-gnu";
 pub const VERSION_ZERO: &str = "0.0.0";
+pub const DEFAULT_USE_UPDATE_STREAM: &str = "true";
+pub const DEFAULT_DOWNLOAD_ATTEMPTS: u32 = 3;
+pub const DEFAULT_CACHE_KEEP_VERSIONS: usize = 3;
+pub const DEFAULT_MIN_FREE_DISK_MB: u64 = 256;
+pub const DEFAULT_RESTART_POLICY: &str = "on-failure";
+pub const DEFAULT_MAX_RESTARTS: u32 = 5;
+pub const DEFAULT_RESTART_WINDOW_SECS: u64 = 300;
+pub const DEFAULT_RESTART_BACKOFF_MS: u64 = 1000;
+pub const DEFAULT_RESTART_BACKOFF_MAX_MS: u64 = 60_000;
+pub const DEFAULT_DRAIN_TIMEOUT_SECS: u64 = 30;
+pub const DEFAULT_HANDOVER: &str = "false";
+pub const DEFAULT_LOG_MAX_MB: u64 = 10;
+pub const DEFAULT_LOG_KEEP_FILES: usize = 5;
+pub const DEFAULT_LOG_PREFIX: &str = "false";
+pub const DEFAULT_STDERR_TAIL_LINES: usize = 50;
+pub const DEFAULT_HEALTH_CHECK_STATUS: u16 = 200;
+pub const DEFAULT_HEALTH_CHECK_TIMEOUT_MS: u64 = 2000;
+pub const DEFAULT_HEALTH_CHECK_INTERVAL_SECS: u64 = 10;
+pub const DEFAULT_HEALTH_CHECK_STARTUP_SECS: u64 = 60;
+pub const DEFAULT_HEALTH_CHECK_SUCCESS_THRESHOLD: u32 = 1;
+pub const DEFAULT_HEALTH_CHECK_FAILURE_THRESHOLD: u32 = 3;
+const BYTES_PER_MB: u64 = 1024 * 1024;
 
 #[derive(Debug)]
 pub struct Config {
     pub cvm_server_url: String,
     pub client_id: String,
     pub app_id: String,
+    /// Rust target triple of the platform, e.g. x86_64-unknown-linux-gnu. Detected from the
+    /// platform the client was built for unless CVM_TARGET is set.
     pub architecture: String,
+    /// Wait for new versions on a server-sent event stream instead of polling. Polling is still
+    /// used whenever the stream is unavailable.
+    pub use_update_stream: bool,
+    /// Daily UTC window in which optional updates are applied. Mandatory updates are applied
+    /// immediately. Updates are applied as soon as they are found when no window is set.
+    pub maintenance_window: Option<MaintenanceWindow>,
+    /// Labels describing this client, e.g. site=berlin,region=eu. The server targets releases at
+    /// clients by their labels.
+    pub labels: BTreeMap<String, String>,
+    /// Root of the build cache, see [`crate::cache::ArtifactCache`]. Set with CVM_CACHE_DIR and
+    /// defaults to `$XDG_CACHE_HOME/cvm` or `~/.cache/cvm`.
+    pub cache_dir: PathBuf,
+    /// How many of the most recently downloaded versions are kept in the cache. The running version
+    /// and the last version known to start are kept regardless.
+    pub cache_keep_versions: usize,
+    /// Older versions are removed from the cache while it holds more than this. Set with
+    /// CVM_CACHE_MAX_MB, unlimited when unset or 0.
+    pub cache_max_bytes: Option<u64>,
+    /// Downloads are refused when less space than this would be left on the cache's volume. Set
+    /// with CVM_MIN_FREE_DISK_MB.
+    pub min_free_disk_bytes: u64,
+    /// How many times a build download is attempted. Interrupted downloads resume where they
+    /// stopped.
+    pub download_attempts: u32,
+    /// How the app is restarted when it exits on its own. Set with CVM_RESTART_POLICY (always,
+    /// on-failure or never), CVM_MAX_RESTARTS, CVM_RESTART_WINDOW_SECS, CVM_RESTART_BACKOFF_MS and
+    /// CVM_RESTART_BACKOFF_MAX_MS.
+    pub restart_policy: RestartPolicy,
+    /// When the app counts as healthy. The probe is set with CVM_HEALTH_CHECK, see
+    /// [`HealthProbe::parse`], and tuned with CVM_HEALTH_CHECK_STATUS, CVM_HEALTH_CHECK_TIMEOUT_MS,
+    /// CVM_HEALTH_CHECK_INTERVAL_SECS, CVM_HEALTH_CHECK_STARTUP_SECS,
+    /// CVM_HEALTH_CHECK_SUCCESS_THRESHOLD and CVM_HEALTH_CHECK_FAILURE_THRESHOLD.
+    pub health_check: HealthCheck,
+    /// How long the app may take to exit after Sigterm before it is killed. Set with
+    /// CVM_DRAIN_TIMEOUT_SECS.
+    pub drain_timeout: Duration,
+    /// Start a new version while the previous one keeps running, which is only drained once the new
+    /// version is healthy. Set with CVM_HANDOVER=true. The app has to cope with two versions running
+    /// at the same time.
+    pub handover: bool,
+    /// Addresses, set with CVM_LISTEN, the monitor listens on and passes to every version of the app
+    /// as inherited sockets, see [`crate::sockets::ListenSockets`].
+    pub listen_addresses: Vec<SocketAddr>,
+    /// How the app is started: CVM_APP_ARGS holds its arguments separated by whitespace, CVM_APP_ENV
+    /// environment variables as KEY=value,KEY=value, CVM_APP_WORKING_DIR its working directory, and
+    /// CVM_APP_USER and CVM_APP_GROUP the user and group it runs as, by name or id.
+    pub launch: LaunchConfig,
+    /// Where the app's output goes: log files under CVM_LOG_DIR, rotated at CVM_LOG_MAX_MB with
+    /// CVM_LOG_KEEP_FILES rotated files kept, or the monitor's own output. CVM_LOG_PREFIX=true
+    /// prefixes lines with the version and time, and CVM_STDERR_TAIL_LINES lines of stderr are kept
+    /// for crash reports.
+    pub output: OutputConfig,
+    /// A cgroup (v2) directory, set with CVM_CGROUP_ROOT, under which the app is placed in a cgroup
+    /// of its own. Only supported on Linux, and the monitor needs to be allowed to create cgroups
+    /// there.
+    pub cgroup_root: Option<PathBuf>,
 }
 
 impl Config {
@@ -45,33 +187,264 @@ impl Config {
         let cvm_server_url = get_env_var_or("CVM_SERVER_URL", DEFAULT_CVM_SERVER_URL);
         let client_id = get_env_var_or("CLIENT_ID", DEFAULT_CLIENT_ID);
         let app_id = get_env_var_or("APP_ID", DEFAULT_APP_ID);
-        let architecture = get_architecture()?;
+        let architecture = match std::env::var("CVM_TARGET") {
+            Ok(target) if !target.trim().is_empty() => target.trim().to_string(),
+            _ => get_architecture()?,
+        };
+        let use_update_stream =
+            get_env_var_or("CVM_USE_UPDATE_STREAM", DEFAULT_USE_UPDATE_STREAM) == "true";
+        let maintenance_window = match std::env::var("CVM_MAINTENANCE_WINDOW") {
+            Ok(value) if !value.trim().is_empty() => Some(
+                MaintenanceWindow::parse(&value).ok_or(InvalidMaintenanceWindow { value })?,
+            ),
+            _ => None,
+        };
+        let labels = parse_labels(&get_env_var_or("CVM_LABELS", ""))?;
+        let cache_dir = match std::env::var("CVM_CACHE_DIR") {
+            Ok(dir) if !dir.trim().is_empty() => PathBuf::from(dir.trim()),
+            _ => default_cache_dir(),
+        };
+        let cache_keep_versions =
+            get_env_number("CVM_CACHE_KEEP_VERSIONS", DEFAULT_CACHE_KEEP_VERSIONS, 1)?;
+        let cache_max_bytes = Some(get_env_number("CVM_CACHE_MAX_MB", 0, 0)? * BYTES_PER_MB)
+            .filter(|max_bytes| *max_bytes > 0);
+        let min_free_disk_bytes =
+            get_env_number("CVM_MIN_FREE_DISK_MB", DEFAULT_MIN_FREE_DISK_MB, 0)? * BYTES_PER_MB;
+        let download_attempts = get_env_number("CVM_DOWNLOAD_ATTEMPTS", DEFAULT_DOWNLOAD_ATTEMPTS, 1)?;
+        let restart_mode = get_env_var_or("CVM_RESTART_POLICY", DEFAULT_RESTART_POLICY);
+        let restart_policy = RestartPolicy {
+            mode: RestartMode::parse(&restart_mode).ok_or(InvalidRestartPolicy {
+                value: restart_mode,
+            })?,
+            max_restarts: get_env_number("CVM_MAX_RESTARTS", DEFAULT_MAX_RESTARTS, 0)?,
+            window: Duration::from_secs(get_env_number(
+                "CVM_RESTART_WINDOW_SECS",
+                DEFAULT_RESTART_WINDOW_SECS,
+                1,
+            )?),
+            backoff_base: Duration::from_millis(get_env_number(
+                "CVM_RESTART_BACKOFF_MS",
+                DEFAULT_RESTART_BACKOFF_MS,
+                0,
+            )?),
+            backoff_max: Duration::from_millis(get_env_number(
+                "CVM_RESTART_BACKOFF_MAX_MS",
+                DEFAULT_RESTART_BACKOFF_MAX_MS,
+                0,
+            )?),
+        };
+
+        let expected_status =
+            get_env_number("CVM_HEALTH_CHECK_STATUS", DEFAULT_HEALTH_CHECK_STATUS, 100)?;
+        let probe = match std::env::var("CVM_HEALTH_CHECK") {
+            Ok(value) if !value.trim().is_empty() => Some(
+                HealthProbe::parse(&value, expected_status).ok_or(InvalidHealthCheck { value })?,
+            ),
+            _ => None,
+        };
+        let health_check = HealthCheck {
+            probe,
+            timeout: Duration::from_millis(get_env_number(
+                "CVM_HEALTH_CHECK_TIMEOUT_MS",
+                DEFAULT_HEALTH_CHECK_TIMEOUT_MS,
+                1,
+            )?),
+            interval: Duration::from_secs(get_env_number(
+                "CVM_HEALTH_CHECK_INTERVAL_SECS",
+                DEFAULT_HEALTH_CHECK_INTERVAL_SECS,
+                1,
+            )?),
+            startup_timeout: Duration::from_secs(get_env_number(
+                "CVM_HEALTH_CHECK_STARTUP_SECS",
+                DEFAULT_HEALTH_CHECK_STARTUP_SECS,
+                1,
+            )?),
+            success_threshold: get_env_number(
+                "CVM_HEALTH_CHECK_SUCCESS_THRESHOLD",
+                DEFAULT_HEALTH_CHECK_SUCCESS_THRESHOLD,
+                1,
+            )?,
+            failure_threshold: get_env_number(
+                "CVM_HEALTH_CHECK_FAILURE_THRESHOLD",
+                DEFAULT_HEALTH_CHECK_FAILURE_THRESHOLD,
+                1,
+            )?,
+        };
+
+        let drain_timeout = Duration::from_secs(get_env_number(
+            "CVM_DRAIN_TIMEOUT_SECS",
+            DEFAULT_DRAIN_TIMEOUT_SECS,
+            0,
+        )?);
+        let handover = get_env_var_or("CVM_HANDOVER", DEFAULT_HANDOVER) == "true";
+        let listen_addresses = parse_listen_addresses(&get_env_var_or("CVM_LISTEN", ""))?;
+        let launch = launch_config()?;
+        let output = OutputConfig {
+            log_dir: match std::env::var("CVM_LOG_DIR") {
+                Ok(dir) if !dir.trim().is_empty() => Some(PathBuf::from(dir.trim())),
+                _ => None,
+            },
+            max_file_bytes: get_env_number("CVM_LOG_MAX_MB", DEFAULT_LOG_MAX_MB, 1)? * BYTES_PER_MB,
+            keep_files: get_env_number("CVM_LOG_KEEP_FILES", DEFAULT_LOG_KEEP_FILES, 0)?,
+            prefix: get_env_var_or("CVM_LOG_PREFIX", DEFAULT_LOG_PREFIX) == "true",
+            tail_lines: get_env_number("CVM_STDERR_TAIL_LINES", DEFAULT_STDERR_TAIL_LINES, 0)?,
+        };
+        let cgroup_root = match std::env::var("CVM_CGROUP_ROOT") {
+            Ok(dir) if !dir.trim().is_empty() => Some(PathBuf::from(dir.trim())),
+            _ => None,
+        };
 
         Ok(Config {
             cvm_server_url,
             client_id,
             app_id,
             architecture,
+            use_update_stream,
+            maintenance_window,
+            labels,
+            cache_dir,
+            cache_keep_versions,
+            cache_max_bytes,
+            min_free_disk_bytes,
+            download_attempts,
+            restart_policy,
+            health_check,
+            drain_timeout,
+            handover,
+            listen_addresses,
+            launch,
+            output,
+            cgroup_root,
         })
     }
 }
 
-fn get_env_var_or<'a>(key: &str, default: &str) -> String {
+/// Reads a number of at least `minimum` from the environment.
+fn get_env_number<T: FromStr + PartialOrd>(key: &str, default: T, minimum: T) -> Result<T> {
+    match std::env::var(key) {
+        Ok(value) if !value.trim().is_empty() => value
+            .trim()
+            .parse()
+            .ok()
+            .filter(|number| *number >= minimum)
+            .ok_or(InvalidNumber {
+                key: key.to_string(),
+                value,
+            }),
+        _ => Ok(default),
+    }
+}
+
+fn default_cache_dir() -> PathBuf {
+    let non_empty = |key: &str| std::env::var(key).ok().filter(|value| !value.trim().is_empty());
+    match (non_empty("XDG_CACHE_HOME"), non_empty("HOME")) {
+        (Some(cache_home), _) => PathBuf::from(cache_home).join("cvm"),
+        (None, Some(home)) => PathBuf::from(home).join(".cache").join("cvm"),
+        (None, None) => PathBuf::from("cvm_cache"),
+    }
+}
+
+/// Parses labels in the format `key=value,key=value`. Whether keys and values are valid is left to
+/// the server.
+pub fn parse_labels(value: &str) -> Result<BTreeMap<String, String>> {
+    parse_pairs(value).ok_or(InvalidLabels {
+        value: value.to_string(),
+    })
+}
+
+/// Parses environment variables for the app in the format `KEY=value,KEY=value`.
+pub fn parse_app_env(value: &str) -> Result<BTreeMap<String, String>> {
+    parse_pairs(value).ok_or(InvalidAppEnv {
+        value: value.to_string(),
+    })
+}
+
+/// Parses `key=value,key=value`, where neither keys nor values may be empty.
+fn parse_pairs(value: &str) -> Option<BTreeMap<String, String>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() && !value.trim().is_empty() => {
+                Some((key.trim().to_string(), value.trim().to_string()))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Reads how the app is started from the environment, see [`Config::launch`].
+fn launch_config() -> Result<LaunchConfig> {
+    let non_empty = |key: &str| std::env::var(key).ok().filter(|value| !value.trim().is_empty());
+    let user = match non_empty("CVM_APP_USER") {
+        Some(value) => Some(find_user(&value).ok_or(UnknownUser { value })?),
+        None => None,
+    };
+    let gid = match non_empty("CVM_APP_GROUP") {
+        Some(value) => Some(find_group(&value).ok_or(UnknownGroup { value })?),
+        None => user.and_then(|(_, gid)| gid),
+    };
+    Ok(LaunchConfig {
+        args: get_env_var_or("CVM_APP_ARGS", "")
+            .split_whitespace()
+            .map(str::to_string)
+            .collect(),
+        env: parse_app_env(&get_env_var_or("CVM_APP_ENV", ""))?,
+        working_dir: non_empty("CVM_APP_WORKING_DIR").map(|dir| dir.trim().to_string()),
+        uid: user.map(|(uid, _)| uid),
+        gid,
+    })
+}
+
+/// Parses socket addresses in the format `<ip>:<port>,<ip>:<port>`, with IPv6 addresses in
+/// brackets.
+pub fn parse_listen_addresses(value: &str) -> Result<Vec<SocketAddr>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|address| !address.is_empty())
+        .map(|address| {
+            address.parse().map_err(|_| InvalidListenAddresses {
+                value: value.to_string(),
+            })
+        })
+        .collect()
+}
+
+fn get_env_var_or(key: &str, default: &str) -> String {
     std::env::var(key).unwrap_or_else(|_| default.to_string())
 }
 
+/// Returns the target triple of the platform the client was built for. Binaries built for this
+/// target are known to run here, and the server falls back to compatible targets when there is no
+/// such build.
 pub fn get_architecture() -> Result<String> {
-    // only supporting x86_64 and windows currently.
-    // couldn't get mac m1 to build on debian.
-    let os = std::env::consts::OS;
-    let arch = std::env::consts::ARCH;
-    if arch != "x86_64" {
-        Err(ArchitectureNotSupported)
-    } else if os == "linux" {
-        Ok("x86_64-unknown-linux-gnu".to_string())
-    } else if os == "linux" {
-        Ok("x86_64-pc-windows-gnu".to_string())
+    let arch = match std::env::consts::ARCH {
+        "x86" => "i686",
+        // arm triples encode the instruction set version and float abi, which can't be detected
+        // from ARCH alone.
+        "arm" => return Err(ArchitectureNotSupported),
+        arch => arch,
+    };
+
+    match std::env::consts::OS {
+        "linux" => Ok(format!("{}-unknown-linux-{}", arch, target_env().unwrap_or("gnu"))),
+        "windows" => Ok(format!("{}-pc-windows-{}", arch, target_env().unwrap_or("msvc"))),
+        "macos" => Ok(format!("{}-apple-darwin", arch)),
+        "freebsd" => Ok(format!("{}-unknown-freebsd", arch)),
+        _ => Err(OSNotSupported),
+    }
+}
+
+fn target_env() -> Option<&'static str> {
+    if cfg!(target_env = "musl") {
+        Some("musl")
+    } else if cfg!(target_env = "gnu") {
+        Some("gnu")
+    } else if cfg!(target_env = "msvc") {
+        Some("msvc")
     } else {
-        Err(OSNotSupported)
+        None
     }
 }
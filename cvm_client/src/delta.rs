@@ -0,0 +1,30 @@
+use crate::errors::CvmError::DigestMismatch;
+use crate::errors::Result;
+use sha2::{Digest, Sha256};
+
+/// Lowercase hex sha256 digest of the content.
+pub fn sha256_hex(content: &[u8]) -> String {
+    hex::encode(Sha256::digest(content))
+}
+
+/// Fails unless the content has the expected hex sha256 digest.
+pub fn verify_sha256(content: &[u8], expected: &str) -> Result<()> {
+    let actual = sha256_hex(content);
+    if actual.eq_ignore_ascii_case(expected) {
+        Ok(())
+    } else {
+        Err(DigestMismatch {
+            expected: expected.to_string(),
+            actual,
+        })
+    }
+}
+
+/// Applies a patch served by the CVM server, a zstd compressed bsdiff patch, to the old build and
+/// returns the new build.
+pub fn apply_patch(old: &[u8], patch: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut decoder = zstd::Decoder::new(patch)?;
+    let mut new = Vec::new();
+    bsdiff::patch(old, &mut decoder, &mut new)?;
+    Ok(new)
+}
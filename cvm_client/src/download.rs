@@ -0,0 +1,285 @@
+use crate::cache::ensure_free_space;
+use crate::encoding::{ContentEncoding, DecodingWriter, DigestWriter, ACCEPTED_ENCODINGS};
+use crate::errors::CvmError::{DigestMismatch, NewVersionDownloadFailed};
+use crate::errors::{map_io_error, CvmError, Result};
+use crate::map_reqwuest_error;
+use reqwest::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_RANGE, RANGE};
+use reqwest::StatusCode;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Log a line whenever another tenth of a download arrived, or another 10 MiB when the size is
+/// unknown.
+const LOG_STEPS: u64 = 10;
+const LOG_INTERVAL_BYTES: u64 = 10 * 1024 * 1024;
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Progress of a build download, in bytes of the response as sent by the server, which are fewer
+/// than the bytes written to disk when the response is compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DownloadProgress {
+    /// Bytes received, including those of earlier attempts that were resumed.
+    pub received: u64,
+    /// Size of the download when the server sent it.
+    pub total: Option<u64>,
+}
+
+impl DownloadProgress {
+    pub fn percentage(&self) -> Option<u64> {
+        self.total
+            .filter(|total| *total > 0)
+            .map(|total| self.received.min(total) * 100 / total)
+    }
+}
+
+pub type ProgressCallback = Arc<dyn Fn(DownloadProgress) + Send + Sync>;
+
+/// How builds are downloaded.
+#[derive(Clone)]
+pub struct DownloadOptions {
+    /// How many times a download is attempted.
+    pub attempts: u32,
+    /// Downloads are refused when less space than this would be left on the volume.
+    pub min_free_disk_bytes: u64,
+    pub progress: Option<ProgressCallback>,
+}
+
+/// Why an attempt failed. Interrupted attempts are retried and resume from the partial file,
+/// failed ones are not and leave nothing behind. Refused ones are not retried either, but leave
+/// the partial file to be resumed later.
+enum AttemptError {
+    Interrupted(CvmError),
+    Failed(CvmError),
+    Refused(CvmError),
+}
+
+/// Downloads `url` to `destination` and returns the digest of the build.
+///
+/// The build is streamed to `{destination}.partial` and renamed once it is complete and matches
+/// `expected_sha256`. Interrupted transfers are retried up to `options.attempts` times in total,
+/// resuming with a `Range` request for the rest of the uncompressed build. A partial file left by
+/// an earlier run is resumed the same way.
+pub(crate) async fn download(
+    client: &reqwest::Client,
+    url: &str,
+    destination: &Path,
+    expected_sha256: Option<&str>,
+    options: &DownloadOptions,
+) -> Result<String> {
+    let attempts = options.attempts;
+    let file_name = destination
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let partial_path = staging_path(destination);
+
+    let mut attempt = 1;
+    loop {
+        match download_attempt(client, url, &partial_path, expected_sha256, options).await {
+            Ok(sha256) => {
+                std::fs::rename(&partial_path, destination).map_err(map_io_error)?;
+                return Ok(sha256);
+            }
+            Err(AttemptError::Interrupted(err)) if attempt < attempts => {
+                println!(
+                    "Download of {} interrupted ({}), retrying {}/{}",
+                    file_name,
+                    err,
+                    attempt + 1,
+                    attempts
+                );
+                tokio::time::sleep(RETRY_DELAY * attempt).await;
+                attempt += 1;
+            }
+            Err(AttemptError::Interrupted(err)) | Err(AttemptError::Refused(err)) => return Err(err),
+            Err(AttemptError::Failed(err)) => {
+                let _ = std::fs::remove_file(&partial_path);
+                return Err(err);
+            }
+        }
+    }
+}
+
+/// The path a build is written to before it is verified and renamed to `destination`.
+pub(crate) fn staging_path(destination: &Path) -> PathBuf {
+    let mut path = destination.as_os_str().to_owned();
+    path.push(".partial");
+    PathBuf::from(path)
+}
+
+async fn download_attempt(
+    client: &reqwest::Client,
+    url: &str,
+    partial_path: &Path,
+    expected_sha256: Option<&str>,
+    options: &DownloadOptions,
+) -> std::result::Result<String, AttemptError> {
+    let interrupted = |err: reqwest::Error| AttemptError::Interrupted(map_reqwuest_error(err));
+    let failed = |message: String| AttemptError::Failed(NewVersionDownloadFailed { message });
+    // Compressed streams can't be resumed mid-way, but their decompressed prefix is a prefix of
+    // the uncompressed build, so the rest is requested uncompressed.
+    let offset = std::fs::metadata(partial_path).map(|metadata| metadata.len()).unwrap_or(0);
+    let request = if offset > 0 {
+        client
+            .get(url)
+            .header(RANGE, format!("bytes={}-", offset))
+            .header(ACCEPT_ENCODING, "identity")
+    } else {
+        client.get(url).header(ACCEPT_ENCODING, ACCEPTED_ENCODINGS)
+    };
+    let mut response = request.send().await.map_err(interrupted)?;
+
+    let resumed = match response.status() {
+        StatusCode::PARTIAL_CONTENT if offset > 0 => {
+            let content_range = response
+                .headers()
+                .get(CONTENT_RANGE)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default();
+            if !content_range.starts_with(&format!("bytes {}-", offset)) {
+                return Err(failed(format!("Unexpected content range: {}", content_range)));
+            }
+            true
+        }
+        // The partial file is as long as the build or longer, so it can't be the build's prefix.
+        StatusCode::RANGE_NOT_SATISFIABLE if offset > 0 => {
+            let _ = std::fs::remove_file(partial_path);
+            return Err(AttemptError::Interrupted(NewVersionDownloadFailed {
+                message: "The partial download doesn't match the build".to_string(),
+            }));
+        }
+        status if status.is_success() => false,
+        _ => {
+            let err = response.error_for_status().expect_err("not a success status");
+            return Err(AttemptError::Failed(map_reqwuest_error(err)));
+        }
+    };
+
+    let content_encoding = response
+        .headers()
+        .get(CONTENT_ENCODING)
+        .and_then(|value| value.to_str().ok());
+    let encoding = ContentEncoding::parse(content_encoding)
+        .filter(|encoding| !resumed || *encoding == ContentEncoding::Identity)
+        .ok_or_else(|| failed(format!("Unsupported content encoding: {}", content_encoding.unwrap_or_default())))?;
+    let decode_error = |err: std::io::Error| failed(format!("Unable to decode the download: {}", err));
+    let io_error = |err: std::io::Error| AttemptError::Failed(map_io_error(err));
+    // Compressed responses grow on disk, so their length only tells the least they need.
+    if let (Some(length), Some(dir)) = (response.content_length(), partial_path.parent()) {
+        ensure_free_space(dir, length.saturating_add(options.min_free_disk_bytes))
+            .map_err(AttemptError::Refused)?;
+    }
+
+    let digest_writer = if resumed {
+        let file = OpenOptions::new().append(true).open(partial_path).map_err(io_error)?;
+        let existing = File::open(partial_path).map_err(io_error)?;
+        DigestWriter::continuing(BufWriter::new(file), existing).map_err(io_error)?
+    } else {
+        DigestWriter::new(BufWriter::new(File::create(partial_path).map_err(io_error)?))
+    };
+    let mut dest = DecodingWriter::new(encoding, digest_writer).map_err(decode_error)?;
+    let mut progress_log = ProgressLog::new(
+        if resumed { offset } else { 0 },
+        response.content_length().map(|length| length + if resumed { offset } else { 0 }),
+        options.progress.as_ref(),
+    );
+    loop {
+        match response.chunk().await {
+            Ok(Some(chunk)) => {
+                dest.write_all(&chunk).map_err(decode_error)?;
+                progress_log.advance(chunk.len() as u64);
+            }
+            Ok(None) => break,
+            Err(err) => {
+                // Keep what was decoded so far for the next attempt to resume from.
+                let _ = dest.flush();
+                return Err(interrupted(err));
+            }
+        }
+    }
+    let (mut file, sha256) = dest.finish().map_err(decode_error)?.finish();
+    file.flush().map_err(io_error)?;
+    progress_log.finish();
+
+    match expected_sha256 {
+        Some(expected) if !expected.eq_ignore_ascii_case(&sha256) => {
+            let mismatch = DigestMismatch {
+                expected: expected.to_string(),
+                actual: sha256,
+            };
+            if resumed {
+                // The build may have changed since the partial file was written. Start over.
+                let _ = std::fs::remove_file(partial_path);
+                Err(AttemptError::Interrupted(mismatch))
+            } else {
+                Err(AttemptError::Failed(mismatch))
+            }
+        }
+        _ => Ok(sha256),
+    }
+}
+
+/// Reports progress to the callback on every chunk and logs it for operators now and then.
+struct ProgressLog<'a> {
+    progress: DownloadProgress,
+    last_logged: u64,
+    callback: Option<&'a ProgressCallback>,
+}
+
+impl<'a> ProgressLog<'a> {
+    fn new(received: u64, total: Option<u64>, callback: Option<&'a ProgressCallback>) -> Self {
+        if received > 0 {
+            println!("Resuming download at {}", format_bytes(received));
+        }
+        ProgressLog {
+            progress: DownloadProgress { received, total },
+            last_logged: received,
+            callback,
+        }
+    }
+
+    fn advance(&mut self, length: u64) {
+        self.progress.received += length;
+        if let Some(callback) = self.callback {
+            callback(self.progress);
+        }
+        let step = match self.progress.total {
+            Some(total) if total >= LOG_STEPS => total / LOG_STEPS,
+            _ => LOG_INTERVAL_BYTES,
+        };
+        if self.progress.received - self.last_logged >= step {
+            self.log();
+        }
+    }
+
+    fn finish(&mut self) {
+        if self.progress.received != self.last_logged {
+            self.log();
+        }
+    }
+
+    fn log(&mut self) {
+        self.last_logged = self.progress.received;
+        match (self.progress.total, self.progress.percentage()) {
+            (Some(total), Some(percentage)) => println!(
+                "Downloaded {} of {} ({}%)",
+                format_bytes(self.progress.received),
+                format_bytes(total),
+                percentage
+            ),
+            _ => println!("Downloaded {}", format_bytes(self.progress.received)),
+        }
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const MIB: u64 = 1024 * 1024;
+    if bytes >= MIB {
+        format!("{:.1} MiB", bytes as f64 / MIB as f64)
+    } else {
+        format!("{:.1} KiB", bytes as f64 / 1024.0)
+    }
+}
@@ -0,0 +1,112 @@
+use sha2::{Digest, Sha256};
+use std::io::{self, Write};
+
+/// The `Accept-Encoding` sent with downloads, listing the encodings [`DecodingWriter`] decodes.
+pub const ACCEPTED_ENCODINGS: &str = "zstd, gzip";
+
+/// How the body of a download is encoded, read from its `Content-Encoding` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentEncoding {
+    Zstd,
+    Gzip,
+    Identity,
+}
+
+impl ContentEncoding {
+    /// Returns `None` for encodings the client can't decode.
+    pub fn parse(content_encoding: Option<&str>) -> Option<ContentEncoding> {
+        match content_encoding.map(|value| value.trim().to_ascii_lowercase()).as_deref() {
+            None | Some("") | Some("identity") => Some(ContentEncoding::Identity),
+            Some("zstd") => Some(ContentEncoding::Zstd),
+            Some("gzip") | Some("x-gzip") => Some(ContentEncoding::Gzip),
+            Some(_) => None,
+        }
+    }
+}
+
+/// Decodes what is written to it into the inner writer, so that downloads are decompressed while
+/// they are written to disk rather than after.
+pub enum DecodingWriter<W: Write> {
+    Zstd(zstd::stream::write::Decoder<'static, W>),
+    Gzip(flate2::write::GzDecoder<W>),
+    Identity(W),
+}
+
+impl<W: Write> DecodingWriter<W> {
+    pub fn new(encoding: ContentEncoding, inner: W) -> io::Result<Self> {
+        Ok(match encoding {
+            ContentEncoding::Zstd => DecodingWriter::Zstd(zstd::stream::write::Decoder::new(inner)?),
+            ContentEncoding::Gzip => DecodingWriter::Gzip(flate2::write::GzDecoder::new(inner)),
+            ContentEncoding::Identity => DecodingWriter::Identity(inner),
+        })
+    }
+
+    /// Decodes what is still buffered and returns the inner writer.
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            DecodingWriter::Zstd(mut decoder) => {
+                decoder.flush()?;
+                Ok(decoder.into_inner())
+            }
+            DecodingWriter::Gzip(decoder) => decoder.finish(),
+            DecodingWriter::Identity(inner) => Ok(inner),
+        }
+    }
+}
+
+impl<W: Write> Write for DecodingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            DecodingWriter::Zstd(decoder) => decoder.write(buf),
+            DecodingWriter::Gzip(decoder) => decoder.write(buf),
+            DecodingWriter::Identity(inner) => inner.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            DecodingWriter::Zstd(decoder) => decoder.flush(),
+            DecodingWriter::Gzip(decoder) => decoder.flush(),
+            DecodingWriter::Identity(inner) => inner.flush(),
+        }
+    }
+}
+
+/// Computes the sha256 digest of everything written through it.
+pub struct DigestWriter<W: Write> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> DigestWriter<W> {
+    pub fn new(inner: W) -> Self {
+        DigestWriter {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Continues the digest of a file that `inner` appends to, hashing what the file holds already.
+    pub fn continuing(inner: W, mut existing: impl io::Read) -> io::Result<Self> {
+        let mut writer = DigestWriter::new(inner);
+        io::copy(&mut existing, &mut writer.hasher)?;
+        Ok(writer)
+    }
+
+    /// Returns the inner writer and the lowercase hex digest.
+    pub fn finish(self) -> (W, String) {
+        (self.inner, hex::encode(self.hasher.finalize()))
+    }
+}
+
+impl<W: Write> Write for DigestWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
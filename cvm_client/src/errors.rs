@@ -13,6 +13,15 @@ pub enum CvmError {
     ShutdownFailed { message: String },
     ServerUnreachable { message: String },
     SerializingClientDetailsFailed { message: String },
+    UpdateStreamUnavailable { message: String },
+    UpdateStreamNotSupported,
+    DigestMismatch { expected: String, actual: String },
+    PatchFailed { message: String },
+    CacheUnavailable { message: String },
+    InsufficientDiskSpace { path: String, available: u64, required: u64 },
+    /// The app didn't pass its health check and was stopped with the status.
+    HealthCheckFailed { message: String, status: ExitStatus },
+    ListenFailed { address: String, message: String },
 }
 
 impl fmt::Display for CvmError {
@@ -49,6 +58,32 @@ impl fmt::Display for CvmError {
             CvmError::ShutdownFailed { message } => {
                 write!(f, "Shutdown process failed: {}", message)
             }
+            CvmError::UpdateStreamUnavailable { message } => {
+                write!(f, "Update stream unavailable: {}", message)
+            }
+            CvmError::UpdateStreamNotSupported => {
+                write!(f, "Update server does not support update streams")
+            }
+            CvmError::DigestMismatch { expected, actual } => {
+                write!(f, "Digest mismatch: expected sha256 {} but got {}", expected, actual)
+            }
+            CvmError::PatchFailed { message } => {
+                write!(f, "Unable to apply patch: {}", message)
+            }
+            CvmError::CacheUnavailable { message } => {
+                write!(f, "Build cache error: {}", message)
+            }
+            CvmError::InsufficientDiskSpace { path, available, required } => write!(
+                f,
+                "Not enough disk space for {}: {} bytes available, {} bytes required",
+                path, available, required
+            ),
+            CvmError::HealthCheckFailed { message, .. } => {
+                write!(f, "Health check failed: {}", message)
+            }
+            CvmError::ListenFailed { address, message } => {
+                write!(f, "Unable to listen on {}: {}", address, message)
+            }
         }
     }
 }
@@ -60,7 +95,7 @@ pub fn map_io_error(io_err: std::io::Error) -> CvmError {
 }
 
 pub fn map_reqwuest_error(reqwuest_err: reqwest::Error) -> CvmError {
-    println!("{}", reqwuest_err.to_string());
+    println!("{}", reqwuest_err);
     CvmError::NewVersionDownloadFailed {
         message: reqwuest_err.to_string(),
     }
@@ -0,0 +1,241 @@
+use crate::errors::CvmError::{HealthCheckFailed, ProcessExitEarly};
+use crate::errors::Result;
+use crate::process::AppProcess;
+use regex::Regex;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+
+/// How often the startup probe is repeated until it passes.
+const STARTUP_PROBE_INTERVAL: Duration = Duration::from_secs(1);
+/// Without a probe, the app is healthy once it is still running after this many checks a second
+/// apart.
+const STARTUP_CHECKS: u32 = 3;
+
+/// A check of whether the app is healthy.
+#[derive(Debug, Clone)]
+pub enum HealthProbe {
+    /// A GET request is answered with the status.
+    Http { url: String, expected_status: u16 },
+    /// A TCP connection to the address is accepted.
+    Tcp { address: String },
+    /// The command exits with status 0.
+    Command { program: String, args: Vec<String> },
+    /// A line the app writes to stdout matches the pattern. Once a line matched, the probe keeps
+    /// passing, so it only tells whether the app started.
+    Stdout { pattern: Regex },
+}
+
+impl HealthProbe {
+    /// Parses an `http://` or `https://` url, `tcp:<host>:<port>`, `command:<program> <args>` or
+    /// `stdout:<regex>`. The arguments of commands are separated by whitespace and can't be quoted.
+    pub fn parse(value: &str, expected_status: u16) -> Option<HealthProbe> {
+        let value = value.trim();
+        if value.starts_with("http://") || value.starts_with("https://") {
+            return Some(HealthProbe::Http {
+                url: value.to_string(),
+                expected_status,
+            });
+        }
+        let (kind, target) = value.split_once(':')?;
+        match kind {
+            "tcp" if !target.trim().is_empty() => Some(HealthProbe::Tcp {
+                address: target.trim().to_string(),
+            }),
+            "command" => {
+                let mut words = target.split_whitespace().map(str::to_string);
+                Some(HealthProbe::Command {
+                    program: words.next()?,
+                    args: words.collect(),
+                })
+            }
+            "stdout" => Some(HealthProbe::Stdout {
+                pattern: Regex::new(target).ok()?,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Runs the probe once. Returns why it failed.
+    pub async fn check(&self, timeout: Duration, output: &OutputMatch) -> std::result::Result<(), String> {
+        match self {
+            HealthProbe::Http { url, expected_status } => {
+                let response = reqwest::Client::new()
+                    .get(url)
+                    .timeout(timeout)
+                    .send()
+                    .await
+                    .map_err(|err| format!("GET {} failed: {}", url, err))?;
+                if response.status().as_u16() != *expected_status {
+                    return Err(format!(
+                        "GET {} answered {}, expected {}",
+                        url,
+                        response.status(),
+                        expected_status
+                    ));
+                }
+                Ok(())
+            }
+            HealthProbe::Tcp { address } => {
+                match tokio::time::timeout(timeout, tokio::net::TcpStream::connect(address)).await {
+                    Ok(Ok(_)) => Ok(()),
+                    Ok(Err(err)) => Err(format!("Unable to connect to {}: {}", address, err)),
+                    Err(_) => Err(format!("Connecting to {} timed out", address)),
+                }
+            }
+            HealthProbe::Command { program, args } => {
+                let status = tokio::process::Command::new(program)
+                    .args(args)
+                    .stdout(Stdio::null())
+                    .kill_on_drop(true)
+                    .status();
+                match tokio::time::timeout(timeout, status).await {
+                    Ok(Ok(status)) if status.success() => Ok(()),
+                    Ok(Ok(status)) => Err(format!("{} exited with {}", program, status)),
+                    Ok(Err(err)) => Err(format!("Unable to run {}: {}", program, err)),
+                    Err(_) => Err(format!("{} timed out", program)),
+                }
+            }
+            HealthProbe::Stdout { pattern } => {
+                if output.matched() {
+                    Ok(())
+                } else {
+                    Err(format!("No line of output matched {}", pattern))
+                }
+            }
+        }
+    }
+}
+
+/// Formats the probe the way [`HealthProbe::parse`] reads it.
+impl std::fmt::Display for HealthProbe {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HealthProbe::Http { url, .. } => write!(f, "{}", url),
+            HealthProbe::Tcp { address } => write!(f, "tcp:{}", address),
+            HealthProbe::Command { program, args } if args.is_empty() => write!(f, "command:{}", program),
+            HealthProbe::Command { program, args } => write!(f, "command:{} {}", program, args.join(" ")),
+            HealthProbe::Stdout { pattern } => write!(f, "stdout:{}", pattern),
+        }
+    }
+}
+
+/// Whether a line of the app's stdout matched the stdout probe, see
+/// [`crate::output::OutputCapture::capture`].
+#[derive(Debug, Clone, Default)]
+pub struct OutputMatch(Arc<AtomicBool>);
+
+impl OutputMatch {
+    pub fn matched(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Records a match when the line of output matches the pattern.
+    pub fn check_line(&self, pattern: &Regex, line: &str) {
+        if !self.matched() && pattern.is_match(line) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+/// When the app counts as healthy. Without a probe it is healthy as long as it keeps running.
+#[derive(Debug, Clone)]
+pub struct HealthCheck {
+    pub probe: Option<HealthProbe>,
+    /// How long a single probe may take.
+    pub timeout: Duration,
+    /// Time between probes while the app runs.
+    pub interval: Duration,
+    /// How long the app has after starting to pass the probe.
+    pub startup_timeout: Duration,
+    /// Passes in a row after which the app started successfully.
+    pub success_threshold: u32,
+    /// Failures in a row after which the running app is unhealthy.
+    pub failure_threshold: u32,
+}
+
+impl HealthCheck {
+    /// Waits until the app that was just started passes the probe `success_threshold` times in a
+    /// row. Fails with [`ProcessExitEarly`] when the app exits first, and stops the app and fails
+    /// with [`HealthCheckFailed`] when it doesn't pass within the startup timeout.
+    pub async fn wait_until_healthy(&self, app: &mut AppProcess, output: &OutputMatch) -> Result<()> {
+        let Some(probe) = &self.probe else {
+            return wait_until_started(app).await;
+        };
+        let deadline = Instant::now() + self.startup_timeout;
+        let mut passes = 0;
+        let mut last_failure = String::new();
+        loop {
+            exit_status(app)?;
+            match probe.check(self.timeout, output).await {
+                Ok(()) => {
+                    passes += 1;
+                    if passes >= self.success_threshold {
+                        return Ok(());
+                    }
+                }
+                Err(message) => {
+                    passes = 0;
+                    last_failure = message;
+                }
+            }
+            if Instant::now() >= deadline {
+                return Err(HealthCheckFailed {
+                    message: format!(
+                        "not healthy within {:?}: {}",
+                        self.startup_timeout, last_failure
+                    ),
+                    status: app.kill().await?,
+                });
+            }
+            sleep(STARTUP_PROBE_INTERVAL.min(self.interval)).await;
+        }
+    }
+
+    /// Probes the running app on the interval and returns the last failure once the probe failed
+    /// `failure_threshold` times in a row. Never returns without a probe, and stdout probes are
+    /// only used at startup.
+    pub async fn until_unhealthy(&self) -> String {
+        let probe = match &self.probe {
+            Some(HealthProbe::Stdout { .. }) | None => return std::future::pending().await,
+            Some(probe) => probe,
+        };
+        let mut failures = 0;
+        loop {
+            sleep(self.interval).await;
+            match probe.check(self.timeout, &OutputMatch::default()).await {
+                Ok(()) => failures = 0,
+                Err(message) => {
+                    failures += 1;
+                    println!(
+                        "Health check failed ({}/{}): {}",
+                        failures, self.failure_threshold, message
+                    );
+                    if failures >= self.failure_threshold {
+                        return message;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Checks that the app is still running a few times, a second apart.
+async fn wait_until_started(app: &mut AppProcess) -> Result<()> {
+    for _ in 0..STARTUP_CHECKS {
+        exit_status(app)?;
+        println!("status not ready yet, let's really wait");
+        sleep(Duration::from_secs(1)).await;
+    }
+    Ok(())
+}
+
+/// Fails with [`ProcessExitEarly`] when the app exited.
+fn exit_status(app: &mut AppProcess) -> Result<()> {
+    match app.try_wait()? {
+        Some(status) => Err(ProcessExitEarly { status }),
+        None => Ok(()),
+    }
+}
@@ -1,26 +1,129 @@
 use crate::config::Config;
+use crate::delta::{apply_patch, verify_sha256};
+use crate::cache::{ArtifactCache, CacheEntry, CacheKey, RetentionPolicy};
+use crate::download::{download, staging_path, DownloadOptions, DownloadProgress};
+use crate::errors::CvmError::{PatchFailed, UpdateStreamNotSupported, UpdateStreamUnavailable};
 use crate::errors::{map_io_error, map_serialize_error, Result};
-use crate::map_reqwuest_error;
+use crate::{map_reqwuest_error, strip_version_from_file_name, ShutdownOutcome};
+use chrono::{DateTime, Utc};
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
-use std::env::current_dir;
-use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::sync::Arc;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use tempfile::Builder;
 use url::Url;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LatestVersionResponse {
     pub build_id: String,
     pub version: String,
     pub url: String,
+    /// A newer version exists.
     pub update_required: bool,
+    /// The running version is no longer supported and the update must not be deferred. Older
+    /// servers don't send it.
+    #[serde(default)]
+    pub update_mandatory: bool,
+    #[serde(default)]
+    pub min_supported_version: Option<String>,
+    #[serde(default)]
+    pub release_notes: Option<String>,
+    #[serde(default)]
+    pub released_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub git_commit: Option<String>,
+    /// Free-form json object describing the version.
+    #[serde(default)]
+    pub metadata: serde_json::Value,
+    /// Hex sha256 digest of the build, which downloads are verified against when set.
+    #[serde(default)]
+    pub sha256: Option<String>,
+    /// Patches that turn builds of earlier versions into this build.
+    #[serde(default)]
+    pub patches: Vec<PatchResponse>,
+}
+
+/// A patch from the build of `from_version` to the latest build, which is usually much smaller than
+/// the build itself.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PatchResponse {
+    pub from_version: String,
+    pub url: String,
+    pub sha256: String,
+    pub size: u64,
 }
 
 impl LatestVersionResponse {
     pub fn get_file_name(&self) -> String {
-        self.url.split('/').last().unwrap().to_string()
+        self.url.split('/').next_back().unwrap().to_string()
+    }
+
+    /// Describes the version and what changed in it for logging.
+    pub fn describe_release(&self) -> String {
+        let mut description = self.version.clone();
+        if let Some(git_commit) = &self.git_commit {
+            description += &format!(" (commit {})", git_commit);
+        }
+        if let Some(released_at) = &self.released_at {
+            description += &format!(" released {}", released_at.to_rfc3339());
+        }
+        if let Some(release_notes) = self.release_notes.as_deref().filter(|notes| !notes.is_empty()) {
+            description += &format!("\n{}", release_notes);
+        }
+        description
+    }
+}
+
+/// Name of the server-sent event that carries a `LatestVersionResponse`.
+const LATEST_VERSION_EVENT: &str = "latest";
+
+/// Stream of latest versions pushed by the CVM server as server-sent events.
+pub struct LatestVersionStream {
+    response: reqwest::Response,
+    buffer: Vec<u8>,
+}
+
+impl LatestVersionStream {
+    /// Waits for the next latest version pushed by the server. Returns `None` once the server
+    /// closes the stream.
+    pub async fn next_latest(&mut self) -> Result<Option<LatestVersionResponse>> {
+        loop {
+            if let Some(end) = self.buffer.windows(2).position(|window| window == b"\n\n") {
+                let event: Vec<u8> = self.buffer.drain(..end + 2).collect();
+                if let Some(data) = parse_event_data(&String::from_utf8_lossy(&event), LATEST_VERSION_EVENT) {
+                    let latest = serde_json::from_str(&data).map_err(map_serialize_error)?;
+                    return Ok(Some(latest));
+                }
+                continue;
+            }
+
+            match self.response.chunk().await.map_err(map_reqwuest_error)? {
+                Some(chunk) => self.buffer.extend_from_slice(&chunk),
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+/// Returns the data of a server-sent event if it has the expected event name. Comments, such as
+/// the keep-alive messages sent by the server, and other events are ignored.
+fn parse_event_data(event: &str, expected_name: &str) -> Option<String> {
+    let mut name = "message";
+    let mut data: Vec<&str> = Vec::new();
+    for line in event.lines() {
+        if let Some(value) = line.strip_prefix("event:") {
+            name = value.trim();
+        } else if let Some(value) = line.strip_prefix("data:") {
+            data.push(value.strip_prefix(' ').unwrap_or(value));
+        }
+    }
+    if name == expected_name && !data.is_empty() {
+        Some(data.join("\n"))
+    } else {
+        None
     }
 }
 
@@ -29,20 +132,114 @@ impl LatestVersionResponse {
 /// A http client that is used to communicate with the CVM server.
 /// This client has utilities to retrieve the latest version of an application and report
 /// successful or unsuccessful startups of the version retrieved.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ClientDetails {
     pub client_id: String,
     pub app_id: String,
     pub current_running_version: String,
     pub architecture: String,
+    pub labels: BTreeMap<String, String>,
+}
+
+/// Json body of a rollback report, the client details with the version that failed to start.
+#[derive(Serialize)]
+struct RollbackReport<'a> {
+    #[serde(flatten)]
+    client_details: &'a ClientDetails,
+    failed_version: &'a str,
+}
+
+/// Json body of a crash report, the client details with how the app exited.
+#[derive(Serialize)]
+struct CrashReport<'a> {
+    #[serde(flatten)]
+    client_details: &'a ClientDetails,
+    exit_code: Option<i32>,
+    signal: Option<i32>,
+    crash_count: u32,
+    restarting: bool,
+    stderr_tail: Option<String>,
+}
+
+/// Why a build failed to start, sent to the CVM server along with the failure.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct StartupFailure {
+    /// Missing when the app was killed by `signal`, or never ran.
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    /// Milliseconds from starting the build until it failed.
+    pub uptime_ms: u64,
+    /// The last lines the app wrote to stderr.
+    pub stderr_tail: Option<String>,
+    /// The health probe the app didn't pass, as it was configured.
+    pub failed_probe: Option<String>,
+    pub error: String,
+    pub host: HostInfo,
+}
+
+/// The machine the client runs on.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct HostInfo {
+    pub hostname: Option<String>,
+    pub os: String,
+    /// The kernel's release, e.g. `6.1.0-18-amd64`.
+    pub kernel: Option<String>,
+    pub arch: String,
+}
+
+impl HostInfo {
+    pub fn current() -> HostInfo {
+        HostInfo {
+            hostname: nix::unistd::gethostname()
+                .ok()
+                .and_then(|hostname| hostname.into_string().ok()),
+            os: std::env::consts::OS.to_string(),
+            kernel: nix::sys::utsname::uname()
+                .ok()
+                .map(|uname| uname.release().to_string_lossy().into_owned()),
+            arch: std::env::consts::ARCH.to_string(),
+        }
+    }
+}
+
+/// Json body of a failure report, the client details with why the build failed to start.
+#[derive(Serialize)]
+struct FailureReport<'a> {
+    #[serde(flatten)]
+    client_details: &'a ClientDetails,
+    #[serde(flatten)]
+    failure: &'a StartupFailure,
+}
+
+/// Json body of a shutdown report, the client details with how the app stopped.
+#[derive(Serialize)]
+struct StoppedReport<'a> {
+    #[serde(flatten)]
+    client_details: &'a ClientDetails,
+    exit_code: Option<i32>,
+    signal: Option<i32>,
+    killed: bool,
 }
 
+/// Clones share the connection pool and the build cache.
+#[derive(Clone)]
 pub struct CvmHttpClient {
     pub client_details: ClientDetails,
     pub latest_version_url: Url,
+    pub latest_version_stream_url: Url,
     report_success_url: Url,
     report_failure_url: Url,
+    report_rollback_url: Url,
+    report_crash_url: Url,
+    report_stopped_url: Url,
     client: reqwest::Client,
+    /// ETag of the last latest version response, sent as If-None-Match so the server can answer
+    /// with 304 Not Modified when nothing changed.
+    latest_version_etag: Option<String>,
+    cached_latest_version: Option<LatestVersionResponse>,
+    download_options: DownloadOptions,
+    cache: ArtifactCache,
+    retention: RetentionPolicy,
 }
 
 impl CvmHttpClient {
@@ -50,26 +247,55 @@ impl CvmHttpClient {
         let latest_version_url =
             Url::from_str(format!("{}/application/latest", config.cvm_server_url).as_str())
                 .expect("invalid latest_version_url");
+        let latest_version_stream_url =
+            Url::from_str(format!("{}/application/latest/stream", config.cvm_server_url).as_str())
+                .expect("invalid latest_version_stream_url");
         let report_success_url =
             Url::from_str(format!("{}/client/success", config.cvm_server_url).as_str())
                 .expect("invalid report_success_url");
         let report_failure_url =
             Url::from_str(format!("{}/client/failure", &config.cvm_server_url).as_str())
                 .expect("invalid report_failure_url");
+        let report_rollback_url =
+            Url::from_str(format!("{}/client/rollback", &config.cvm_server_url).as_str())
+                .expect("invalid report_rollback_url");
+        let report_crash_url =
+            Url::from_str(format!("{}/client/crash", &config.cvm_server_url).as_str())
+                .expect("invalid report_crash_url");
+        let report_stopped_url =
+            Url::from_str(format!("{}/client/stopped", &config.cvm_server_url).as_str())
+                .expect("invalid report_stopped_url");
         let client = reqwest::Client::new();
         let client_details = ClientDetails {
             client_id: config.client_id,
             app_id: config.app_id,
             current_running_version: version.to_string(),
             architecture: config.architecture,
+            labels: config.labels,
         };
 
         CvmHttpClient {
             client_details,
             latest_version_url,
+            latest_version_stream_url,
             report_success_url,
             client,
             report_failure_url,
+            report_rollback_url,
+            report_crash_url,
+            report_stopped_url,
+            latest_version_etag: None,
+            cached_latest_version: None,
+            download_options: DownloadOptions {
+                attempts: config.download_attempts,
+                min_free_disk_bytes: config.min_free_disk_bytes,
+                progress: None,
+            },
+            cache: ArtifactCache::new(config.cache_dir),
+            retention: RetentionPolicy {
+                keep_versions: config.cache_keep_versions,
+                max_bytes: config.cache_max_bytes,
+            },
         }
     }
 
@@ -77,23 +303,38 @@ impl CvmHttpClient {
         self.client_details.current_running_version = version.to_string();
     }
 
-    /// Checks the CVM server for the latest version and returns the result.
+    /// Checks the CVM server for the latest version and returns the result. The previous response
+    /// is returned when the server reports that it has not changed.
     pub async fn check_latest(&mut self) -> Result<LatestVersionResponse> {
         let payload = serde_json::to_value(&self.client_details).map_err(map_serialize_error)?;
-        let response = self
+        let mut request = self
             .client
-            .post(&self.latest_version_url.to_string())
-            .json(&payload)
-            .send()
-            .await
-            .map_err(map_reqwuest_error)?;
+            .post(self.latest_version_url.to_string())
+            .json(&payload);
+        if let (Some(etag), Some(_)) = (&self.latest_version_etag, &self.cached_latest_version) {
+            request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+        }
+        let response = request.send().await.map_err(map_reqwuest_error)?;
         println!("{}", &payload);
-        let result = response
-            .error_for_status()
-            .unwrap()
-            .json::<LatestVersionResponse>()
-            .await
-            .map_err(map_reqwuest_error)?;
+
+        let result = match (response.status(), &self.cached_latest_version) {
+            (StatusCode::NOT_MODIFIED, Some(cached)) => cached.clone(),
+            _ => {
+                let response = response.error_for_status().map_err(map_reqwuest_error)?;
+                let etag = response
+                    .headers()
+                    .get(reqwest::header::ETAG)
+                    .and_then(|etag| etag.to_str().ok())
+                    .map(String::from);
+                let result = response
+                    .json::<LatestVersionResponse>()
+                    .await
+                    .map_err(map_reqwuest_error)?;
+                self.latest_version_etag = etag;
+                self.cached_latest_version = Some(result.clone());
+                result
+            }
+        };
 
         if !result
             .version
@@ -105,12 +346,37 @@ impl CvmHttpClient {
         Ok(result)
     }
 
+    /// Opens a stream on which the CVM server pushes the latest version as soon as it changes.
+    /// The current latest version is pushed immediately after the stream opens.
+    pub async fn subscribe_latest(&mut self) -> Result<LatestVersionStream> {
+        let payload = serde_json::to_value(&self.client_details).map_err(map_serialize_error)?;
+        let response = self
+            .client
+            .post(self.latest_version_stream_url.to_string())
+            .header(reqwest::header::ACCEPT, "text/event-stream")
+            .json(&payload)
+            .send()
+            .await
+            .map_err(map_reqwuest_error)?;
+
+        match response.status() {
+            StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED => Err(UpdateStreamNotSupported),
+            status if !status.is_success() => Err(UpdateStreamUnavailable {
+                message: format!("Update server responded with {}", status),
+            }),
+            _ => Ok(LatestVersionStream {
+                response,
+                buffer: Vec::new(),
+            }),
+        }
+    }
+
     /// Used to report successful startup of the latest version to the CVM server
     pub async fn report_healthy(&mut self) -> Result<()> {
         let payload = serde_json::to_value(&self.client_details).map_err(map_serialize_error)?;
         let response = self
             .client
-            .post(&self.report_success_url.to_string())
+            .post(self.report_success_url.to_string())
             .json(&payload)
             .send()
             .await
@@ -121,12 +387,17 @@ impl CvmHttpClient {
         Ok(())
     }
 
-    /// Used to report failed startup of the latest version to the CVM server
-    pub async fn report_failure(&mut self) -> Result<()> {
-        let payload = serde_json::to_value(&self.client_details).map_err(map_serialize_error)?;
+    /// Used to report failed startup of the latest version to the CVM server, along with why it
+    /// failed.
+    pub async fn report_failure(&mut self, failure: &StartupFailure) -> Result<()> {
+        let payload = serde_json::to_value(FailureReport {
+            client_details: &self.client_details,
+            failure,
+        })
+        .map_err(map_serialize_error)?;
         let response = self
             .client
-            .post(&self.report_failure_url.to_string())
+            .post(self.report_failure_url.to_string())
             .json(&payload)
             .send()
             .await
@@ -137,33 +408,227 @@ impl CvmHttpClient {
         Ok(())
     }
 
-    /// Downloads the app found at url passed in.
-    pub async fn download_version(&mut self, url: &str) -> Result<PathBuf> {
-        Builder::new()
-            .prefix("cvm_tmp_downloads")
-            .tempdir()
-            .map_err(map_io_error)?;
+    /// Used to report to the CVM server that the running version was started again after
+    /// `failed_version` failed to start.
+    pub async fn report_rollback(&mut self, failed_version: &str) -> Result<()> {
+        let payload = serde_json::to_value(RollbackReport {
+            client_details: &self.client_details,
+            failed_version,
+        })
+        .map_err(map_serialize_error)?;
         let response = self
             .client
-            .get(url)
+            .post(self.report_rollback_url.to_string())
+            .json(&payload)
+            .send()
+            .await
+            .map_err(map_reqwuest_error)?;
+
+        response.error_for_status().map_err(map_reqwuest_error)?;
+
+        Ok(())
+    }
+
+    /// Used to report to the CVM server that the running version exited on its own. `crash_count` is
+    /// the number of exits within the restart policy's window, and `stderr_tail` the last lines the
+    /// app wrote to stderr.
+    pub async fn report_crash(
+        &mut self,
+        status: &ExitStatus,
+        crash_count: u32,
+        restarting: bool,
+        stderr_tail: Option<String>,
+    ) -> Result<()> {
+        let payload = serde_json::to_value(CrashReport {
+            client_details: &self.client_details,
+            exit_code: status.code(),
+            signal: status.signal(),
+            crash_count,
+            restarting,
+            stderr_tail,
+        })
+        .map_err(map_serialize_error)?;
+        let response = self
+            .client
+            .post(self.report_crash_url.to_string())
+            .json(&payload)
+            .send()
+            .await
+            .map_err(map_reqwuest_error)?;
+
+        response.error_for_status().map_err(map_reqwuest_error)?;
+
+        Ok(())
+    }
+
+    /// Used to report to the CVM server that the client shut down and stopped the running version.
+    pub async fn report_stopped(&mut self, outcome: &ShutdownOutcome) -> Result<()> {
+        let status = outcome.status();
+        let payload = serde_json::to_value(StoppedReport {
+            client_details: &self.client_details,
+            exit_code: status.code(),
+            signal: status.signal(),
+            killed: matches!(outcome, ShutdownOutcome::Killed(_)),
+        })
+        .map_err(map_serialize_error)?;
+        let response = self
+            .client
+            .post(self.report_stopped_url.to_string())
+            .json(&payload)
             .send()
             .await
             .map_err(map_reqwuest_error)?;
-        let file_path: PathBuf;
-        let mut dest = {
-            let file_name = response
-                .url()
-                .path_segments()
-                .and_then(|segments| segments.last())
-                .and_then(|name| if name.is_empty() { None } else { Some(name) })
-                .unwrap_or("tmp.bin");
-            file_path = current_dir().unwrap().join(file_name);
-            File::create(file_name).map_err(map_io_error)?
+
+        response.error_for_status().map_err(map_reqwuest_error)?;
+
+        Ok(())
+    }
+
+    /// Downloads the latest build. When the server offers a patch from the version of the cached
+    /// build, the patch is downloaded and applied instead. The result is verified against the
+    /// build's digest, and the whole build is downloaded when anything about the patch fails.
+    pub async fn download_latest(
+        &mut self,
+        latest: &LatestVersionResponse,
+        cached_build: Option<&Path>,
+    ) -> Result<PathBuf> {
+        if let Some(cached_build) = cached_build {
+            match self.download_patched(latest, cached_build).await {
+                Ok(Some(path)) => return Ok(path),
+                Ok(None) => {}
+                Err(err) => println!("Patching {} failed, downloading the whole build: {}", latest.version, err),
+            }
+        }
+
+        self.download_version(&latest.version, &latest.url, latest.sha256.as_deref())
+            .await
+    }
+
+    /// Builds the latest version by patching the cached build. Returns `None` when there is no patch
+    /// from the cached build's version or the build's digest is unknown.
+    async fn download_patched(
+        &mut self,
+        latest: &LatestVersionResponse,
+        cached_build: &Path,
+    ) -> Result<Option<PathBuf>> {
+        let cached_version = strip_version_from_file_name(cached_build);
+        let (Some(patch), Some(sha256)) = (
+            latest.patches.iter().find(|patch| patch.from_version == cached_version),
+            &latest.sha256,
+        ) else {
+            return Ok(None);
         };
 
-        let content = response.bytes().await.map_err(map_reqwuest_error)?;
-        let _ = dest.write_all(&content);
+        let response = self
+            .client
+            .get(&patch.url)
+            .send()
+            .await
+            .map_err(map_reqwuest_error)?
+            .error_for_status()
+            .map_err(map_reqwuest_error)?;
+        let patch_content = response.bytes().await.map_err(map_reqwuest_error)?;
+        verify_sha256(&patch_content, &patch.sha256)?;
 
-        Ok(file_path)
+        let key = self.cache_key(&latest.version);
+        let path = self.cache.prepare(&key, &file_name_from_url(&latest.url))?;
+        // Patching reads and writes whole builds, which would hold up the app's probes and output
+        // capture on the runtime.
+        let (cache, cached_build, destination, expected_sha256, min_free_disk_bytes) = (
+            self.cache.clone(),
+            cached_build.to_path_buf(),
+            path.clone(),
+            sha256.clone(),
+            self.download_options.min_free_disk_bytes,
+        );
+        tokio::task::spawn_blocking(move || {
+            let old = std::fs::read(cached_build).map_err(map_io_error)?;
+            let new = apply_patch(&old, &patch_content).map_err(|err| PatchFailed {
+                message: err.to_string(),
+            })?;
+            verify_sha256(&new, &expected_sha256)?;
+            cache.ensure_free_space((new.len() as u64).saturating_add(min_free_disk_bytes))?;
+            let staging_path = staging_path(&destination);
+            std::fs::write(&staging_path, new).map_err(map_io_error)?;
+            std::fs::rename(&staging_path, &destination).map_err(map_io_error)
+        })
+        .await
+        .map_err(|err| PatchFailed {
+            message: err.to_string(),
+        })??;
+        self.cache.commit(&key, &path, sha256)?;
+        println!(
+            "Patched {} to {} with a {} byte patch",
+            cached_version, latest.version, patch.size
+        );
+        Ok(Some(path))
     }
+
+    /// Sets a callback that is called with the progress of build downloads as data arrives.
+    pub fn on_download_progress(&mut self, callback: impl Fn(DownloadProgress) + Send + Sync + 'static) {
+        self.download_options.progress = Some(Arc::new(callback));
+    }
+
+    /// Returns the cached build of the version when it is intact and, when `expected_sha256` is
+    /// given, the build the server offers.
+    pub fn cached_build(&self, version: &str, expected_sha256: Option<&str>) -> Option<PathBuf> {
+        self.cache.lookup(&self.cache_key(version), expected_sha256)
+    }
+
+    /// Downloads the version of the app found at url passed in to the cache. The download is
+    /// streamed to disk, decompressed on the way when the server compressed it, and verified
+    /// against `expected_sha256` when it is known. Interrupted downloads are resumed, see
+    /// [`download`].
+    pub async fn download_version(
+        &mut self,
+        version: &str,
+        url: &str,
+        expected_sha256: Option<&str>,
+    ) -> Result<PathBuf> {
+        let key = self.cache_key(version);
+        let path = self.cache.prepare(&key, &file_name_from_url(url))?;
+        self.cache.ensure_free_space(self.download_options.min_free_disk_bytes)?;
+        let sha256 = download(&self.client, url, &path, expected_sha256, &self.download_options).await?;
+        self.cache.commit(&key, &path, &sha256)?;
+        Ok(path)
+    }
+
+    /// Records that the cached build of the version passed its startup checks.
+    pub fn mark_known_good(&self, version: &str) -> Result<CacheEntry> {
+        self.cache.mark_known_good(&self.cache_key(version))
+    }
+
+    /// Returns the cached build that most recently passed its startup checks when it is still
+    /// intact.
+    pub fn last_known_good_build(&self) -> Option<(CacheEntry, PathBuf)> {
+        let entry = self
+            .cache
+            .last_known_good(&self.client_details.app_id, &self.client_details.architecture)?;
+        let path = self.cache.lookup(&entry.key(), None)?;
+        Some((entry, path))
+    }
+
+    /// Removes the cached builds the retention policy doesn't keep. The build of the running version
+    /// and the last known good build are kept.
+    pub fn enforce_cache_retention(&self, running_version: &str) -> Result<Vec<CacheEntry>> {
+        self.cache
+            .enforce_retention(self.retention, &self.cache_key(running_version))
+    }
+
+    fn cache_key(&self, version: &str) -> CacheKey {
+        CacheKey {
+            app_id: self.client_details.app_id.clone(),
+            version: version.to_string(),
+            architecture: self.client_details.architecture.clone(),
+        }
+    }
+}
+
+/// The last path segment of the url, which names the build, e.g. `infinite_hello_0.2.0`.
+fn file_name_from_url(url: &str) -> String {
+    Url::parse(url)
+        .ok()
+        .and_then(|url| url.path_segments()?.next_back().map(str::to_string))
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "tmp.bin".to_string())
 }
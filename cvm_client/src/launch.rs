@@ -0,0 +1,99 @@
+use crate::errors::{map_io_error, Result};
+use nix::unistd::{Gid, Group, Uid, User};
+use std::collections::BTreeMap;
+use std::fs::Permissions;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::Command;
+
+/// Mode of the app's binary: everyone may run it, only its owner may change it.
+const BINARY_MODE: u32 = 0o755;
+
+/// How the app's binary is started. `${CVM_VERSION}`, `${CVM_APP_ID}` and `${CVM_CLIENT_ID}` in the
+/// arguments, environment values and working directory are replaced with the values of the build
+/// that starts, see [`LaunchVars`], and the app finds them in its environment as well. Everything
+/// else is inherited from the monitor.
+#[derive(Debug, Clone, Default)]
+pub struct LaunchConfig {
+    pub args: Vec<String>,
+    pub env: BTreeMap<String, String>,
+    pub working_dir: Option<String>,
+    /// The user the app runs as, which needs the monitor to run as root.
+    pub uid: Option<Uid>,
+    /// The group the app runs as. Defaults to the primary group of the user when only that is set.
+    pub gid: Option<Gid>,
+}
+
+/// What is known about the build that starts.
+#[derive(Debug, Clone, Copy)]
+pub struct LaunchVars<'a> {
+    pub version: &'a str,
+    pub app_id: &'a str,
+    pub client_id: &'a str,
+}
+
+impl LaunchVars<'_> {
+    fn env(&self) -> [(&'static str, &str); 3] {
+        [
+            ("CVM_VERSION", self.version),
+            ("CVM_APP_ID", self.app_id),
+            ("CVM_CLIENT_ID", self.client_id),
+        ]
+    }
+
+    /// Replaces `${NAME}` for each of the variables.
+    pub fn expand(&self, value: &str) -> String {
+        self.env()
+            .iter()
+            .fold(value.to_string(), |value, (name, replacement)| {
+                value.replace(&format!("${{{}}}", name), replacement)
+            })
+    }
+}
+
+impl LaunchConfig {
+    /// Prepares the command that starts the binary at the path, and makes the binary executable.
+    pub fn command(&self, path: &Path, vars: &LaunchVars) -> Result<Command> {
+        std::fs::set_permissions(path, Permissions::from_mode(BINARY_MODE)).map_err(map_io_error)?;
+
+        let mut command = Command::new(path);
+        command
+            .args(self.args.iter().map(|arg| vars.expand(arg)))
+            .envs(vars.env())
+            .envs(self.env.iter().map(|(key, value)| (key, vars.expand(value))));
+        if let Some(working_dir) = &self.working_dir {
+            command.current_dir(vars.expand(working_dir));
+        }
+        if let Some(uid) = self.uid {
+            command.uid(uid.as_raw());
+        }
+        if let Some(gid) = self.gid {
+            command.gid(gid.as_raw());
+        }
+        Ok(command)
+    }
+}
+
+/// Looks up a user by name or id. Returns the user's id and primary group, which is unknown for
+/// ids without an entry in the user database.
+pub fn find_user(user: &str) -> Option<(Uid, Option<Gid>)> {
+    match user.trim().parse::<u32>() {
+        Ok(id) => {
+            let uid = Uid::from_raw(id);
+            Some((uid, User::from_uid(uid).ok().flatten().map(|user| user.gid)))
+        }
+        Err(_) => User::from_name(user.trim())
+            .ok()
+            .flatten()
+            .map(|user| (user.uid, Some(user.gid))),
+    }
+}
+
+/// Looks up a group by name or id.
+pub fn find_group(group: &str) -> Option<Gid> {
+    match group.trim().parse::<u32>() {
+        Ok(id) => Some(Gid::from_raw(id)),
+        Err(_) => Group::from_name(group.trim()).ok().flatten().map(|group| group.gid),
+    }
+}
@@ -1,22 +1,95 @@
+pub mod cache;
 pub mod config;
+pub mod delta;
+pub mod download;
+pub mod encoding;
 pub mod errors;
+pub mod health;
 pub mod http_client;
+pub mod launch;
+pub mod output;
+pub mod process;
+pub mod signals;
+pub mod sockets;
+pub mod supervisor;
+pub mod update_policy;
 
 use crate::config::{Config, VERSION_ZERO};
-use crate::errors::CvmError::{ProcessExitEarly, ProcessFailedToStart};
-use crate::errors::Result;
-use crate::errors::{map_io_error, map_reqwuest_error};
-use crate::http_client::CvmHttpClient;
-use std::env::current_dir;
-use std::os::unix::fs::PermissionsExt;
-use std::path::PathBuf;
-use std::process::{Child, Command};
-use std::time::Duration;
+use crate::errors::CvmError::{
+    HealthCheckFailed, ProcessExitEarly, ShutdownFailed, UpdateStreamNotSupported,
+    UpdateStreamUnavailable,
+};
+use crate::errors::{CvmError, Result};
+use crate::errors::map_reqwuest_error;
+use crate::http_client::{CvmHttpClient, HostInfo, LatestVersionResponse, StartupFailure};
+use crate::health::{HealthCheck, HealthProbe, OutputMatch};
+use crate::launch::{LaunchConfig, LaunchVars};
+use crate::output::{OutputCapture, OutputConfig, OutputTail};
+use crate::process::{adopt_orphans, AppProcess};
+use crate::signals::MonitorControl;
+use crate::sockets::ListenSockets;
+use crate::supervisor::{CrashTracker, RestartDecision, RestartPolicy};
+use crate::update_policy::{Rollback, UpdateDecision, UpdatePolicy};
+use chrono::{DateTime, Utc};
+use nix::sys::signal::Signal;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
 use tokio::time::sleep;
 
+/// An update stream that stays silent for longer than this is considered dropped. The server
+/// sends keep-alive comments well within this window.
+const UPDATE_STREAM_IDLE_TIMEOUT: Duration = Duration::from_secs(45);
+/// How often the app is checked for having exited while it shuts down.
+const SHUTDOWN_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+/// How long the last lines of stderr of an app that exited may take to be read before they are
+/// reported.
+const STDERR_EOF_TIMEOUT: Duration = Duration::from_millis(500);
+
 pub struct CvmClientMonitor {
     pub http_client: CvmHttpClient,
     version_check_poll_interval: Duration,
+    /// Waits for new versions while the app runs.
+    watch: VersionWatch,
+    restart_policy: RestartPolicy,
+    health_check: HealthCheck,
+    /// How long the previous version may take to exit after Sigterm before it is killed.
+    drain_timeout: Duration,
+    /// The app is started in a new cgroup under this directory when it is set.
+    cgroup_root: Option<PathBuf>,
+    /// How the app is started.
+    launch: LaunchConfig,
+    /// Where the app's output goes.
+    output: OutputConfig,
+    /// The last lines of stderr of the app that was started last.
+    stderr_tail: OutputTail,
+    /// Sockets passed to every version of the app, bound before the first version starts.
+    listen_sockets: ListenSockets,
+    /// Path of the build that ran last, which patches to the next version are applied to.
+    current_build_path: Option<PathBuf>,
+    /// Whether a new version is started while the previous one keeps running, see
+    /// [`Self::handover_from`].
+    handover: bool,
+    /// In handover mode, the previous version, which keeps running until the new version is healthy.
+    handover_from: Option<AppProcess>,
+    /// Shutdown and version check requests, see [`Self::control`].
+    control: MonitorControl,
+}
+
+/// What the monitor needs to wait for a new version, see [`VersionWatch::poll_until_new_version`].
+/// It is kept apart from the rest of the monitor so that the app can be restarted while a wait is
+/// in progress.
+#[derive(Default)]
+struct VersionWatch {
+    use_update_stream: bool,
+    update_policy: UpdatePolicy,
+    /// Set while an optional update waits for the maintenance window.
+    deferred_update_until: Option<DateTime<Utc>>,
+    /// Set after a version failed to start and the last known good build was started instead.
+    rollback: Option<Rollback>,
+    /// Shared with the monitor, see [`CvmClientMonitor::control`].
+    control: MonitorControl,
     life_time_duration: Option<chrono::TimeDelta>,
     life_time_duration_reached: bool,
 }
@@ -43,21 +116,52 @@ impl CvmClientMonitor {
         version_check_poll_interval: Duration,
         life_time_duration: Option<chrono::TimeDelta>,
     ) -> Self {
+        adopt_orphans();
+        let control = MonitorControl::default();
         CvmClientMonitor {
+            watch: VersionWatch {
+                use_update_stream: config.use_update_stream,
+                update_policy: UpdatePolicy {
+                    maintenance_window: config.maintenance_window,
+                },
+                deferred_update_until: None,
+                rollback: None,
+                control: control.clone(),
+                life_time_duration,
+                life_time_duration_reached: false,
+            },
+            restart_policy: config.restart_policy,
+            health_check: config.health_check.clone(),
+            drain_timeout: config.drain_timeout,
+            cgroup_root: config.cgroup_root.clone(),
+            launch: config.launch.clone(),
+            output: config.output.clone(),
+            stderr_tail: OutputTail::default(),
+            listen_sockets: ListenSockets::new(config.listen_addresses.clone()),
+            current_build_path: None,
+            handover: config.handover,
+            handover_from: None,
+            control,
             http_client: CvmHttpClient::new(config, VERSION_ZERO),
             version_check_poll_interval,
-            life_time_duration,
-            life_time_duration_reached: false,
         }
     }
 
+    /// A handle to ask the monitor to shut down or to check for a new version now, for example from
+    /// signal handlers, see [`MonitorControl::listen_for_signals`].
+    pub fn control(&self) -> MonitorControl {
+        self.control.clone()
+    }
+
     /// Starts a child process for the app and while the parent process polls for a new version.
     /// When a new version is found, the running child process will shut down. A new process
     /// will be started with the latest version.
+    /// Returns once a shutdown was requested and the app was stopped.
     pub async fn run_and_remain_alive(&mut self) -> Result<()> {
-        loop {
+        while !self.control.shutdown_requested() {
             self.run_latest_until_version_outdated().await?;
         }
+        Ok(())
     }
 
     /// Runs a specific version of the app until a new version is found. This is particularly
@@ -66,9 +170,13 @@ impl CvmClientMonitor {
         &mut self,
         version_url: &str,
     ) -> Result<RunResult> {
-        let version_path = &self.http_client.download_version(version_url).await?;
-        let last_version_ran = strip_version_from_file_name(&version_path);
-        &self.run_until_new_version_found(version_path).await?;
+        let version = strip_version_from_file_name(Path::new(version_url));
+        let version_path = &match self.http_client.cached_build(&version, None) {
+            Some(path) => path,
+            None => self.http_client.download_version(&version, version_url, None).await?,
+        };
+        self.run_until_new_version_found(version_path).await?;
+        let last_version_ran = self.last_version_ran(version_path);
         let latest_version_detected = self
             .http_client
             .client_details
@@ -78,16 +186,44 @@ impl CvmClientMonitor {
         Ok(RunResult {
             last_version_ran,
             latest_version_detected,
-            life_time_duration_reached: self.life_time_duration_reached,
+            life_time_duration_reached: self.watch.life_time_duration_reached,
         })
     }
 
     /// Runs the latest version of the app until a new version is found. If a new version is found
     /// then the current version is gracefully shutdown and the result of the run is returned.
+    /// In handover mode the previous version keeps running when the latest version can't be
+    /// downloaded.
     pub async fn run_latest_until_version_outdated(&mut self) -> Result<RunResult> {
-        let latest_path = &self.get_latest_file_path().await?;
-        let last_version_ran = strip_version_from_file_name(&latest_path);
-        &self.run_until_new_version_found(latest_path).await?;
+        let control = self.control.clone();
+        let latest = tokio::select! {
+            latest = self.get_latest_file_path() => Some(latest),
+            _ = control.until_shutdown() => None,
+        };
+        let latest_path = &match latest {
+            None => {
+                println!("Shutdown requested, no longer getting the latest version.");
+                if let Some(previous) = self.handover_from.take() {
+                    self.shut_down(previous).await?;
+                }
+                self.current_build_path.clone().unwrap_or_default()
+            }
+            Some(Ok(path)) => {
+                self.run_until_new_version_found(&path).await?;
+                path
+            }
+            Some(Err(err)) => {
+                let Some(previous) = self.handover_from.take() else {
+                    return Err(err);
+                };
+                let running_version = self.running_version();
+                println!("Unable to get the new version, keeping {} running: {}", running_version, err);
+                self.http_client.set_version(&running_version);
+                self.supervise_until_new_version_found(previous).await?;
+                self.current_build_path.clone().unwrap_or_default()
+            }
+        };
+        let last_version_ran = self.last_version_ran(latest_path);
         let latest_version_detected = self
             .http_client
             .client_details
@@ -97,23 +233,29 @@ impl CvmClientMonitor {
         Ok(RunResult {
             last_version_ran,
             latest_version_detected,
-            life_time_duration_reached: self.life_time_duration_reached,
+            life_time_duration_reached: self.watch.life_time_duration_reached,
         })
     }
 
-    /// Calls the server to get the latest version number. If it is not currently on the file system
-    /// then it is downloaded.
+    /// The version of the build that ran, which is the last known good build after a rollback.
+    fn last_version_ran(&self, started_path: &Path) -> String {
+        strip_version_from_file_name(self.current_build_path.as_deref().unwrap_or(started_path))
+    }
+
+    /// Calls the server to get the latest version number. If it is not in the cache yet, or the
+    /// cached build doesn't match the digest the server reports, it is downloaded.
     async fn get_latest_file_path(&mut self) -> Result<PathBuf> {
         let latest_version_response = self.http_client.check_latest().await?;
-        let file_name = &latest_version_response.get_file_name();
-        // TODO: use named error in place of unwrap.
-        let file_path = current_dir().unwrap().join(file_name);
-        if file_path.exists() {
+        println!("Latest version: {}", latest_version_response.describe_release());
+        if let Some(file_path) = self
+            .http_client
+            .cached_build(&latest_version_response.version, latest_version_response.sha256.as_deref())
+        {
             return Ok(file_path);
         }
         let new_path = self
             .http_client
-            .download_version(&latest_version_response.url)
+            .download_latest(&latest_version_response, self.current_build_path.as_deref())
             .await?;
 
         Ok(new_path)
@@ -121,121 +263,600 @@ impl CvmClientMonitor {
 
     /// Starts a separate process to run the application. While the application is running, the
     /// parent process polls for a new version. If a new version is found, the child process is
-    /// gracefully shutdown.
-    async fn run_until_new_version_found(&mut self, latest_path: &PathBuf) -> Result<()> {
-        let current_running_version: Child;
-        match start_process(latest_path).await {
+    /// gracefully shutdown, or in handover mode kept running until the next version is healthy.
+    /// When the application fails to start, the previous version is kept running in handover mode,
+    /// see [`Self::abort_handover`], and otherwise the last known good build is started instead, see
+    /// [`Self::roll_back`].
+    async fn run_until_new_version_found(&mut self, latest_path: &Path) -> Result<()> {
+        if self.control.shutdown_requested() {
+            if let Some(previous) = self.handover_from.take() {
+                self.shut_down(previous).await?;
+            }
+            return Ok(());
+        }
+        self.listen_sockets.bind()?;
+        let previous = self.handover_from.take();
+        let previous_stderr_tail = self.stderr_tail.clone();
+        let current_running_version: AppProcess;
+        let started = Instant::now();
+        match self.start_process(latest_path).await {
+            Ok(child) if self.control.shutdown_requested() => {
+                // The app may not be healthy yet, see start_process. It is stopped and reported as
+                // such below, after the previous version.
+                current_running_version = child;
+                if let Some(previous) = previous {
+                    let outcome = graceful_shutdown(previous, self.drain_timeout).await?;
+                    println!("Stopped the previous version: {}", outcome);
+                }
+            }
             Ok(child) => {
                 current_running_version = child;
-                &self.http_client.report_healthy().await;
+                if let Some(previous) = previous {
+                    let outcome = graceful_shutdown(previous, self.drain_timeout).await?;
+                    println!("Handed over from the previous version: {}", outcome);
+                }
+                self.current_build_path = Some(latest_path.to_path_buf());
+                let _ = self.http_client.report_healthy().await;
+                self.clean_up_cache(latest_path);
             }
             Err(err) => {
-                &self.http_client.report_failure().await?;
-                return Err(err);
+                let failure = self.startup_failure(&err, started.elapsed()).await;
+                if let Err(report_err) = self.http_client.report_failure(&failure).await {
+                    println!("Unable to report the failed startup: {}", report_err);
+                }
+                current_running_version = match previous {
+                    Some(previous) => {
+                        // Crashes of the previous version are reported with its own stderr.
+                        self.stderr_tail = previous_stderr_tail;
+                        self.abort_handover(latest_path, previous, err).await
+                    }
+                    None => {
+                        self.current_build_path = Some(latest_path.to_path_buf());
+                        self.roll_back(latest_path, err).await?
+                    }
+                };
             }
         }
-        let new_version_found = self
-            .poll_until_new_version(self.version_check_poll_interval)
-            .await;
+        self.supervise_until_new_version_found(current_running_version).await
+    }
+
+    /// Starts the build as the launch configuration says, with the monitor's health check, cgroup,
+    /// listening sockets and output capture.
+    async fn start_process(&mut self, path: &Path) -> Result<AppProcess> {
+        let version = strip_version_from_file_name(path);
+        let client_details = &self.http_client.client_details;
+        let command = self.launch.command(
+            path,
+            &LaunchVars {
+                version: &version,
+                app_id: &client_details.app_id,
+                client_id: &client_details.client_id,
+            },
+        )?;
+        let output = OutputCapture::new(&self.output, &version);
+        self.stderr_tail = output.stderr_tail().clone();
+        start_process(
+            command,
+            &self.health_check,
+            self.cgroup_root.as_deref(),
+            &self.listen_sockets,
+            &output,
+            &self.control,
+        )
+        .await
+    }
+
+    /// Describes why the build that ran for `uptime` failed to start, for the failure report.
+    async fn startup_failure(&self, err: &CvmError, uptime: Duration) -> StartupFailure {
+        let status = match err {
+            ProcessExitEarly { status } | HealthCheckFailed { status, .. } => Some(status),
+            _ => None,
+        };
+        let failed_probe = match err {
+            HealthCheckFailed { .. } => self.health_check.probe.as_ref().map(ToString::to_string),
+            _ => None,
+        };
+        StartupFailure {
+            exit_code: status.and_then(ExitStatus::code),
+            signal: status.and_then(ExitStatus::signal),
+            uptime_ms: uptime.as_millis() as u64,
+            stderr_tail: self.stderr_tail_text().await,
+            failed_probe,
+            error: err.to_string(),
+            host: HostInfo::current(),
+        }
+    }
+
+    /// The last lines of stderr of the app that was started last, once they were read, or after
+    /// [`STDERR_EOF_TIMEOUT`] when the stream stays open.
+    async fn stderr_tail_text(&self) -> Option<String> {
+        self.stderr_tail.wait_for_eof(STDERR_EOF_TIMEOUT).await;
+        self.stderr_tail.text()
+    }
+
+    /// Keeps the app running while polling for a new version. When it exits on its own or fails its
+    /// health check, it is restarted as the restart policy says. When a shutdown is requested, the
+    /// app is gracefully shutdown and the shutdown is reported to the server.
+    async fn supervise_until_new_version_found(&mut self, running: AppProcess) -> Result<()> {
+        let mut child = Some(running);
+        let mut crashes = CrashTracker::new(self.restart_policy);
+        let health_check = self.health_check.clone();
+        let control = self.control.clone();
+        // The wait for a new version carries on while the app is restarted, so it runs on the watch
+        // and a copy of the http client, which are put back once it ends.
+        let mut watch = std::mem::take(&mut self.watch);
+        let mut http_client = self.http_client.clone();
+        let supervised = {
+            let poll = watch.poll_until_new_version(&mut http_client, self.version_check_poll_interval);
+            tokio::pin!(poll);
+            loop {
+                let Some(running) = child.as_mut() else {
+                    tokio::select! {
+                        new_version_found = &mut poll => break Ok(new_version_found),
+                        _ = control.until_shutdown() => break Ok(false),
+                    }
+                };
+                let exited = tokio::select! {
+                    new_version_found = &mut poll => break Ok(new_version_found),
+                    _ = control.until_shutdown() => break Ok(false),
+                    status = running.wait_for_exit() => status.map(|_| ()),
+                    failure = health_check.until_unhealthy() => {
+                        println!("The app is unhealthy, stopping it: {}", failure);
+                        Ok(())
+                    }
+                };
+                if let Err(err) = exited {
+                    break Err(err);
+                }
+                // Helpers the app started would otherwise be left running next to the restarted app.
+                let status = match running.kill().await {
+                    Ok(status) => status,
+                    Err(err) => break Err(err),
+                };
+                child = self.restart_after_exit(status, &mut crashes).await;
+            }
+        };
+        self.watch = watch;
+        self.http_client = http_client;
+        let new_version_found = supervised?;
+        if control.shutdown_requested() {
+            if let Some(child) = child {
+                self.shut_down(child).await?;
+            }
+            return Ok(());
+        }
         if new_version_found {
             // It's possible a lifetime duration was passed so this may not always be true especially
             // during integration tests.
-            let _ = graceful_shutdown(current_running_version)?;
+            if let Some(child) = child {
+                if self.handover {
+                    println!("Keeping the previous version running until the new version is healthy.");
+                    self.handover_from = Some(child);
+                } else {
+                    let outcome = graceful_shutdown(child, self.drain_timeout).await?;
+                    println!("Stopped the previous version: {}", outcome);
+                }
+            }
         }
         Ok(())
     }
 
-    /// Polls server on an interval for new version. When a new version is found true is returned.
+    /// Gracefully shuts down the app because the monitor is shutting down, and reports it.
+    async fn shut_down(&mut self, app: AppProcess) -> Result<()> {
+        let outcome = graceful_shutdown(app, self.drain_timeout).await?;
+        println!("Stopped the app: {}", outcome);
+        if let Err(err) = self.http_client.report_stopped(&outcome).await {
+            println!("Unable to report the shutdown: {}", err);
+        }
+        Ok(())
+    }
+
+    /// Keeps the previous version running after the build at failed_path failed to start during a
+    /// handover, and reports it to the server as a rollback. The failed version is held back until
+    /// the server offers another version.
+    async fn abort_handover(&mut self, failed_path: &Path, previous: AppProcess, err: CvmError) -> AppProcess {
+        let failed_version = strip_version_from_file_name(failed_path);
+        let running_version = self.running_version();
+        println!(
+            "{} failed to start ({}), aborting the handover and keeping {} running",
+            failed_version, err, running_version
+        );
+        self.http_client.set_version(&running_version);
+        self.watch.rollback = Some(Rollback {
+            failed_version: failed_version.clone(),
+            running_version: running_version.clone(),
+        });
+        if let Err(err) = self.http_client.report_rollback(&failed_version).await {
+            println!("Unable to report the rollback to {}: {}", running_version, err);
+        }
+        previous
+    }
+
+    /// The version of the build that runs, or ran last.
+    fn running_version(&self) -> String {
+        self.current_build_path
+            .as_deref()
+            .map(strip_version_from_file_name)
+            .unwrap_or_default()
+    }
+
+    /// Reports an exit of the app to the server and restarts the build that ran as the restart policy
+    /// says. Returns the restarted process, or `None` when the app stays stopped until the next
+    /// version is found.
+    async fn restart_after_exit(
+        &mut self,
+        mut status: ExitStatus,
+        crashes: &mut CrashTracker,
+    ) -> Option<AppProcess> {
+        let build_path = self.current_build_path.clone()?;
+        let version = strip_version_from_file_name(&build_path);
+        loop {
+            let decision = crashes.on_exit(status, Instant::now());
+            let restarting = matches!(decision, RestartDecision::RestartAfter(_));
+            println!(
+                "{} exited with {} ({} exits within the restart window)",
+                version,
+                status,
+                crashes.crash_count()
+            );
+            if let Err(err) = self
+                .http_client
+                .report_crash(&status, crashes.crash_count(), restarting, self.stderr_tail_text().await)
+                .await
+            {
+                println!("Unable to report the exit of {}: {}", version, err);
+            }
+            let RestartDecision::RestartAfter(delay) = decision else {
+                println!("Not restarting {}, waiting for a new version.", version);
+                return None;
+            };
+
+            println!("Restarting {} in {:?}", version, delay);
+            let control = self.control.clone();
+            tokio::select! {
+                _ = sleep(delay) => {}
+                _ = control.until_shutdown() => return None,
+            }
+            match self.start_process(&build_path).await {
+                Ok(child) => return Some(child),
+                Err(ProcessExitEarly { status: exit_status })
+                | Err(HealthCheckFailed { status: exit_status, .. }) => status = exit_status,
+                Err(err) => {
+                    println!("Unable to restart {}: {}", version, err);
+                    return None;
+                }
+            }
+        }
+    }
+
+    /// Starts the last known good build after the build at failed_path failed to start, and reports
+    /// the rollback to the server. The failed version is held back until the server offers another
+    /// version. Returns the original error when there is no other known good build or it fails to
+    /// start as well.
+    async fn roll_back(&mut self, failed_path: &Path, err: CvmError) -> Result<AppProcess> {
+        let failed_version = strip_version_from_file_name(failed_path);
+        let Some((entry, path)) = self
+            .http_client
+            .last_known_good_build()
+            .filter(|(entry, _)| entry.version != failed_version)
+        else {
+            println!("{} failed to start and there is no known good build to roll back to", failed_version);
+            return Err(err);
+        };
+        println!("{} failed to start ({}), rolling back to {}", failed_version, err, entry.version);
+        let child = match self.start_process(&path).await {
+            Ok(child) => child,
+            Err(rollback_err) => {
+                println!("Rolling back to {} failed: {}", entry.version, rollback_err);
+                return Err(err);
+            }
+        };
+
+        self.current_build_path = Some(path.clone());
+        self.http_client.set_version(&entry.version);
+        self.watch.rollback = Some(Rollback {
+            failed_version: failed_version.clone(),
+            running_version: entry.version.clone(),
+        });
+        if let Err(err) = self.http_client.report_rollback(&failed_version).await {
+            println!("Unable to report the rollback to {}: {}", entry.version, err);
+        }
+        let _ = self.http_client.report_healthy().await;
+        self.clean_up_cache(&path);
+        Ok(child)
+    }
+
+    /// Records the build that just started as known good and removes the builds the cache retention
+    /// policy doesn't keep. Problems are only logged as they don't affect the running build.
+    fn clean_up_cache(&self, running_path: &Path) {
+        let running_version = strip_version_from_file_name(running_path);
+        if let Err(err) = self.http_client.mark_known_good(&running_version) {
+            println!("Unable to record {} as known good: {}", running_version, err);
+        }
+        if let Err(err) = self.http_client.enforce_cache_retention(&running_version) {
+            println!("Unable to clean up the build cache: {}", err);
+        }
+    }
+
+    /// Waits for a new version and returns true once one is found, see
+    /// [`VersionWatch::poll_until_new_version`].
+    pub async fn poll_until_new_version(&mut self, poll_interval: Duration) -> bool {
+        self.watch.poll_until_new_version(&mut self.http_client, poll_interval).await
+    }
+}
+
+impl VersionWatch {
+    /// Waits for a new version and returns true once one is found. The server's update stream is
+    /// preferred; while it is unavailable the server is polled on an interval and the stream is
+    /// retried after every poll.
+    /// Optional updates found outside the maintenance window are held back until it opens.
     /// If life_time_duration is set, the polling will end at the end of the specified lifetime.
     /// life_time_duration is primarily used to allow the application to halt during integration
     /// tests.
-    pub async fn poll_until_new_version(&mut self, poll_interval: Duration) -> bool {
+    /// A requested version check, see [`MonitorControl::request_version_check`], checks the server
+    /// right away.
+    async fn poll_until_new_version(
+        &mut self,
+        http_client: &mut CvmHttpClient,
+        poll_interval: Duration,
+    ) -> bool {
         let mut interval = tokio::time::interval(poll_interval);
+        let control = self.control.clone();
         let start_poll_time = chrono::Utc::now();
 
         loop {
-            if self.life_time_duration.is_some() {
+            let now = chrono::Utc::now();
+            let mut remaining_life_time = None;
+            if let Some(end_time) = &self.life_time_duration {
                 let elapsed_time = chrono::Utc::now() - start_poll_time;
-                let end_time = &self.life_time_duration.unwrap();
                 if end_time.le(&elapsed_time) {
                     println!("Lifetime duration has passed!");
                     self.life_time_duration_reached = true;
                     return false; // Exit the loop if the lifetime duration has passed
                 }
+                remaining_life_time = (*end_time - elapsed_time).to_std().ok();
             }
-            interval.tick().await;
-            let latest_version = &self.http_client.check_latest().await;
+            let mut deferred_wait = None;
+            if let Some(deferred_until) = self.deferred_update_until {
+                if deferred_until <= now {
+                    println!("Maintenance window has opened, applying update.");
+                    self.deferred_update_until = None;
+                    return true;
+                }
+                deferred_wait = (deferred_until - now).to_std().ok();
+            }
+            let mut check_requested = false;
+            if self.use_update_stream {
+                let max_wait = remaining_life_time.into_iter().chain(deferred_wait).min();
+                let stream_result = tokio::select! {
+                    result = self.wait_for_new_version_on_stream(http_client, max_wait) => result,
+                    _ = control.version_check_requested() => {
+                        check_requested = true;
+                        Ok(false)
+                    }
+                };
+                match stream_result {
+                    Ok(true) => return true,
+                    Ok(false) if check_requested => {}
+                    // The lifetime ended or a deferred update became due while waiting, both of which
+                    // are handled at the top of the loop.
+                    Ok(false) => continue,
+                    Err(UpdateStreamNotSupported) => {
+                        println!("Update server does not support update streams, polling instead.");
+                        self.use_update_stream = false;
+                    }
+                    Err(err) => println!(
+                        "Update stream dropped, falling back to polling: {}",
+                        err
+                    ),
+                }
+            }
+            if !check_requested {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = control.version_check_requested() => {}
+                }
+            }
+            let latest_version = &http_client.check_latest().await;
             match latest_version {
                 Ok(response) => {
-                    if response.update_required {
+                    if self.should_update_now(http_client, response) {
                         return true;
                     }
                 }
                 // For now, we will keep polling in case the server comes back online.
                 Err(err) => println!(
                     "Error calling server to check version: {}. Error: {}",
-                    &self.http_client.latest_version_url,
+                    &http_client.latest_version_url,
                     &err.to_string()
                 ),
             }
         }
     }
+
+    /// Applies the update policy to the latest version reported by the server and returns true
+    /// when the update should be applied now.
+    fn should_update_now(
+        &mut self,
+        http_client: &mut CvmHttpClient,
+        latest: &LatestVersionResponse,
+    ) -> bool {
+        if let Some(rollback) = &self.rollback {
+            if rollback.holds_back(latest) {
+                // The version check recorded the failed version as the running one.
+                http_client.set_version(&rollback.running_version);
+                self.deferred_update_until = None;
+                return false;
+            }
+            println!(
+                "{} is available, resuming updates held back since {} failed to start.",
+                latest.version, rollback.failed_version
+            );
+            self.rollback = None;
+        }
+        match self.update_policy.decide(latest, chrono::Utc::now()) {
+            UpdateDecision::UpToDate => {
+                self.deferred_update_until = None;
+                false
+            }
+            UpdateDecision::ApplyNow => {
+                self.deferred_update_until = None;
+                true
+            }
+            UpdateDecision::DeferUntil(deferred_until) => {
+                if self.deferred_update_until != Some(deferred_until) {
+                    println!(
+                        "Deferring optional update to {} until the maintenance window opens at {}.",
+                        latest.version, deferred_until
+                    );
+                }
+                self.deferred_update_until = Some(deferred_until);
+                false
+            }
+        }
+    }
+
+    /// Waits on the server's update stream until a new version is pushed, in which case true is
+    /// returned. False is returned when max_wait elapses first. Any problem with the stream,
+    /// including the server closing it, is returned as an error so the caller can fall back to
+    /// polling.
+    async fn wait_for_new_version_on_stream(
+        &mut self,
+        http_client: &mut CvmHttpClient,
+        max_wait: Option<Duration>,
+    ) -> Result<bool> {
+        let deadline = max_wait.map(|max_wait| tokio::time::Instant::now() + max_wait);
+        let mut stream = http_client.subscribe_latest().await?;
+
+        loop {
+            let wait = match deadline {
+                Some(deadline) => deadline
+                    .saturating_duration_since(tokio::time::Instant::now())
+                    .min(UPDATE_STREAM_IDLE_TIMEOUT),
+                None => UPDATE_STREAM_IDLE_TIMEOUT,
+            };
+            match tokio::time::timeout(wait, stream.next_latest()).await {
+                Ok(Ok(Some(latest))) => {
+                    if latest.version != http_client.client_details.current_running_version {
+                        http_client.set_version(&latest.version);
+                    }
+                    if self.should_update_now(http_client, &latest) {
+                        return Ok(true);
+                    }
+                }
+                Ok(Ok(None)) => {
+                    return Err(UpdateStreamUnavailable {
+                        message: "closed by server".to_string(),
+                    })
+                }
+                Ok(Err(err)) => return Err(err),
+                Err(_) if deadline.is_some_and(|deadline| deadline <= tokio::time::Instant::now()) => {
+                    return Ok(false)
+                }
+                Err(_) => {
+                    return Err(UpdateStreamUnavailable {
+                        message: "no message received before the idle timeout".to_string(),
+                    })
+                }
+            }
+        }
+    }
 }
 
-/// Starts the process found at the path_buf. Once started, the application will be checked
-/// up to three times to ensure it does not exit early.
-pub async fn start_process(path_buf: &PathBuf) -> Result<Child> {
-    // Set file permissions to 777
-    let mut perms = std::fs::metadata(&path_buf)
-        .map_err(map_io_error)?
-        .permissions();
-    perms.set_mode(0o777);
+/// Starts the command in a process group of its own and waits until it passes the startup health
+/// check, see [`HealthCheck::wait_until_healthy`]. The process is placed in a new cgroup under
+/// `cgroup_root` when it is set, and inherits the bound listen_sockets. Its stdout and stderr are
+/// captured by output. When a shutdown is requested through control, the app is returned without
+/// waiting for it to become healthy, so that it can be shut down.
+pub async fn start_process(
+    mut command: Command,
+    health_check: &HealthCheck,
+    cgroup_root: Option<&Path>,
+    listen_sockets: &ListenSockets,
+    output: &OutputCapture,
+    control: &MonitorControl,
+) -> Result<AppProcess> {
+    command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    listen_sockets.pass_to(&mut command)?;
+    let mut app = AppProcess::spawn(command, cgroup_root)?;
+    let stdout_match = OutputMatch::default();
+    let stdout_probe = match &health_check.probe {
+        Some(HealthProbe::Stdout { pattern }) => Some(pattern),
+        _ => None,
+    };
+    output.capture(&mut app, stdout_probe, &stdout_match);
 
-    std::fs::set_permissions(&path_buf, perms).map_err(map_io_error)?;
+    tokio::select! {
+        healthy = health_check.wait_until_healthy(&mut app, &stdout_match) => healthy?,
+        _ = control.until_shutdown() => {}
+    }
+    Ok(app)
+}
 
-    let mut child = Command::new(path_buf)
-        .spawn()
-        // TODO: Use named error
-        .expect("Failed to start the process");
-    let mut cnt_proc_checked = 0;
+/// How the app stopped after it was asked to shut down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownOutcome {
+    /// The app exited with the status within the drain timeout.
+    Exited(ExitStatus),
+    /// The app didn't exit within the drain timeout and was killed.
+    Killed(ExitStatus),
+}
 
-    // TODO: Move 3 to constant.
-    while cnt_proc_checked < 3 {
-        match child.try_wait() {
-            Ok(Some(status)) => return Err(ProcessExitEarly { status }),
-            Ok(None) => {
-                println!("status not ready yet, let's really wait");
-                cnt_proc_checked += 1;
-                sleep(Duration::from_secs(1)).await;
-            }
-            Err(e) => {
-                return Err(ProcessFailedToStart {
-                    message: e.to_string(),
-                })
+impl ShutdownOutcome {
+    pub fn status(&self) -> ExitStatus {
+        match self {
+            ShutdownOutcome::Exited(status) | ShutdownOutcome::Killed(status) => *status,
+        }
+    }
+}
+
+impl std::fmt::Display for ShutdownOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShutdownOutcome::Exited(status) => write!(f, "exited with {}", status),
+            ShutdownOutcome::Killed(status) => {
+                write!(f, "killed after the drain timeout ({})", status)
             }
         }
     }
-    Ok(child)
 }
 
 /// Shuts down an application using Sigterm and waits for the shutdown to occur.
 /// The application may need to be drained for in flight messages which is why we wait for shutdown.
-pub fn graceful_shutdown(child: Child) -> Result<bool> {
-    let sigterm = ctrlc::Signal::SIGTERM.to_string();
-    let mut kill = Command::new("kill")
-        .args(["-s", &sigterm, &child.id().to_string()])
-        .spawn()
-        // TODO: Use named error
-        .expect("Unable to send command");
-
-    // TODO: Specify a wait time before issuing a kill. Use named error.
-    kill.wait().expect("couldn't wait");
-    Ok(true)
+/// The whole process group is signalled, and the shutdown only completes once the helpers the
+/// application started exited as well. Processes still running after the drain timeout are killed
+/// with Sigkill. Either way the application is reaped.
+pub async fn graceful_shutdown(mut app: AppProcess, drain_timeout: Duration) -> Result<ShutdownOutcome> {
+    let pid = app.id();
+    let shutdown_failed = |err: &dyn std::fmt::Display| ShutdownFailed {
+        message: format!("process {}: {}", pid, err),
+    };
+    app.signal(Signal::SIGTERM).map_err(|err| shutdown_failed(&err))?;
+
+    let deadline = Instant::now() + drain_timeout;
+    let mut status = None;
+    loop {
+        if status.is_none() {
+            status = app.try_wait()?;
+        }
+        if let Some(status) = status.filter(|_| !app.helpers_running()) {
+            return Ok(ShutdownOutcome::Exited(status));
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+        sleep(SHUTDOWN_CHECK_INTERVAL.min(remaining)).await;
+    }
+
+    println!("Process {} didn't exit within {:?}, killing it", pid, drain_timeout);
+    let status = app.kill().await.map_err(|err| shutdown_failed(&err))?;
+    Ok(ShutdownOutcome::Killed(status))
 }
 
 /// Extracts the version number from the file name. Assumes the file name will be in the format
 /// file_name_0.3.2
-pub fn strip_version_from_file_name(path_buf: &PathBuf) -> String {
+pub fn strip_version_from_file_name(path_buf: &Path) -> String {
     path_buf
         .file_name()
         .unwrap()
@@ -1,15 +1,12 @@
 use cvm::config::Config;
 use cvm::CvmClientMonitor;
 use std::process;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
 use std::time::Duration;
 
 const POLL_NEW_VERSION_INTERVAL: Duration = Duration::from_secs(5);
 
 #[tokio::main]
 async fn main() {
-    start_signal_listener();
     let config = create_config_or_shutdown();
     run_client(config).await;
 }
@@ -25,6 +22,11 @@ fn create_config_or_shutdown() -> Config {
 
 async fn run_client(config: Config) {
     let mut cvm_client = CvmClientMonitor::new(config, POLL_NEW_VERSION_INTERVAL, None);
+    if let Err(err) = cvm_client.control().listen_for_signals() {
+        eprintln!("Error listening for signals: {}", err);
+        process::exit(1);
+    }
+    println!("Running...");
     match cvm_client.run_and_remain_alive().await {
         Ok(_) => {
             println!("Gracefully shutting down.");
@@ -36,18 +38,3 @@ async fn run_client(config: Config) {
         }
     }
 }
-
-fn start_signal_listener() {
-    let running = Arc::new(AtomicUsize::new(0));
-    let r = running.clone();
-    ctrlc::set_handler(move || {
-        let prev = r.fetch_add(1, Ordering::SeqCst);
-        if prev == 0 {
-            println!("Exiting...");
-        } else {
-            process::exit(0);
-        }
-    })
-    .expect("Error setting Ctrl-C handler");
-    println!("Running...");
-}
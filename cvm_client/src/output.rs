@@ -0,0 +1,241 @@
+use crate::health::OutputMatch;
+use crate::process::AppProcess;
+use regex::Regex;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often a tail is checked for its stream having been read to the end.
+const EOF_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Where the app's output goes.
+#[derive(Debug, Clone)]
+pub struct OutputConfig {
+    /// Output is written to `<log_dir>/<version>/stdout.log` and `stderr.log` when set, and to the
+    /// monitor's own stdout and stderr otherwise.
+    pub log_dir: Option<PathBuf>,
+    /// Size after which a log file is rotated.
+    pub max_file_bytes: u64,
+    /// Rotated files kept next to each log file, as `stdout.log.1` (newest) and up.
+    pub keep_files: usize,
+    /// Whether lines are prefixed with the version and the time they were read.
+    pub prefix: bool,
+    /// Lines of stderr kept in memory, see [`OutputTail`].
+    pub tail_lines: usize,
+}
+
+/// The last lines the app wrote to a stream.
+#[derive(Debug, Clone, Default)]
+pub struct OutputTail {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    capacity: usize,
+    /// Whether the stream is still being read, which ends when the app and its helpers close it.
+    reading: Arc<AtomicBool>,
+}
+
+impl OutputTail {
+    pub fn new(capacity: usize) -> OutputTail {
+        OutputTail {
+            lines: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+            reading: Arc::default(),
+        }
+    }
+
+    fn push(&self, line: &str) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut lines = self.lines.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if lines.len() == self.capacity {
+            lines.pop_front();
+        }
+        lines.push_back(line.to_string());
+    }
+
+    /// Waits up to `max_wait` for the stream to be read to the end, so that the lines the app wrote
+    /// right before it exited are in the tail. Returns whether it was. Helpers the app started may
+    /// keep the stream open after it exited, which is why the wait is bounded.
+    pub async fn wait_for_eof(&self, max_wait: Duration) -> bool {
+        let started = Instant::now();
+        while self.reading.load(Ordering::Acquire) {
+            if started.elapsed() >= max_wait {
+                return false;
+            }
+            tokio::time::sleep(EOF_CHECK_INTERVAL).await;
+        }
+        true
+    }
+
+    /// The lines, oldest first.
+    pub fn lines(&self) -> Vec<String> {
+        let lines = self.lines.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        lines.iter().cloned().collect()
+    }
+
+    /// The lines joined with newlines, or `None` when there are none.
+    pub fn text(&self) -> Option<String> {
+        Some(self.lines().join("\n")).filter(|text| !text.is_empty())
+    }
+}
+
+/// Captures the output of one start of a version of the app.
+#[derive(Debug, Clone)]
+pub struct OutputCapture {
+    config: OutputConfig,
+    version: String,
+    stderr_tail: OutputTail,
+}
+
+impl OutputCapture {
+    pub fn new(config: &OutputConfig, version: &str) -> OutputCapture {
+        OutputCapture {
+            config: config.clone(),
+            version: version.to_string(),
+            stderr_tail: OutputTail::new(config.tail_lines),
+        }
+    }
+
+    /// The last lines the app wrote to stderr.
+    pub fn stderr_tail(&self) -> &OutputTail {
+        &self.stderr_tail
+    }
+
+    /// Forwards the app's piped stdout and stderr until the app closes them. Lines of stdout are
+    /// also checked against the pattern of a stdout probe, which records a match in `stdout_match`.
+    pub fn capture(&self, app: &mut AppProcess, stdout_probe: Option<&Regex>, stdout_match: &OutputMatch) {
+        if let Some(stdout) = app.take_stdout() {
+            let pattern = stdout_probe.cloned();
+            let stdout_match = stdout_match.clone();
+            let on_line = move |line: &str| {
+                if let Some(pattern) = &pattern {
+                    stdout_match.check_line(pattern, line);
+                }
+            };
+            self.forward(stdout, "stdout", on_line, || {});
+        }
+        if let Some(stderr) = app.take_stderr() {
+            let tail = self.stderr_tail.clone();
+            tail.reading.store(true, Ordering::Release);
+            let reading = tail.reading.clone();
+            let on_eof = move || reading.store(false, Ordering::Release);
+            self.forward(stderr, "stderr", move |line| tail.push(line), on_eof);
+        }
+    }
+
+    /// Forwards the lines of the stream to its log file, or to the monitor's stream of the same name,
+    /// on a thread of its own, and calls `on_eof` once the stream is read to the end. The monitor's
+    /// stream is used when the log file can't be opened.
+    fn forward<R, F, E>(&self, stream: R, name: &'static str, mut on_line: F, on_eof: E)
+    where
+        R: Read + Send + 'static,
+        F: FnMut(&str) + Send + 'static,
+        E: FnOnce() + Send + 'static,
+    {
+        let mut log = self.config.log_dir.as_ref().and_then(|log_dir| {
+            let path = log_dir.join(&self.version).join(format!("{}.log", name));
+            match RotatingLog::open(&path, self.config.max_file_bytes, self.config.keep_files) {
+                Ok(log) => Some(log),
+                Err(err) => {
+                    println!("Unable to open {}, forwarding the app's {}: {}", path.display(), name, err);
+                    None
+                }
+            }
+        });
+        let prefix = self.config.prefix.then(|| self.version.clone());
+        std::thread::spawn(move || {
+            let mut reader = BufReader::new(stream);
+            let mut bytes = Vec::new();
+            while reader.read_until(b'\n', &mut bytes).is_ok_and(|read| read > 0) {
+                let text = String::from_utf8_lossy(&bytes);
+                let line = text.trim_end_matches(['\n', '\r']);
+                on_line(line);
+                let line = match &prefix {
+                    Some(version) => format!("[{} {}] {}", version, chrono::Utc::now().to_rfc3339(), line),
+                    None => line.to_string(),
+                };
+                match &mut log {
+                    Some(log) => {
+                        if let Err(err) = log.write_line(&line) {
+                            println!("Unable to write the app's {} to {}: {}", name, log.path.display(), err);
+                        }
+                    }
+                    None if name == "stderr" => eprintln!("{}", line),
+                    None => println!("{}", line),
+                }
+                bytes.clear();
+            }
+            on_eof();
+        });
+    }
+}
+
+/// A log file that is rotated once it grows beyond the maximum size.
+#[derive(Debug)]
+pub struct RotatingLog {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_bytes: u64,
+    keep_files: usize,
+}
+
+impl RotatingLog {
+    /// Opens the log for appending, creating it and its directory when needed.
+    pub fn open(path: &Path, max_bytes: u64, keep_files: usize) -> std::io::Result<RotatingLog> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingLog {
+            path: path.to_path_buf(),
+            file,
+            size,
+            max_bytes,
+            keep_files,
+        })
+    }
+
+    /// Appends the line, rotating the log first when the line wouldn't fit anymore. Lines longer
+    /// than the maximum size get a file of their own.
+    pub fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        let length = line.len() as u64 + 1;
+        if self.size > 0 && self.size + length > self.max_bytes {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += length;
+        Ok(())
+    }
+
+    /// Moves `log.1` to `log.2` and so on, dropping the oldest file, and starts a new log.
+    fn rotate(&mut self) -> std::io::Result<()> {
+        if self.keep_files > 0 {
+            for index in (1..self.keep_files).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    std::fs::rename(&from, self.rotated_path(index + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated_path(1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        PathBuf::from(path)
+    }
+}
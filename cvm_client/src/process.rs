@@ -0,0 +1,172 @@
+use crate::errors::CvmError::ProcessFailedToStart;
+use crate::errors::{map_io_error, Result};
+use nix::errno::Errno;
+use nix::sys::signal::{self, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::Pid;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStderr, ChildStdout, Command, ExitStatus};
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+
+/// How often the app is checked for having exited.
+const EXIT_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+/// How long the processes of the app are given to disappear after Sigkill.
+const KILL_TIMEOUT: Duration = Duration::from_secs(1);
+/// How often the processes of the app are checked for having disappeared after Sigkill.
+const KILL_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The app's process. It leads a process group of its own so that the helpers it starts are
+/// stopped along with it. On Linux the app can additionally be placed in a cgroup, which also holds
+/// helpers that left the group.
+#[derive(Debug)]
+pub struct AppProcess {
+    child: Child,
+    cgroup: Option<Cgroup>,
+}
+
+impl AppProcess {
+    /// Starts the command in a new process group, and in a new cgroup under `cgroup_root` when it is
+    /// set. The app still runs when it can't be placed in the cgroup.
+    pub fn spawn(mut command: Command, cgroup_root: Option<&Path>) -> Result<AppProcess> {
+        let child = command
+            .process_group(0)
+            .spawn()
+            .map_err(|err| ProcessFailedToStart {
+                message: err.to_string(),
+            })?;
+        let cgroup = cgroup_root.and_then(|root| match Cgroup::create(root, child.id()) {
+            Ok(cgroup) => Some(cgroup),
+            Err(err) => {
+                println!("Unable to place process {} in a cgroup: {}", child.id(), err);
+                None
+            }
+        });
+        Ok(AppProcess { child, cgroup })
+    }
+
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    pub fn take_stdout(&mut self) -> Option<ChildStdout> {
+        self.child.stdout.take()
+    }
+
+    pub fn take_stderr(&mut self) -> Option<ChildStderr> {
+        self.child.stderr.take()
+    }
+
+    /// Returns the exit status of the app once it exited. Helpers it started may still run.
+    pub fn try_wait(&mut self) -> Result<Option<ExitStatus>> {
+        self.child.try_wait().map_err(map_io_error)
+    }
+
+    /// Waits for the app to exit. std's Child can't be awaited, so it is checked on an interval.
+    pub async fn wait_for_exit(&mut self) -> Result<ExitStatus> {
+        loop {
+            if let Some(status) = self.try_wait()? {
+                return Ok(status);
+            }
+            sleep(EXIT_CHECK_INTERVAL).await;
+        }
+    }
+
+    /// Sends the signal to every process of the app.
+    pub fn signal(&self, signal: Signal) -> nix::Result<()> {
+        match signal::killpg(self.process_group(), signal) {
+            Ok(()) | Err(Errno::ESRCH) => {}
+            Err(err) => return Err(err),
+        }
+        for pid in self.cgroup.iter().flat_map(Cgroup::pids) {
+            let _ = signal::kill(pid, signal);
+        }
+        Ok(())
+    }
+
+    /// Whether processes of the app other than the app itself still run. Only meaningful once the
+    /// app exited, as members of the group that were orphaned are reaped on the way.
+    pub fn helpers_running(&self) -> bool {
+        let group = Pid::from_raw(-self.process_group().as_raw());
+        while let Ok(status) = waitpid(group, Some(WaitPidFlag::WNOHANG)) {
+            if status == WaitStatus::StillAlive {
+                break;
+            }
+        }
+        signal::killpg(self.process_group(), None).is_ok()
+            || self.cgroup.as_ref().is_some_and(|cgroup| !cgroup.pids().is_empty())
+    }
+
+    /// Kills every process of the app and returns the app's exit status. Like
+    /// [`Self::wait_for_exit`], the app is checked on an interval.
+    pub async fn kill(&mut self) -> Result<ExitStatus> {
+        let _ = self.signal(Signal::SIGKILL);
+        let status = loop {
+            if let Some(status) = self.try_wait()? {
+                break status;
+            }
+            sleep(KILL_CHECK_INTERVAL).await;
+        };
+        let deadline = Instant::now() + KILL_TIMEOUT;
+        while self.helpers_running() && Instant::now() < deadline {
+            sleep(KILL_CHECK_INTERVAL).await;
+        }
+        Ok(status)
+    }
+
+    fn process_group(&self) -> Pid {
+        Pid::from_raw(self.child.id() as i32)
+    }
+}
+
+impl Drop for AppProcess {
+    fn drop(&mut self) {
+        if let Some(cgroup) = &self.cgroup {
+            cgroup.remove();
+        }
+    }
+}
+
+/// Makes the monitor the parent of processes the app orphans, so that they can be reaped when the
+/// app is stopped instead of lingering as zombies under an init that doesn't reap them. Only
+/// supported on Linux.
+pub fn adopt_orphans() {
+    #[cfg(target_os = "linux")]
+    if let Err(err) = nix::sys::prctl::set_child_subreaper(true) {
+        println!("Unable to adopt the app's orphaned processes: {}", err);
+    }
+}
+
+/// A cgroup (v2) holding the processes of the app, named after the app's process id.
+#[derive(Debug)]
+struct Cgroup {
+    path: PathBuf,
+}
+
+impl Cgroup {
+    fn create(root: &Path, pid: u32) -> std::io::Result<Cgroup> {
+        let path = root.join(format!("cvm-{}", pid));
+        std::fs::create_dir_all(&path)?;
+        let cgroup = Cgroup { path };
+        if let Err(err) = std::fs::write(cgroup.path.join("cgroup.procs"), pid.to_string()) {
+            cgroup.remove();
+            return Err(err);
+        }
+        Ok(cgroup)
+    }
+
+    fn pids(&self) -> Vec<Pid> {
+        std::fs::read_to_string(self.path.join("cgroup.procs"))
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| line.trim().parse().ok())
+            .map(Pid::from_raw)
+            .collect()
+    }
+
+    /// Removes the cgroup, which only succeeds once it is empty.
+    fn remove(&self) {
+        let _ = std::fs::remove_dir(&self.path);
+    }
+}
@@ -0,0 +1,88 @@
+use crate::errors::{map_io_error, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Notify;
+
+/// Requests to a running [`crate::CvmClientMonitor`], usually sent by the signal handlers of
+/// [`MonitorControl::listen_for_signals`]. Clones share the same requests.
+#[derive(Debug, Clone, Default)]
+pub struct MonitorControl {
+    state: Arc<ControlState>,
+}
+
+#[derive(Debug, Default)]
+struct ControlState {
+    shutdown_requested: AtomicBool,
+    shutdown: Notify,
+    version_check: Notify,
+}
+
+impl MonitorControl {
+    /// Asks the monitor to stop the app and return. The request can't be taken back.
+    pub fn request_shutdown(&self) {
+        self.state.shutdown_requested.store(true, Ordering::SeqCst);
+        self.state.shutdown.notify_waiters();
+    }
+
+    pub fn shutdown_requested(&self) -> bool {
+        self.state.shutdown_requested.load(Ordering::SeqCst)
+    }
+
+    /// Completes once a shutdown was requested, immediately when it already was.
+    pub async fn until_shutdown(&self) {
+        loop {
+            // Created before the flag is checked, so a request in between isn't missed.
+            let notified = self.state.shutdown.notified();
+            if self.shutdown_requested() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Asks the monitor to check for a new version now instead of at the next poll. Requests made
+    /// while no check is awaited are kept until the next one.
+    pub fn request_version_check(&self) {
+        self.state.version_check.notify_one();
+    }
+
+    /// Completes once a version check was requested.
+    pub async fn version_check_requested(&self) {
+        self.state.version_check.notified().await;
+    }
+
+    /// Turns Sigterm and Sigint into shutdown requests, and Sighup into version check requests. A
+    /// second Sigterm or Sigint doesn't cut the shutdown short, as exiting before the app stopped
+    /// would leave it running without a monitor; the drain timeout bounds how long it takes.
+    /// Must be called from within a tokio runtime.
+    pub fn listen_for_signals(&self) -> Result<()> {
+        let mut terminate = signal(SignalKind::terminate()).map_err(map_io_error)?;
+        let mut interrupt = signal(SignalKind::interrupt()).map_err(map_io_error)?;
+        let mut hangup = signal(SignalKind::hangup()).map_err(map_io_error)?;
+        let control = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    Some(()) = terminate.recv() => control.on_shutdown_signal("SIGTERM"),
+                    Some(()) = interrupt.recv() => control.on_shutdown_signal("SIGINT"),
+                    Some(()) = hangup.recv() => {
+                        println!("Received SIGHUP, checking for a new version.");
+                        control.request_version_check();
+                    }
+                    else => break,
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn on_shutdown_signal(&self, name: &str) {
+        if self.shutdown_requested() {
+            println!("Received {}, already waiting for the app to stop.", name);
+        } else {
+            println!("Received {}, stopping the app and exiting...", name);
+            self.request_shutdown();
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use crate::errors::CvmError::{ListenFailed, ProcessFailedToStart};
+use crate::errors::Result;
+use nix::libc::{self, c_char};
+use std::collections::BTreeMap;
+use std::ffi::{CString, OsStr, OsString};
+use std::net::{SocketAddr, TcpListener};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+/// The file descriptor the passed sockets start at, see sd_listen_fds(3).
+const LISTEN_FDS_START: RawFd = 3;
+/// Room for the digits of any pid.
+const PID_DIGITS: usize = 20;
+
+extern "C" {
+    static mut environ: *const *const c_char;
+}
+
+/// Listening sockets the monitor owns and passes to every version of the app the way systemd's
+/// socket activation does: as file descriptors 3 and up, announced with the `LISTEN_FDS` and
+/// `LISTEN_PID` environment variables. The sockets stay open across versions, so a new version
+/// accepts connections on them while the previous one still drains.
+#[derive(Debug, Default)]
+pub struct ListenSockets {
+    addresses: Vec<SocketAddr>,
+    listeners: Vec<TcpListener>,
+}
+
+impl ListenSockets {
+    pub fn new(addresses: Vec<SocketAddr>) -> ListenSockets {
+        ListenSockets {
+            addresses,
+            listeners: Vec::new(),
+        }
+    }
+
+    /// Listens on the addresses, unless that happened already.
+    pub fn bind(&mut self) -> Result<()> {
+        if !self.listeners.is_empty() {
+            return Ok(());
+        }
+        self.listeners = self
+            .addresses
+            .iter()
+            .map(|address| {
+                TcpListener::bind(address).map_err(|err| ListenFailed {
+                    address: address.to_string(),
+                    message: err.to_string(),
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(())
+    }
+
+    /// The addresses the sockets are bound to, which tell the ports picked for port 0.
+    pub fn local_addresses(&self) -> Vec<SocketAddr> {
+        self.listeners
+            .iter()
+            .filter_map(|listener| listener.local_addr().ok())
+            .collect()
+    }
+
+    /// Makes the command start with the bound sockets, in the order of the addresses. Nothing is
+    /// passed before [`Self::bind`].
+    pub fn pass_to(&self, command: &mut Command) -> Result<()> {
+        if self.listeners.is_empty() {
+            return Ok(());
+        }
+        let fds = self.listeners.iter().map(AsRawFd::as_raw_fd).collect();
+        let mut exec = PreparedExec::new(command, fds)?;
+        // Safety: only async-signal-safe functions are called between fork and exec.
+        unsafe {
+            command.pre_exec(move || exec.exec());
+        }
+        Ok(())
+    }
+}
+
+/// Everything needed to exec a command with the sockets, prepared before the fork because
+/// allocating in the forked child isn't safe. `LISTEN_PID` has to name the app itself, which is only
+/// known in the child, so its value is written into the prepared buffer there.
+struct PreparedExec {
+    program: CString,
+    _args: Vec<CString>,
+    argv: Vec<*const c_char>,
+    _env: Vec<CString>,
+    listen_pid: Vec<u8>,
+    envp: Vec<*const c_char>,
+    fds: Vec<RawFd>,
+    moved_fds: Vec<RawFd>,
+}
+
+// Safety: the pointers only point into the buffers owned by the same PreparedExec.
+unsafe impl Send for PreparedExec {}
+unsafe impl Sync for PreparedExec {}
+
+impl PreparedExec {
+    fn new(command: &Command, fds: Vec<RawFd>) -> Result<PreparedExec> {
+        let mut vars: BTreeMap<OsString, OsString> = std::env::vars_os().collect();
+        for (key, value) in command.get_envs() {
+            match value {
+                Some(value) => vars.insert(key.to_os_string(), value.to_os_string()),
+                None => vars.remove(key),
+            };
+        }
+        vars.remove(OsStr::new("LISTEN_FDNAMES"));
+        vars.remove(OsStr::new("LISTEN_PID"));
+        vars.insert("LISTEN_FDS".into(), fds.len().to_string().into());
+
+        let program = c_string(command.get_program().as_bytes().to_vec())?;
+        let args = std::iter::once(Ok(program.clone()))
+            .chain(command.get_args().map(|arg| c_string(arg.as_bytes().to_vec())))
+            .collect::<Result<Vec<_>>>()?;
+        let env = vars
+            .into_iter()
+            .map(|(key, value)| {
+                let mut var = key.into_vec();
+                var.push(b'=');
+                var.extend(value.into_vec());
+                c_string(var)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut listen_pid = b"LISTEN_PID=".to_vec();
+        listen_pid.resize(listen_pid.len() + PID_DIGITS + 1, 0);
+
+        let argv = args.iter().map(|arg| arg.as_ptr()).chain([std::ptr::null()]).collect();
+        let envp = env
+            .iter()
+            .map(|var| var.as_ptr())
+            .chain([listen_pid.as_ptr().cast(), std::ptr::null()])
+            .collect();
+        let moved_fds = vec![-1; fds.len()];
+        Ok(PreparedExec {
+            program,
+            _args: args,
+            argv,
+            _env: env,
+            listen_pid,
+            envp,
+            fds,
+            moved_fds,
+        })
+    }
+
+    /// Runs in the forked child. Only returns when exec failed.
+    fn exec(&mut self) -> std::io::Result<()> {
+        let count = self.fds.len() as RawFd;
+        unsafe {
+            // Moving the sockets out of the way first keeps one from being overwritten by another
+            // before it was moved to its place.
+            for (fd, moved) in self.fds.iter().zip(self.moved_fds.iter_mut()) {
+                *moved = libc::fcntl(*fd, libc::F_DUPFD, LISTEN_FDS_START + count);
+                if *moved < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            for (target, moved) in (LISTEN_FDS_START..).zip(self.moved_fds.iter()) {
+                // Duplicates don't inherit close-on-exec, so the sockets stay open in the app.
+                if libc::dup2(*moved, target) < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                libc::close(*moved);
+            }
+
+            // Written through the buffer's pointer, which envp points into as well.
+            let digits = std::slice::from_raw_parts_mut(
+                self.listen_pid.as_mut_ptr().add(b"LISTEN_PID=".len()),
+                PID_DIGITS,
+            );
+            write_decimal(libc::getpid() as u64, digits);
+            environ = self.envp.as_ptr();
+            libc::execvp(self.program.as_ptr(), self.argv.as_ptr());
+        }
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+fn c_string(bytes: Vec<u8>) -> Result<CString> {
+    CString::new(bytes).map_err(|err| ProcessFailedToStart {
+        message: err.to_string(),
+    })
+}
+
+/// Writes the digits of the number to the start of the buffer, followed by a nul when there's room,
+/// without allocating.
+fn write_decimal(mut number: u64, buffer: &mut [u8]) {
+    let mut digits = [0u8; PID_DIGITS];
+    let mut count = 0;
+    loop {
+        digits[count] = b'0' + (number % 10) as u8;
+        count += 1;
+        number /= 10;
+        if number == 0 || count == digits.len() {
+            break;
+        }
+    }
+    for (slot, digit) in buffer.iter_mut().zip(digits[..count].iter().rev()) {
+        *slot = *digit;
+    }
+    if let Some(end) = buffer.get_mut(count) {
+        *end = 0;
+    }
+}
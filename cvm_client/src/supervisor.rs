@@ -0,0 +1,98 @@
+use std::collections::VecDeque;
+use std::process::ExitStatus;
+use std::time::{Duration, Instant};
+
+/// Which exits of the app are followed by a restart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartMode {
+    Always,
+    OnFailure,
+    Never,
+}
+
+impl RestartMode {
+    /// Parses `always`, `on-failure` or `never`.
+    pub fn parse(value: &str) -> Option<RestartMode> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "always" => Some(RestartMode::Always),
+            "on-failure" => Some(RestartMode::OnFailure),
+            "never" => Some(RestartMode::Never),
+            _ => None,
+        }
+    }
+}
+
+/// How the monitor restarts the app after it exits on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestartPolicy {
+    pub mode: RestartMode,
+    /// The app is left stopped once it exited more than this many times within `window`.
+    pub max_restarts: u32,
+    pub window: Duration,
+    /// Delay before the first restart within the window, doubled for every further restart.
+    pub backoff_base: Duration,
+    pub backoff_max: Duration,
+}
+
+/// What the monitor does after the app exited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartDecision {
+    RestartAfter(Duration),
+    /// The app stays stopped until the next version is started.
+    Stop,
+}
+
+/// Tracks the exits of the app's process and applies the restart policy to them.
+#[derive(Debug, Clone)]
+pub struct CrashTracker {
+    policy: RestartPolicy,
+    exits: VecDeque<Instant>,
+}
+
+impl CrashTracker {
+    pub fn new(policy: RestartPolicy) -> Self {
+        CrashTracker {
+            policy,
+            exits: VecDeque::new(),
+        }
+    }
+
+    /// Records an exit of the app at `now` and decides whether it is restarted.
+    pub fn on_exit(&mut self, status: ExitStatus, now: Instant) -> RestartDecision {
+        while self
+            .exits
+            .front()
+            .is_some_and(|exit| now.duration_since(*exit) >= self.policy.window)
+        {
+            self.exits.pop_front();
+        }
+        self.exits.push_back(now);
+
+        let restart = match self.policy.mode {
+            RestartMode::Always => true,
+            RestartMode::OnFailure => !status.success(),
+            RestartMode::Never => false,
+        };
+        if !restart || self.crash_count() > self.policy.max_restarts {
+            return RestartDecision::Stop;
+        }
+        RestartDecision::RestartAfter(self.backoff())
+    }
+
+    /// How many times the app exited within the policy's window.
+    pub fn crash_count(&self) -> u32 {
+        self.exits.len() as u32
+    }
+
+    /// The exponential delay for the current crash count with jitter, between half and all of it, so
+    /// that clients that crash together don't restart in lockstep.
+    fn backoff(&self) -> Duration {
+        let exponent = self.crash_count().saturating_sub(1).min(16);
+        let delay = self
+            .policy
+            .backoff_base
+            .saturating_mul(1 << exponent)
+            .min(self.policy.backoff_max);
+        delay / 2 + (delay / 2).mul_f64(fastrand::f64())
+    }
+}
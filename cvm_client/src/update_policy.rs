@@ -0,0 +1,91 @@
+use crate::http_client::LatestVersionResponse;
+use chrono::{DateTime, Days, NaiveTime, Utc};
+
+/// A daily window, in UTC, during which optional updates may be applied. A window whose end is
+/// before its start spans midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaintenanceWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl MaintenanceWindow {
+    /// Parses a window in the format `HH:MM-HH:MM`, e.g. `02:00-04:30`.
+    pub fn parse(value: &str) -> Option<MaintenanceWindow> {
+        let (start, end) = value.trim().split_once('-')?;
+        let start = NaiveTime::parse_from_str(start.trim(), "%H:%M").ok()?;
+        let end = NaiveTime::parse_from_str(end.trim(), "%H:%M").ok()?;
+        if start == end {
+            return None;
+        }
+        Some(MaintenanceWindow { start, end })
+    }
+
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start < self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+
+    /// Returns `now` when it is inside the window, otherwise the next time the window opens.
+    pub fn next_opening(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        if self.contains(now.time()) {
+            return now;
+        }
+        let opening = now.date_naive().and_time(self.start).and_utc();
+        if opening > now {
+            opening
+        } else {
+            opening + Days::new(1)
+        }
+    }
+}
+
+/// What the monitor should do about the latest version reported by the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateDecision {
+    UpToDate,
+    ApplyNow,
+    DeferUntil(DateTime<Utc>),
+}
+
+/// Decides when updates are applied. Mandatory updates are always applied immediately; optional
+/// updates wait for the maintenance window when one is configured.
+#[derive(Debug, Clone, Default)]
+pub struct UpdatePolicy {
+    pub maintenance_window: Option<MaintenanceWindow>,
+}
+
+impl UpdatePolicy {
+    pub fn decide(&self, latest: &LatestVersionResponse, now: DateTime<Utc>) -> UpdateDecision {
+        if !latest.update_required {
+            return UpdateDecision::UpToDate;
+        }
+        if latest.update_mandatory {
+            return UpdateDecision::ApplyNow;
+        }
+        match &self.maintenance_window {
+            Some(window) if !window.contains(now.time()) => {
+                UpdateDecision::DeferUntil(window.next_opening(now))
+            }
+            _ => UpdateDecision::ApplyNow,
+        }
+    }
+}
+
+/// A version that failed to start, after which the monitor went back to `running_version`. The
+/// failed version isn't retried until the server offers a different one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rollback {
+    pub failed_version: String,
+    pub running_version: String,
+}
+
+impl Rollback {
+    /// Whether the latest version is the version that failed, which is not to be updated to.
+    pub fn holds_back(&self, latest: &LatestVersionResponse) -> bool {
+        latest.version == self.failed_version
+    }
+}
@@ -0,0 +1,190 @@
+#[cfg(test)]
+mod cache_tests {
+    use cvm::cache::{ArtifactCache, CacheKey, RetentionPolicy};
+    use cvm::delta::sha256_hex;
+    use cvm::errors::CvmError;
+    use std::path::Path;
+    use std::time::{Duration, SystemTime};
+
+    fn cache(name: &str) -> ArtifactCache {
+        let root = std::env::temp_dir().join(format!("cvm_cache_test_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        ArtifactCache::new(root)
+    }
+
+    fn key(version: &str) -> CacheKey {
+        CacheKey {
+            app_id: "50b473ee-35b3-4252-8998-6be4d4130d3a".to_string(),
+            version: version.to_string(),
+            architecture: "x86_64-unknown-linux-gnu".to_string(),
+        }
+    }
+
+    #[test]
+    fn it_lays_out_entries_by_app_version_and_architecture() {
+        let cache = cache("layout");
+        let path = cache.prepare(&key("0.2.0"), "infinite_hello_0.2.0").unwrap();
+        assert_eq!(
+            path,
+            cache
+                .root()
+                .join("50b473ee-35b3-4252-8998-6be4d4130d3a")
+                .join("0.2.0")
+                .join("x86_64-unknown-linux-gnu")
+                .join("infinite_hello_0.2.0")
+        );
+        assert!(path.parent().unwrap().is_dir());
+    }
+
+    #[test]
+    fn it_only_serves_builds_recorded_in_the_manifest() {
+        let cache = cache("manifest");
+        let path = cache.prepare(&key("0.2.0"), "infinite_hello_0.2.0").unwrap();
+        std::fs::write(&path, b"hello").unwrap();
+        assert!(cache.lookup(&key("0.2.0"), None).is_none());
+
+        let entry = cache.commit(&key("0.2.0"), &path, &sha256_hex(b"hello")).unwrap();
+        assert_eq!(entry.size, 5);
+        assert_eq!(cache.entry(&key("0.2.0")), Some(entry));
+        assert_eq!(cache.lookup(&key("0.2.0"), None), Some(path.clone()));
+        assert_eq!(cache.lookup(&key("0.2.0"), Some(&sha256_hex(b"hello").to_uppercase())), Some(path));
+        assert!(cache.lookup(&key("0.3.0"), None).is_none());
+    }
+
+    #[test]
+    fn it_rejects_path_components_escaping_the_cache() {
+        let cache = cache("escape");
+        assert!(cache.prepare(&key(".."), "infinite_hello").is_err());
+        assert!(cache.prepare(&key("0.2.0/../.."), "infinite_hello").is_err());
+        assert!(cache.prepare(&key("0.2.0"), "../infinite_hello").is_err());
+        assert!(cache.prepare(&key(""), "infinite_hello").is_err());
+    }
+
+    /// Caches a build of the version with `size` bytes.
+    fn cache_build(cache: &ArtifactCache, version: &str, size: usize) -> CacheKey {
+        let key = key(version);
+        let path = cache.prepare(&key, &format!("infinite_hello_{}", version)).unwrap();
+        let content = vec![version.len() as u8; size];
+        std::fs::write(&path, &content).unwrap();
+        cache.commit(&key, &path, &sha256_hex(&content)).unwrap();
+        key
+    }
+
+    fn cached_versions(cache: &ArtifactCache) -> Vec<String> {
+        let app_id = key("").app_id;
+        cache.entries(&app_id).into_iter().map(|entry| entry.version).collect()
+    }
+
+    #[test]
+    fn it_keeps_the_most_recent_versions() {
+        let cache = cache("keep_versions");
+        for version in ["0.1.0", "0.2.0", "0.3.0", "0.4.0"] {
+            cache_build(&cache, version, 10);
+        }
+        let policy = RetentionPolicy {
+            keep_versions: 2,
+            max_bytes: None,
+        };
+        let removed = cache.enforce_retention(policy, &key("0.4.0")).unwrap();
+        assert_eq!(removed.len(), 2);
+        assert_eq!(cached_versions(&cache), vec!["0.4.0", "0.3.0"]);
+        assert!(!cache.root().join(key("0.1.0").app_id).join("0.1.0").exists());
+    }
+
+    #[test]
+    fn it_always_keeps_the_running_and_last_known_good_builds() {
+        let cache = cache("protected");
+        cache_build(&cache, "0.1.0", 10);
+        cache.mark_known_good(&key("0.1.0")).unwrap();
+        for version in ["0.2.0", "0.3.0", "0.4.0"] {
+            cache_build(&cache, version, 10);
+        }
+        let policy = RetentionPolicy {
+            keep_versions: 1,
+            max_bytes: None,
+        };
+        cache.enforce_retention(policy, &key("0.2.0")).unwrap();
+        assert_eq!(cached_versions(&cache), vec!["0.4.0", "0.2.0", "0.1.0"]);
+        assert_eq!(
+            cache.last_known_good(&key("0.1.0").app_id, &key("0.1.0").architecture).map(|entry| entry.version),
+            Some("0.1.0".to_string())
+        );
+    }
+
+    #[test]
+    fn it_removes_the_oldest_builds_until_the_cache_fits() {
+        let cache = cache("max_bytes");
+        for version in ["0.1.0", "0.2.0", "0.3.0", "0.4.0"] {
+            cache_build(&cache, version, 100);
+        }
+        let policy = RetentionPolicy {
+            keep_versions: 10,
+            max_bytes: Some(250),
+        };
+        cache.enforce_retention(policy, &key("0.1.0")).unwrap();
+        assert_eq!(cached_versions(&cache), vec!["0.4.0", "0.1.0"]);
+    }
+
+    #[test]
+    fn it_keeps_the_most_recent_versions_of_each_architecture() {
+        let cache = cache("keep_architectures");
+        let arm = |version: &str| CacheKey {
+            architecture: "aarch64-unknown-linux-gnu".to_string(),
+            ..key(version)
+        };
+        for version in ["0.1.0", "0.2.0"] {
+            cache_build(&cache, version, 10);
+            let path = cache.prepare(&arm(version), "infinite_hello").unwrap();
+            std::fs::write(&path, b"arm").unwrap();
+            cache.commit(&arm(version), &path, &sha256_hex(b"arm")).unwrap();
+        }
+        let policy = RetentionPolicy {
+            keep_versions: 1,
+            max_bytes: None,
+        };
+        cache.enforce_retention(policy, &key("0.2.0")).unwrap();
+        assert!(cache.entry(&key("0.2.0")).is_some());
+        assert!(cache.entry(&arm("0.2.0")).is_some());
+        assert!(cache.entry(&key("0.1.0")).is_none());
+        assert!(cache.entry(&arm("0.1.0")).is_none());
+    }
+
+    /// Writes the partial download of the build at `path`, last modified at the time.
+    fn write_partial(path: &Path, modified: SystemTime) {
+        let partial = std::fs::File::create(format!("{}.partial", path.display())).unwrap();
+        partial.set_modified(modified).unwrap();
+    }
+
+    #[test]
+    fn it_removes_downloads_that_never_completed() {
+        let cache = cache("orphans");
+        let running = cache_build(&cache, "0.2.0", 10);
+        // Left behind by an earlier run.
+        let orphan = cache.prepare(&key("0.1.0"), "infinite_hello_0.1.0").unwrap();
+        write_partial(&orphan, SystemTime::now() - Duration::from_secs(60));
+        // Kept by a download of this run that is to be resumed.
+        let resumable = cache.prepare(&key("0.3.0"), "infinite_hello_0.3.0").unwrap();
+        write_partial(&resumable, SystemTime::now() + Duration::from_secs(1));
+        let policy = RetentionPolicy {
+            keep_versions: 10,
+            max_bytes: None,
+        };
+        cache.enforce_retention(policy, &running).unwrap();
+        assert!(!orphan.parent().unwrap().exists());
+        assert!(resumable.parent().unwrap().exists());
+        assert!(cache.lookup(&running, None).is_some());
+    }
+
+    #[test]
+    fn it_refuses_to_fill_the_disk() {
+        let cache = cache("disk_space");
+        assert!(cache.ensure_free_space(0).is_ok());
+        match cache.ensure_free_space(u64::MAX) {
+            Err(CvmError::InsufficientDiskSpace { available, required, .. }) => {
+                assert!(available < required);
+                assert_eq!(required, u64::MAX);
+            }
+            other => panic!("expected insufficient disk space, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,36 @@
+#[cfg(test)]
+mod config_tests {
+    use cvm::config::{parse_app_env, parse_labels, parse_listen_addresses};
+
+    #[test]
+    fn it_parses_labels() {
+        let labels = parse_labels("site=berlin, region = eu,").unwrap();
+        assert_eq!(labels.get("site").map(String::as_str), Some("berlin"));
+        assert_eq!(labels.get("region").map(String::as_str), Some("eu"));
+        assert!(parse_labels("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn it_rejects_malformed_labels() {
+        assert!(parse_labels("site").is_err());
+        assert!(parse_labels("site=").is_err());
+        assert!(parse_labels("=berlin").is_err());
+    }
+
+    #[test]
+    fn it_parses_listen_addresses() {
+        let addresses = parse_listen_addresses("0.0.0.0:8080, [::1]:8443,").unwrap();
+        assert_eq!(addresses, ["0.0.0.0:8080".parse().unwrap(), "[::1]:8443".parse().unwrap()]);
+        assert!(parse_listen_addresses("").unwrap().is_empty());
+        assert!(parse_listen_addresses("localhost:8080").is_err());
+        assert!(parse_listen_addresses("0.0.0.0").is_err());
+    }
+
+    #[test]
+    fn it_parses_the_app_env() {
+        let env = parse_app_env("RUST_LOG=info, DATABASE_URL=postgres://db/app?ssl=true").unwrap();
+        assert_eq!(env.get("RUST_LOG").map(String::as_str), Some("info"));
+        assert_eq!(env.get("DATABASE_URL").map(String::as_str), Some("postgres://db/app?ssl=true"));
+        assert!(parse_app_env("RUST_LOG").is_err());
+    }
+}
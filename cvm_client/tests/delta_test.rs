@@ -0,0 +1,45 @@
+#[cfg(test)]
+mod delta_tests {
+    use cvm::delta::{apply_patch, sha256_hex, verify_sha256};
+    use cvm::errors::CvmError;
+
+    fn builds() -> (Vec<u8>, Vec<u8>) {
+        let old: Vec<u8> = (0..10_000u32).flat_map(|i| i.to_le_bytes()).collect();
+        let mut new = old.clone();
+        new[400..416].copy_from_slice(b"version 0.2.0!!!");
+        (old, new)
+    }
+
+    fn patch(old: &[u8], new: &[u8]) -> Vec<u8> {
+        let mut encoder = zstd::Encoder::new(Vec::new(), 19).unwrap();
+        bsdiff::diff(old, new, &mut encoder).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn it_applies_patches() {
+        let (old, new) = builds();
+        let patched = apply_patch(&old, &patch(&old, &new)).unwrap();
+        assert_eq!(patched, new);
+        assert!(verify_sha256(&patched, &sha256_hex(&new)).is_ok());
+    }
+
+    #[test]
+    fn it_rejects_corrupt_patches() {
+        let (old, new) = builds();
+        let mut patch = patch(&old, &new);
+        patch.truncate(patch.len() / 2);
+        assert!(apply_patch(&old, &patch).is_err());
+    }
+
+    #[test]
+    fn it_detects_digest_mismatches() {
+        let (old, new) = builds();
+        let expected = sha256_hex(&new).to_ascii_uppercase();
+        assert!(verify_sha256(&new, &expected).is_ok());
+        match verify_sha256(&old, &expected) {
+            Err(CvmError::DigestMismatch { actual, .. }) => assert_eq!(actual, sha256_hex(&old)),
+            other => panic!("expected a digest mismatch, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,217 @@
+#[cfg(test)]
+mod download_tests {
+    use cvm::cache::{ArtifactCache, CacheKey};
+    use cvm::config::Config;
+    use cvm::delta::sha256_hex;
+    use cvm::download::DownloadProgress;
+    use cvm::errors::CvmError;
+    use cvm::http_client::CvmHttpClient;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+
+    fn build() -> Vec<u8> {
+        (0..50_000u32).flat_map(|i| i.to_le_bytes()).collect()
+    }
+
+    /// Serves `content` with support for `Range: bytes=start-`. The first response is cut off
+    /// after `cut_at` bytes when set. Returns the url and the number of requests served.
+    async fn serve(content: Vec<u8>, cut_at: Option<usize>) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let served = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                    let read = socket.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..read]);
+                }
+                let request = String::from_utf8_lossy(&request).to_lowercase();
+                let start = request
+                    .lines()
+                    .find_map(|line| line.strip_prefix("range: bytes="))
+                    .and_then(|range| range.trim().trim_end_matches('-').parse::<usize>().ok());
+                let first = served.fetch_add(1, Ordering::SeqCst) == 0;
+
+                let header = match start {
+                    Some(start) if start >= content.len() => {
+                        format!("HTTP/1.1 416 Range Not Satisfiable\r\ncontent-range: bytes */{}\r\ncontent-length: 0\r\n\r\n", content.len())
+                    }
+                    Some(start) => format!(
+                        "HTTP/1.1 206 Partial Content\r\ncontent-range: bytes {}-{}/{}\r\ncontent-length: {}\r\n\r\n",
+                        start,
+                        content.len() - 1,
+                        content.len(),
+                        content.len() - start
+                    ),
+                    None => format!("HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n", content.len()),
+                };
+                let body = match start {
+                    Some(start) if start >= content.len() => &content[..0],
+                    Some(start) => &content[start..],
+                    None => &content[..],
+                };
+                let body = match cut_at {
+                    Some(cut_at) if first => &body[..cut_at],
+                    _ => body,
+                };
+                socket.write_all(header.as_bytes()).await.unwrap();
+                socket.write_all(body).await.unwrap();
+                let _ = socket.shutdown().await;
+            }
+        });
+        (format!("http://{}", address), requests)
+    }
+
+    static CACHES: AtomicUsize = AtomicUsize::new(0);
+
+    /// Returns a client downloading to a cache of its own, along with the cache.
+    fn create_http_client() -> (CvmHttpClient, ArtifactCache) {
+        let mut config = Config::new().expect("Failed to parse config");
+        config.cache_dir = std::env::temp_dir().join(format!(
+            "cvm_download_test_{}_{}",
+            std::process::id(),
+            CACHES.fetch_add(1, Ordering::SeqCst)
+        ));
+        let cache = ArtifactCache::new(&config.cache_dir);
+        (CvmHttpClient::new(config, "0.1.0"), cache)
+    }
+
+    fn key(http_client: &CvmHttpClient, version: &str) -> CacheKey {
+        CacheKey {
+            app_id: http_client.client_details.app_id.clone(),
+            version: version.to_string(),
+            architecture: http_client.client_details.architecture.clone(),
+        }
+    }
+
+    /// Leaves a partial download behind as an earlier run would have.
+    fn write_partial(cache: &ArtifactCache, key: &CacheKey, file_name: &str, content: &[u8]) {
+        let path = cache.prepare(key, file_name).unwrap();
+        std::fs::write(PathBuf::from(format!("{}.partial", path.display())), content).unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_resumes_interrupted_downloads() {
+        let content = build();
+        let (server, requests) = serve(content.clone(), Some(70_000)).await;
+        let (mut http_client, cache) = create_http_client();
+        let progress = Arc::new(Mutex::new(Vec::<DownloadProgress>::new()));
+        let reported = progress.clone();
+        http_client.on_download_progress(move |progress| reported.lock().unwrap().push(progress));
+
+        let url = format!("{}/resumed_0.2.0", server);
+        let path = http_client
+            .download_version("0.2.0", &url, Some(&sha256_hex(&content)))
+            .await
+            .expect("Failed to download");
+        assert_eq!(path, cache.entry_dir(&key(&http_client, "0.2.0")).unwrap().join("resumed_0.2.0"));
+        assert_eq!(std::fs::read(&path).unwrap(), content);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        assert!(!path.with_file_name("resumed_0.2.0.partial").exists());
+
+        let progress = progress.lock().unwrap();
+        let last = progress.last().expect("No progress reported");
+        assert_eq!(last.received, content.len() as u64);
+        assert_eq!(last.percentage(), Some(100));
+        assert!(progress.iter().any(|progress| progress.received == 70_000));
+    }
+
+    #[tokio::test]
+    async fn it_resumes_partial_downloads_of_earlier_runs() {
+        let content = build();
+        let (server, requests) = serve(content.clone(), None).await;
+        let (mut http_client, cache) = create_http_client();
+        write_partial(&cache, &key(&http_client, "0.2.0"), "earlier_0.2.0", &content[..1234]);
+
+        let url = format!("{}/earlier_0.2.0", server);
+        let path = http_client
+            .download_version("0.2.0", &url, Some(&sha256_hex(&content)))
+            .await
+            .expect("Failed to download");
+        assert_eq!(std::fs::read(path).unwrap(), content);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn it_starts_over_when_the_partial_download_is_stale() {
+        let content = build();
+        let (server, requests) = serve(content.clone(), None).await;
+        let (mut http_client, cache) = create_http_client();
+        write_partial(&cache, &key(&http_client, "0.2.0"), "stale_0.2.0", b"not the build");
+        let oversized = vec![0u8; content.len() + 1];
+        write_partial(&cache, &key(&http_client, "0.3.0"), "oversized_0.3.0", &oversized);
+
+        for (version, name) in [("0.2.0", "stale_0.2.0"), ("0.3.0", "oversized_0.3.0")] {
+            let url = format!("{}/{}", server, name);
+            let path = http_client
+                .download_version(version, &url, Some(&sha256_hex(&content)))
+                .await
+                .expect("Failed to download");
+            assert_eq!(std::fs::read(path).unwrap(), content);
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn it_rejects_downloads_with_the_wrong_digest() {
+        let (server, _) = serve(build(), None).await;
+        let (mut http_client, cache) = create_http_client();
+        let url = format!("{}/mismatch_0.2.0", server);
+        let result = http_client
+            .download_version("0.2.0", &url, Some(&"0".repeat(64)))
+            .await;
+        assert!(matches!(result, Err(CvmError::DigestMismatch { .. })));
+        let dir = cache.entry_dir(&key(&http_client, "0.2.0")).unwrap();
+        assert!(!dir.join("mismatch_0.2.0").exists());
+        assert!(!dir.join("mismatch_0.2.0.partial").exists());
+        assert!(http_client.cached_build("0.2.0", None).is_none());
+    }
+
+    #[tokio::test]
+    async fn it_records_downloads_in_the_cache_manifest() {
+        let content = build();
+        let (server, requests) = serve(content.clone(), None).await;
+        let (mut http_client, cache) = create_http_client();
+        let sha256 = sha256_hex(&content);
+        assert!(http_client.cached_build("0.2.0", Some(&sha256)).is_none());
+
+        let url = format!("{}/cached_0.2.0", server);
+        let path = http_client
+            .download_version("0.2.0", &url, None)
+            .await
+            .expect("Failed to download");
+        let entry = cache.entry(&key(&http_client, "0.2.0")).expect("No manifest written");
+        assert_eq!(entry.sha256, sha256);
+        assert_eq!(entry.size, content.len() as u64);
+        assert_eq!(entry.file_name, "cached_0.2.0");
+        assert_eq!(http_client.cached_build("0.2.0", Some(&sha256)), Some(path.clone()));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // Another build of the version, or a corrupt file, is not served from the cache.
+        assert!(http_client.cached_build("0.2.0", Some(&"0".repeat(64))).is_none());
+        std::fs::write(&path, b"corrupt").unwrap();
+        assert!(http_client.cached_build("0.2.0", None).is_none());
+    }
+
+    #[tokio::test]
+    async fn it_refuses_downloads_when_the_disk_is_nearly_full() {
+        let (server, requests) = serve(build(), None).await;
+        let mut config = Config::new().expect("Failed to parse config");
+        config.cache_dir = std::env::temp_dir().join(format!("cvm_download_test_full_{}", std::process::id()));
+        config.min_free_disk_bytes = u64::MAX;
+        let mut http_client = CvmHttpClient::new(config, "0.1.0");
+
+        let url = format!("{}/full_0.2.0", server);
+        let result = http_client.download_version("0.2.0", &url, None).await;
+        assert!(matches!(result, Err(CvmError::InsufficientDiskSpace { .. })));
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,59 @@
+#[cfg(test)]
+mod encoding_tests {
+    use cvm::delta::sha256_hex;
+    use cvm::encoding::{ContentEncoding, DecodingWriter, DigestWriter};
+    use std::io::Write;
+
+    fn build() -> Vec<u8> {
+        b"infinite hello ".repeat(4096)
+    }
+
+    fn encode(encoding: ContentEncoding, content: &[u8]) -> Vec<u8> {
+        match encoding {
+            ContentEncoding::Zstd => zstd::encode_all(content, 3).unwrap(),
+            ContentEncoding::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(content).unwrap();
+                encoder.finish().unwrap()
+            }
+            ContentEncoding::Identity => content.to_vec(),
+        }
+    }
+
+    /// Decodes in small chunks like a streamed download would arrive.
+    fn decode(encoding: ContentEncoding, encoded: &[u8]) -> std::io::Result<(Vec<u8>, String)> {
+        let mut writer = DecodingWriter::new(encoding, DigestWriter::new(Vec::new()))?;
+        for chunk in encoded.chunks(1000) {
+            writer.write_all(chunk)?;
+        }
+        Ok(writer.finish()?.finish())
+    }
+
+    #[test]
+    fn it_parses_content_encodings() {
+        assert_eq!(ContentEncoding::parse(None), Some(ContentEncoding::Identity));
+        assert_eq!(ContentEncoding::parse(Some("identity")), Some(ContentEncoding::Identity));
+        assert_eq!(ContentEncoding::parse(Some("zstd")), Some(ContentEncoding::Zstd));
+        assert_eq!(ContentEncoding::parse(Some("GZIP")), Some(ContentEncoding::Gzip));
+        assert_eq!(ContentEncoding::parse(Some("br")), None);
+    }
+
+    #[test]
+    fn it_decodes_while_digesting_the_decoded_content() {
+        let content = build();
+        for encoding in [ContentEncoding::Zstd, ContentEncoding::Gzip, ContentEncoding::Identity] {
+            let encoded = encode(encoding, &content);
+            let (decoded, sha256) = decode(encoding, &encoded).unwrap();
+            assert_eq!(decoded, content, "{:?}", encoding);
+            assert_eq!(sha256, sha256_hex(&content), "{:?}", encoding);
+        }
+    }
+
+    #[test]
+    fn it_rejects_corrupt_content() {
+        let mut encoded = encode(ContentEncoding::Gzip, &build());
+        encoded[20] ^= 0xff;
+        let result = decode(ContentEncoding::Gzip, &encoded);
+        assert!(result.is_err());
+    }
+}
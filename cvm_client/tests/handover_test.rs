@@ -0,0 +1,246 @@
+#[cfg(test)]
+mod handover_tests {
+    use cvm::cache::{ArtifactCache, CacheKey};
+    use cvm::config::Config;
+    use cvm::delta::sha256_hex;
+    use cvm::health::{HealthCheck, HealthProbe};
+    use cvm::CvmClientMonitor;
+    use nix::sys::signal;
+    use nix::unistd::Pid;
+    use regex::Regex;
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{TcpListener, TcpStream};
+
+    const WAIT: Duration = Duration::from_secs(10);
+
+    /// Stand-in for the CVM server. It offers `latest` and records the reports it receives.
+    #[derive(Clone, Default)]
+    struct StandIn {
+        latest: Arc<Mutex<String>>,
+        digests: Arc<Mutex<HashMap<String, String>>>,
+        reports: Arc<Mutex<Vec<(String, serde_json::Value)>>>,
+    }
+
+    impl StandIn {
+        fn offer(&self, version: &str) {
+            *self.latest.lock().unwrap() = version.to_string();
+        }
+
+        fn reports(&self, path: &str) -> Vec<serde_json::Value> {
+            let reports = self.reports.lock().unwrap();
+            reports
+                .iter()
+                .filter(|(report_path, _)| report_path == path)
+                .map(|(_, body)| body.clone())
+                .collect()
+        }
+
+        async fn start(&self) -> String {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+            let stand_in = self.clone();
+            tokio::spawn(async move {
+                loop {
+                    let (socket, _) = listener.accept().await.unwrap();
+                    tokio::spawn(stand_in.clone().answer(socket));
+                }
+            });
+            format!("http://{}", address)
+        }
+
+        /// Answers one request and closes the connection.
+        async fn answer(self, socket: TcpStream) {
+            let mut reader = BufReader::new(socket);
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).await.unwrap();
+            let path = request_line.split_whitespace().nth(1).unwrap_or_default().to_string();
+            let mut content_length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).await.unwrap();
+                if header.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+
+            let response = if path == "/application/latest" {
+                let latest = self.latest.lock().unwrap().clone();
+                serde_json::json!({
+                    "build_id": format!("build-{}", latest),
+                    "version": latest,
+                    "url": format!("http://127.0.0.1/app_{}", latest),
+                    "update_required": body["current_running_version"] != latest.as_str(),
+                    "sha256": self.digests.lock().unwrap().get(&latest),
+                })
+                .to_string()
+            } else {
+                self.reports.lock().unwrap().push((path, body));
+                String::new()
+            };
+            let response = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                response.len(),
+                response
+            );
+            let _ = reader.get_mut().write_all(response.as_bytes()).await;
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cvm_handover_test_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// An app that notes whether the version that started before it still runs, becomes ready after
+    /// a moment and runs until Sigterm.
+    fn app_script(dir: &Path) -> String {
+        format!(
+            r#"#!/bin/sh
+dir={}
+if [ -f "$dir/running" ] && kill -0 "$(cat "$dir/running")" 2>/dev/null; then
+    cat "$dir/running" > "$dir/previous_running_$CVM_VERSION"
+fi
+trap 'exit 0' TERM
+sleep 0.5
+echo ready
+echo $$ > "$dir/running"
+echo $$ > "$dir/ready_$CVM_VERSION"
+for i in $(seq 300); do sleep 0.1; done
+"#,
+            dir.display()
+        )
+    }
+
+    /// Puts the build of the version in the cache the monitor uses, so that it isn't downloaded.
+    fn cache_build(config: &Config, stand_in: &StandIn, version: &str, script: &str) {
+        let key = CacheKey {
+            app_id: config.app_id.clone(),
+            version: version.to_string(),
+            architecture: config.architecture.clone(),
+        };
+        let cache = ArtifactCache::new(&config.cache_dir);
+        let path = cache.prepare(&key, &format!("app_{}", version)).unwrap();
+        std::fs::write(&path, script).unwrap();
+        let sha256 = sha256_hex(script.as_bytes());
+        cache.commit(&key, &path, &sha256).unwrap();
+        stand_in.digests.lock().unwrap().insert(version.to_string(), sha256);
+    }
+
+    async fn handover_config(name: &str, stand_in: &StandIn) -> (Config, PathBuf) {
+        let dir = temp_dir(name);
+        let mut config = Config::new().unwrap();
+        config.cvm_server_url = stand_in.start().await;
+        config.use_update_stream = false;
+        config.handover = true;
+        config.cache_dir = dir.join("cache");
+        config.drain_timeout = Duration::from_secs(5);
+        config.health_check = HealthCheck {
+            probe: Some(HealthProbe::Stdout {
+                pattern: Regex::new("^ready$").unwrap(),
+            }),
+            timeout: Duration::from_secs(1),
+            interval: Duration::from_millis(50),
+            startup_timeout: Duration::from_secs(5),
+            success_threshold: 1,
+            failure_threshold: 3,
+        };
+        (config, dir)
+    }
+
+    fn read_pid(path: &Path) -> Option<Pid> {
+        let pid = std::fs::read_to_string(path).ok()?;
+        pid.trim().parse().ok().map(Pid::from_raw)
+    }
+
+    fn is_running(pid: Pid) -> bool {
+        signal::kill(pid, None).is_ok()
+    }
+
+    async fn wait_until(what: &str, condition: impl Fn() -> bool) {
+        let started = Instant::now();
+        while !condition() {
+            assert!(started.elapsed() < WAIT, "timed out waiting until {}", what);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn it_drains_the_previous_version_once_the_new_version_is_healthy() {
+        let stand_in = StandIn::default();
+        let (config, dir) = handover_config("healthy", &stand_in).await;
+        cache_build(&config, &stand_in, "0.1.0", &app_script(&dir));
+        cache_build(&config, &stand_in, "0.2.0", &app_script(&dir));
+        stand_in.offer("0.1.0");
+        let mut monitor = CvmClientMonitor::new(config, Duration::from_secs(60), None);
+        let control = monitor.control();
+
+        let (result, ()) = tokio::join!(monitor.run_and_remain_alive(), async {
+            wait_until("0.1.0 is ready", || dir.join("ready_0.1.0").exists()).await;
+            let previous = read_pid(&dir.join("ready_0.1.0")).unwrap();
+
+            stand_in.offer("0.2.0");
+            control.request_version_check();
+            wait_until("0.2.0 is ready", || dir.join("ready_0.2.0").exists()).await;
+            // 0.1.0 still ran when 0.2.0 started, and is stopped once 0.2.0 passed its probe.
+            assert_eq!(read_pid(&dir.join("previous_running_0.2.0")), Some(previous));
+            wait_until("0.1.0 is drained", || !is_running(previous)).await;
+            assert!(is_running(read_pid(&dir.join("ready_0.2.0")).unwrap()));
+            wait_until("0.2.0 is reported healthy", || stand_in.reports("/client/success").len() == 2).await;
+
+            control.request_shutdown();
+        });
+        result.unwrap();
+        assert!(!is_running(read_pid(&dir.join("ready_0.2.0")).unwrap()));
+    }
+
+    #[tokio::test]
+    async fn it_keeps_the_previous_version_running_when_the_new_version_fails() {
+        let stand_in = StandIn::default();
+        let (config, dir) = handover_config("failed", &stand_in).await;
+        cache_build(&config, &stand_in, "0.1.0", &app_script(&dir));
+        cache_build(&config, &stand_in, "0.2.0", "#!/bin/sh\necho broken >&2\nexit 1\n");
+        stand_in.offer("0.1.0");
+        let mut monitor = CvmClientMonitor::new(config, Duration::from_secs(60), None);
+        let control = monitor.control();
+
+        let (result, previous) = tokio::join!(monitor.run_and_remain_alive(), async {
+            wait_until("0.1.0 is ready", || dir.join("ready_0.1.0").exists()).await;
+            let previous = read_pid(&dir.join("ready_0.1.0")).unwrap();
+
+            stand_in.offer("0.2.0");
+            control.request_version_check();
+            wait_until("the rollback is reported", || !stand_in.reports("/client/rollback").is_empty()).await;
+            let rollback = &stand_in.reports("/client/rollback")[0];
+            assert_eq!(rollback["failed_version"], "0.2.0");
+            assert_eq!(rollback["current_running_version"], "0.1.0");
+            assert_eq!(stand_in.reports("/client/failure")[0]["stderr_tail"], "broken");
+
+            // The failed version is held back, so checking again keeps 0.1.0 running as it was.
+            control.request_version_check();
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            assert!(is_running(previous));
+            assert_eq!(read_pid(&dir.join("ready_0.1.0")), Some(previous));
+            assert_eq!(stand_in.reports("/client/failure").len(), 1);
+
+            control.request_shutdown();
+            previous
+        });
+        result.unwrap();
+        assert!(!is_running(previous));
+        assert_eq!(stand_in.reports("/client/stopped").len(), 1);
+    }
+}
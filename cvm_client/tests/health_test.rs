@@ -0,0 +1,204 @@
+#[cfg(test)]
+mod health_tests {
+    use cvm::errors::CvmError;
+    use cvm::health::{HealthCheck, HealthProbe, OutputMatch};
+    use cvm::output::{OutputCapture, OutputConfig};
+    use cvm::process::AppProcess;
+    use cvm::signals::MonitorControl;
+    use cvm::sockets::ListenSockets;
+    use cvm::{graceful_shutdown, start_process};
+    use regex::Regex;
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::{Command, Stdio};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn health_check(probe: HealthProbe) -> HealthCheck {
+        HealthCheck {
+            probe: Some(probe),
+            timeout: Duration::from_secs(1),
+            interval: Duration::from_millis(10),
+            startup_timeout: Duration::from_secs(2),
+            success_threshold: 1,
+            failure_threshold: 2,
+        }
+    }
+
+    fn command(program: &str, args: &[&str]) -> HealthProbe {
+        HealthProbe::Command {
+            program: program.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+        }
+    }
+
+    /// Answers every request with the status line.
+    async fn serve_status(status: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = [0u8; 1024];
+                let _ = socket.read(&mut buf).await;
+                let response = format!("HTTP/1.1 {}\r\ncontent-length: 0\r\n\r\n", status);
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        format!("http://{}/health", address)
+    }
+
+    /// Starts a shell script with its stdout watched for the pattern.
+    fn spawn_watched(script: &str, pattern: &str) -> (AppProcess, OutputMatch) {
+        let mut command = Command::new("sh");
+        command.args(["-c", script]).stdout(Stdio::piped());
+        let mut app = AppProcess::spawn(command, None).unwrap();
+        let output = OutputMatch::default();
+        let pattern = Regex::new(pattern).unwrap();
+        let stdout = BufReader::new(app.take_stdout().unwrap());
+        let watcher = output.clone();
+        std::thread::spawn(move || {
+            for line in stdout.lines().map_while(|line| line.ok()) {
+                watcher.check_line(&pattern, &line);
+            }
+        });
+        (app, output)
+    }
+
+    #[test]
+    fn it_parses_probes() {
+        assert!(matches!(
+            HealthProbe::parse("http://127.0.0.1:8080/health", 204),
+            Some(HealthProbe::Http { expected_status: 204, .. })
+        ));
+        assert!(matches!(
+            HealthProbe::parse("tcp:127.0.0.1:8080", 200),
+            Some(HealthProbe::Tcp { address }) if address == "127.0.0.1:8080"
+        ));
+        assert!(matches!(
+            HealthProbe::parse("command:/usr/bin/check --quick", 200),
+            Some(HealthProbe::Command { program, args }) if program == "/usr/bin/check" && args == ["--quick"]
+        ));
+        assert!(matches!(
+            HealthProbe::parse("stdout:listening on .*", 200),
+            Some(HealthProbe::Stdout { .. })
+        ));
+        for invalid in ["ftp://example.com", "tcp:", "command:", "stdout:(", "ready"] {
+            assert!(HealthProbe::parse(invalid, 200).is_none(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn it_formats_probes_the_way_they_are_parsed() {
+        for probe in [
+            "http://127.0.0.1:8080/health",
+            "tcp:127.0.0.1:8080",
+            "command:/usr/bin/check",
+            "command:/usr/bin/check --quick",
+            "stdout:listening on .*",
+        ] {
+            assert_eq!(HealthProbe::parse(probe, 200).unwrap().to_string(), probe);
+        }
+    }
+
+    #[tokio::test]
+    async fn it_probes_http_tcp_and_commands() {
+        let timeout = Duration::from_secs(1);
+        let output = OutputMatch::default();
+        let ok = HealthProbe::Http {
+            url: serve_status("200 OK").await,
+            expected_status: 200,
+        };
+        assert!(ok.check(timeout, &output).await.is_ok());
+        let unavailable = HealthProbe::Http {
+            url: serve_status("503 Service Unavailable").await,
+            expected_status: 200,
+        };
+        assert!(unavailable.check(timeout, &output).await.is_err());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let tcp = HealthProbe::Tcp {
+            address: listener.local_addr().unwrap().to_string(),
+        };
+        assert!(tcp.check(timeout, &output).await.is_ok());
+        drop(listener);
+        assert!(tcp.check(timeout, &output).await.is_err());
+
+        assert!(command("true", &[]).check(timeout, &output).await.is_ok());
+        assert!(command("false", &[]).check(timeout, &output).await.is_err());
+        assert!(command("sleep", &["5"]).check(Duration::from_millis(100), &output).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn it_waits_for_the_startup_probe() {
+        let check = health_check(HealthProbe::Stdout {
+            pattern: Regex::new("ready").unwrap(),
+        });
+        let (mut child, output) = spawn_watched("echo starting; sleep 0.3; echo ready; sleep 30", "ready");
+        assert!(check.wait_until_healthy(&mut child, &output).await.is_ok());
+        assert!(output.matched());
+        child.kill().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_stops_apps_that_never_pass_the_startup_probe() {
+        let check = health_check(HealthProbe::Stdout {
+            pattern: Regex::new("ready").unwrap(),
+        });
+        let (mut child, output) = spawn_watched("echo starting; sleep 30", "ready");
+        match check.wait_until_healthy(&mut child, &output).await {
+            Err(CvmError::HealthCheckFailed { status, .. }) => assert_eq!(status.signal(), Some(9)),
+            other => panic!("expected a failed health check, got {:?}", other.err()),
+        }
+
+        let (mut child, output) = spawn_watched("exit 3", "ready");
+        match check.wait_until_healthy(&mut child, &output).await {
+            Err(CvmError::ProcessExitEarly { status }) => assert_eq!(status.code(), Some(3)),
+            other => panic!("expected an early exit, got {:?}", other.err()),
+        }
+    }
+
+    #[tokio::test]
+    async fn it_reports_apps_that_fail_probes_in_a_row() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let check = health_check(HealthProbe::Tcp {
+            address: listener.local_addr().unwrap().to_string(),
+        });
+        drop(listener);
+        let failure = tokio::time::timeout(Duration::from_secs(5), check.until_unhealthy())
+            .await
+            .expect("the probe failures weren't reported");
+        assert!(failure.contains("Unable to connect"), "{}", failure);
+    }
+
+    #[tokio::test]
+    async fn it_stops_waiting_for_apps_to_become_healthy_on_shutdown() {
+        let mut check = health_check(HealthProbe::Stdout {
+            pattern: Regex::new("ready").unwrap(),
+        });
+        check.startup_timeout = Duration::from_secs(30);
+        let output = OutputCapture::new(
+            &OutputConfig {
+                log_dir: None,
+                max_file_bytes: 1024,
+                keep_files: 0,
+                prefix: false,
+                tail_lines: 0,
+            },
+            "1.2.3",
+        );
+        let control = MonitorControl::default();
+        let mut command = Command::new("sh");
+        command.args(["-c", "exec sleep 30"]);
+        let sockets = ListenSockets::new(vec![]);
+        let starting = start_process(command, &check, None, &sockets, &output, &control);
+        control.request_shutdown();
+        let app = tokio::time::timeout(Duration::from_secs(5), starting)
+            .await
+            .expect("the shutdown didn't end the wait")
+            .unwrap();
+        let outcome = graceful_shutdown(app, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(outcome.status().signal(), Some(15));
+    }
+}
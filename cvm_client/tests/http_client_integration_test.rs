@@ -2,6 +2,8 @@
 mod http_client_integration_tests {
     use cvm::config::Config;
     use cvm::http_client::*;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
 
     fn get_config() -> Config {
         Config::new().expect("Failed to parse config")
@@ -21,6 +23,34 @@ mod http_client_integration_tests {
         assert_eq!(result.version, "0.2.0".to_string());
     }
 
+    #[tokio::test]
+    async fn it_reuses_the_latest_version_when_unchanged() {
+        let mut http_client = create_http_client();
+        // The first check moves the client to the latest version, which changes the ETag.
+        http_client.check_latest().await.expect("Failed to fetch latest version");
+        let second = http_client.check_latest().await.expect("Failed to fetch latest version");
+        let third = http_client.check_latest().await.expect("Failed to fetch latest version");
+        assert_eq!(second.build_id, third.build_id);
+        assert_eq!(third.version, "0.2.0".to_string());
+        assert!(!third.update_required);
+    }
+
+    #[tokio::test]
+    async fn it_streams_the_latest_version() {
+        let mut http_client = create_http_client();
+        let mut stream = http_client
+            .subscribe_latest()
+            .await
+            .expect("Failed to open update stream");
+        let result = stream
+            .next_latest()
+            .await
+            .expect("Failed to read update stream")
+            .expect("Update stream closed before sending the latest version");
+        assert_eq!(result.version, "0.2.0".to_string());
+        assert!(result.update_required);
+    }
+
     #[tokio::test]
     async fn it_reports_success() {
         let mut http_client = create_http_client();
@@ -31,7 +61,32 @@ mod http_client_integration_tests {
     #[tokio::test]
     async fn it_reports_failure() {
         let mut http_client = create_http_client();
-        let result = http_client.report_failure().await;
+        let failure = StartupFailure {
+            exit_code: Some(1),
+            uptime_ms: 1500,
+            stderr_tail: Some("address already in use".to_string()),
+            failed_probe: Some("tcp:127.0.0.1:8080".to_string()),
+            error: "Health check failed: not healthy within 30s".to_string(),
+            host: HostInfo::current(),
+            ..StartupFailure::default()
+        };
+        let result = http_client.report_failure(&failure).await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn it_reports_a_rollback() {
+        let mut http_client = create_http_client();
+        let result = http_client.report_rollback("0.2.0").await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn it_reports_a_crash() {
+        let mut http_client = create_http_client();
+        let result = http_client
+            .report_crash(&ExitStatus::from_raw(1 << 8), 1, true, Some("panicked".to_string()))
+            .await;
         assert!(result.is_ok());
     }
 }
@@ -0,0 +1,62 @@
+#[cfg(test)]
+mod launch_tests {
+    use cvm::launch::{find_group, find_user, LaunchConfig, LaunchVars};
+    use std::collections::BTreeMap;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+
+    const VARS: LaunchVars<'static> = LaunchVars {
+        version: "1.2.3",
+        app_id: "app",
+        client_id: "client",
+    };
+
+    fn write_script(name: &str, script: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cvm_launch_test_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app_1.2.3");
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+        path
+    }
+
+    #[test]
+    fn it_expands_the_launch_vars() {
+        assert_eq!(VARS.expand("/srv/${CVM_APP_ID}/${CVM_VERSION}"), "/srv/app/1.2.3");
+        assert_eq!(VARS.expand("${CVM_CLIENT_ID}-${HOME}"), "client-${HOME}");
+    }
+
+    #[test]
+    fn it_starts_the_app_as_configured() {
+        let path = write_script("configured", "echo \"$@\" $CVM_VERSION $CVM_APP_ID $CVM_CLIENT_ID $GREETING; pwd");
+        let working_dir = path.parent().unwrap().to_path_buf();
+        let launch = LaunchConfig {
+            args: vec!["--port".to_string(), "80${CVM_VERSION}".to_string()],
+            env: BTreeMap::from([("GREETING".to_string(), "hello-${CVM_CLIENT_ID}".to_string())]),
+            working_dir: Some(working_dir.to_string_lossy().to_string()),
+            ..LaunchConfig::default()
+        };
+        let output = launch.command(&path, &VARS).unwrap().output().unwrap();
+        let output = String::from_utf8(output.stdout).unwrap();
+        let expected = format!(
+            "--port 801.2.3 1.2.3 app client hello-client\n{}\n",
+            working_dir.canonicalize().unwrap().display()
+        );
+        assert_eq!(output, expected);
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o755);
+    }
+
+    #[test]
+    fn it_finds_users_and_groups() {
+        let (uid, gid) = find_user("0").unwrap();
+        assert_eq!(uid.as_raw(), 0);
+        assert_eq!(gid.map(|gid| gid.as_raw()), Some(0));
+        assert_eq!(find_user("root").map(|(uid, _)| uid.as_raw()), Some(0));
+        assert!(find_user("cvm-no-such-user").is_none());
+
+        assert_eq!(find_group("0").map(|gid| gid.as_raw()), Some(0));
+        assert_eq!(find_group("root").map(|gid| gid.as_raw()), Some(0));
+        assert!(find_group("cvm-no-such-group").is_none());
+    }
+}
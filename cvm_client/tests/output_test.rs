@@ -0,0 +1,101 @@
+#[cfg(test)]
+mod output_tests {
+    use cvm::health::OutputMatch;
+    use cvm::output::{OutputCapture, OutputConfig, RotatingLog};
+    use cvm::process::AppProcess;
+    use regex::Regex;
+    use std::path::PathBuf;
+    use std::process::{Command, Stdio};
+    use std::time::Duration;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cvm_output_test_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn output_config(log_dir: Option<PathBuf>) -> OutputConfig {
+        OutputConfig {
+            log_dir,
+            max_file_bytes: 1024,
+            keep_files: 2,
+            prefix: true,
+            tail_lines: 2,
+        }
+    }
+
+    /// Runs the script with its output captured and waits until it exited and its output was read.
+    async fn run_captured(capture: &OutputCapture, script: &str, stdout_probe: Option<&Regex>) -> OutputMatch {
+        let mut command = Command::new("sh");
+        command.args(["-c", script]).stdout(Stdio::piped()).stderr(Stdio::piped());
+        let mut app = AppProcess::spawn(command, None).unwrap();
+        let stdout_match = OutputMatch::default();
+        capture.capture(&mut app, stdout_probe, &stdout_match);
+        app.wait_for_exit().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        stdout_match
+    }
+
+    #[test]
+    fn it_rotates_logs_by_size() {
+        let dir = temp_dir("rotate");
+        let path = dir.join("stdout.log");
+        let mut log = RotatingLog::open(&path, 10, 2).unwrap();
+        for line in ["first", "second", "third", "fourth"] {
+            log.write_line(line).unwrap();
+        }
+        let read = |path: PathBuf| std::fs::read_to_string(path).unwrap();
+        assert_eq!(read(path.clone()), "fourth\n");
+        assert_eq!(read(dir.join("stdout.log.1")), "third\n");
+        assert_eq!(read(dir.join("stdout.log.2")), "second\n");
+        assert!(!dir.join("stdout.log.3").exists());
+
+        // Reopening appends to the current file.
+        let mut log = RotatingLog::open(&path, 100, 2).unwrap();
+        log.write_line("fifth").unwrap();
+        assert_eq!(read(path), "fourth\nfifth\n");
+    }
+
+    #[tokio::test]
+    async fn it_captures_output_to_per_version_log_files() {
+        let dir = temp_dir("capture");
+        let capture = OutputCapture::new(&output_config(Some(dir.clone())), "1.2.3");
+        let ready = Regex::new("^ready$").unwrap();
+        let stdout_match = run_captured(
+            &capture,
+            "echo starting; echo ready; echo one >&2; echo two >&2; echo three >&2",
+            Some(&ready),
+        )
+        .await;
+        assert!(stdout_match.matched());
+
+        let stdout = std::fs::read_to_string(dir.join("1.2.3").join("stdout.log")).unwrap();
+        let lines: Vec<&str> = stdout.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("[1.2.3 ") && lines[0].ends_with("] starting"), "{}", lines[0]);
+        let stderr = std::fs::read_to_string(dir.join("1.2.3").join("stderr.log")).unwrap();
+        assert_eq!(stderr.lines().count(), 3);
+        assert_eq!(capture.stderr_tail().lines(), ["two", "three"]);
+        assert_eq!(capture.stderr_tail().text().as_deref(), Some("two\nthree"));
+    }
+
+    #[tokio::test]
+    async fn it_waits_for_the_last_lines_of_stderr() {
+        let capture = OutputCapture::new(&output_config(None), "1.2.3");
+        let tail = capture.stderr_tail();
+        assert!(tail.wait_for_eof(Duration::ZERO).await);
+
+        // The helper keeps stderr open after the app exited.
+        let mut command = Command::new("sh");
+        command
+            .args(["-c", "echo crashed >&2; sleep 1 &"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let mut app = AppProcess::spawn(command, None).unwrap();
+        capture.capture(&mut app, None, &OutputMatch::default());
+        app.wait_for_exit().await.unwrap();
+        assert!(!tail.wait_for_eof(Duration::from_millis(100)).await);
+        assert!(tail.wait_for_eof(Duration::from_secs(5)).await);
+        assert_eq!(tail.text().as_deref(), Some("crashed"));
+    }
+}
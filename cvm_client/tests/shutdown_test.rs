@@ -0,0 +1,100 @@
+#[cfg(test)]
+mod shutdown_tests {
+    use cvm::process::{adopt_orphans, AppProcess};
+    use cvm::{graceful_shutdown, ShutdownOutcome};
+    use nix::sys::signal;
+    use nix::unistd::Pid;
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::{Command, Stdio};
+    use std::time::{Duration, Instant};
+
+    fn spawn(script: &str) -> AppProcess {
+        let mut command = Command::new("sh");
+        command.args(["-c", script]);
+        AppProcess::spawn(command, None).unwrap()
+    }
+
+    /// Starts a script that prints the pid of a helper it started in the background.
+    fn spawn_with_helper(script: &str) -> (AppProcess, Pid) {
+        let mut command = Command::new("sh");
+        command.args(["-c", script]).stdout(Stdio::piped());
+        let mut app = AppProcess::spawn(command, None).unwrap();
+        let mut line = String::new();
+        BufReader::new(app.take_stdout().unwrap()).read_line(&mut line).unwrap();
+        (app, Pid::from_raw(line.trim().parse().unwrap()))
+    }
+
+    fn is_running(pid: Pid) -> bool {
+        signal::kill(pid, None).is_ok()
+    }
+
+    #[tokio::test]
+    async fn it_stops_apps_with_sigterm() {
+        let child = spawn("exec sleep 30");
+        let outcome = graceful_shutdown(child, Duration::from_secs(5)).await.unwrap();
+        assert!(matches!(outcome, ShutdownOutcome::Exited(_)));
+        assert_eq!(outcome.status().signal(), Some(15));
+    }
+
+    #[tokio::test]
+    async fn it_returns_the_exit_code_of_apps_draining_on_sigterm() {
+        let child = spawn("trap 'exit 3' TERM; while true; do sleep 0.1; done");
+        // Give the shell time to install the trap.
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let outcome = graceful_shutdown(child, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(outcome, ShutdownOutcome::Exited(std::process::ExitStatus::from_raw(3 << 8)));
+        assert_eq!(outcome.status().code(), Some(3));
+    }
+
+    #[tokio::test]
+    async fn it_kills_apps_ignoring_sigterm_after_the_drain_timeout() {
+        // Ignored signals stay ignored across exec.
+        let child = spawn("trap '' TERM; exec sleep 30");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let started = Instant::now();
+        let outcome = graceful_shutdown(child, Duration::from_millis(500)).await.unwrap();
+        assert!(matches!(outcome, ShutdownOutcome::Killed(_)));
+        assert_eq!(outcome.status().signal(), Some(9));
+        assert!(started.elapsed() >= Duration::from_millis(500));
+    }
+
+    #[tokio::test]
+    async fn it_reaps_apps_that_already_exited() {
+        let child = spawn("exit 0");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let outcome = graceful_shutdown(child, Duration::from_secs(5)).await.unwrap();
+        assert!(matches!(outcome, ShutdownOutcome::Exited(status) if status.success()));
+    }
+
+    #[tokio::test]
+    async fn it_waits_for_the_helpers_of_apps_to_exit() {
+        adopt_orphans();
+        let (app, helper) = spawn_with_helper("sleep 30 & echo $!; wait");
+        assert!(is_running(helper));
+        let outcome = graceful_shutdown(app, Duration::from_secs(5)).await.unwrap();
+        assert!(matches!(outcome, ShutdownOutcome::Exited(_)));
+        assert!(!is_running(helper));
+    }
+
+    #[tokio::test]
+    async fn it_kills_apps_and_their_helpers() {
+        adopt_orphans();
+        let (mut app, helper) = spawn_with_helper("(trap '' TERM; exec sleep 30) & echo $!; wait");
+        let status = app.kill().await.unwrap();
+        assert_eq!(status.signal(), Some(9));
+        assert!(!is_running(helper));
+    }
+
+    #[tokio::test]
+    async fn it_kills_helpers_ignoring_sigterm_after_the_drain_timeout() {
+        adopt_orphans();
+        // The app exits on Sigterm, but leaves a helper behind that ignores it.
+        let (app, helper) = spawn_with_helper("(trap '' TERM; exec sleep 30) & echo $!; wait");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let outcome = graceful_shutdown(app, Duration::from_millis(500)).await.unwrap();
+        assert!(matches!(outcome, ShutdownOutcome::Killed(_)));
+        assert_eq!(outcome.status().signal(), Some(15));
+        assert!(!is_running(helper));
+    }
+}
@@ -0,0 +1,54 @@
+#[cfg(test)]
+mod signals_tests {
+    use cvm::signals::MonitorControl;
+    use nix::sys::signal::{raise, Signal};
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    #[tokio::test]
+    async fn it_completes_shutdown_waits_requested_before_and_after() {
+        let control = MonitorControl::default();
+        let waiting = tokio::spawn({
+            let control = control.clone();
+            async move { control.until_shutdown().await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!control.shutdown_requested());
+        control.request_shutdown();
+        timeout(WAIT, waiting).await.expect("the shutdown wasn't seen").unwrap();
+
+        assert!(control.shutdown_requested());
+        timeout(WAIT, control.until_shutdown()).await.expect("the shutdown was forgotten");
+    }
+
+    #[tokio::test]
+    async fn it_keeps_version_check_requests_until_awaited() {
+        let control = MonitorControl::default();
+        control.request_version_check();
+        timeout(WAIT, control.version_check_requested())
+            .await
+            .expect("the version check request was lost");
+        assert!(timeout(Duration::from_millis(50), control.version_check_requested())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn it_turns_signals_into_requests() {
+        let control = MonitorControl::default();
+        control.listen_for_signals().unwrap();
+
+        raise(Signal::SIGHUP).unwrap();
+        timeout(WAIT, control.version_check_requested())
+            .await
+            .expect("SIGHUP didn't request a version check");
+        assert!(!control.shutdown_requested());
+
+        raise(Signal::SIGTERM).unwrap();
+        timeout(WAIT, control.until_shutdown())
+            .await
+            .expect("SIGTERM didn't request a shutdown");
+    }
+}
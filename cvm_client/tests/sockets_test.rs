@@ -0,0 +1,48 @@
+#[cfg(test)]
+mod sockets_tests {
+    use cvm::sockets::ListenSockets;
+    use std::net::TcpStream;
+    use std::process::{Command, Stdio};
+
+    fn bound_sockets(count: usize) -> ListenSockets {
+        let mut sockets = ListenSockets::new(vec!["127.0.0.1:0".parse().unwrap(); count]);
+        sockets.bind().unwrap();
+        sockets
+    }
+
+    fn run(sockets: &ListenSockets, script: &str) -> String {
+        let mut command = Command::new("sh");
+        command.args(["-c", script]).env("APP_ENV", "test").stdout(Stdio::piped());
+        sockets.pass_to(&mut command).unwrap();
+        let output = command.output().unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    }
+
+    #[test]
+    fn it_passes_sockets_the_systemd_way() {
+        let sockets = bound_sockets(2);
+        let output = run(
+            &sockets,
+            "echo $LISTEN_FDS $APP_ENV; [ \"$LISTEN_PID\" = $$ ] && [ -S /dev/fd/3 ] && [ -S /dev/fd/4 ] && echo inherited",
+        );
+        assert_eq!(output, "2 test\ninherited");
+    }
+
+    #[test]
+    fn it_keeps_the_sockets_open_across_apps() {
+        let sockets = bound_sockets(1);
+        assert_eq!(run(&sockets, "echo $LISTEN_FDS"), "1");
+        assert_eq!(run(&sockets, "echo $LISTEN_FDS"), "1");
+        // Connections are queued on the socket until the next version accepts them.
+        let address = sockets.local_addresses()[0];
+        assert!(TcpStream::connect(address).is_ok());
+    }
+
+    #[test]
+    fn it_passes_nothing_before_binding() {
+        let sockets = ListenSockets::new(vec!["127.0.0.1:0".parse().unwrap()]);
+        assert_eq!(run(&sockets, "echo ${LISTEN_FDS:-none}"), "none");
+        assert!(sockets.local_addresses().is_empty());
+    }
+}
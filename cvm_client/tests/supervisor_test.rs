@@ -0,0 +1,84 @@
+#[cfg(test)]
+mod supervisor_tests {
+    use cvm::supervisor::{CrashTracker, RestartDecision, RestartMode, RestartPolicy};
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+    use std::time::{Duration, Instant};
+
+    fn policy(mode: RestartMode) -> RestartPolicy {
+        RestartPolicy {
+            mode,
+            max_restarts: 3,
+            window: Duration::from_secs(60),
+            backoff_base: Duration::from_secs(1),
+            backoff_max: Duration::from_secs(4),
+        }
+    }
+
+    fn exited_with(code: i32) -> ExitStatus {
+        ExitStatus::from_raw(code << 8)
+    }
+
+    fn restart_delay(decision: RestartDecision) -> Duration {
+        match decision {
+            RestartDecision::RestartAfter(delay) => delay,
+            RestartDecision::Stop => panic!("expected a restart"),
+        }
+    }
+
+    #[test]
+    fn it_parses_restart_modes() {
+        assert_eq!(RestartMode::parse("always"), Some(RestartMode::Always));
+        assert_eq!(RestartMode::parse(" On-Failure "), Some(RestartMode::OnFailure));
+        assert_eq!(RestartMode::parse("never"), Some(RestartMode::Never));
+        assert_eq!(RestartMode::parse("sometimes"), None);
+    }
+
+    #[test]
+    fn it_restarts_the_exits_the_mode_covers() {
+        let now = Instant::now();
+        let mut on_failure = CrashTracker::new(policy(RestartMode::OnFailure));
+        assert!(matches!(on_failure.on_exit(exited_with(1), now), RestartDecision::RestartAfter(_)));
+        assert_eq!(on_failure.on_exit(exited_with(0), now), RestartDecision::Stop);
+        let killed = ExitStatus::from_raw(9);
+        assert!(matches!(on_failure.on_exit(killed, now), RestartDecision::RestartAfter(_)));
+
+        let mut always = CrashTracker::new(policy(RestartMode::Always));
+        assert!(matches!(always.on_exit(exited_with(0), now), RestartDecision::RestartAfter(_)));
+
+        let mut never = CrashTracker::new(policy(RestartMode::Never));
+        assert_eq!(never.on_exit(exited_with(1), now), RestartDecision::Stop);
+        assert_eq!(never.crash_count(), 1);
+    }
+
+    #[test]
+    fn it_stops_after_too_many_exits_within_the_window() {
+        let start = Instant::now();
+        let mut tracker = CrashTracker::new(policy(RestartMode::OnFailure));
+        for second in 0..3 {
+            let decision = tracker.on_exit(exited_with(1), start + Duration::from_secs(second));
+            assert!(matches!(decision, RestartDecision::RestartAfter(_)));
+        }
+        assert_eq!(tracker.on_exit(exited_with(1), start + Duration::from_secs(3)), RestartDecision::Stop);
+        assert_eq!(tracker.crash_count(), 4);
+
+        // Exits older than the window no longer count.
+        let later = start + Duration::from_secs(62);
+        assert!(matches!(tracker.on_exit(exited_with(1), later), RestartDecision::RestartAfter(_)));
+        assert_eq!(tracker.crash_count(), 2);
+    }
+
+    #[test]
+    fn it_backs_off_exponentially_with_jitter() {
+        let now = Instant::now();
+        let mut tracker = CrashTracker::new(RestartPolicy {
+            max_restarts: 10,
+            ..policy(RestartMode::Always)
+        });
+        for expected in [1, 2, 4, 4] {
+            let delay = restart_delay(tracker.on_exit(exited_with(1), now));
+            let expected = Duration::from_secs(expected);
+            assert!(delay >= expected / 2 && delay <= expected, "{:?} for {:?}", delay, expected);
+        }
+    }
+}
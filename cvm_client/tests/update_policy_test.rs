@@ -0,0 +1,92 @@
+#[cfg(test)]
+mod update_policy_tests {
+    use chrono::{DateTime, NaiveTime, TimeZone, Utc};
+    use cvm::http_client::LatestVersionResponse;
+    use cvm::update_policy::{MaintenanceWindow, Rollback, UpdateDecision, UpdatePolicy};
+
+    fn latest(update_required: bool, update_mandatory: bool) -> LatestVersionResponse {
+        LatestVersionResponse {
+            build_id: "76055cdf-202e-4d23-906c-b93f003bef27".to_string(),
+            version: "0.2.0".to_string(),
+            url: "https://example.com/infinite_hello_0.2.0".to_string(),
+            update_required,
+            update_mandatory,
+            min_supported_version: None,
+            release_notes: None,
+            released_at: None,
+            git_commit: None,
+            metadata: serde_json::json!({}),
+            sha256: None,
+            patches: vec![],
+        }
+    }
+
+    fn at(hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, 1, hour, minute, 0).unwrap()
+    }
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn it_parses_maintenance_windows() {
+        let window = MaintenanceWindow::parse("02:00-04:30").unwrap();
+        assert_eq!(window.start, time(2, 0));
+        assert_eq!(window.end, time(4, 30));
+        assert!(MaintenanceWindow::parse("02:00").is_none());
+        assert!(MaintenanceWindow::parse("25:00-04:00").is_none());
+        assert!(MaintenanceWindow::parse("02:00-02:00").is_none());
+    }
+
+    #[test]
+    fn it_handles_windows_spanning_midnight() {
+        let window = MaintenanceWindow::parse("23:00-01:00").unwrap();
+        assert!(window.contains(time(23, 30)));
+        assert!(window.contains(time(0, 30)));
+        assert!(!window.contains(time(1, 0)));
+        assert!(!window.contains(time(12, 0)));
+        assert_eq!(window.next_opening(at(12, 0)), at(23, 0));
+    }
+
+    #[test]
+    fn it_applies_updates_immediately_without_a_window() {
+        let policy = UpdatePolicy::default();
+        assert_eq!(policy.decide(&latest(true, false), at(12, 0)), UpdateDecision::ApplyNow);
+        assert_eq!(policy.decide(&latest(false, false), at(12, 0)), UpdateDecision::UpToDate);
+    }
+
+    #[test]
+    fn it_defers_optional_updates_to_the_maintenance_window() {
+        let policy = UpdatePolicy {
+            maintenance_window: MaintenanceWindow::parse("02:00-04:00"),
+        };
+        assert_eq!(
+            policy.decide(&latest(true, false), at(12, 0)),
+            UpdateDecision::DeferUntil(at(2, 0) + chrono::Days::new(1))
+        );
+        assert_eq!(policy.decide(&latest(true, false), at(3, 0)), UpdateDecision::ApplyNow);
+    }
+
+    #[test]
+    fn it_applies_mandatory_updates_outside_the_maintenance_window() {
+        let policy = UpdatePolicy {
+            maintenance_window: MaintenanceWindow::parse("02:00-04:00"),
+        };
+        assert_eq!(policy.decide(&latest(true, true), at(12, 0)), UpdateDecision::ApplyNow);
+    }
+
+    #[test]
+    fn it_holds_back_the_version_that_was_rolled_back_from() {
+        let rollback = Rollback {
+            failed_version: "0.2.0".to_string(),
+            running_version: "0.1.0".to_string(),
+        };
+        assert!(rollback.holds_back(&latest(true, true)));
+        let newer = LatestVersionResponse {
+            version: "0.2.1".to_string(),
+            ..latest(true, false)
+        };
+        assert!(!rollback.holds_back(&newer));
+    }
+}
@@ -5,6 +5,7 @@ CREATE TABLE IF NOT EXISTS applications
     id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
     name VARCHAR(255) NOT NULL,
     description VARCHAR(255) NOT NULL,
+    min_supported_version VARCHAR(255),
     created_at TIMESTAMP with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL
 );
 
@@ -16,7 +17,11 @@ CREATE TABLE IF NOT EXISTS clients
     updated_at         TIMESTAMP with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL,
     build_version      Varchar(255) NOT NULL,
     version            VARCHAR(255) NOT NULL,
-    enabled            BOOLEAN DEFAULT TRUE
+    enabled            BOOLEAN DEFAULT TRUE,
+    -- Labels sent by the client with its latest version checks, and labels set by admins, which
+    -- take precedence.
+    reported_labels    JSONB DEFAULT '{}' NOT NULL,
+    labels             JSONB DEFAULT '{}' NOT NULL
 );
 
 CREATE TABLE IF NOT EXISTS application_versions
@@ -24,7 +29,17 @@ CREATE TABLE IF NOT EXISTS application_versions
     id            UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
     app_id        UUID REFERENCES applications (id) NOT NULL,
     version       VARCHAR(255) NOT NULL,
-    latest        BOOLEAN DEFAULT FALSE
+    latest        BOOLEAN DEFAULT FALSE,
+    mandatory     BOOLEAN DEFAULT FALSE NOT NULL,
+    release_notes TEXT,
+    released_at   TIMESTAMP with time zone,
+    git_commit    VARCHAR(64),
+    metadata      JSONB DEFAULT '{}' NOT NULL,
+    -- Percentage of clients that are served this version instead of the latest version while it
+    -- is being rolled out.
+    rollout_percentage INTEGER CHECK (rollout_percentage BETWEEN 0 AND 100),
+    -- Label selector of the clients this version is served to instead of the latest version.
+    selector           TEXT
 );
 
 CREATE TABLE IF NOT EXISTS application_builds
@@ -35,5 +50,202 @@ CREATE TABLE IF NOT EXISTS application_builds
     success_count INTEGER          DEFAULT 0,
     failed_count  INTEGER          DEFAULT 0,
     url           VARCHAR(255) NOT NULL,
-    disabled      BOOLEAN          DEFAULT FALSE
-);
\ No newline at end of file
+    disabled      BOOLEAN          DEFAULT FALSE,
+    -- Hex sha256 digest of the artifact, which clients verify downloads and patches against.
+    sha256        VARCHAR(64)
+);
+CREATE TABLE IF NOT EXISTS audit_log
+(
+    id          UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    actor       VARCHAR(255) NOT NULL,
+    action      VARCHAR(255) NOT NULL,
+    target_type VARCHAR(255) NOT NULL,
+    target_id   UUID NOT NULL,
+    app_id      UUID,
+    before      JSONB,
+    after       JSONB,
+    created_at  TIMESTAMP with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_created_at_idx ON audit_log (created_at);
+
+CREATE TABLE IF NOT EXISTS webhook_subscriptions
+(
+    id          UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    url         VARCHAR(2048) NOT NULL,
+    secret      VARCHAR(255) NOT NULL,
+    event_types TEXT[] DEFAULT '{}' NOT NULL,
+    app_id      UUID REFERENCES applications (id),
+    enabled     BOOLEAN DEFAULT TRUE NOT NULL,
+    created_at  TIMESTAMP with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS webhook_deliveries
+(
+    id              UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    subscription_id UUID REFERENCES webhook_subscriptions (id) ON DELETE CASCADE NOT NULL,
+    event_id        UUID NOT NULL,
+    event_type      VARCHAR(255) NOT NULL,
+    payload         JSONB NOT NULL,
+    status          VARCHAR(32) DEFAULT 'pending' NOT NULL,
+    attempts        INTEGER DEFAULT 0 NOT NULL,
+    response_status INTEGER,
+    last_error      TEXT,
+    created_at      TIMESTAMP with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    updated_at      TIMESTAMP with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+-- Rust target triples that builds can be published for. Clients may report an alias instead of
+-- the triple, and are served a fallback target's build (lowest priority first) when there is no
+-- build for their own target.
+CREATE TABLE IF NOT EXISTS targets
+(
+    id          UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    triple      VARCHAR(255) UNIQUE NOT NULL,
+    description VARCHAR(255) DEFAULT '' NOT NULL,
+    created_at  TIMESTAMP with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS target_aliases
+(
+    alias  VARCHAR(255) PRIMARY KEY,
+    triple VARCHAR(255) REFERENCES targets (triple) ON DELETE CASCADE NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS target_fallbacks
+(
+    triple   VARCHAR(255) REFERENCES targets (triple) ON DELETE CASCADE NOT NULL,
+    fallback VARCHAR(255) REFERENCES targets (triple) ON DELETE CASCADE NOT NULL,
+    priority INTEGER DEFAULT 0 NOT NULL,
+    PRIMARY KEY (triple, fallback),
+    CHECK (triple <> fallback)
+);
+
+INSERT INTO targets (triple, description)
+VALUES ('x86_64-unknown-linux-gnu', '64-bit Linux (glibc)'),
+       ('x86_64-unknown-linux-musl', '64-bit Linux (musl, statically linked)'),
+       ('aarch64-unknown-linux-gnu', 'ARM64 Linux (glibc)'),
+       ('aarch64-unknown-linux-musl', 'ARM64 Linux (musl, statically linked)'),
+       ('x86_64-pc-windows-gnu', '64-bit Windows (MinGW)'),
+       ('x86_64-pc-windows-msvc', '64-bit Windows (MSVC)'),
+       ('aarch64-pc-windows-msvc', 'ARM64 Windows (MSVC)'),
+       ('x86_64-apple-darwin', '64-bit macOS'),
+       ('aarch64-apple-darwin', 'ARM64 macOS')
+ON CONFLICT (triple) DO NOTHING;
+
+INSERT INTO target_aliases (alias, triple)
+VALUES ('x86_64-linux-gnu', 'x86_64-unknown-linux-gnu'),
+       ('aarch64-linux-gnu', 'aarch64-unknown-linux-gnu'),
+       ('arm64-apple-darwin', 'aarch64-apple-darwin')
+ON CONFLICT (alias) DO NOTHING;
+
+-- Statically linked musl binaries run on glibc systems, MinGW and MSVC binaries run on any
+-- Windows, and x86_64 binaries run emulated on ARM64 macOS and Windows.
+INSERT INTO target_fallbacks (triple, fallback, priority)
+VALUES ('x86_64-unknown-linux-gnu', 'x86_64-unknown-linux-musl', 0),
+       ('aarch64-unknown-linux-gnu', 'aarch64-unknown-linux-musl', 0),
+       ('x86_64-pc-windows-msvc', 'x86_64-pc-windows-gnu', 0),
+       ('x86_64-pc-windows-gnu', 'x86_64-pc-windows-msvc', 0),
+       ('aarch64-pc-windows-msvc', 'x86_64-pc-windows-msvc', 0),
+       ('aarch64-pc-windows-msvc', 'x86_64-pc-windows-gnu', 1),
+       ('aarch64-apple-darwin', 'x86_64-apple-darwin', 0)
+ON CONFLICT (triple, fallback) DO NOTHING;
+
+CREATE TABLE IF NOT EXISTS release_schedules
+(
+    id             UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    app_id         UUID REFERENCES applications (id) NOT NULL,
+    app_version_id UUID REFERENCES application_versions (id) NOT NULL,
+    status         VARCHAR(32) DEFAULT 'pending' NOT NULL,
+    last_error     TEXT,
+    created_by     VARCHAR(255) NOT NULL,
+    created_at     TIMESTAMP with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    updated_at     TIMESTAMP with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS release_schedule_steps
+(
+    id                 UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    schedule_id        UUID REFERENCES release_schedules (id) ON DELETE CASCADE NOT NULL,
+    rollout_percentage INTEGER NOT NULL CHECK (rollout_percentage BETWEEN 1 AND 100),
+    run_at             TIMESTAMP with time zone NOT NULL,
+    executed_at        TIMESTAMP with time zone
+);
+
+CREATE INDEX IF NOT EXISTS release_schedule_steps_run_at_idx
+    ON release_schedule_steps (run_at) WHERE executed_at IS NULL;
+
+-- Artifacts stored by the server, see `ArtifactStore`.
+CREATE TABLE IF NOT EXISTS build_artifacts
+(
+    build_id   UUID PRIMARY KEY REFERENCES application_builds (id) ON DELETE CASCADE,
+    file_name  VARCHAR(255) NOT NULL,
+    size       BIGINT NOT NULL,
+    created_at TIMESTAMP with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+-- Binary patches from a build to the build of the next version for the same target.
+CREATE TABLE IF NOT EXISTS build_patches
+(
+    from_build_id UUID REFERENCES application_builds (id) ON DELETE CASCADE NOT NULL,
+    to_build_id   UUID REFERENCES application_builds (id) ON DELETE CASCADE NOT NULL,
+    sha256        VARCHAR(64) NOT NULL,
+    size          BIGINT NOT NULL,
+    created_at    TIMESTAMP with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    PRIMARY KEY (from_build_id, to_build_id)
+);
+
+-- Clients that relaunched their last known good version after a new version failed to start.
+CREATE TABLE IF NOT EXISTS client_rollbacks
+(
+    id             UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    client_id      UUID REFERENCES clients (id) ON DELETE CASCADE NOT NULL,
+    app_id         UUID REFERENCES applications (id) NOT NULL,
+    architecture   VARCHAR(255) NOT NULL,
+    failed_version VARCHAR(255) NOT NULL,
+    version        VARCHAR(255) NOT NULL,
+    created_at     TIMESTAMP with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+-- Exits of the app a client was running, reported whether or not the client restarted it.
+CREATE TABLE IF NOT EXISTS client_crashes
+(
+    id           UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    client_id    UUID REFERENCES clients (id) ON DELETE CASCADE NOT NULL,
+    app_id       UUID REFERENCES applications (id) NOT NULL,
+    architecture VARCHAR(255) NOT NULL,
+    version      VARCHAR(255) NOT NULL,
+    exit_code    INTEGER,
+    signal       INTEGER,
+    -- Exits within the client's restart window, including this one.
+    crash_count  INTEGER NOT NULL,
+    restarting   BOOLEAN NOT NULL,
+    -- The last lines the app wrote to stderr.
+    stderr_tail  TEXT,
+    created_at   TIMESTAMP with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+-- Why a build failed to start on a client, reported along with the failure. Kept apart from
+-- client_crashes: a failed startup belongs to a build, counts towards auto disabling it and is
+-- deleted with it, while a crash is an exit of a build that had started and passed its health check.
+CREATE TABLE IF NOT EXISTS build_failure_reports
+(
+    id           UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    build_id     UUID REFERENCES application_builds (id) ON DELETE CASCADE NOT NULL,
+    client_id    UUID REFERENCES clients (id) ON DELETE CASCADE NOT NULL,
+    app_id       UUID REFERENCES applications (id) NOT NULL,
+    architecture VARCHAR(255) NOT NULL,
+    version      VARCHAR(255) NOT NULL,
+    exit_code    INTEGER,
+    signal       INTEGER,
+    -- Milliseconds from starting the build until it failed.
+    uptime_ms    BIGINT,
+    -- The last lines the app wrote to stderr.
+    stderr_tail  TEXT,
+    -- The health probe the build didn't pass.
+    failed_probe TEXT,
+    error        TEXT,
+    -- Free-form json object describing the client's host, e.g. its hostname and kernel.
+    host         JSONB,
+    created_at   TIMESTAMP with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
@@ -1,12 +1,15 @@
-use sqlx::{Acquire, PgPool, Postgres};
+use sqlx::{Acquire, PgConnection, PgPool, Postgres, Transaction, TransactionManager};
 use uuid::{Uuid};
 use chrono::prelude::*;
+use std::ops::{Deref, DerefMut};
+use serde::Serialize;
 use sqlx::pool::PoolConnection;
-use sqlx::postgres::any::AnyConnectionBackend;
-use sqlx::postgres::PgPoolOptions;
+use sqlx::postgres::{PgPoolOptions, PgTransactionManager};
+use sqlx::types::Json;
 use crate::app_store::AppStoreError::{BuildCreationError, RecordCreationError, RowNotFound, TransactionFailure, VersionCreationError, ConnectionError};
-use crate::config::{Config, CONFIG};
-use crate::db_commands::{DELETE_APPLICATION, DELETE_CLIENT_BY_ID, INSERT_APPLICATION_BUILD, INSERT_APPLICATION_VERSION, INSERT_CLIENT, INSERT_INTO_APPLICATION, QUERY_ADVISORY_LOCK, QUERY_APPLICATION_BUILD_VERSION, QUERY_APPLICATION_BY_ID, QUERY_APPLICATION_VERSION, QUERY_CLIENT, QUERY_LATEST_BUILD_VERSION, UPDATE_APPLICATION_BUILD_FAILURE, UPDATE_APPLICATION_BUILD_SUCCESS, UPDATE_CLIENT};
+use crate::config::Config;
+use crate::selector::{Labels, Selector};
+use crate::db_commands::{CLEAR_LATEST_APPLICATION_VERSION, DELETE_APPLICATION, DELETE_CLIENT_BY_ID, INSERT_APPLICATION_BUILD, INSERT_APPLICATION_VERSION, INSERT_AUDIT_ENTRY, INSERT_CLIENT, INSERT_INTO_APPLICATION, QUERY_ADVISORY_LOCK, QUERY_APPLICATION_BUILD_BY_ID, QUERY_APPLICATION_BUILD_VERSION, QUERY_APPLICATION_BUILDS_BY_VERSION, QUERY_APPLICATION_BY_ID, QUERY_APPLICATION_VERSION, QUERY_APPLICATION_VERSION_BY_NUMBER, QUERY_AUDIT_LOG, QUERY_CLIENT, QUERY_LATEST_APPLICATION_VERSION, QUERY_LATEST_BUILD_VERSION, QUERY_LATEST_RESOLVED_BUILD, QUERY_WEBHOOK_DELIVERIES, QUERY_PENDING_WEBHOOK_DELIVERIES, QUERY_WEBHOOK_SUBSCRIPTION_BY_ID, QUERY_WEBHOOK_SUBSCRIPTIONS, QUERY_WEBHOOK_SUBSCRIPTIONS_FOR_EVENT, SET_LATEST_APPLICATION_VERSION, UPDATE_APPLICATION_BUILD_DISABLED, UPDATE_APPLICATION_BUILD_FAILURE, UPDATE_APPLICATION_BUILD_SUCCESS, UPDATE_APPLICATION_VERSION_BUILDS_DISABLED, UPDATE_CLIENT, UPDATE_CLIENT_ENABLED, UPDATE_WEBHOOK_DELIVERY, UPDATE_WEBHOOK_SUBSCRIPTION_ENABLED, INSERT_WEBHOOK_DELIVERY, INSERT_WEBHOOK_SUBSCRIPTION, NOTIFY_RELEASE_CHANGED, QUERY_TARGET, QUERY_TARGETS, INSERT_TARGET, UPSERT_TARGET_ALIAS, UPSERT_TARGET_FALLBACK, UPDATE_APPLICATION_MIN_SUPPORTED_VERSION, UPDATE_APPLICATION_VERSION_MANDATORY, UPDATE_APPLICATION_VERSION_METADATA, CLEAR_APPLICATION_VERSION_ROLLOUTS, CLEAR_APPLICATION_VERSION_TARGETING, UPDATE_APPLICATION_VERSION_ROLLOUT, INSERT_RELEASE_SCHEDULE, INSERT_RELEASE_SCHEDULE_STEP, QUERY_RELEASE_SCHEDULE, QUERY_RELEASE_SCHEDULES, QUERY_ACTIVE_RELEASE_SCHEDULE_COUNT, QUERY_RELEASE_SCHEDULE_STEPS, QUERY_NEXT_DUE_RELEASE_STEP, UPDATE_RELEASE_SCHEDULE_STEP_EXECUTED, UPDATE_RELEASE_SCHEDULE_STATUS, QUERY_CLIENTS_BY_APP, UPDATE_CLIENT_LABELS, UPDATE_APPLICATION_VERSION_SELECTOR, QUERY_TARGETED_APPLICATION_VERSIONS, UPDATE_APPLICATION_BUILD_SHA256, UPSERT_BUILD_ARTIFACT, QUERY_BUILD_ARTIFACT, QUERY_STORED_SIBLING_BUILDS, UPSERT_BUILD_PATCH, QUERY_BUILD_PATCHES_TO, QUERY_BUILD_PATCH, INSERT_CLIENT_ROLLBACK, QUERY_CLIENT_ROLLBACKS, INSERT_CLIENT_CRASH, QUERY_CLIENT_CRASHES, INSERT_BUILD_FAILURE_REPORT, QUERY_BUILD_FAILURE_REPORTS};
 
 #[derive(Debug)]
 pub enum AppStoreError {
@@ -33,25 +36,58 @@ impl std::fmt::Display for AppStoreError {
 
 pub type Result<T> = std::result::Result<T, AppStoreError>;
 
-#[derive(sqlx::FromRow, Debug, Clone)]
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
 pub struct Client {
-    id: Uuid,
-    app_id: Uuid,
-    updated_at: DateTime<Utc>,
-    version: String,
-    enabled: bool,
-    created_at: DateTime<Utc>,
+    pub id: Uuid,
+    pub app_id: Uuid,
+    pub updated_at: DateTime<Utc>,
+    pub version: String,
+    pub enabled: bool,
+    pub created_at: DateTime<Utc>,
+    /// Labels sent by the client with its latest version checks.
+    pub reported_labels: Json<Labels>,
+    /// Labels set by admins. They take precedence over reported labels with the same key.
+    pub labels: Json<Labels>,
 }
 
-#[derive(sqlx::FromRow, Debug)]
+impl Client {
+    /// Labels selectors are evaluated against.
+    pub fn effective_labels(&self) -> Labels {
+        self.reported_labels.merged(&self.labels)
+    }
+}
+
+#[derive(sqlx::FromRow, Serialize, Debug)]
 pub struct ApplicationVersion {
     pub id: Uuid,
     pub app_id: Uuid,
     pub version: String,
     pub latest: bool,
+    /// Clients running an older version must update as soon as possible.
+    pub mandatory: bool,
+    pub release_notes: Option<String>,
+    /// Set when the version is first promoted unless it was set beforehand.
+    pub released_at: Option<DateTime<Utc>>,
+    pub git_commit: Option<String>,
+    /// Free-form json object describing the version.
+    pub metadata: serde_json::Value,
+    /// Percentage of clients served this version instead of the latest version while it is being
+    /// rolled out.
+    pub rollout_percentage: Option<i32>,
+    /// Label selector of the clients that are served this version instead of the latest version.
+    pub selector: Option<String>,
 }
 
-#[derive(sqlx::FromRow, Debug)]
+/// Changes to the descriptive fields of a version. Unset fields are left unchanged.
+#[derive(Default, Debug)]
+pub struct VersionMetadataUpdate {
+    pub release_notes: Option<String>,
+    pub released_at: Option<DateTime<Utc>>,
+    pub git_commit: Option<String>,
+    pub metadata: Option<serde_json::Value>,
+}
+
+#[derive(sqlx::FromRow, Serialize, Debug)]
 pub struct ApplicationBuild {
     pub id: Uuid,
     pub app_version_id: Uuid,
@@ -59,20 +95,285 @@ pub struct ApplicationBuild {
     pub success_count: i32,
     pub failed_count: i32,
     pub url: String,
-    pub disabled: bool
+    pub disabled: bool,
+    pub sha256: Option<String>,
+}
+
+/// A client relaunching the version it ran before after `failed_version` failed to start.
+#[derive(sqlx::FromRow, Serialize, Debug)]
+pub struct ClientRollback {
+    pub id: Uuid,
+    pub client_id: Uuid,
+    pub app_id: Uuid,
+    pub architecture: String,
+    pub failed_version: String,
+    pub version: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// An exit of the app a client was running. `crash_count` counts the exits within the client's
+/// restart window.
+#[derive(sqlx::FromRow, Serialize, Debug)]
+pub struct ClientCrash {
+    pub id: Uuid,
+    pub client_id: Uuid,
+    pub app_id: Uuid,
+    pub architecture: String,
+    pub version: String,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub crash_count: i32,
+    pub restarting: bool,
+    pub stderr_tail: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A client's report of an exit of the app it was running.
+pub struct NewClientCrash<'a> {
+    pub client_id: Uuid,
+    pub app_id: Uuid,
+    pub architecture: &'a str,
+    pub version: &'a str,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub crash_count: i32,
+    pub restarting: bool,
+    pub stderr_tail: Option<&'a str>,
+}
+
+/// Why a build failed to start on a client. `uptime_ms` is the time from starting the build until it
+/// failed, and `failed_probe` the health probe it didn't pass.
+#[derive(sqlx::FromRow, Serialize, Debug)]
+pub struct BuildFailureReport {
+    pub id: Uuid,
+    pub build_id: Uuid,
+    pub client_id: Uuid,
+    pub app_id: Uuid,
+    pub architecture: String,
+    pub version: String,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub uptime_ms: Option<i64>,
+    pub stderr_tail: Option<String>,
+    pub failed_probe: Option<String>,
+    pub error: Option<String>,
+    pub host: Option<serde_json::Value>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A client's report of a build that failed to start.
+pub struct NewBuildFailureReport<'a> {
+    pub build_id: Uuid,
+    pub client_id: Uuid,
+    pub app_id: Uuid,
+    pub architecture: &'a str,
+    pub version: &'a str,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub uptime_ms: Option<i64>,
+    pub stderr_tail: Option<&'a str>,
+    pub failed_probe: Option<&'a str>,
+    pub error: Option<&'a str>,
+    pub host: Option<&'a serde_json::Value>,
+}
+
+/// A build artifact stored by the server.
+#[derive(sqlx::FromRow, Serialize, Debug)]
+pub struct BuildArtifact {
+    pub build_id: Uuid,
+    pub file_name: String,
+    pub size: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A build with a stored artifact and its version number.
+#[derive(sqlx::FromRow, Debug)]
+pub struct StoredBuild {
+    pub build_id: Uuid,
+    pub version: String,
+}
+
+/// A binary patch from one build to another.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone, PartialEq)]
+pub struct BuildPatch {
+    pub from_build_id: Uuid,
+    pub to_build_id: Uuid,
+    pub from_version: String,
+    pub sha256: String,
+    pub size: i64,
+}
+
+/// The build a client should run together with its version number and the application's update
+/// requirements.
+#[derive(sqlx::FromRow, Debug)]
+pub struct ResolvedBuild {
+    pub build_id: Uuid,
+    pub app_version_id: Uuid,
+    pub version: String,
+    pub url: String,
+    pub sha256: Option<String>,
+    pub release_notes: Option<String>,
+    pub released_at: Option<DateTime<Utc>>,
+    pub git_commit: Option<String>,
+    pub metadata: serde_json::Value,
+    pub min_supported_version: Option<String>,
+    pub mandatory_versions: Vec<String>,
+}
+
+/// A scheduled promotion of a version, carried out in one or more steps.
+#[derive(sqlx::FromRow, Serialize, Debug)]
+pub struct ReleaseSchedule {
+    pub id: Uuid,
+    pub app_id: Uuid,
+    pub app_version_id: Uuid,
+    pub version: String,
+    pub status: String,
+    pub last_error: Option<String>,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Rolls the version of a schedule out to a percentage of clients at `run_at`. The version is
+/// promoted by the step that reaches 100 percent.
+#[derive(sqlx::FromRow, Serialize, Debug)]
+pub struct ReleaseScheduleStep {
+    pub id: Uuid,
+    pub schedule_id: Uuid,
+    pub rollout_percentage: i32,
+    pub run_at: DateTime<Utc>,
+    pub executed_at: Option<DateTime<Utc>>,
 }
 
 #[derive(sqlx::FromRow, Debug)]
+pub struct DueReleaseStep {
+    pub id: Uuid,
+    pub schedule_id: Uuid,
+    pub rollout_percentage: i32,
+    pub app_id: Uuid,
+    pub app_version_id: Uuid,
+}
+
+/// A target triple builds can be published for. `fallbacks` are the targets whose builds are
+/// served to clients of this target when it has no build of its own, in order of preference.
+#[derive(sqlx::FromRow, Serialize, Debug)]
+pub struct Target {
+    pub id: Uuid,
+    pub triple: String,
+    pub description: String,
+    pub aliases: Vec<String>,
+    pub fallbacks: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(sqlx::FromRow, Serialize, Debug)]
 pub struct Application {
     pub id: Uuid,
     pub name: String,
     pub description: String,
+    /// Clients running an older version must update as soon as possible.
+    pub min_supported_version: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A recorded administrative change. `before` and `after` hold the json representation of the
+/// target record around the change and are empty for creations and deletions respectively.
+#[derive(sqlx::FromRow, Serialize, Debug)]
+pub struct AuditEntry {
+    pub id: Uuid,
+    pub actor: String,
+    pub action: String,
+    pub target_type: String,
+    pub target_id: Uuid,
+    pub app_id: Option<Uuid>,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+    pub created_at: DateTime<Utc>,
+}
+
+pub struct NewAuditEntry<'a> {
+    pub actor: &'a str,
+    pub action: &'a str,
+    pub target_type: &'a str,
+    pub target_id: Uuid,
+    pub app_id: Option<Uuid>,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+}
+
+/// Filters applied when reading the audit log. Unset filters match every entry.
+#[derive(Default, Debug)]
+pub struct AuditFilter {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub app_id: Option<Uuid>,
+    pub target_id: Option<Uuid>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// An endpoint that receives signed json events. An empty `event_types` list subscribes to every
+/// event and an empty `app_id` subscribes to events of every application.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct WebhookSubscription {
+    pub id: Uuid,
+    pub url: String,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub event_types: Vec<String>,
+    pub app_id: Option<Uuid>,
+    pub enabled: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A single event sent to a subscription, along with the outcome of the latest attempt.
+#[derive(sqlx::FromRow, Serialize, Debug)]
+pub struct WebhookDelivery {
+    pub id: Uuid,
+    pub subscription_id: Uuid,
+    pub event_id: Uuid,
+    pub event_type: String,
+    pub payload: serde_json::Value,
+    pub status: String,
+    pub attempts: i32,
+    pub response_status: Option<i32>,
+    pub last_error: Option<String>,
     pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
 }
 
 pub struct AppStore {
     app_config: &'static Config,
-    connection_pool: PoolConnection<Postgres>
+    connection_pool: StoreConnection
+}
+
+/// The connection an [`AppStore`] runs its queries on.
+enum StoreConnection {
+    Pooled(PoolConnection<Postgres>),
+    /// Rolled back when it is dropped before [`AppStore::commit`].
+    Transaction(Transaction<'static, Postgres>),
+}
+
+impl Deref for StoreConnection {
+    type Target = PgConnection;
+
+    fn deref(&self) -> &PgConnection {
+        match self {
+            StoreConnection::Pooled(connection) => connection,
+            StoreConnection::Transaction(transaction) => transaction,
+        }
+    }
+}
+
+impl DerefMut for StoreConnection {
+    fn deref_mut(&mut self) -> &mut PgConnection {
+        match self {
+            StoreConnection::Pooled(connection) => connection,
+            StoreConnection::Transaction(transaction) => transaction,
+        }
+    }
 }
 
 
@@ -81,34 +382,53 @@ impl AppStore {
     pub async fn from_config(app_config: &'static Config) -> Result<Self> {
         let pool = PgPoolOptions::new().connect(&app_config.db_url).await.expect("Database connection failed");
         let connection_pool = pool.acquire().await.map_err(|err| { TransactionFailure{message: err.to_string()}})?;
-        let mut store = AppStore { app_config, connection_pool };
+        let mut store = AppStore { app_config, connection_pool: StoreConnection::Pooled(connection_pool) };
         store.begin().await.expect("Database connection failed");
         Ok(store)
     }
 
     pub async fn from_pg_pool(app_config: &'static Config, pool: &PgPool) -> Result<Self> {
         let connection_pool = pool.acquire().await.map_err(|err| { TransactionFailure{message: err.to_string()}})?;
-        let mut store = AppStore { app_config, connection_pool };
+        let mut store = AppStore { app_config, connection_pool: StoreConnection::Pooled(connection_pool) };
         store.begin().await.expect("Database connection failed");
         Ok(store)
     }
 
     pub async fn from_pool_connection(app_config: &'static Config, connection_pool: PoolConnection<Postgres>) -> Result<Self> {
-        let mut store = AppStore { app_config, connection_pool };
+        let mut store = AppStore { app_config, connection_pool: StoreConnection::Pooled(connection_pool) };
         store.begin().await.expect("Database connection failed");
         Ok(store)
     }
 
+    /// Starts a transaction on a connection of its own. Changes made through the returned store are
+    /// only kept once it is committed with `commit`; dropping it before, for example when an error
+    /// is returned early, rolls them back.
+    pub async fn begin_transaction(app_config: &'static Config, pool: &PgPool) -> Result<Self> {
+        let transaction = pool.begin().await.map_err(|err| { TransactionFailure{message: err.to_string()}})?;
+        Ok(AppStore { app_config, connection_pool: StoreConnection::Transaction(transaction) })
+    }
+
+    /// Commits the transaction of a store started with `begin_transaction`. Other stores have
+    /// nothing to commit.
+    pub async fn commit(self) -> Result<()> {
+        match self.connection_pool {
+            StoreConnection::Transaction(transaction) => transaction
+                .commit()
+                .await
+                .map_err(|err| { TransactionFailure{message: err.to_string()}}),
+            StoreConnection::Pooled(_) => Ok(()),
+        }
+    }
+
     pub async fn begin(&mut self) -> Result<()> {
-        self.connection_pool
-            .begin()
+        Acquire::begin(&mut *self.connection_pool)
             .await
             .expect("Database connection failed");
         Ok(())
     }
 
     pub async fn end_transaction(&mut self) -> Result<()> {
-        self.connection_pool.commit().await.map_err(|err| { TransactionFailure{message: err.to_string()}})
+        PgTransactionManager::commit(&mut self.connection_pool).await.map_err(|err| { TransactionFailure{message: err.to_string()}})
     }
 
     pub async fn create_client(&mut self, app_id: Uuid, build_ver: &str) -> Result<Client> {
@@ -146,7 +466,7 @@ impl AppStore {
         let mut query: String = QUERY_APPLICATION_BUILD_VERSION.to_string();
 
         if for_update {
-            query += " FOR UPDATE"
+            query += " FOR UPDATE OF ab"
         }
 
         sqlx::query_as::<_, ApplicationBuild>(&query)
@@ -198,10 +518,17 @@ impl AppStore {
         Ok(())
     }
     
-    pub async fn update_client_version(&mut self, client_id: Uuid, new_version: &str) -> Result<()> {
+    /// Records the version a client runs, and the labels it reported when it sent any.
+    pub async fn update_client_version(
+        &mut self,
+        client_id: Uuid,
+        new_version: &str,
+        reported_labels: Option<&Labels>,
+    ) -> Result<()> {
         sqlx::query(UPDATE_CLIENT)
             .bind(new_version)
             .bind(client_id)
+            .bind(reported_labels.map(Json))
             .execute(&mut *self.connection_pool)
             .await
             .map_err(|err| {
@@ -213,6 +540,148 @@ impl AppStore {
         Ok(())
     }
     
+    pub async fn find_client(&mut self, client_id: Uuid) -> Result<Option<Client>> {
+        sqlx::query_as::<_, Client>(QUERY_CLIENT)
+            .bind(client_id)
+            .fetch_optional(&mut *self.connection_pool)
+            .await
+            .map_err(|err| {
+                RowNotFound {
+                    id: client_id.to_string(),
+                    message: err.to_string()
+                }
+            })
+    }
+
+    pub async fn get_clients(&mut self, app_id: Uuid) -> Result<Vec<Client>> {
+        sqlx::query_as::<_, Client>(QUERY_CLIENTS_BY_APP)
+            .bind(app_id)
+            .fetch_all(&mut *self.connection_pool)
+            .await
+            .map_err(|err| {
+                RowNotFound {
+                    id: app_id.to_string(),
+                    message: err.to_string()
+                }
+            })
+    }
+
+    pub async fn record_client_rollback(
+        &mut self,
+        client_id: Uuid,
+        app_id: Uuid,
+        architecture: &str,
+        failed_version: &str,
+        version: &str,
+    ) -> Result<ClientRollback> {
+        sqlx::query_as::<_, ClientRollback>(INSERT_CLIENT_ROLLBACK)
+            .bind(client_id)
+            .bind(app_id)
+            .bind(architecture)
+            .bind(failed_version)
+            .bind(version)
+            .fetch_one(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RecordCreationError { message: err.to_string() })
+    }
+
+    pub async fn get_client_rollbacks(
+        &mut self,
+        app_id: Uuid,
+        client_id: Option<Uuid>,
+        limit: i64,
+    ) -> Result<Vec<ClientRollback>> {
+        sqlx::query_as::<_, ClientRollback>(QUERY_CLIENT_ROLLBACKS)
+            .bind(app_id)
+            .bind(client_id)
+            .bind(limit)
+            .fetch_all(&mut *self.connection_pool)
+            .await
+            .map_err(|err| TransactionFailure { message: err.to_string() })
+    }
+
+    pub async fn record_client_crash(&mut self, crash: &NewClientCrash<'_>) -> Result<ClientCrash> {
+        sqlx::query_as::<_, ClientCrash>(INSERT_CLIENT_CRASH)
+            .bind(crash.client_id)
+            .bind(crash.app_id)
+            .bind(crash.architecture)
+            .bind(crash.version)
+            .bind(crash.exit_code)
+            .bind(crash.signal)
+            .bind(crash.crash_count)
+            .bind(crash.restarting)
+            .bind(crash.stderr_tail)
+            .fetch_one(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RecordCreationError { message: err.to_string() })
+    }
+
+    pub async fn get_client_crashes(
+        &mut self,
+        app_id: Uuid,
+        client_id: Option<Uuid>,
+        limit: i64,
+    ) -> Result<Vec<ClientCrash>> {
+        sqlx::query_as::<_, ClientCrash>(QUERY_CLIENT_CRASHES)
+            .bind(app_id)
+            .bind(client_id)
+            .bind(limit)
+            .fetch_all(&mut *self.connection_pool)
+            .await
+            .map_err(|err| TransactionFailure { message: err.to_string() })
+    }
+
+    pub async fn record_build_failure_report(
+        &mut self,
+        report: &NewBuildFailureReport<'_>,
+    ) -> Result<BuildFailureReport> {
+        sqlx::query_as::<_, BuildFailureReport>(INSERT_BUILD_FAILURE_REPORT)
+            .bind(report.build_id)
+            .bind(report.client_id)
+            .bind(report.app_id)
+            .bind(report.architecture)
+            .bind(report.version)
+            .bind(report.exit_code)
+            .bind(report.signal)
+            .bind(report.uptime_ms)
+            .bind(report.stderr_tail)
+            .bind(report.failed_probe)
+            .bind(report.error)
+            .bind(report.host)
+            .fetch_one(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RecordCreationError { message: err.to_string() })
+    }
+
+    pub async fn get_build_failure_reports(
+        &mut self,
+        build_id: Uuid,
+        client_id: Option<Uuid>,
+        limit: i64,
+    ) -> Result<Vec<BuildFailureReport>> {
+        sqlx::query_as::<_, BuildFailureReport>(QUERY_BUILD_FAILURE_REPORTS)
+            .bind(build_id)
+            .bind(client_id)
+            .bind(limit)
+            .fetch_all(&mut *self.connection_pool)
+            .await
+            .map_err(|err| TransactionFailure { message: err.to_string() })
+    }
+
+    pub async fn set_client_labels(&mut self, client_id: Uuid, labels: &Labels) -> Result<Client> {
+        sqlx::query_as::<_, Client>(UPDATE_CLIENT_LABELS)
+            .bind(client_id)
+            .bind(Json(labels))
+            .fetch_one(&mut *self.connection_pool)
+            .await
+            .map_err(|err| {
+                RowNotFound {
+                    id: client_id.to_string(),
+                    message: err.to_string()
+                }
+            })
+    }
+
     pub async fn get_client_by_id(&mut self, client_id: Uuid) -> Result<Client> {
         sqlx::query_as::<_, Client>(QUERY_CLIENT)
             .bind(client_id)
@@ -271,12 +740,87 @@ impl AppStore {
             })
     }
 
+    /// Records the digest of a build's artifact and, when the server stores the artifact, the url
+    /// it is served at.
+    pub async fn set_application_build_sha256(
+        &mut self,
+        build_id: Uuid,
+        sha256: &str,
+        url: Option<&str>,
+    ) -> Result<ApplicationBuild> {
+        sqlx::query_as::<_, ApplicationBuild>(UPDATE_APPLICATION_BUILD_SHA256)
+            .bind(build_id)
+            .bind(sha256)
+            .bind(url)
+            .fetch_one(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RowNotFound { id: build_id.to_string(), message: err.to_string() })
+    }
+
+    pub async fn save_build_artifact(&mut self, build_id: Uuid, file_name: &str, size: i64) -> Result<BuildArtifact> {
+        sqlx::query_as::<_, BuildArtifact>(UPSERT_BUILD_ARTIFACT)
+            .bind(build_id)
+            .bind(file_name)
+            .bind(size)
+            .fetch_one(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RecordCreationError { message: err.to_string() })
+    }
+
+    pub async fn get_build_artifact(&mut self, build_id: Uuid) -> Result<Option<BuildArtifact>> {
+        sqlx::query_as::<_, BuildArtifact>(QUERY_BUILD_ARTIFACT)
+            .bind(build_id)
+            .fetch_optional(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RowNotFound { id: build_id.to_string(), message: err.to_string() })
+    }
+
+    /// Builds with stored artifacts for the same application and target as the build, including
+    /// the build itself.
+    pub async fn get_stored_sibling_builds(&mut self, build_id: Uuid) -> Result<Vec<StoredBuild>> {
+        sqlx::query_as::<_, StoredBuild>(QUERY_STORED_SIBLING_BUILDS)
+            .bind(build_id)
+            .fetch_all(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RowNotFound { id: build_id.to_string(), message: err.to_string() })
+    }
+
+    pub async fn save_build_patch(&mut self, from_build_id: Uuid, to_build_id: Uuid, sha256: &str, size: i64) -> Result<()> {
+        sqlx::query(UPSERT_BUILD_PATCH)
+            .bind(from_build_id)
+            .bind(to_build_id)
+            .bind(sha256)
+            .bind(size)
+            .execute(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RecordCreationError { message: err.to_string() })?;
+        Ok(())
+    }
+
+    /// Patches that turn other builds into this build.
+    pub async fn get_build_patches_to(&mut self, build_id: Uuid) -> Result<Vec<BuildPatch>> {
+        sqlx::query_as::<_, BuildPatch>(QUERY_BUILD_PATCHES_TO)
+            .bind(build_id)
+            .fetch_all(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RowNotFound { id: build_id.to_string(), message: err.to_string() })
+    }
+
+    pub async fn get_build_patch(&mut self, from_build_id: Uuid, to_build_id: Uuid) -> Result<Option<BuildPatch>> {
+        sqlx::query_as::<_, BuildPatch>(QUERY_BUILD_PATCH)
+            .bind(from_build_id)
+            .bind(to_build_id)
+            .fetch_optional(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RowNotFound { id: format!("{} -> {}", from_build_id, to_build_id), message: err.to_string() })
+    }
+
     pub async fn get_latest_application_version_build(
         &mut self,
         app_id: Uuid,
         build_version: &str,
     ) -> Result<ApplicationBuild> {
-        sqlx::query_as::<_, ApplicationBuild>(&QUERY_LATEST_BUILD_VERSION)
+        sqlx::query_as::<_, ApplicationBuild>(QUERY_LATEST_BUILD_VERSION)
             .bind(build_version)
             .bind(app_id)
             .fetch_one(&mut *self.connection_pool)
@@ -289,6 +833,30 @@ impl AppStore {
             })
     }
     
+    /// Returns the enabled build the client should run together with its version number in a
+    /// single query. This is the latest version unless the client falls within the rollout of a
+    /// newer version.
+    pub async fn resolve_latest_build(
+        &mut self,
+        app_id: Uuid,
+        build_version: &str,
+        client_id: Uuid,
+        targeted_version_id: Option<Uuid>,
+    ) -> Result<ResolvedBuild> {
+        sqlx::query_as::<_, ResolvedBuild>(QUERY_LATEST_RESOLVED_BUILD)
+            .bind(build_version)
+            .bind(app_id)
+            .bind(client_id)
+            .bind(targeted_version_id)
+            .fetch_one(&mut *self.connection_pool)
+            .await
+            .map_err(|err| {
+                RowNotFound {
+                    id: format!("App ID: {}, Version: {}", app_id, build_version),
+                    message: err.to_string(),
+                }
+            })
+    }
 
     pub async fn create_application(
         &mut self,
@@ -333,38 +901,551 @@ impl AppStore {
     }
 
 
-    pub async fn query_advisory_lock(&mut self, lock_id: Uuid) -> Result<bool> {
-        let uuid = lock_id.to_string();
-        sqlx::query_scalar(QUERY_ADVISORY_LOCK)
-            .bind(uuid)
+    pub async fn get_application_version(&mut self, app_id: Uuid, version: &str) -> Result<ApplicationVersion> {
+        sqlx::query_as::<_, ApplicationVersion>(QUERY_APPLICATION_VERSION_BY_NUMBER)
+            .bind(app_id)
+            .bind(version)
             .fetch_one(&mut *self.connection_pool)
             .await
-            .map_err(|e| TransactionFailure {
-                message: e.to_string(),
+            .map_err(|err| match err {
+                sqlx::Error::RowNotFound => RowNotFound {
+                    id: format!("App ID: {}, Version: {}", app_id, version),
+                    message: err.to_string(),
+                },
+                err => TransactionFailure { message: err.to_string() },
             })
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use sqlx::{Executor};
-    use crate::config::{DB_HOST_DEFAULT, DB_PWD_DEFAULT, DB_URL_TEMPLATE, DB_USER_DEFAULT, DEFAULT_VERSION};
-    use once_cell::sync::Lazy;
 
-    static TEST_CONFIG: Lazy<Config> = Lazy::new(|| Config {
-        db_url: "postgresql://postgres@127.0.0.1:5432/client_version_manager_test".to_string(),
-        db_name: "client_version_manager_test".to_string(),
-        db_host: DB_HOST_DEFAULT.to_string(),
-        db_user: DB_USER_DEFAULT.to_string(),
-        db_pwd: DB_PWD_DEFAULT.to_string(),
-        content_url: DB_URL_TEMPLATE.to_string(),
-        default_version: DEFAULT_VERSION.to_string(),
-    });
+    /// Returns the version currently flagged as latest for the application, if any.
+    pub async fn get_latest_application_version(&mut self, app_id: Uuid) -> Result<Option<ApplicationVersion>> {
+        sqlx::query_as::<_, ApplicationVersion>(QUERY_LATEST_APPLICATION_VERSION)
+            .bind(app_id)
+            .fetch_optional(&mut *self.connection_pool)
+            .await
+            .map_err(|err| {
+                RowNotFound {
+                    id: format!("App ID: {}", app_id),
+                    message: err.to_string(),
+                }
+            })
+    }
 
-    async fn setup() -> Result<(AppStore)> {
-        let store = AppStore::from_config(&TEST_CONFIG).await?;
-        Ok( store )
+    /// Flags the version as the latest for its application and clears the flag on every other
+    /// version of the application. Rollouts and selectors of every version of the application end,
+    /// as they would otherwise keep serving an older version to some clients.
+    pub async fn promote_application_version(&mut self, version_id: Uuid) -> Result<ApplicationVersion> {
+        let version = self.get_application_version_by_id(version_id).await?;
+        let mut transaction = Acquire::begin(&mut *self.connection_pool)
+            .await
+            .map_err(|err| TransactionFailure { message: err.to_string() })?;
+        sqlx::query(CLEAR_LATEST_APPLICATION_VERSION)
+            .bind(version.app_id)
+            .execute(&mut *transaction)
+            .await
+            .map_err(|err| TransactionFailure { message: err.to_string() })?;
+        sqlx::query(CLEAR_APPLICATION_VERSION_TARGETING)
+            .bind(version.app_id)
+            .execute(&mut *transaction)
+            .await
+            .map_err(|err| TransactionFailure { message: err.to_string() })?;
+        let promoted = sqlx::query_as::<_, ApplicationVersion>(SET_LATEST_APPLICATION_VERSION)
+            .bind(version_id)
+            .fetch_one(&mut *transaction)
+            .await
+            .map_err(|err| TransactionFailure { message: err.to_string() })?;
+        transaction.commit().await.map_err(|err| TransactionFailure { message: err.to_string() })?;
+        Ok(promoted)
+    }
+
+    pub async fn set_application_min_supported_version(
+        &mut self,
+        app_id: Uuid,
+        min_supported_version: Option<&str>,
+    ) -> Result<Application> {
+        sqlx::query_as::<_, Application>(UPDATE_APPLICATION_MIN_SUPPORTED_VERSION)
+            .bind(app_id)
+            .bind(min_supported_version)
+            .fetch_one(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RowNotFound { id: app_id.to_string(), message: err.to_string() })
+    }
+
+    pub async fn set_application_version_mandatory(&mut self, version_id: Uuid, mandatory: bool) -> Result<ApplicationVersion> {
+        sqlx::query_as::<_, ApplicationVersion>(UPDATE_APPLICATION_VERSION_MANDATORY)
+            .bind(version_id)
+            .bind(mandatory)
+            .fetch_one(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RowNotFound { id: version_id.to_string(), message: err.to_string() })
+    }
+
+    pub async fn update_application_version_metadata(
+        &mut self,
+        version_id: Uuid,
+        update: &VersionMetadataUpdate,
+    ) -> Result<ApplicationVersion> {
+        sqlx::query_as::<_, ApplicationVersion>(UPDATE_APPLICATION_VERSION_METADATA)
+            .bind(version_id)
+            .bind(&update.release_notes)
+            .bind(update.released_at)
+            .bind(&update.git_commit)
+            .bind(&update.metadata)
+            .fetch_one(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RowNotFound { id: version_id.to_string(), message: err.to_string() })
+    }
+
+    /// Serves the version to a percentage of the application's clients, ending the rollout of any
+    /// other version. `None` ends the rollout of the version.
+    pub async fn set_application_version_rollout(
+        &mut self,
+        version_id: Uuid,
+        rollout_percentage: Option<i32>,
+    ) -> Result<ApplicationVersion> {
+        let version = self.get_application_version_by_id(version_id).await?;
+        if rollout_percentage.is_some() {
+            sqlx::query(CLEAR_APPLICATION_VERSION_ROLLOUTS)
+                .bind(version.app_id)
+                .bind(version_id)
+                .execute(&mut *self.connection_pool)
+                .await
+                .map_err(|err| TransactionFailure { message: err.to_string() })?;
+        }
+        sqlx::query_as::<_, ApplicationVersion>(UPDATE_APPLICATION_VERSION_ROLLOUT)
+            .bind(version_id)
+            .bind(rollout_percentage)
+            .fetch_one(&mut *self.connection_pool)
+            .await
+            .map_err(|err| TransactionFailure { message: err.to_string() })
+    }
+
+    /// Serves the version to the clients matching the selector instead of the latest version.
+    /// `None` serves it to no one until it is promoted.
+    pub async fn set_application_version_selector(
+        &mut self,
+        version_id: Uuid,
+        selector: Option<&Selector>,
+    ) -> Result<ApplicationVersion> {
+        sqlx::query_as::<_, ApplicationVersion>(UPDATE_APPLICATION_VERSION_SELECTOR)
+            .bind(version_id)
+            .bind(selector.map(Selector::as_str))
+            .fetch_one(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RowNotFound { id: version_id.to_string(), message: err.to_string() })
+    }
+
+    /// Versions, other than the latest, that are targeted at the clients matching their selector.
+    pub async fn get_targeted_application_versions(&mut self, app_id: Uuid) -> Result<Vec<ApplicationVersion>> {
+        sqlx::query_as::<_, ApplicationVersion>(QUERY_TARGETED_APPLICATION_VERSIONS)
+            .bind(app_id)
+            .fetch_all(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RowNotFound { id: app_id.to_string(), message: err.to_string() })
+    }
+
+    pub async fn get_application_build_by_id(&mut self, build_id: Uuid) -> Result<ApplicationBuild> {
+        sqlx::query_as::<_, ApplicationBuild>(QUERY_APPLICATION_BUILD_BY_ID)
+            .bind(build_id)
+            .fetch_one(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RowNotFound { id: format!("App build ID: {}", build_id), message: err.to_string() })
+    }
+
+    pub async fn get_application_builds_by_version(&mut self, version_id: Uuid) -> Result<Vec<ApplicationBuild>> {
+        sqlx::query_as::<_, ApplicationBuild>(QUERY_APPLICATION_BUILDS_BY_VERSION)
+            .bind(version_id)
+            .fetch_all(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RowNotFound { id: format!("App Version ID: {}", version_id), message: err.to_string() })
+    }
+
+    pub async fn set_application_build_disabled(&mut self, build_id: Uuid, disabled: bool) -> Result<ApplicationBuild> {
+        sqlx::query_as::<_, ApplicationBuild>(UPDATE_APPLICATION_BUILD_DISABLED)
+            .bind(build_id)
+            .bind(disabled)
+            .fetch_one(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RowNotFound { id: format!("App build ID: {}", build_id), message: err.to_string() })
+    }
+
+    /// Disables or re-enables every build of a version.
+    pub async fn set_application_version_disabled(&mut self, version_id: Uuid, disabled: bool) -> Result<Vec<ApplicationBuild>> {
+        sqlx::query_as::<_, ApplicationBuild>(UPDATE_APPLICATION_VERSION_BUILDS_DISABLED)
+            .bind(version_id)
+            .bind(disabled)
+            .fetch_all(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RowNotFound { id: format!("App Version ID: {}", version_id), message: err.to_string() })
+    }
+
+    pub async fn set_client_enabled(&mut self, client_id: Uuid, enabled: bool) -> Result<Client> {
+        sqlx::query_as::<_, Client>(UPDATE_CLIENT_ENABLED)
+            .bind(client_id)
+            .bind(enabled)
+            .fetch_one(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RowNotFound { id: client_id.to_string(), message: err.to_string() })
+    }
+
+    pub async fn create_audit_entry(&mut self, entry: &NewAuditEntry<'_>) -> Result<AuditEntry> {
+        sqlx::query_as::<_, AuditEntry>(INSERT_AUDIT_ENTRY)
+            .bind(entry.actor)
+            .bind(entry.action)
+            .bind(entry.target_type)
+            .bind(entry.target_id)
+            .bind(entry.app_id)
+            .bind(&entry.before)
+            .bind(&entry.after)
+            .fetch_one(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RecordCreationError { message: err.to_string() })
+    }
+
+    /// Returns audit entries matching the filter, newest first.
+    pub async fn get_audit_entries(&mut self, filter: &AuditFilter) -> Result<Vec<AuditEntry>> {
+        sqlx::query_as::<_, AuditEntry>(QUERY_AUDIT_LOG)
+            .bind(&filter.actor)
+            .bind(&filter.action)
+            .bind(filter.app_id)
+            .bind(filter.target_id)
+            .bind(filter.since)
+            .bind(filter.until)
+            .bind(filter.limit)
+            .bind(filter.offset.unwrap_or(0))
+            .fetch_all(&mut *self.connection_pool)
+            .await
+            .map_err(|err| TransactionFailure { message: err.to_string() })
+    }
+
+    pub async fn create_webhook_subscription(
+        &mut self,
+        url: &str,
+        secret: &str,
+        event_types: &[String],
+        app_id: Option<Uuid>,
+    ) -> Result<WebhookSubscription> {
+        sqlx::query_as::<_, WebhookSubscription>(INSERT_WEBHOOK_SUBSCRIPTION)
+            .bind(url)
+            .bind(secret)
+            .bind(event_types)
+            .bind(app_id)
+            .fetch_one(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RecordCreationError { message: err.to_string() })
+    }
+
+    pub async fn get_webhook_subscriptions(&mut self) -> Result<Vec<WebhookSubscription>> {
+        sqlx::query_as::<_, WebhookSubscription>(QUERY_WEBHOOK_SUBSCRIPTIONS)
+            .fetch_all(&mut *self.connection_pool)
+            .await
+            .map_err(|err| TransactionFailure { message: err.to_string() })
+    }
+
+    pub async fn get_webhook_subscription_by_id(&mut self, id: Uuid) -> Result<WebhookSubscription> {
+        sqlx::query_as::<_, WebhookSubscription>(QUERY_WEBHOOK_SUBSCRIPTION_BY_ID)
+            .bind(id)
+            .fetch_one(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RowNotFound { id: id.to_string(), message: err.to_string() })
+    }
+
+    /// Returns the enabled subscriptions interested in an event of the given application.
+    pub async fn get_webhook_subscriptions_for_event(
+        &mut self,
+        event_type: &str,
+        app_id: Uuid,
+    ) -> Result<Vec<WebhookSubscription>> {
+        sqlx::query_as::<_, WebhookSubscription>(QUERY_WEBHOOK_SUBSCRIPTIONS_FOR_EVENT)
+            .bind(event_type)
+            .bind(app_id)
+            .fetch_all(&mut *self.connection_pool)
+            .await
+            .map_err(|err| TransactionFailure { message: err.to_string() })
+    }
+
+    pub async fn set_webhook_subscription_enabled(&mut self, id: Uuid, enabled: bool) -> Result<WebhookSubscription> {
+        sqlx::query_as::<_, WebhookSubscription>(UPDATE_WEBHOOK_SUBSCRIPTION_ENABLED)
+            .bind(id)
+            .bind(enabled)
+            .fetch_one(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RowNotFound { id: id.to_string(), message: err.to_string() })
+    }
+
+    pub async fn create_webhook_delivery(
+        &mut self,
+        subscription_id: Uuid,
+        event_id: Uuid,
+        event_type: &str,
+        payload: &serde_json::Value,
+    ) -> Result<WebhookDelivery> {
+        sqlx::query_as::<_, WebhookDelivery>(INSERT_WEBHOOK_DELIVERY)
+            .bind(subscription_id)
+            .bind(event_id)
+            .bind(event_type)
+            .bind(payload)
+            .fetch_one(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RecordCreationError { message: err.to_string() })
+    }
+
+    pub async fn update_webhook_delivery(
+        &mut self,
+        id: Uuid,
+        status: &str,
+        attempts: i32,
+        response_status: Option<i32>,
+        last_error: Option<&str>,
+    ) -> Result<()> {
+        sqlx::query(UPDATE_WEBHOOK_DELIVERY)
+            .bind(id)
+            .bind(status)
+            .bind(attempts)
+            .bind(response_status)
+            .bind(last_error)
+            .execute(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RowNotFound { id: id.to_string(), message: err.to_string() })?;
+        Ok(())
+    }
+
+    /// Returns the most recent deliveries, optionally limited to one subscription or status.
+    pub async fn get_webhook_deliveries(
+        &mut self,
+        subscription_id: Option<Uuid>,
+        status: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<WebhookDelivery>> {
+        sqlx::query_as::<_, WebhookDelivery>(QUERY_WEBHOOK_DELIVERIES)
+            .bind(subscription_id)
+            .bind(status)
+            .bind(limit)
+            .fetch_all(&mut *self.connection_pool)
+            .await
+            .map_err(|err| TransactionFailure { message: err.to_string() })
+    }
+
+    /// Returns the deliveries of enabled subscriptions that have not succeeded or run out of
+    /// attempts yet.
+    pub async fn get_pending_webhook_deliveries(&mut self) -> Result<Vec<WebhookDelivery>> {
+        sqlx::query_as::<_, WebhookDelivery>(QUERY_PENDING_WEBHOOK_DELIVERIES)
+            .fetch_all(&mut *self.connection_pool)
+            .await
+            .map_err(|err| TransactionFailure { message: err.to_string() })
+    }
+
+    /// Announces to every server replica that the releases of an application changed so that open
+    /// update streams re-resolve the latest version.
+    pub async fn notify_release_changed(&mut self, app_id: Uuid) -> Result<()> {
+        sqlx::query(NOTIFY_RELEASE_CHANGED)
+            .bind(app_id.to_string())
+            .execute(&mut *self.connection_pool)
+            .await
+            .map_err(|err| TransactionFailure { message: err.to_string() })?;
+        Ok(())
+    }
+
+    /// Returns the target with the given triple or alias, or `None` when it is not registered.
+    pub async fn get_target(&mut self, triple_or_alias: &str) -> Result<Option<Target>> {
+        sqlx::query_as::<_, Target>(QUERY_TARGET)
+            .bind(triple_or_alias)
+            .fetch_optional(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RowNotFound { id: triple_or_alias.to_string(), message: err.to_string() })
+    }
+
+    pub async fn get_targets(&mut self) -> Result<Vec<Target>> {
+        sqlx::query_as::<_, Target>(QUERY_TARGETS)
+            .fetch_all(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RowNotFound { id: "targets".to_string(), message: err.to_string() })
+    }
+
+    pub async fn create_target(&mut self, triple: &str, description: &str) -> Result<Target> {
+        sqlx::query(INSERT_TARGET)
+            .bind(triple)
+            .bind(description)
+            .execute(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RecordCreationError { message: err.to_string() })?;
+        self.get_registered_target(triple).await
+    }
+
+    /// Points an alias at a target, replacing the target it pointed at before.
+    pub async fn set_target_alias(&mut self, alias: &str, triple: &str) -> Result<Target> {
+        sqlx::query(UPSERT_TARGET_ALIAS)
+            .bind(alias)
+            .bind(triple)
+            .execute(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RecordCreationError { message: err.to_string() })?;
+        self.get_registered_target(triple).await
+    }
+
+    /// Adds a fallback to a target or changes the priority of an existing one. Lower priorities
+    /// are preferred.
+    pub async fn set_target_fallback(&mut self, triple: &str, fallback: &str, priority: i32) -> Result<Target> {
+        sqlx::query(UPSERT_TARGET_FALLBACK)
+            .bind(triple)
+            .bind(fallback)
+            .bind(priority)
+            .execute(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RecordCreationError { message: err.to_string() })?;
+        self.get_registered_target(triple).await
+    }
+
+    async fn get_registered_target(&mut self, triple: &str) -> Result<Target> {
+        self.get_target(triple).await?.ok_or_else(|| RowNotFound {
+            id: triple.to_string(),
+            message: "Target is not registered".to_string(),
+        })
+    }
+
+    pub async fn create_release_schedule(
+        &mut self,
+        app_id: Uuid,
+        app_version_id: Uuid,
+        created_by: &str,
+        steps: &[(i32, DateTime<Utc>)],
+    ) -> Result<ReleaseSchedule> {
+        let schedule_id: Uuid = sqlx::query_scalar(INSERT_RELEASE_SCHEDULE)
+            .bind(app_id)
+            .bind(app_version_id)
+            .bind(created_by)
+            .fetch_one(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RecordCreationError { message: err.to_string() })?;
+        for (rollout_percentage, run_at) in steps {
+            sqlx::query(INSERT_RELEASE_SCHEDULE_STEP)
+                .bind(schedule_id)
+                .bind(rollout_percentage)
+                .bind(run_at)
+                .execute(&mut *self.connection_pool)
+                .await
+                .map_err(|err| RecordCreationError { message: err.to_string() })?;
+        }
+        self.get_release_schedule(schedule_id).await
+    }
+
+    pub async fn get_release_schedule(&mut self, id: Uuid) -> Result<ReleaseSchedule> {
+        sqlx::query_as::<_, ReleaseSchedule>(QUERY_RELEASE_SCHEDULE)
+            .bind(id)
+            .fetch_one(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RowNotFound { id: id.to_string(), message: err.to_string() })
+    }
+
+    /// Returns schedules, newest first, optionally limited to an application and a status.
+    pub async fn get_release_schedules(
+        &mut self,
+        app_id: Option<Uuid>,
+        status: Option<&str>,
+    ) -> Result<Vec<ReleaseSchedule>> {
+        sqlx::query_as::<_, ReleaseSchedule>(QUERY_RELEASE_SCHEDULES)
+            .bind(app_id)
+            .bind(status)
+            .fetch_all(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RowNotFound { id: "release schedules".to_string(), message: err.to_string() })
+    }
+
+    /// Number of schedules of the application that have not finished yet.
+    pub async fn count_active_release_schedules(&mut self, app_id: Uuid) -> Result<i64> {
+        sqlx::query_scalar(QUERY_ACTIVE_RELEASE_SCHEDULE_COUNT)
+            .bind(app_id)
+            .fetch_one(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RowNotFound { id: app_id.to_string(), message: err.to_string() })
+    }
+
+    pub async fn get_release_schedule_steps(&mut self, schedule_id: Uuid) -> Result<Vec<ReleaseScheduleStep>> {
+        sqlx::query_as::<_, ReleaseScheduleStep>(QUERY_RELEASE_SCHEDULE_STEPS)
+            .bind(schedule_id)
+            .fetch_all(&mut *self.connection_pool)
+            .await
+            .map_err(|err| RowNotFound { id: schedule_id.to_string(), message: err.to_string() })
+    }
+
+    /// Returns the earliest step of an unfinished schedule that is due and was not executed yet.
+    pub async fn get_next_due_release_step(&mut self) -> Result<Option<DueReleaseStep>> {
+        sqlx::query_as::<_, DueReleaseStep>(QUERY_NEXT_DUE_RELEASE_STEP)
+            .fetch_optional(&mut *self.connection_pool)
+            .await
+            .map_err(|err| TransactionFailure { message: err.to_string() })
+    }
+
+    pub async fn mark_release_step_executed(&mut self, step_id: Uuid) -> Result<()> {
+        sqlx::query(UPDATE_RELEASE_SCHEDULE_STEP_EXECUTED)
+            .bind(step_id)
+            .execute(&mut *self.connection_pool)
+            .await
+            .map_err(|err| TransactionFailure { message: err.to_string() })?;
+        Ok(())
+    }
+
+    pub async fn set_release_schedule_status(
+        &mut self,
+        schedule_id: Uuid,
+        status: &str,
+        last_error: Option<&str>,
+    ) -> Result<()> {
+        sqlx::query(UPDATE_RELEASE_SCHEDULE_STATUS)
+            .bind(schedule_id)
+            .bind(status)
+            .bind(last_error)
+            .execute(&mut *self.connection_pool)
+            .await
+            .map_err(|err| TransactionFailure { message: err.to_string() })?;
+        Ok(())
+    }
+
+    /// Takes a lock shared by every server replica until the current transaction ends. Returns
+    /// false without waiting when another connection holds the lock.
+    pub async fn try_advisory_xact_lock(&mut self, lock_id: i64) -> Result<bool> {
+        sqlx::query_scalar(QUERY_ADVISORY_LOCK)
+            .bind(lock_id)
+            .fetch_one(&mut *self.connection_pool)
+            .await
+            .map_err(|e| TransactionFailure {
+                message: e.to_string(),
+            })
+    }
+}
+
+#[cfg(test)]
+#[allow(unused_parens, clippy::needless_borrow, clippy::bool_assert_comparison)]
+pub(crate) mod tests {
+    use super::*;
+    use sqlx::{Executor};
+    use crate::config::{DB_HOST_DEFAULT, DB_PWD_DEFAULT, DB_URL_TEMPLATE, DB_USER_DEFAULT, DEFAULT_VERSION};
+    use once_cell::sync::Lazy;
+
+    pub(crate) static TEST_CONFIG: Lazy<Config> = Lazy::new(|| Config {
+        db_url: "postgresql://postgres@127.0.0.1:5432/client_version_manager_test".to_string(),
+        db_name: "client_version_manager_test".to_string(),
+        db_host: DB_HOST_DEFAULT.to_string(),
+        db_user: DB_USER_DEFAULT.to_string(),
+        db_pwd: DB_PWD_DEFAULT.to_string(),
+        content_url: DB_URL_TEMPLATE.to_string(),
+        default_version: DEFAULT_VERSION.to_string(),
+        webhook_max_attempts: 3,
+        webhook_retry_base_delay_ms: 10,
+        auto_disable_failure_threshold: None,
+        release_scheduler_interval_secs: 1,
+        artifact_dir: std::env::temp_dir().join("cvm_test_artifacts").to_string_lossy().to_string(),
+        artifact_base_url: "http://127.0.0.1:3000".to_string(),
+        artifact_max_size_mb: 16,
+    });
+
+    pub(crate) async fn setup() -> Result<(AppStore)> {
+        let store = AppStore::from_config(&TEST_CONFIG).await?;
+        Ok( store )
+    }
+
+    pub(crate) async fn setup_transaction() -> Result<AppStore> {
+        let pool = PgPoolOptions::new().connect(&TEST_CONFIG.db_url).await.expect("Database connection failed");
+        AppStore::begin_transaction(&TEST_CONFIG, &pool).await
     }
 
     macro_rules! setup_context {
@@ -395,12 +1476,179 @@ mod tests {
         let mut store = setup_context!();
         let app = store.create_application(&"abc", "abcd").await.unwrap();
         let client = store.create_client(app.id, "0.0.1").await.unwrap();
-        let update_result = store.update_client_version(client.id, "0.0.2").await;
+        let update_result = store.update_client_version(client.id, "0.0.2", None).await;
         assert!(update_result.is_ok());
         let updated_client = store.get_client_by_id(client.id).await.unwrap();
         assert_eq!(updated_client.version, "0.0.2");
     }
 
+    #[tokio::test]
+    async fn test_client_rollbacks() {
+        let mut store = setup_context!();
+        let app = store.create_application("abc", "abcd").await.unwrap();
+        let client = store.create_client(app.id, "0.0.1").await.unwrap();
+        let other_client = store.create_client(app.id, "0.0.1").await.unwrap();
+        store
+            .record_client_rollback(client.id, app.id, "x86_64-unknown-linux-gnu", "0.0.2", "0.0.1")
+            .await
+            .unwrap();
+        store
+            .record_client_rollback(other_client.id, app.id, "x86_64-unknown-linux-gnu", "0.0.2", "0.0.1")
+            .await
+            .unwrap();
+
+        assert_eq!(store.get_client_rollbacks(app.id, None, 10).await.unwrap().len(), 2);
+        let rollbacks = store.get_client_rollbacks(app.id, Some(client.id), 10).await.unwrap();
+        assert_eq!(rollbacks.len(), 1);
+        assert_eq!(rollbacks[0].failed_version, "0.0.2");
+        assert_eq!(rollbacks[0].version, "0.0.1");
+    }
+
+    #[tokio::test]
+    async fn test_client_crashes() {
+        let mut store = setup_context!();
+        let app = store.create_application("abc", "abcd").await.unwrap();
+        let client = store.create_client(app.id, "0.0.1").await.unwrap();
+        let crash = |crash_count, restarting| NewClientCrash {
+            client_id: client.id,
+            app_id: app.id,
+            architecture: "x86_64-unknown-linux-gnu",
+            version: "0.0.1",
+            exit_code: None,
+            signal: Some(9),
+            crash_count,
+            restarting,
+            stderr_tail: Some("thread 'main' panicked"),
+        };
+        store.record_client_crash(&crash(1, true)).await.unwrap();
+        store.record_client_crash(&crash(2, false)).await.unwrap();
+
+        let crashes = store.get_client_crashes(app.id, Some(client.id), 10).await.unwrap();
+        assert_eq!(crashes.len(), 2);
+        assert_eq!(crashes[0].crash_count, 2);
+        assert!(!crashes[0].restarting);
+        assert_eq!(crashes[0].signal, Some(9));
+        assert_eq!(crashes[0].stderr_tail.as_deref(), Some("thread 'main' panicked"));
+        assert_eq!(store.get_client_crashes(app.id, Some(client.id), 1).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_build_failure_reports() {
+        let mut store = setup_context!();
+        let app = store.create_application("abc", "abcd").await.unwrap();
+        let client = store.create_client(app.id, "0.0.1").await.unwrap();
+        let version = store.create_application_version(app.id, "0.0.2", true).await.unwrap();
+        let build = store.create_application_build(version.id, "x86_64", "http://example.com/0.0.2").await.unwrap();
+        let host = serde_json::json!({ "hostname": "edge-1", "os": "linux" });
+        let report = |exit_code| NewBuildFailureReport {
+            build_id: build.id,
+            client_id: client.id,
+            app_id: app.id,
+            architecture: "x86_64",
+            version: "0.0.2",
+            exit_code,
+            signal: None,
+            uptime_ms: Some(1500),
+            stderr_tail: Some("address already in use"),
+            failed_probe: Some("tcp:127.0.0.1:8080"),
+            error: Some("Exited with status code: exit status: 1"),
+            host: Some(&host),
+        };
+        store.record_build_failure_report(&report(Some(1))).await.unwrap();
+        store.record_build_failure_report(&report(Some(2))).await.unwrap();
+
+        let reports = store.get_build_failure_reports(build.id, None, 10).await.unwrap();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].exit_code, Some(2));
+        assert_eq!(reports[0].uptime_ms, Some(1500));
+        assert_eq!(reports[0].failed_probe.as_deref(), Some("tcp:127.0.0.1:8080"));
+        assert_eq!(reports[0].host.as_ref(), Some(&host));
+        assert_eq!(store.get_build_failure_reports(build.id, Some(client.id), 1).await.unwrap().len(), 1);
+        assert!(store.get_build_failure_reports(build.id, Some(Uuid::new_v4()), 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_client_labels() {
+        let mut store = setup_context!();
+        let app = store.create_application("abc", "abcd").await.unwrap();
+        let client = store.create_client(app.id, "0.0.1").await.unwrap();
+        let reported: Labels = serde_json::from_str(r#"{"region": "us", "site": "lab"}"#).unwrap();
+        store.update_client_version(client.id, "0.0.2", Some(&reported)).await.unwrap();
+        // Leaving out the labels keeps the reported labels.
+        store.update_client_version(client.id, "0.0.2", None).await.unwrap();
+
+        let admin: Labels = serde_json::from_str(r#"{"region": "eu"}"#).unwrap();
+        let client = store.set_client_labels(client.id, &admin).await.unwrap();
+        assert_eq!(client.reported_labels.0, reported);
+        let effective = client.effective_labels();
+        assert_eq!(effective.get("region"), Some("eu"));
+        assert_eq!(effective.get("site"), Some("lab"));
+
+        let clients = store.get_clients(app.id).await.unwrap();
+        assert_eq!(clients.len(), 1);
+        assert!(store.find_client(Uuid::new_v4()).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_targeted_build() {
+        let mut store = setup_context!();
+        let app = store.create_application("abc", "abcd").await.unwrap();
+        let latest = store.create_application_version(app.id, "0.0.1", true).await.unwrap();
+        let next = store.create_application_version(app.id, "0.0.2", false).await.unwrap();
+        let latest_build = store.create_application_build(latest.id, "x86_64", "http://example.com/0.0.1").await.unwrap();
+        let next_build = store.create_application_build(next.id, "x86_64", "http://example.com/0.0.2").await.unwrap();
+
+        let selector = Selector::parse("region=eu").unwrap();
+        let next = store.set_application_version_selector(next.id, Some(&selector)).await.unwrap();
+        assert_eq!(next.selector.as_deref(), Some("region=eu"));
+        let targeted = store.get_targeted_application_versions(app.id).await.unwrap();
+        assert_eq!(targeted.iter().map(|version| version.id).collect::<Vec<_>>(), vec![next.id]);
+
+        let resolved = store.resolve_latest_build(app.id, "x86_64", Uuid::new_v4(), Some(next.id)).await.unwrap();
+        assert_eq!(resolved.build_id, next_build.id);
+        let resolved = store.resolve_latest_build(app.id, "x86_64", Uuid::new_v4(), None).await.unwrap();
+        assert_eq!(resolved.build_id, latest_build.id);
+
+        // Promotion serves the version to every client.
+        let promoted = store.promote_application_version(next.id).await.unwrap();
+        assert_eq!(promoted.selector, None);
+        assert!(store.get_targeted_application_versions(app.id).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_build_artifacts_and_patches() {
+        let mut store = setup_context!();
+        let app = store.create_application("abc", "abcd").await.unwrap();
+        let old_version = store.create_application_version(app.id, "0.0.1", false).await.unwrap();
+        let new_version = store.create_application_version(app.id, "0.0.2", true).await.unwrap();
+        let old = store.create_application_build(old_version.id, "x86_64", "http://example.com/0.0.1").await.unwrap();
+        let new = store.create_application_build(new_version.id, "x86_64", "http://example.com/0.0.2").await.unwrap();
+        let other_target = store.create_application_build(new_version.id, "aarch64", "http://example.com/0.0.2").await.unwrap();
+        assert!(store.get_build_artifact(old.id).await.unwrap().is_none());
+
+        for build in [&old, &new, &other_target] {
+            store.save_build_artifact(build.id, "app_0.0.1", 10).await.unwrap();
+        }
+        let digest = "a".repeat(64);
+        let updated = store.set_application_build_sha256(new.id, &digest, Some("http://cvm/artifacts/app_0.0.2")).await.unwrap();
+        assert_eq!(updated.sha256.as_deref(), Some(digest.as_str()));
+        assert_eq!(updated.url, "http://cvm/artifacts/app_0.0.2");
+
+        let mut siblings: Vec<_> = store.get_stored_sibling_builds(new.id).await.unwrap().into_iter().map(|build| build.version).collect();
+        siblings.sort();
+        assert_eq!(siblings, vec!["0.0.1", "0.0.2"]);
+
+        store.save_build_patch(old.id, new.id, &digest, 4).await.unwrap();
+        let patches = store.get_build_patches_to(new.id).await.unwrap();
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].from_version, "0.0.1");
+        assert_eq!(store.get_build_patch(old.id, new.id).await.unwrap(), Some(patches[0].clone()));
+        assert!(store.get_build_patch(new.id, old.id).await.unwrap().is_none());
+
+        let resolved = store.resolve_latest_build(app.id, "x86_64", Uuid::new_v4(), None).await.unwrap();
+        assert_eq!(resolved.sha256, Some(digest));
+    }
+
     #[tokio::test]
     async fn test_delete_client() {
         let mut store = setup_context!();
@@ -420,4 +1668,244 @@ mod tests {
         let build = store.create_application_build(app_version.id, build_version, url).await.unwrap();
         assert_eq!(build.url, url);
     }
+
+    #[tokio::test]
+    async fn test_promote_application_version() {
+        let mut store = setup_context!();
+        let app = store.create_application("abc", "abcd").await.unwrap();
+        let first = store.create_application_version(app.id, "0.0.1", true).await.unwrap();
+        let second = store.create_application_version(app.id, "0.0.2", false).await.unwrap();
+        let promoted = store.promote_application_version(second.id).await.unwrap();
+        assert!(promoted.latest);
+        let latest = store.get_latest_application_version(app.id).await.unwrap().unwrap();
+        assert_eq!(latest.id, second.id);
+        let first = store.get_application_version_by_id(first.id).await.unwrap();
+        assert!(!first.latest);
+        assert!(promoted.released_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_update_application_version_metadata() {
+        let mut store = setup_context!();
+        let app = store.create_application("abc", "abcd").await.unwrap();
+        let version = store.create_application_version(app.id, "0.0.1", false).await.unwrap();
+        assert_eq!(version.metadata, serde_json::json!({}));
+
+        let update = VersionMetadataUpdate {
+            release_notes: Some("Fixes startup".to_string()),
+            metadata: Some(serde_json::json!({ "pipeline": 42 })),
+            ..Default::default()
+        };
+        let updated = store.update_application_version_metadata(version.id, &update).await.unwrap();
+        assert_eq!(updated.release_notes.as_deref(), Some("Fixes startup"));
+        assert_eq!(updated.metadata, serde_json::json!({ "pipeline": 42 }));
+
+        // Unset fields are left unchanged.
+        let update = VersionMetadataUpdate { git_commit: Some("abc123".to_string()), ..Default::default() };
+        let updated = store.update_application_version_metadata(version.id, &update).await.unwrap();
+        assert_eq!(updated.git_commit.as_deref(), Some("abc123"));
+        assert_eq!(updated.release_notes.as_deref(), Some("Fixes startup"));
+        assert_eq!(updated.metadata, serde_json::json!({ "pipeline": 42 }));
+    }
+
+    #[tokio::test]
+    async fn test_create_and_query_audit_entries() {
+        let mut store = setup_context!();
+        let app = store.create_application("abc", "abcd").await.unwrap();
+        let entry = store.create_audit_entry(&NewAuditEntry {
+            actor: "tester",
+            action: "application.create",
+            target_type: "application",
+            target_id: app.id,
+            app_id: Some(app.id),
+            before: None,
+            after: Some(serde_json::json!({ "name": "abc" })),
+        }).await.unwrap();
+        assert_eq!(entry.actor, "tester");
+
+        let filter = AuditFilter { app_id: Some(app.id), ..Default::default() };
+        let entries = store.get_audit_entries(&filter).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].after, Some(serde_json::json!({ "name": "abc" })));
+
+        let filter = AuditFilter { app_id: Some(app.id), action: Some("version.promote".to_string()), ..Default::default() };
+        assert!(store.get_audit_entries(&filter).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_latest_build() {
+        let mut store = setup_context!();
+        let app = store.create_application("abc", "abcd").await.unwrap();
+        let old_version = store.create_application_version(app.id, "0.0.1", false).await.unwrap();
+        let latest_version = store.create_application_version(app.id, "0.0.2", true).await.unwrap();
+        store.create_application_build(old_version.id, "x86_64", "http://example.com/0.0.1").await.unwrap();
+        let build = store.create_application_build(latest_version.id, "x86_64", "http://example.com/0.0.2").await.unwrap();
+
+        let resolved = store.resolve_latest_build(app.id, "x86_64", Uuid::new_v4(), None).await.unwrap();
+        assert_eq!(resolved.build_id, build.id);
+        assert_eq!(resolved.version, "0.0.2");
+        assert_eq!(resolved.min_supported_version, None);
+        assert!(resolved.mandatory_versions.is_empty());
+
+        store.set_application_min_supported_version(app.id, Some("0.0.2")).await.unwrap();
+        store.set_application_version_mandatory(old_version.id, true).await.unwrap();
+        let resolved = store.resolve_latest_build(app.id, "x86_64", Uuid::new_v4(), None).await.unwrap();
+        assert_eq!(resolved.min_supported_version, Some("0.0.2".to_string()));
+        assert_eq!(resolved.mandatory_versions, vec!["0.0.1".to_string()]);
+
+        store.set_application_build_disabled(build.id, true).await.unwrap();
+        assert!(store.resolve_latest_build(app.id, "x86_64", Uuid::new_v4(), None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_latest_build_during_rollout() {
+        let mut store = setup_context!();
+        let app = store.create_application("abc", "abcd").await.unwrap();
+        let latest = store.create_application_version(app.id, "0.0.1", true).await.unwrap();
+        let next = store.create_application_version(app.id, "0.0.2", false).await.unwrap();
+        let latest_build = store.create_application_build(latest.id, "x86_64", "http://example.com/0.0.1").await.unwrap();
+        let next_build = store.create_application_build(next.id, "x86_64", "http://example.com/0.0.2").await.unwrap();
+        let clients: Vec<Uuid> = (0..20).map(|_| Uuid::new_v4()).collect();
+
+        let next = store.set_application_version_rollout(next.id, Some(0)).await.unwrap();
+        assert_eq!(next.rollout_percentage, Some(0));
+        for client_id in &clients {
+            let resolved = store.resolve_latest_build(app.id, "x86_64", *client_id, None).await.unwrap();
+            assert_eq!(resolved.build_id, latest_build.id);
+        }
+
+        store.set_application_version_rollout(next.id, Some(100)).await.unwrap();
+        for client_id in &clients {
+            let resolved = store.resolve_latest_build(app.id, "x86_64", *client_id, None).await.unwrap();
+            assert_eq!(resolved.build_id, next_build.id);
+        }
+
+        // Promotion ends the rollout.
+        let promoted = store.promote_application_version(next.id).await.unwrap();
+        assert_eq!(promoted.rollout_percentage, None);
+    }
+
+    #[tokio::test]
+    async fn test_promote_over_an_active_rollout() {
+        let mut store = setup_context!();
+        let app = store.create_application("abc", "abcd").await.unwrap();
+        let first = store.create_application_version(app.id, "1.0.0", true).await.unwrap();
+        let rolled_out = store.create_application_version(app.id, "1.1.0", false).await.unwrap();
+        let promoted = store.create_application_version(app.id, "1.2.0", false).await.unwrap();
+        for version in [&first, &rolled_out, &promoted] {
+            store.create_application_build(version.id, "x86_64", "http://example.com/app").await.unwrap();
+        }
+        store.set_application_version_rollout(rolled_out.id, Some(100)).await.unwrap();
+        let selector = Selector::parse("region=eu").unwrap();
+        store.set_application_version_selector(rolled_out.id, Some(&selector)).await.unwrap();
+
+        store.promote_application_version(promoted.id).await.unwrap();
+        let rolled_out = store.get_application_version_by_id(rolled_out.id).await.unwrap();
+        assert_eq!(rolled_out.rollout_percentage, None);
+        assert!(rolled_out.selector.is_none());
+        let resolved = store.resolve_latest_build(app.id, "x86_64", Uuid::new_v4(), None).await.unwrap();
+        assert_eq!(resolved.version, "1.2.0");
+
+        // A rollout of an older version doesn't take clients back.
+        store.set_application_version_rollout(first.id, Some(100)).await.unwrap();
+        let resolved = store.resolve_latest_build(app.id, "x86_64", Uuid::new_v4(), None).await.unwrap();
+        assert_eq!(resolved.version, "1.2.0");
+    }
+
+    #[tokio::test]
+    async fn test_release_schedule_steps_become_due() {
+        let mut store = setup_context!();
+        let app = store.create_application("abc", "abcd").await.unwrap();
+        let version = store.create_application_version(app.id, "0.0.1", false).await.unwrap();
+        let now = Utc::now();
+        let schedule = store
+            .create_release_schedule(app.id, version.id, "tester", &[(5, now - chrono::Duration::minutes(1)), (100, now + chrono::Duration::hours(1))])
+            .await
+            .unwrap();
+        assert_eq!(schedule.status, "pending");
+        assert_eq!(schedule.version, "0.0.1");
+        assert_eq!(store.count_active_release_schedules(app.id).await.unwrap(), 1);
+
+        let steps = store.get_release_schedule_steps(schedule.id).await.unwrap();
+        assert_eq!(steps.iter().map(|step| step.rollout_percentage).collect::<Vec<_>>(), vec![5, 100]);
+
+        store.set_release_schedule_status(schedule.id, "cancelled", None).await.unwrap();
+        assert_eq!(store.count_active_release_schedules(app.id).await.unwrap(), 0);
+        let schedules = store.get_release_schedules(Some(app.id), Some("cancelled")).await.unwrap();
+        assert_eq!(schedules.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_advisory_xact_lock_is_exclusive() {
+        let lock_id = rand_lock_id();
+        let mut first = setup_transaction().await.unwrap();
+        let mut second = setup_transaction().await.unwrap();
+        assert!(first.try_advisory_xact_lock(lock_id).await.unwrap());
+        assert!(!second.try_advisory_xact_lock(lock_id).await.unwrap());
+
+        // The lock is released with the transaction.
+        first.commit().await.unwrap();
+        assert!(second.try_advisory_xact_lock(lock_id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_dropped_transactions_are_rolled_back() {
+        let mut transaction = setup_transaction().await.unwrap();
+        let app = transaction.create_application("abc", "abcd").await.unwrap();
+        drop(transaction);
+
+        let mut store = setup_context!();
+        assert!(store.get_application_by_id(app.id).await.is_err());
+        let mut transaction = setup_transaction().await.unwrap();
+        let app = transaction.create_application("abc", "abcd").await.unwrap();
+        transaction.commit().await.unwrap();
+        assert!(store.get_application_by_id(app.id).await.is_ok());
+    }
+
+    fn rand_lock_id() -> i64 {
+        Uuid::new_v4().as_u64_pair().0 as i64
+    }
+
+    #[tokio::test]
+    async fn test_resolve_latest_build_with_target_fallback() {
+        let mut store = setup_context!();
+        let suffix = Uuid::new_v4().simple().to_string();
+        let gnu = format!("x86_64-test{}-linux-gnu", suffix);
+        let musl = format!("x86_64-test{}-linux-musl", suffix);
+        store.create_target(&gnu, "").await.unwrap();
+        store.create_target(&musl, "").await.unwrap();
+        let target = store.set_target_fallback(&gnu, &musl, 0).await.unwrap();
+        assert_eq!(target.fallbacks, vec![musl.clone()]);
+
+        let app = store.create_application("abc", "abcd").await.unwrap();
+        let version = store.create_application_version(app.id, "0.0.1", true).await.unwrap();
+        let musl_build = store.create_application_build(version.id, &musl, "http://example.com/musl").await.unwrap();
+        let resolved = store.resolve_latest_build(app.id, &gnu, Uuid::new_v4(), None).await.unwrap();
+        assert_eq!(resolved.build_id, musl_build.id);
+        let build = store.get_application_build(app.id, "0.0.1", &gnu, false).await.unwrap();
+        assert_eq!(build.id, musl_build.id);
+
+        // A build for the target itself is preferred over its fallbacks.
+        let gnu_build = store.create_application_build(version.id, &gnu, "http://example.com/gnu").await.unwrap();
+        let resolved = store.resolve_latest_build(app.id, &gnu, Uuid::new_v4(), None).await.unwrap();
+        assert_eq!(resolved.build_id, gnu_build.id);
+
+        // Fallbacks only apply in one direction.
+        store.set_application_build_disabled(musl_build.id, true).await.unwrap();
+        assert!(store.resolve_latest_build(app.id, &musl, Uuid::new_v4(), None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_target_aliases() {
+        let mut store = setup_context!();
+        let triple = format!("aarch64-test{}-linux-gnu", Uuid::new_v4().simple());
+        let alias = format!("{}-alias", triple);
+        store.create_target(&triple, "test target").await.unwrap();
+        let target = store.set_target_alias(&alias, &triple).await.unwrap();
+        assert_eq!(target.aliases, vec![alias.clone()]);
+
+        let target = store.get_target(&alias).await.unwrap().unwrap();
+        assert_eq!(target.triple, triple);
+        assert!(store.get_target("unknown-unknown-none").await.unwrap().is_none());
+    }
 }
@@ -0,0 +1,397 @@
+use crate::config::Config;
+use sha2::{Digest, Sha256};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+const PATCH_COMPRESSION_LEVEL: i32 = 19;
+const BUILD_COMPRESSION_LEVEL: i32 = 15;
+
+/// Encodings builds are stored and served in, in order of preference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentEncoding {
+    Zstd,
+    Gzip,
+    Identity,
+}
+
+impl ContentEncoding {
+    const COMPRESSED: [ContentEncoding; 2] = [ContentEncoding::Zstd, ContentEncoding::Gzip];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentEncoding::Zstd => "zstd",
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Identity => "identity",
+        }
+    }
+
+    fn extension(&self) -> Option<&'static str> {
+        match self {
+            ContentEncoding::Zstd => Some("zst"),
+            ContentEncoding::Gzip => Some("gz"),
+            ContentEncoding::Identity => None,
+        }
+    }
+
+    fn compress(&self, content: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            ContentEncoding::Zstd => zstd::encode_all(content, BUILD_COMPRESSION_LEVEL),
+            ContentEncoding::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+                encoder.write_all(content)?;
+                encoder.finish()
+            }
+            ContentEncoding::Identity => Ok(content.to_vec()),
+        }
+    }
+}
+
+/// Returns the encodings acceptable to a client sending the `Accept-Encoding` header, most
+/// preferred first. Higher quality values win and zstd is preferred over gzip when they tie.
+/// Identity is always acceptable as a last resort, as every build is stored uncompressed.
+pub fn negotiate_encodings(accept_encoding: Option<&str>) -> Vec<ContentEncoding> {
+    let mut qualities: Vec<(&str, f32)> = Vec::new();
+    for item in accept_encoding.unwrap_or_default().split(',') {
+        let mut parts = item.split(';');
+        let coding = parts.next().unwrap_or_default().trim();
+        let quality = parts
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if !coding.is_empty() {
+            qualities.push((coding, quality));
+        }
+    }
+    let quality = |encoding: ContentEncoding| {
+        qualities
+            .iter()
+            .find(|(coding, _)| coding.eq_ignore_ascii_case(encoding.as_str()))
+            .or_else(|| qualities.iter().find(|(coding, _)| *coding == "*"))
+            .map(|(_, quality)| *quality)
+            .unwrap_or(0.0)
+    };
+
+    let mut encodings: Vec<(ContentEncoding, f32)> = ContentEncoding::COMPRESSED
+        .into_iter()
+        .map(|encoding| (encoding, quality(encoding)))
+        .filter(|(_, quality)| *quality > 0.0)
+        .collect();
+    encodings.sort_by(|a, b| b.1.total_cmp(&a.1));
+    encodings
+        .into_iter()
+        .map(|(encoding, _)| encoding)
+        .chain([ContentEncoding::Identity])
+        .collect()
+}
+
+/// Digest and size of a stored file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredFile {
+    pub sha256: String,
+    pub size: i64,
+}
+
+/// Stores build artifacts, and the binary patches between builds of consecutive versions, on the
+/// local file system:
+///
+/// - `{ARTIFACT_DIR}/builds/{build_id}`
+/// - `{ARTIFACT_DIR}/builds/{build_id}.zst` and `.gz`, compressed variants of the build
+/// - `{ARTIFACT_DIR}/patches/{from_build_id}_{to_build_id}.bsdiff.zst`
+///
+/// Patches turn the `from` build into the `to` build. They are bsdiff patches compressed with
+/// zstd, as bsdiff leaves the mostly empty difference blocks to be compressed.
+#[derive(Clone)]
+pub struct ArtifactStore {
+    root: PathBuf,
+}
+
+impl ArtifactStore {
+    pub fn new(app_config: &Config) -> Self {
+        ArtifactStore::at(&app_config.artifact_dir)
+    }
+
+    pub fn at(root: impl Into<PathBuf>) -> Self {
+        ArtifactStore { root: root.into() }
+    }
+
+    pub fn build_path(&self, build_id: Uuid) -> PathBuf {
+        self.build_variant_path(build_id, ContentEncoding::Identity)
+    }
+
+    pub fn build_variant_path(&self, build_id: Uuid, encoding: ContentEncoding) -> PathBuf {
+        let file_name = match encoding.extension() {
+            Some(extension) => format!("{}.{}", build_id, extension),
+            None => build_id.to_string(),
+        };
+        self.root.join("builds").join(file_name)
+    }
+
+    pub fn patch_path(&self, from_build_id: Uuid, to_build_id: Uuid) -> PathBuf {
+        self.root
+            .join("patches")
+            .join(format!("{}_{}.bsdiff.zst", from_build_id, to_build_id))
+    }
+
+    /// Stores the artifact of a build along with its compressed variants, replacing any previous
+    /// artifact. Variants that don't make the build smaller, e.g. of an archive that is compressed
+    /// already, are left out. Compressing is cpu bound and runs on the blocking thread pool.
+    pub async fn store_build(&self, build_id: Uuid, content: &[u8]) -> std::io::Result<StoredFile> {
+        for encoding in ContentEncoding::COMPRESSED {
+            let path = self.build_variant_path(build_id, encoding);
+            let raw = content.to_vec();
+            let compressed = tokio::task::spawn_blocking(move || encoding.compress(&raw))
+                .await
+                .map_err(std::io::Error::other)??;
+            if compressed.len() < content.len() {
+                write_file(&path, &compressed).await?;
+            } else if let Err(err) = tokio::fs::remove_file(&path).await {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    return Err(err);
+                }
+            }
+        }
+        write_file(&self.build_path(build_id), content).await?;
+        Ok(describe(content))
+    }
+
+    pub async fn read_build(&self, build_id: Uuid) -> std::io::Result<Vec<u8>> {
+        tokio::fs::read(self.build_path(build_id)).await
+    }
+
+    /// Opens the build in the first of the encodings that is stored, for streaming it to a client.
+    /// Returns the file along with its length.
+    pub async fn open_build_encoded(
+        &self,
+        build_id: Uuid,
+        encodings: &[ContentEncoding],
+    ) -> std::io::Result<(ContentEncoding, tokio::fs::File, u64)> {
+        for encoding in encodings.iter().chain([&ContentEncoding::Identity]) {
+            match tokio::fs::File::open(self.build_variant_path(build_id, *encoding)).await {
+                Ok(file) => {
+                    let length = file.metadata().await?.len();
+                    return Ok((*encoding, file, length));
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Err(std::io::ErrorKind::NotFound.into())
+    }
+
+    pub async fn read_patch(&self, from_build_id: Uuid, to_build_id: Uuid) -> std::io::Result<Vec<u8>> {
+        tokio::fs::read(self.patch_path(from_build_id, to_build_id)).await
+    }
+
+    /// Computes and stores the patch from one stored build to another. Diffing is cpu bound and
+    /// runs on the blocking thread pool.
+    pub async fn create_patch(&self, from_build_id: Uuid, to_build_id: Uuid) -> std::io::Result<StoredFile> {
+        let old = self.read_build(from_build_id).await?;
+        let new = self.read_build(to_build_id).await?;
+        let patch = tokio::task::spawn_blocking(move || {
+            let mut patch = zstd::Encoder::new(Vec::new(), PATCH_COMPRESSION_LEVEL)?;
+            bsdiff::diff(&old, &new, &mut patch)?;
+            patch.finish()
+        })
+        .await
+        .map_err(std::io::Error::other)??;
+
+        write_file(&self.patch_path(from_build_id, to_build_id), &patch).await?;
+        Ok(describe(&patch))
+    }
+}
+
+/// An inclusive range of bytes requested with a `Range` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn length(&self) -> u64 {
+        self.end - self.start + 1
+    }
+}
+
+/// Parses a `Range` header for a file of the given length. Only a single range is supported:
+/// `bytes=start-end`, `bytes=start-` and `bytes=-suffix_length`. Returns `None` when the header
+/// should be ignored, i.e. the whole file served, and `Some(Err(()))` when the range can't be
+/// satisfied.
+pub fn parse_byte_range(value: &str, length: u64) -> Option<Result<ByteRange, ()>> {
+    let (start, end) = value.trim().strip_prefix("bytes=")?.trim().split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+    let range = match (start.is_empty(), end.is_empty()) {
+        (false, _) => {
+            let start: u64 = start.parse().ok()?;
+            let end = if end.is_empty() {
+                u64::MAX
+            } else {
+                end.parse().ok().filter(|end| *end >= start)?
+            };
+            if start >= length {
+                return Some(Err(()));
+            }
+            ByteRange {
+                start,
+                end: end.min(length - 1),
+            }
+        }
+        (true, false) => {
+            let suffix_length: u64 = end.parse().ok()?;
+            if suffix_length == 0 || length == 0 {
+                return Some(Err(()));
+            }
+            ByteRange {
+                start: length.saturating_sub(suffix_length),
+                end: length - 1,
+            }
+        }
+        (true, true) => return None,
+    };
+    Some(Ok(range))
+}
+
+/// Lowercase hex sha256 digest of the content.
+pub fn sha256_hex(content: &[u8]) -> String {
+    hex::encode(Sha256::digest(content))
+}
+
+/// Returns the digest in lowercase when it is a hex sha256 digest.
+pub fn parse_sha256(value: &str) -> Option<String> {
+    let value = value.trim().to_ascii_lowercase();
+    (value.len() == 64 && value.chars().all(|c| c.is_ascii_hexdigit())).then_some(value)
+}
+
+fn describe(content: &[u8]) -> StoredFile {
+    StoredFile {
+        sha256: sha256_hex(content),
+        size: content.len() as i64,
+    }
+}
+
+/// Writes to a temporary file first so that readers never see a partially written file.
+async fn write_file(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    tokio::fs::write(&partial, content).await?;
+    tokio::fs::rename(&partial, path).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_store() -> ArtifactStore {
+        ArtifactStore::at(std::env::temp_dir().join(format!("cvm_artifacts_{}", Uuid::new_v4())))
+    }
+
+    #[tokio::test]
+    async fn test_store_build() {
+        let store = test_store();
+        let build_id = Uuid::new_v4();
+        let stored = store.store_build(build_id, b"hello").await.unwrap();
+        assert_eq!(stored.size, 5);
+        assert_eq!(
+            stored.sha256,
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+        assert_eq!(store.read_build(build_id).await.unwrap(), b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_patch_turns_the_old_build_into_the_new_build() {
+        let store = test_store();
+        let (old_id, new_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let old: Vec<u8> = (0..20_000u32).flat_map(|i| i.to_le_bytes()).collect();
+        let mut new = old.clone();
+        new[1000..1016].copy_from_slice(b"version 0.2.0!!!");
+        store.store_build(old_id, &old).await.unwrap();
+        store.store_build(new_id, &new).await.unwrap();
+
+        let stored = store.create_patch(old_id, new_id).await.unwrap();
+        let patch = store.read_patch(old_id, new_id).await.unwrap();
+        assert_eq!(stored.sha256, sha256_hex(&patch));
+        assert!(patch.len() < new.len() / 10);
+
+        let mut patched = Vec::new();
+        let mut decoder = zstd::Decoder::new(patch.as_slice()).unwrap();
+        bsdiff::patch(&old, &mut decoder, &mut patched).unwrap();
+        assert_eq!(patched, new);
+    }
+
+    #[tokio::test]
+    async fn test_store_build_keeps_compressed_variants() {
+        let store = test_store();
+        let build_id = Uuid::new_v4();
+        let content = b"hello hello hello hello hello hello hello hello".repeat(100);
+        store.store_build(build_id, &content).await.unwrap();
+
+        let (encoding, zstd) = read_encoded(&store, build_id, "gzip, zstd").await;
+        assert_eq!(encoding, ContentEncoding::Zstd);
+        assert_eq!(zstd::decode_all(zstd.as_slice()).unwrap(), content);
+
+        let (encoding, gzip) = read_encoded(&store, build_id, "gzip").await;
+        assert_eq!(encoding, ContentEncoding::Gzip);
+        let mut decoded = Vec::new();
+        std::io::Read::read_to_end(&mut flate2::read::GzDecoder::new(gzip.as_slice()), &mut decoded).unwrap();
+        assert_eq!(decoded, content);
+
+        // Replacing the build with one that doesn't compress drops the stale variants.
+        let incompressible: Vec<u8> = (0..64u8).map(|i| i.wrapping_mul(151) ^ 0x5a).collect();
+        store.store_build(build_id, &incompressible).await.unwrap();
+        let (encoding, identity) = read_encoded(&store, build_id, "zstd, gzip").await;
+        assert_eq!(encoding, ContentEncoding::Identity);
+        assert_eq!(identity, incompressible);
+    }
+
+    async fn read_encoded(store: &ArtifactStore, build_id: Uuid, accept_encoding: &str) -> (ContentEncoding, Vec<u8>) {
+        let (encoding, mut file, length) = store
+            .open_build_encoded(build_id, &negotiate_encodings(Some(accept_encoding)))
+            .await
+            .unwrap();
+        let mut content = Vec::new();
+        tokio::io::AsyncReadExt::read_to_end(&mut file, &mut content).await.unwrap();
+        assert_eq!(content.len() as u64, length);
+        (encoding, content)
+    }
+
+    #[test]
+    fn test_parse_byte_range() {
+        let range = |start, end| Some(Ok(ByteRange { start, end }));
+        assert_eq!(parse_byte_range("bytes=0-99", 1000), range(0, 99));
+        assert_eq!(parse_byte_range("bytes=500-", 1000), range(500, 999));
+        assert_eq!(parse_byte_range("bytes=900-2000", 1000), range(900, 999));
+        assert_eq!(parse_byte_range("bytes=-100", 1000), range(900, 999));
+        assert_eq!(parse_byte_range("bytes=-2000", 1000), range(0, 999));
+        assert_eq!(parse_byte_range("bytes=1000-", 1000), Some(Err(())));
+        assert_eq!(parse_byte_range("bytes=-0", 1000), Some(Err(())));
+        assert_eq!(parse_byte_range("bytes=5-1", 1000), None);
+        assert_eq!(parse_byte_range("bytes=0-1,5-9", 1000), None);
+        assert_eq!(parse_byte_range("items=0-1", 1000), None);
+        assert_eq!(range(10, 19).unwrap().unwrap().length(), 10);
+    }
+
+    #[test]
+    fn test_negotiate_encodings() {
+        use ContentEncoding::*;
+        assert_eq!(negotiate_encodings(None), vec![Identity]);
+        assert_eq!(negotiate_encodings(Some("gzip, deflate, br")), vec![Gzip, Identity]);
+        assert_eq!(negotiate_encodings(Some("gzip, zstd")), vec![Zstd, Gzip, Identity]);
+        assert_eq!(negotiate_encodings(Some("zstd;q=0.5, gzip")), vec![Gzip, Zstd, Identity]);
+        assert_eq!(negotiate_encodings(Some("*")), vec![Zstd, Gzip, Identity]);
+        assert_eq!(negotiate_encodings(Some("*, zstd;q=0")), vec![Gzip, Identity]);
+        assert_eq!(negotiate_encodings(Some("ZSTD; q=0.8")), vec![Zstd, Identity]);
+    }
+
+    #[test]
+    fn test_parse_sha256() {
+        let digest = "2CF24DBA5FB0A30E26E83B2AC5B9E29E1B161E5C1FA7425E73043362938B9824";
+        assert_eq!(parse_sha256(digest), Some(digest.to_ascii_lowercase()));
+        assert_eq!(parse_sha256("abc"), None);
+        assert_eq!(parse_sha256(&"g".repeat(64)), None);
+    }
+}
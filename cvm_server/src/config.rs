@@ -7,6 +7,12 @@ pub const DB_NAME: &str = "client_version_manager";
 pub const CONTENT_URL_DEFAULT: &str = "https://hello-versioned.s3.us-east-1.amazonaws.com/";
 pub const DB_URL_TEMPLATE: &str = "postgresql://{user}@{host}:5432/{db_name}";
 pub const DEFAULT_VERSION: &str = "0.0.0";
+pub const WEBHOOK_MAX_ATTEMPTS_DEFAULT: &str = "5";
+pub const WEBHOOK_RETRY_BASE_DELAY_MS_DEFAULT: &str = "1000";
+pub const RELEASE_SCHEDULER_INTERVAL_SECS_DEFAULT: &str = "30";
+pub const ARTIFACT_DIR_DEFAULT: &str = "artifacts";
+pub const ARTIFACT_BASE_URL_DEFAULT: &str = "http://127.0.0.1:3000";
+pub const ARTIFACT_MAX_SIZE_MB_DEFAULT: &str = "512";
 
 pub struct Config{
     pub db_host: String,
@@ -16,9 +22,24 @@ pub struct Config{
     pub db_url: String,
     pub content_url: String,
     pub default_version: String,
+    /// Number of times a webhook delivery is attempted before it is marked as failed.
+    pub webhook_max_attempts: u32,
+    /// Delay before the first webhook retry. Each following retry doubles the delay.
+    pub webhook_retry_base_delay_ms: u64,
+    /// Number of failed startups after which a build is disabled. Builds are never disabled
+    /// automatically when unset.
+    pub auto_disable_failure_threshold: Option<i32>,
+    /// How often the release scheduler looks for scheduled release steps that are due.
+    pub release_scheduler_interval_secs: u64,
+    /// Directory build artifacts and patches are stored in.
+    pub artifact_dir: String,
+    /// Url clients reach this server at, used in the urls of stored artifacts.
+    pub artifact_base_url: String,
+    /// Largest artifact that can be uploaded.
+    pub artifact_max_size_mb: usize,
 }
 
-fn get_env_var_or<'a>(key: &str, default: &str) -> String {
+fn get_env_var_or(key: &str, default: &str) -> String {
     std::env::var(key).unwrap_or_else(|_| default.to_string())
 }
 
@@ -27,6 +48,27 @@ pub static CONFIG: Lazy<Config> = Lazy::new(|| {
     let db_user = get_env_var_or("DB_USER", DB_USER_DEFAULT);
     let db_pwd = get_env_var_or("DB_PWD", DB_PWD_DEFAULT);
     let content_url = get_env_var_or("CONTENT_URL", CONTENT_URL_DEFAULT);
+    let webhook_max_attempts = get_env_var_or("WEBHOOK_MAX_ATTEMPTS", WEBHOOK_MAX_ATTEMPTS_DEFAULT)
+        .parse()
+        .expect("WEBHOOK_MAX_ATTEMPTS must be a positive number");
+    let webhook_retry_base_delay_ms = get_env_var_or("WEBHOOK_RETRY_BASE_DELAY_MS", WEBHOOK_RETRY_BASE_DELAY_MS_DEFAULT)
+        .parse()
+        .expect("WEBHOOK_RETRY_BASE_DELAY_MS must be a positive number");
+    let auto_disable_failure_threshold = std::env::var("AUTO_DISABLE_FAILURE_THRESHOLD")
+        .ok()
+        .map(|threshold| threshold.parse().expect("AUTO_DISABLE_FAILURE_THRESHOLD must be a number"));
+    let release_scheduler_interval_secs = get_env_var_or("RELEASE_SCHEDULER_INTERVAL_SECS", RELEASE_SCHEDULER_INTERVAL_SECS_DEFAULT)
+        .parse::<u64>()
+        .ok()
+        .filter(|&secs| secs > 0)
+        .expect("RELEASE_SCHEDULER_INTERVAL_SECS must be a positive number");
+    let artifact_dir = get_env_var_or("ARTIFACT_DIR", ARTIFACT_DIR_DEFAULT);
+    let artifact_base_url = get_env_var_or("ARTIFACT_BASE_URL", ARTIFACT_BASE_URL_DEFAULT)
+        .trim_end_matches('/')
+        .to_string();
+    let artifact_max_size_mb = get_env_var_or("ARTIFACT_MAX_SIZE_MB", ARTIFACT_MAX_SIZE_MB_DEFAULT)
+        .parse()
+        .expect("ARTIFACT_MAX_SIZE_MB must be a positive number");
     let db_name = if std::env::var("CARGO_TEST").is_ok() {
         format!("{}_test", DB_NAME)
     } else {
@@ -45,6 +87,13 @@ pub static CONFIG: Lazy<Config> = Lazy::new(|| {
         db_url,
         content_url,
         default_version: DEFAULT_VERSION.to_string(),
+        webhook_max_attempts,
+        webhook_retry_base_delay_ms,
+        auto_disable_failure_threshold,
+        release_scheduler_interval_secs,
+        artifact_dir,
+        artifact_base_url,
+        artifact_max_size_mb,
     }
 });
 
@@ -4,45 +4,146 @@ pub static TABLE_APPLICATION_VERSIONS: &str = "application_versions";
 
 // Queries
 pub static QUERY_CLIENT: &str = r#"
-    SELECT id, app_id, updated_at, version, enabled, created_at FROM clients WHERE id = $1
+    SELECT id, app_id, updated_at, version, enabled, created_at, reported_labels, labels
+    FROM clients WHERE id = $1
 "#;
 
+pub static QUERY_CLIENTS_BY_APP: &str = r#"
+    SELECT id, app_id, updated_at, version, enabled, created_at, reported_labels, labels
+    FROM clients WHERE app_id = $1
+    ORDER BY created_at
+"#;
+
+/// Reported labels are only replaced when the client sent them.
 pub static UPDATE_CLIENT: &str = r#"
-    UPDATE clients SET version = $1, updated_at=now() WHERE id = $2
+    UPDATE clients SET version = $1, reported_labels = coalesce($3, reported_labels), updated_at=now() WHERE id = $2
+"#;
+
+pub static UPDATE_CLIENT_LABELS: &str = r#"
+    UPDATE clients SET labels = $2, updated_at = now() WHERE id = $1
+    RETURNING id, app_id, updated_at, version, enabled, created_at, reported_labels, labels
 "#;
 
 pub static INSERT_CLIENT: &str = r#"
     INSERT INTO clients (app_id, version, build_version)
     VALUES ($1, $2, $3)
-    RETURNING id, app_id, created_at, updated_at, build_version, version, enabled;
+    RETURNING id, app_id, created_at, updated_at, build_version, version, enabled, reported_labels, labels;
 "#;
 
 pub static DELETE_CLIENT_BY_ID: &str = "DELETE FROM clients WHERE id = $1;";
 
+pub static INSERT_CLIENT_ROLLBACK: &str = r#"
+    INSERT INTO client_rollbacks (client_id, app_id, architecture, failed_version, version)
+    VALUES ($1, $2, $3, $4, $5)
+    RETURNING id, client_id, app_id, architecture, failed_version, version, created_at
+"#;
+
+pub static QUERY_CLIENT_ROLLBACKS: &str = r#"
+    SELECT id, client_id, app_id, architecture, failed_version, version, created_at
+    FROM client_rollbacks
+    WHERE app_id = $1
+      and ($2::uuid IS NULL OR client_id = $2)
+    ORDER BY created_at DESC
+    LIMIT $3
+"#;
+
+pub static INSERT_CLIENT_CRASH: &str = r#"
+    INSERT INTO client_crashes (client_id, app_id, architecture, version, exit_code, signal, crash_count, restarting,
+                                stderr_tail)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+    RETURNING id, client_id, app_id, architecture, version, exit_code, signal, crash_count, restarting, stderr_tail,
+              created_at
+"#;
+
+pub static QUERY_CLIENT_CRASHES: &str = r#"
+    SELECT id, client_id, app_id, architecture, version, exit_code, signal, crash_count, restarting, stderr_tail,
+           created_at
+    FROM client_crashes
+    WHERE app_id = $1
+      and ($2::uuid IS NULL OR client_id = $2)
+    ORDER BY created_at DESC
+    LIMIT $3
+"#;
+
+pub static INSERT_BUILD_FAILURE_REPORT: &str = r#"
+    INSERT INTO build_failure_reports (build_id, client_id, app_id, architecture, version, exit_code, signal, uptime_ms,
+                                       stderr_tail, failed_probe, error, host)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+    RETURNING id, build_id, client_id, app_id, architecture, version, exit_code, signal, uptime_ms, stderr_tail,
+              failed_probe, error, host, created_at
+"#;
+
+pub static QUERY_BUILD_FAILURE_REPORTS: &str = r#"
+    SELECT id, build_id, client_id, app_id, architecture, version, exit_code, signal, uptime_ms, stderr_tail,
+           failed_probe, error, host, created_at
+    FROM build_failure_reports
+    WHERE build_id = $1
+      and ($2::uuid IS NULL OR client_id = $2)
+    ORDER BY created_at DESC
+    LIMIT $3
+"#;
+
 pub static QUERY_APPLICATION_VERSION: &str = r#"
-    SELECT id, app_id, version, latest
+    SELECT id, app_id, version, latest, mandatory, release_notes, released_at, git_commit, metadata, rollout_percentage, selector
     FROM application_versions
     WHERE id = $1
 "#;
 
 pub static QUERY_LATEST_BUILD_VERSION: &str = r#"
-    select ab.id, app_version_id, build_version, success_count, failed_count, url, disabled
+    select ab.id, app_version_id, build_version, success_count, failed_count, url, disabled, sha256
     from application_builds ab
     inner join application_versions av on ab.app_version_id = av.id
     where av.latest = true and ab.build_version = $1 and av.app_id = $2 and ab.disabled = false
 "#;
 
+/// Builds for the target itself are preferred over builds for its fallbacks. A version that is being
+/// rolled out is a candidate next to the latest version for the clients whose bucket, derived from
+/// the client and version ids, falls within the rollout percentage, and the newer of the two by
+/// semver is served. The targeted version `$4`, whose selector matched the client's labels, is
+/// preferred over both.
+pub static QUERY_LATEST_RESOLVED_BUILD: &str = r#"
+    select ab.id as build_id, ab.app_version_id, av.version, ab.url, ab.sha256,
+        av.release_notes, av.released_at, av.git_commit, av.metadata,
+        (select min_supported_version from applications where id = $2) as min_supported_version,
+        array(select version from application_versions where app_id = $2 and mandatory = true)::text[] as mandatory_versions
+    from application_builds ab
+    inner join application_versions av on ab.app_version_id = av.id
+    inner join (
+        select $1 as triple, 0 as rank, 0 as priority
+        union all
+        select fallback, 1, priority from target_fallbacks where triple = $1
+    ) candidates on ab.build_version = candidates.triple
+    where av.app_id = $2 and ab.disabled = false and (
+        av.latest = true or av.id = $4 or (
+            av.rollout_percentage is not null
+            and mod(abs(hashtext($3::text || av.id::text)::bigint), 100) < av.rollout_percentage
+        )
+    )
+    order by (av.id = $4) is true desc,
+        string_to_array(regexp_replace(av.version, '[-+].*$', ''), '.')::numeric[] desc,
+        split_part(av.version, '+', 1) like '%-%',
+        candidates.rank, candidates.priority
+    limit 1
+"#;
+
 pub static QUERY_APPLICATION_BUILD_VERSION: &str = r#"
-    select ab.id, app_version_id, build_version, success_count, failed_count, url, disabled
+    select ab.id, app_version_id, build_version, success_count, failed_count, url, disabled, sha256
     from application_builds ab
         inner join application_versions av on ab.app_version_id = av.id
-    where av.app_id = $1 and av.version = $2 and ab.build_version = $3 and ab.disabled = false
+        inner join (
+            select $3 as triple, 0 as rank, 0 as priority
+            union all
+            select fallback, 1, priority from target_fallbacks where triple = $3
+        ) candidates on ab.build_version = candidates.triple
+    where av.app_id = $1 and av.version = $2 and ab.disabled = false
+    order by candidates.rank, candidates.priority
+    limit 1
 "#;
 
 pub static INSERT_APPLICATION_VERSION: &str = r#"
     INSERT INTO application_versions (app_id, version, latest)
     VALUES ($1, $2, $3)
-    RETURNING id, app_id, version, latest;
+    RETURNING id, app_id, version, latest, mandatory, release_notes, released_at, git_commit, metadata, rollout_percentage, selector;
  "#;
 
 pub static UPDATE_APPLICATION_BUILD_SUCCESS: &str = r#"
@@ -60,11 +161,11 @@ pub static UPDATE_APPLICATION_BUILD_FAILURE: &str = r#"
 pub static INSERT_APPLICATION_BUILD: &str = r#"
     INSERT INTO application_builds (url, build_version, app_version_id)
     VALUES ($1, $2, $3)
-    RETURNING id, app_version_id, success_count, failed_count, build_version, url, disabled
+    RETURNING id, app_version_id, success_count, failed_count, build_version, url, disabled, sha256
 "#;
 
 pub static QUERY_APPLICATION_BY_ID: &str = r#"
-    SELECT id, name, description, created_at
+    SELECT id, name, description, min_supported_version, created_at
     FROM applications
     WHERE id = $1
 "#;
@@ -72,7 +173,7 @@ pub static QUERY_APPLICATION_BY_ID: &str = r#"
 pub static INSERT_INTO_APPLICATION: &str = r#"
     INSERT INTO applications (name, description)
     VALUES ($1, $2)
-    RETURNING id, name, description, created_at
+    RETURNING id, name, description, min_supported_version, created_at
 "#;
 
 pub static DELETE_APPLICATION: &str = r#"
@@ -80,5 +181,352 @@ pub static DELETE_APPLICATION: &str = r#"
     WHERE id = $1
 "#;
 
-pub static QUERY_ADVISORY_LOCK: &str = "SELECT pg_try_advisory_xact_lock($1);";
-pub static QUERY_ADVISORY_UNLOCK: &str = "SELECT pg_try_advisory_xact_lock($1);";
\ No newline at end of file
+pub static QUERY_APPLICATION_VERSION_BY_NUMBER: &str = r#"
+    SELECT id, app_id, version, latest, mandatory, release_notes, released_at, git_commit, metadata, rollout_percentage, selector
+    FROM application_versions
+    WHERE app_id = $1 and version = $2
+"#;
+
+pub static QUERY_LATEST_APPLICATION_VERSION: &str = r#"
+    SELECT id, app_id, version, latest, mandatory, release_notes, released_at, git_commit, metadata, rollout_percentage, selector
+    FROM application_versions
+    WHERE app_id = $1 and latest = true
+"#;
+
+pub static CLEAR_LATEST_APPLICATION_VERSION: &str = r#"
+    UPDATE application_versions
+    SET latest = false
+    WHERE app_id = $1 and latest = true
+"#;
+
+pub static SET_LATEST_APPLICATION_VERSION: &str = r#"
+    UPDATE application_versions
+    SET latest = true, rollout_percentage = NULL, selector = NULL, released_at = coalesce(released_at, now())
+    WHERE id = $1
+    RETURNING id, app_id, version, latest, mandatory, release_notes, released_at, git_commit, metadata, rollout_percentage, selector
+"#;
+
+pub static UPDATE_APPLICATION_MIN_SUPPORTED_VERSION: &str = r#"
+    UPDATE applications
+    SET min_supported_version = $2
+    WHERE id = $1
+    RETURNING id, name, description, min_supported_version, created_at
+"#;
+
+pub static UPDATE_APPLICATION_VERSION_MANDATORY: &str = r#"
+    UPDATE application_versions
+    SET mandatory = $2
+    WHERE id = $1
+    RETURNING id, app_id, version, latest, mandatory, release_notes, released_at, git_commit, metadata, rollout_percentage, selector
+"#;
+
+/// Unset parameters keep the current value.
+pub static UPDATE_APPLICATION_VERSION_METADATA: &str = r#"
+    UPDATE application_versions
+    SET release_notes = coalesce($2, release_notes),
+        released_at = coalesce($3, released_at),
+        git_commit = coalesce($4, git_commit),
+        metadata = coalesce($5, metadata)
+    WHERE id = $1
+    RETURNING id, app_id, version, latest, mandatory, release_notes, released_at, git_commit, metadata, rollout_percentage, selector
+"#;
+
+pub static CLEAR_APPLICATION_VERSION_TARGETING: &str = r#"
+    UPDATE application_versions
+    SET rollout_percentage = NULL, selector = NULL
+    WHERE app_id = $1 and (rollout_percentage is not null or selector is not null)
+"#;
+
+pub static CLEAR_APPLICATION_VERSION_ROLLOUTS: &str = r#"
+    UPDATE application_versions
+    SET rollout_percentage = NULL
+    WHERE app_id = $1 and id <> $2 and rollout_percentage is not null
+"#;
+
+pub static UPDATE_APPLICATION_VERSION_ROLLOUT: &str = r#"
+    UPDATE application_versions
+    SET rollout_percentage = $2
+    WHERE id = $1
+    RETURNING id, app_id, version, latest, mandatory, release_notes, released_at, git_commit, metadata, rollout_percentage, selector
+"#;
+
+pub static UPDATE_APPLICATION_VERSION_SELECTOR: &str = r#"
+    UPDATE application_versions
+    SET selector = $2
+    WHERE id = $1
+    RETURNING id, app_id, version, latest, mandatory, release_notes, released_at, git_commit, metadata, rollout_percentage, selector
+"#;
+
+pub static QUERY_TARGETED_APPLICATION_VERSIONS: &str = r#"
+    SELECT id, app_id, version, latest, mandatory, release_notes, released_at, git_commit, metadata, rollout_percentage, selector
+    FROM application_versions
+    WHERE app_id = $1 and selector is not null and latest = false
+"#;
+
+pub static QUERY_APPLICATION_BUILD_BY_ID: &str = r#"
+    select id, app_version_id, build_version, success_count, failed_count, url, disabled, sha256
+    from application_builds
+    where id = $1
+"#;
+
+pub static QUERY_APPLICATION_BUILDS_BY_VERSION: &str = r#"
+    select id, app_version_id, build_version, success_count, failed_count, url, disabled, sha256
+    from application_builds
+    where app_version_id = $1
+"#;
+
+pub static UPDATE_APPLICATION_BUILD_DISABLED: &str = r#"
+    UPDATE application_builds
+    SET disabled = $2
+    WHERE id = $1
+    RETURNING id, app_version_id, build_version, success_count, failed_count, url, disabled, sha256
+"#;
+
+pub static UPDATE_APPLICATION_VERSION_BUILDS_DISABLED: &str = r#"
+    UPDATE application_builds
+    SET disabled = $2
+    WHERE app_version_id = $1
+    RETURNING id, app_version_id, build_version, success_count, failed_count, url, disabled, sha256
+"#;
+
+pub static UPDATE_APPLICATION_BUILD_SHA256: &str = r#"
+    UPDATE application_builds
+    SET sha256 = $2, url = coalesce($3, url)
+    WHERE id = $1
+    RETURNING id, app_version_id, build_version, success_count, failed_count, url, disabled, sha256
+"#;
+
+pub static UPSERT_BUILD_ARTIFACT: &str = r#"
+    INSERT INTO build_artifacts (build_id, file_name, size)
+    VALUES ($1, $2, $3)
+    ON CONFLICT (build_id) DO UPDATE SET file_name = excluded.file_name, size = excluded.size, created_at = now()
+    RETURNING build_id, file_name, size, created_at
+"#;
+
+pub static QUERY_BUILD_ARTIFACT: &str = r#"
+    select build_id, file_name, size, created_at from build_artifacts where build_id = $1
+"#;
+
+/// Builds with stored artifacts for the same application and target as the given build, including
+/// the build itself.
+pub static QUERY_STORED_SIBLING_BUILDS: &str = r#"
+    select ab.id as build_id, av.version
+    from application_builds ab
+        inner join application_versions av on ab.app_version_id = av.id
+        inner join build_artifacts ba on ba.build_id = ab.id
+    where (av.app_id, ab.build_version) = (
+        select av.app_id, ab.build_version
+        from application_builds ab inner join application_versions av on ab.app_version_id = av.id
+        where ab.id = $1
+    )
+"#;
+
+pub static UPSERT_BUILD_PATCH: &str = r#"
+    INSERT INTO build_patches (from_build_id, to_build_id, sha256, size)
+    VALUES ($1, $2, $3, $4)
+    ON CONFLICT (from_build_id, to_build_id) DO UPDATE SET sha256 = excluded.sha256, size = excluded.size, created_at = now()
+"#;
+
+pub static QUERY_BUILD_PATCHES_TO: &str = r#"
+    select bp.from_build_id, bp.to_build_id, av.version as from_version, bp.sha256, bp.size
+    from build_patches bp
+        inner join application_builds ab on bp.from_build_id = ab.id
+        inner join application_versions av on ab.app_version_id = av.id
+    where bp.to_build_id = $1
+"#;
+
+pub static QUERY_BUILD_PATCH: &str = r#"
+    select bp.from_build_id, bp.to_build_id, av.version as from_version, bp.sha256, bp.size
+    from build_patches bp
+        inner join application_builds ab on bp.from_build_id = ab.id
+        inner join application_versions av on ab.app_version_id = av.id
+    where bp.from_build_id = $1 and bp.to_build_id = $2
+"#;
+
+pub static UPDATE_CLIENT_ENABLED: &str = r#"
+    UPDATE clients SET enabled = $2, updated_at = now() WHERE id = $1
+    RETURNING id, app_id, updated_at, version, enabled, created_at, reported_labels, labels
+"#;
+
+pub static INSERT_AUDIT_ENTRY: &str = r#"
+    INSERT INTO audit_log (actor, action, target_type, target_id, app_id, before, after)
+    VALUES ($1, $2, $3, $4, $5, $6, $7)
+    RETURNING id, actor, action, target_type, target_id, app_id, before, after, created_at
+"#;
+
+pub static QUERY_AUDIT_LOG: &str = r#"
+    SELECT id, actor, action, target_type, target_id, app_id, before, after, created_at
+    FROM audit_log
+    WHERE ($1::varchar IS NULL OR actor = $1)
+      and ($2::varchar IS NULL OR action = $2)
+      and ($3::uuid IS NULL OR app_id = $3)
+      and ($4::uuid IS NULL OR target_id = $4)
+      and ($5::timestamptz IS NULL OR created_at >= $5)
+      and ($6::timestamptz IS NULL OR created_at < $6)
+    ORDER BY created_at DESC
+    LIMIT $7 OFFSET $8
+"#;
+
+pub static INSERT_WEBHOOK_SUBSCRIPTION: &str = r#"
+    INSERT INTO webhook_subscriptions (url, secret, event_types, app_id)
+    VALUES ($1, $2, $3, $4)
+    RETURNING id, url, secret, event_types, app_id, enabled, created_at
+"#;
+
+pub static QUERY_WEBHOOK_SUBSCRIPTIONS: &str = r#"
+    SELECT id, url, secret, event_types, app_id, enabled, created_at
+    FROM webhook_subscriptions
+    ORDER BY created_at
+"#;
+
+pub static QUERY_WEBHOOK_SUBSCRIPTION_BY_ID: &str = r#"
+    SELECT id, url, secret, event_types, app_id, enabled, created_at
+    FROM webhook_subscriptions
+    WHERE id = $1
+"#;
+
+pub static QUERY_WEBHOOK_SUBSCRIPTIONS_FOR_EVENT: &str = r#"
+    SELECT id, url, secret, event_types, app_id, enabled, created_at
+    FROM webhook_subscriptions
+    WHERE enabled = true
+      and (cardinality(event_types) = 0 OR $1 = ANY(event_types))
+      and (app_id IS NULL OR app_id = $2)
+"#;
+
+pub static UPDATE_WEBHOOK_SUBSCRIPTION_ENABLED: &str = r#"
+    UPDATE webhook_subscriptions
+    SET enabled = $2
+    WHERE id = $1
+    RETURNING id, url, secret, event_types, app_id, enabled, created_at
+"#;
+
+pub static INSERT_WEBHOOK_DELIVERY: &str = r#"
+    INSERT INTO webhook_deliveries (subscription_id, event_id, event_type, payload)
+    VALUES ($1, $2, $3, $4)
+    RETURNING id, subscription_id, event_id, event_type, payload, status, attempts, response_status, last_error, created_at, updated_at
+"#;
+
+pub static UPDATE_WEBHOOK_DELIVERY: &str = r#"
+    UPDATE webhook_deliveries
+    SET status = $2, attempts = $3, response_status = $4, last_error = $5, updated_at = now()
+    WHERE id = $1
+"#;
+
+/// Deliveries still being retried, oldest first, whose subscription is enabled.
+pub static QUERY_PENDING_WEBHOOK_DELIVERIES: &str = r#"
+    SELECT d.id, d.subscription_id, d.event_id, d.event_type, d.payload, d.status, d.attempts, d.response_status, d.last_error, d.created_at, d.updated_at
+    FROM webhook_deliveries d
+    JOIN webhook_subscriptions s ON s.id = d.subscription_id
+    WHERE d.status = 'pending' and s.enabled
+    ORDER BY d.created_at
+"#;
+
+pub static QUERY_WEBHOOK_DELIVERIES: &str = r#"
+    SELECT id, subscription_id, event_id, event_type, payload, status, attempts, response_status, last_error, created_at, updated_at
+    FROM webhook_deliveries
+    WHERE ($1::uuid IS NULL OR subscription_id = $1)
+      and ($2::varchar IS NULL OR status = $2)
+    ORDER BY created_at DESC
+    LIMIT $3
+"#;
+
+/// Looks a target up by its triple or by one of its aliases.
+pub static QUERY_TARGET: &str = r#"
+    select t.id, t.triple, t.description,
+        array(select alias from target_aliases a where a.triple = t.triple order by alias)::text[] as aliases,
+        array(select fallback from target_fallbacks f where f.triple = t.triple order by priority, fallback)::text[] as fallbacks,
+        t.created_at
+    from targets t
+    where t.triple = coalesce((select triple from target_aliases where alias = $1), $1)
+"#;
+
+pub static QUERY_TARGETS: &str = r#"
+    select t.id, t.triple, t.description,
+        array(select alias from target_aliases a where a.triple = t.triple order by alias)::text[] as aliases,
+        array(select fallback from target_fallbacks f where f.triple = t.triple order by priority, fallback)::text[] as fallbacks,
+        t.created_at
+    from targets t
+    order by t.triple
+"#;
+
+pub static INSERT_TARGET: &str = r#"
+    INSERT INTO targets (triple, description)
+    VALUES ($1, $2)
+"#;
+
+pub static UPSERT_TARGET_ALIAS: &str = r#"
+    INSERT INTO target_aliases (alias, triple)
+    VALUES ($1, $2)
+    ON CONFLICT (alias) DO UPDATE SET triple = excluded.triple
+"#;
+
+pub static UPSERT_TARGET_FALLBACK: &str = r#"
+    INSERT INTO target_fallbacks (triple, fallback, priority)
+    VALUES ($1, $2, $3)
+    ON CONFLICT (triple, fallback) DO UPDATE SET priority = excluded.priority
+"#;
+
+pub static NOTIFY_RELEASE_CHANGED: &str = "SELECT pg_notify('cvm_release_changed', $1);";
+
+pub static INSERT_RELEASE_SCHEDULE: &str = r#"
+    INSERT INTO release_schedules (app_id, app_version_id, created_by)
+    VALUES ($1, $2, $3)
+    RETURNING id
+"#;
+
+pub static INSERT_RELEASE_SCHEDULE_STEP: &str = r#"
+    INSERT INTO release_schedule_steps (schedule_id, rollout_percentage, run_at)
+    VALUES ($1, $2, $3)
+"#;
+
+pub static QUERY_RELEASE_SCHEDULE: &str = r#"
+    select rs.id, rs.app_id, rs.app_version_id, av.version, rs.status, rs.last_error, rs.created_by,
+        rs.created_at, rs.updated_at
+    from release_schedules rs
+    inner join application_versions av on rs.app_version_id = av.id
+    where rs.id = $1
+"#;
+
+pub static QUERY_RELEASE_SCHEDULES: &str = r#"
+    select rs.id, rs.app_id, rs.app_version_id, av.version, rs.status, rs.last_error, rs.created_by,
+        rs.created_at, rs.updated_at
+    from release_schedules rs
+    inner join application_versions av on rs.app_version_id = av.id
+    where ($1::uuid is null or rs.app_id = $1)
+        and ($2::varchar is null or rs.status = $2)
+    order by rs.created_at desc
+"#;
+
+pub static QUERY_ACTIVE_RELEASE_SCHEDULE_COUNT: &str = r#"
+    select count(*) from release_schedules
+    where app_id = $1 and status in ('pending', 'in_progress')
+"#;
+
+pub static QUERY_RELEASE_SCHEDULE_STEPS: &str = r#"
+    select id, schedule_id, rollout_percentage, run_at, executed_at
+    from release_schedule_steps
+    where schedule_id = $1
+    order by run_at, rollout_percentage
+"#;
+
+pub static QUERY_NEXT_DUE_RELEASE_STEP: &str = r#"
+    select s.id, s.schedule_id, s.rollout_percentage, rs.app_id, rs.app_version_id
+    from release_schedule_steps s
+    inner join release_schedules rs on s.schedule_id = rs.id
+    where s.executed_at is null and s.run_at <= now() and rs.status in ('pending', 'in_progress')
+    order by s.run_at, s.rollout_percentage
+    limit 1
+"#;
+
+pub static UPDATE_RELEASE_SCHEDULE_STEP_EXECUTED: &str = r#"
+    UPDATE release_schedule_steps SET executed_at = now() WHERE id = $1
+"#;
+
+pub static UPDATE_RELEASE_SCHEDULE_STATUS: &str = r#"
+    UPDATE release_schedules
+    SET status = $2, last_error = $3, updated_at = now()
+    WHERE id = $1
+"#;
+
+/// Transaction level lock that is released when the transaction ends.
+pub static QUERY_ADVISORY_LOCK: &str = "SELECT pg_try_advisory_xact_lock($1);";
\ No newline at end of file
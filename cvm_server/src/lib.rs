@@ -0,0 +1,10 @@
+pub mod app_store;
+pub mod config;
+pub mod db_commands;
+pub mod server;
+pub mod webhooks;
+pub mod release_events;
+pub mod release_scheduler;
+pub mod targets;
+pub mod selector;
+pub mod artifacts;
@@ -1,7 +1,4 @@
-mod app_store;
-pub mod db_commands;
-pub mod config;
-pub mod server;
+use cvm_server::server;
 
 #[tokio::main]
 async fn main() {
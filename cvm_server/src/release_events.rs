@@ -0,0 +1,101 @@
+use sqlx::postgres::PgListener;
+use sqlx::PgPool;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+/// Postgres channel used to announce that the releases of an application changed. The payload is
+/// the application id.
+pub const RELEASE_CHANGED_CHANNEL: &str = "cvm_release_changed";
+
+const CHANNEL_CAPACITY: usize = 256;
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Fans release change notifications out to the update streams held open by clients.
+///
+/// Changes are announced through Postgres NOTIFY so that every server replica learns about a
+/// promotion regardless of which replica handled the admin request.
+#[derive(Clone)]
+pub struct ReleaseEvents {
+    sender: broadcast::Sender<Uuid>,
+}
+
+impl ReleaseEvents {
+    /// Starts listening for release changes on the database and returns the fan-out handle.
+    pub fn listen(pool: PgPool) -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        let events = ReleaseEvents { sender };
+        let forwarder = events.clone();
+        tokio::spawn(async move { forwarder.forward_notifications(pool).await });
+        events
+    }
+
+    /// Returns a receiver of the ids of applications whose releases changed.
+    pub fn subscribe(&self) -> broadcast::Receiver<Uuid> {
+        self.sender.subscribe()
+    }
+
+    async fn forward_notifications(&self, pool: PgPool) {
+        loop {
+            let mut listener = match PgListener::connect_with(&pool).await {
+                Ok(listener) => listener,
+                Err(err) => {
+                    tracing::error!("Unable to listen for release changes: {}", err);
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                    continue;
+                }
+            };
+            if let Err(err) = listener.listen(RELEASE_CHANGED_CHANNEL).await {
+                tracing::error!("Unable to listen for release changes: {}", err);
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+
+            loop {
+                match listener.recv().await {
+                    Ok(notification) => match Uuid::parse_str(notification.payload()) {
+                        // Nobody listening is not an error; the next stream will resolve on connect.
+                        Ok(app_id) => {
+                            let _ = self.sender.send(app_id);
+                        }
+                        Err(err) => tracing::error!("Invalid release notification: {}", err),
+                    },
+                    Err(err) => {
+                        tracing::error!("Lost release change listener: {}", err);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_store::tests::{setup, TEST_CONFIG};
+
+    #[tokio::test]
+    async fn test_release_change_is_forwarded_to_subscribers() {
+        let pool = PgPool::connect(&TEST_CONFIG.db_url).await.unwrap();
+        let events = ReleaseEvents::listen(pool);
+        let mut receiver = events.subscribe();
+        let app_id = Uuid::new_v4();
+
+        // The listener connects in the background, so keep announcing until it is up.
+        let mut app_store = setup().await.unwrap();
+        let received = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                app_store.notify_release_changed(app_id).await.unwrap();
+                if let Ok(Ok(received)) =
+                    tokio::time::timeout(Duration::from_millis(100), receiver.recv()).await
+                {
+                    return received;
+                }
+            }
+        })
+        .await
+        .expect("Release change was not forwarded");
+        assert_eq!(received, app_id);
+    }
+}
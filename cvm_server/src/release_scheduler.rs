@@ -0,0 +1,226 @@
+use crate::app_store::{AppStore, AppStoreError, DueReleaseStep};
+use crate::config::Config;
+use crate::server::{promote_scheduled_version, set_version_rollout, SYSTEM_ACTOR};
+use crate::webhooks::{WebhookDispatcher, WebhookEvent};
+use sqlx::PgPool;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+
+pub const SCHEDULE_PENDING: &str = "pending";
+pub const SCHEDULE_IN_PROGRESS: &str = "in_progress";
+pub const SCHEDULE_COMPLETED: &str = "completed";
+pub const SCHEDULE_CANCELLED: &str = "cancelled";
+pub const SCHEDULE_FAILED: &str = "failed";
+pub const SCHEDULE_STATUSES: [&str; 5] = [
+    SCHEDULE_PENDING,
+    SCHEDULE_IN_PROGRESS,
+    SCHEDULE_COMPLETED,
+    SCHEDULE_CANCELLED,
+    SCHEDULE_FAILED,
+];
+
+/// Advisory lock held by the replica that is executing release steps ("cvm_schd").
+const RELEASE_SCHEDULER_LOCK_ID: i64 = 0x6376_6d5f_7363_6864;
+
+/// Executes the steps of release schedules once they are due.
+///
+/// Every server replica runs a scheduler. Each step is executed in its own transaction that first
+/// takes a Postgres advisory lock, so only one replica executes steps at a time and a step is
+/// marked as executed in the same transaction that applies it. Webhook subscribers are only
+/// notified once that transaction is committed.
+#[derive(Clone)]
+pub struct ReleaseScheduler {
+    app_config: &'static Config,
+    pool: PgPool,
+    webhooks: WebhookDispatcher,
+}
+
+impl ReleaseScheduler {
+    pub fn new(app_config: &'static Config, pool: PgPool, webhooks: WebhookDispatcher) -> Self {
+        ReleaseScheduler { app_config, pool, webhooks }
+    }
+
+    /// Checks for due steps on the configured interval until the server stops.
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let period = Duration::from_secs(self.app_config.release_scheduler_interval_secs);
+            let mut interval = tokio::time::interval(period);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+            loop {
+                interval.tick().await;
+                if let Err(err) = self.run_due_steps().await {
+                    tracing::error!("Release scheduler failed: {}", err);
+                }
+            }
+        })
+    }
+
+    /// Executes every step that is due, oldest first, and returns how many were executed. Returns
+    /// right away when another replica holds the scheduler lock. A step that fails marks its
+    /// schedule as failed so that its remaining steps are skipped.
+    pub async fn run_due_steps(&self) -> Result<usize, AppStoreError> {
+        let mut executed = 0;
+        loop {
+            // Returning early drops the transaction, which rolls it back and releases the lock.
+            let mut app_store = AppStore::begin_transaction(self.app_config, &self.pool).await?;
+            if !app_store.try_advisory_xact_lock(RELEASE_SCHEDULER_LOCK_ID).await? {
+                return Ok(executed);
+            }
+            let Some(step) = app_store.get_next_due_release_step().await? else {
+                return Ok(executed);
+            };
+
+            match self.execute_step(&mut app_store, &step).await {
+                Ok(event) => {
+                    app_store.commit().await?;
+                    if let Some(event) = event {
+                        self.webhooks.publish_or_log(event).await;
+                    }
+                    executed += 1;
+                }
+                Err(message) => {
+                    drop(app_store);
+                    tracing::error!(
+                        "Release schedule {} failed at {}%: {}",
+                        step.schedule_id,
+                        step.rollout_percentage,
+                        message
+                    );
+                    AppStore::from_pg_pool(self.app_config, &self.pool)
+                        .await?
+                        .set_release_schedule_status(step.schedule_id, SCHEDULE_FAILED, Some(&message))
+                        .await?;
+                }
+            }
+        }
+    }
+
+    /// Rolls the version out to the step's percentage of clients, or promotes it when the step
+    /// reaches every client. Returns the event to publish once the step is committed.
+    async fn execute_step(
+        &self,
+        app_store: &mut AppStore,
+        step: &DueReleaseStep,
+    ) -> Result<Option<WebhookEvent>, String> {
+        app_store
+            .mark_release_step_executed(step.id)
+            .await
+            .map_err(|err| err.to_string())?;
+        let event = if step.rollout_percentage >= 100 {
+            // Completed before the promotion, which cancels the schedules that are still active.
+            app_store
+                .set_release_schedule_status(step.schedule_id, SCHEDULE_COMPLETED, None)
+                .await
+                .map_err(|err| err.to_string())?;
+            Some(promote_scheduled_version(app_store, step.app_version_id).await?)
+        } else {
+            set_version_rollout(app_store, SYSTEM_ACTOR, step.app_version_id, Some(step.rollout_percentage))
+                .await
+                .map_err(|(_, message)| message)?;
+            app_store
+                .set_release_schedule_status(step.schedule_id, SCHEDULE_IN_PROGRESS, None)
+                .await
+                .map_err(|err| err.to_string())?;
+            None
+        };
+        tracing::info!(
+            "Release schedule {} rolled version {} out to {}% of clients",
+            step.schedule_id,
+            step.app_version_id,
+            step.rollout_percentage
+        );
+        Ok(event)
+    }
+}
+
+/// Returns true when a schedule in this status may still execute steps.
+pub fn is_active(status: &str) -> bool {
+    status == SCHEDULE_PENDING || status == SCHEDULE_IN_PROGRESS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_store::tests::{setup, TEST_CONFIG};
+    use chrono::Utc;
+    use sqlx::postgres::PgPoolOptions;
+    use uuid::Uuid;
+
+    async fn scheduler() -> ReleaseScheduler {
+        let pool = PgPoolOptions::new()
+            .connect(&TEST_CONFIG.db_url)
+            .await
+            .expect("Failed to connect to test database");
+        let webhooks = WebhookDispatcher::new(&TEST_CONFIG, pool.clone());
+        ReleaseScheduler::new(&TEST_CONFIG, pool, webhooks)
+    }
+
+    async fn schedule(app_store: &mut AppStore, steps: &[(i32, i64)]) -> (Uuid, Uuid) {
+        let app = app_store.create_application("abc", "abcd").await.unwrap();
+        app_store.create_application_version(app.id, "0.0.1", true).await.unwrap();
+        let version = app_store.create_application_version(app.id, "0.0.2", false).await.unwrap();
+        let now = Utc::now();
+        let steps: Vec<_> = steps
+            .iter()
+            .map(|(percentage, minutes)| (*percentage, now + chrono::Duration::minutes(*minutes)))
+            .collect();
+        let schedule = app_store
+            .create_release_schedule(app.id, version.id, "tester", &steps)
+            .await
+            .unwrap();
+        (schedule.id, version.id)
+    }
+
+    #[tokio::test]
+    async fn test_executes_due_steps_in_order() {
+        let mut app_store = setup().await.unwrap();
+        let (schedule_id, version_id) = schedule(&mut app_store, &[(25, -2), (100, -1)]).await;
+
+        scheduler().await.run_due_steps().await.unwrap();
+
+        let schedule = app_store.get_release_schedule(schedule_id).await.unwrap();
+        assert_eq!(schedule.status, SCHEDULE_COMPLETED);
+        let version = app_store.get_application_version_by_id(version_id).await.unwrap();
+        assert!(version.latest);
+        assert_eq!(version.rollout_percentage, None);
+        let steps = app_store.get_release_schedule_steps(schedule_id).await.unwrap();
+        assert!(steps.iter().all(|step| step.executed_at.is_some()));
+    }
+
+    #[tokio::test]
+    async fn test_promotion_cancels_other_active_schedules() {
+        let mut app_store = setup().await.unwrap();
+        let (ramping_id, _) = schedule(&mut app_store, &[(10, -2), (100, 60)]).await;
+        let ramping = app_store.get_release_schedule(ramping_id).await.unwrap();
+        let next = app_store.create_application_version(ramping.app_id, "0.0.3", false).await.unwrap();
+        let now = Utc::now();
+        let promoting = app_store
+            .create_release_schedule(ramping.app_id, next.id, "tester", &[(100, now - chrono::Duration::minutes(1))])
+            .await
+            .unwrap();
+
+        scheduler().await.run_due_steps().await.unwrap();
+
+        let promoting = app_store.get_release_schedule(promoting.id).await.unwrap();
+        assert_eq!(promoting.status, SCHEDULE_COMPLETED);
+        let ramping = app_store.get_release_schedule(ramping_id).await.unwrap();
+        assert_eq!(ramping.status, SCHEDULE_CANCELLED);
+        let ramped = app_store.get_application_version_by_id(ramping.app_version_id).await.unwrap();
+        assert_eq!(ramped.rollout_percentage, None);
+    }
+
+    #[tokio::test]
+    async fn test_stops_at_steps_that_are_not_due() {
+        let mut app_store = setup().await.unwrap();
+        let (schedule_id, version_id) = schedule(&mut app_store, &[(5, -1), (100, 60)]).await;
+
+        scheduler().await.run_due_steps().await.unwrap();
+
+        let schedule = app_store.get_release_schedule(schedule_id).await.unwrap();
+        assert_eq!(schedule.status, SCHEDULE_IN_PROGRESS);
+        let version = app_store.get_application_version_by_id(version_id).await.unwrap();
+        assert!(!version.latest);
+        assert_eq!(version.rollout_percentage, Some(5));
+    }
+}
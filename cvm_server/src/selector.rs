@@ -0,0 +1,383 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Formatter;
+
+const MAX_KEY_LENGTH: usize = 63;
+const MAX_VALUE_LENGTH: usize = 255;
+
+/// Labels describe a client, e.g. `site=berlin` or `hardware=pi4`. Keys and values are made of
+/// letters, digits and `_ . - / :` so that they can be written in a selector without quoting.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "BTreeMap<String, String>", into = "BTreeMap<String, String>")]
+pub struct Labels(BTreeMap<String, String>);
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct InvalidLabel(String);
+
+impl std::fmt::Display for InvalidLabel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid label: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidLabel {}
+
+fn is_label_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-' | '/' | ':')
+}
+
+fn is_label_word(word: &str, max_length: usize) -> bool {
+    !word.is_empty() && word.len() <= max_length && word.chars().all(is_label_char)
+}
+
+impl Labels {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    pub fn insert(&mut self, key: &str, value: &str) -> Result<(), InvalidLabel> {
+        if !is_label_word(key, MAX_KEY_LENGTH) {
+            return Err(InvalidLabel(key.to_string()));
+        }
+        if !is_label_word(value, MAX_VALUE_LENGTH) {
+            return Err(InvalidLabel(format!("{}={}", key, value)));
+        }
+        self.0.insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.0.remove(key);
+    }
+
+    /// Returns these labels with `overrides` replacing labels that have the same key.
+    pub fn merged(&self, overrides: &Labels) -> Labels {
+        let mut labels = self.clone();
+        labels.0.extend(overrides.0.clone());
+        labels
+    }
+}
+
+impl TryFrom<BTreeMap<String, String>> for Labels {
+    type Error = InvalidLabel;
+
+    fn try_from(value: BTreeMap<String, String>) -> Result<Self, Self::Error> {
+        let mut labels = Labels::default();
+        for (key, value) in value {
+            labels.insert(&key, &value)?;
+        }
+        Ok(labels)
+    }
+}
+
+impl From<Labels> for BTreeMap<String, String> {
+    fn from(labels: Labels) -> Self {
+        labels.0
+    }
+}
+
+/// A boolean expression over client labels, e.g. `region=eu && site!=lab`.
+///
+/// - `key=value` (or `key==value`) matches clients whose label has the value.
+/// - `key!=value` matches clients whose label has another value or that don't have the label.
+/// - `key` matches clients that have the label and `!key` those that don't.
+/// - `&&` binds tighter than `||`, and parentheses group.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Selector {
+    source: String,
+    expression: Expression,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expression {
+    Equals(String, String),
+    NotEquals(String, String),
+    Exists(String),
+    NotExists(String),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct InvalidSelector {
+    selector: String,
+    reason: String,
+}
+
+impl std::fmt::Display for InvalidSelector {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid selector {}: {}", self.selector, self.reason)
+    }
+}
+
+impl std::error::Error for InvalidSelector {}
+
+impl Selector {
+    pub fn parse(value: &str) -> Result<Self, InvalidSelector> {
+        let invalid = |reason: String| InvalidSelector {
+            selector: value.to_string(),
+            reason,
+        };
+        let tokens = tokenize(value).map_err(invalid)?;
+        let mut parser = Parser { tokens, position: 0 };
+        let expression = parser.or().map_err(invalid)?;
+        if let Some(token) = parser.peek() {
+            return Err(invalid(format!("unexpected {}", token)));
+        }
+
+        Ok(Selector {
+            source: value.trim().to_string(),
+            expression,
+        })
+    }
+
+    pub fn matches(&self, labels: &Labels) -> bool {
+        self.expression.matches(labels)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+}
+
+impl Expression {
+    fn matches(&self, labels: &Labels) -> bool {
+        match self {
+            Expression::Equals(key, value) => labels.get(key) == Some(value),
+            Expression::NotEquals(key, value) => labels.get(key) != Some(value),
+            Expression::Exists(key) => labels.get(key).is_some(),
+            Expression::NotExists(key) => labels.get(key).is_none(),
+            Expression::And(left, right) => left.matches(labels) && right.matches(labels),
+            Expression::Or(left, right) => left.matches(labels) || right.matches(labels),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Equals,
+    NotEquals,
+    Not,
+    And,
+    Or,
+    Open,
+    Close,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "'{}'", word),
+            Token::Equals => write!(f, "'='"),
+            Token::NotEquals => write!(f, "'!='"),
+            Token::Not => write!(f, "'!'"),
+            Token::And => write!(f, "'&&'"),
+            Token::Or => write!(f, "'||'"),
+            Token::Open => write!(f, "'('"),
+            Token::Close => write!(f, "')'"),
+        }
+    }
+}
+
+fn tokenize(value: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '=' => {
+                chars.next_if_eq(&'=');
+                Token::Equals
+            }
+            '!' if chars.next_if_eq(&'=').is_some() => Token::NotEquals,
+            '!' => Token::Not,
+            '&' if chars.next_if_eq(&'&').is_some() => Token::And,
+            '|' if chars.next_if_eq(&'|').is_some() => Token::Or,
+            c if is_label_char(c) => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|c| is_label_char(*c)) {
+                    word.push(c);
+                }
+                Token::Word(word)
+            }
+            c => return Err(format!("unexpected '{}'", c)),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Expression, String> {
+        let mut expression = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            expression = Expression::Or(Box::new(expression), Box::new(self.and()?));
+        }
+        Ok(expression)
+    }
+
+    fn and(&mut self) -> Result<Expression, String> {
+        let mut expression = self.term()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            expression = Expression::And(Box::new(expression), Box::new(self.term()?));
+        }
+        Ok(expression)
+    }
+
+    fn term(&mut self) -> Result<Expression, String> {
+        match self.next() {
+            Some(Token::Open) => {
+                let expression = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expression),
+                    _ => Err("missing ')'".to_string()),
+                }
+            }
+            Some(Token::Not) => Ok(Expression::NotExists(self.word()?)),
+            Some(Token::Word(key)) => match self.peek() {
+                Some(Token::Equals) => {
+                    self.next();
+                    Ok(Expression::Equals(key, self.word()?))
+                }
+                Some(Token::NotEquals) => {
+                    self.next();
+                    Ok(Expression::NotEquals(key, self.word()?))
+                }
+                _ => Ok(Expression::Exists(key)),
+            },
+            Some(token) => Err(format!("unexpected {}", token)),
+            None => Err("unexpected end".to_string()),
+        }
+    }
+
+    fn word(&mut self) -> Result<String, String> {
+        match self.next() {
+            Some(Token::Word(word)) => Ok(word),
+            Some(token) => Err(format!("expected a label, found {}", token)),
+            None => Err("expected a label".to_string()),
+        }
+    }
+}
+
+impl TryFrom<String> for Selector {
+    type Error = InvalidSelector;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Selector::parse(&value)
+    }
+}
+
+impl From<Selector> for String {
+    fn from(selector: Selector) -> Self {
+        selector.source
+    }
+}
+
+impl std::fmt::Display for Selector {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(pairs: &[(&str, &str)]) -> Labels {
+        let mut labels = Labels::default();
+        for (key, value) in pairs {
+            labels.insert(key, value).unwrap();
+        }
+        labels
+    }
+
+    #[test]
+    fn test_labels_validate_keys_and_values() {
+        let mut labels = Labels::default();
+        assert!(labels.insert("customer/tier", "gold").is_ok());
+        assert!(labels.insert("site", "eu-west:1").is_ok());
+        assert!(labels.insert("", "x").is_err());
+        assert!(labels.insert("site", "").is_err());
+        assert!(labels.insert("site name", "berlin").is_err());
+        assert!(labels.insert("site", "berlin && x").is_err());
+        assert!(serde_json::from_str::<Labels>(r#"{"region": "eu"}"#).is_ok());
+        assert!(serde_json::from_str::<Labels>(r#"{"region": "e u"}"#).is_err());
+    }
+
+    #[test]
+    fn test_merged_labels_prefer_overrides() {
+        let reported = labels(&[("region", "us"), ("site", "lab")]);
+        let merged = reported.merged(&labels(&[("region", "eu")]));
+        assert_eq!(merged, labels(&[("region", "eu"), ("site", "lab")]));
+    }
+
+    #[test]
+    fn test_selector_matches_labels() {
+        let client = labels(&[("region", "eu"), ("site", "berlin"), ("hardware", "pi4")]);
+        for (selector, expected) in [
+            ("region=eu", true),
+            ("region==eu", true),
+            ("region=us", false),
+            ("region=eu && site!=lab", true),
+            ("region=eu && site!=berlin", false),
+            ("customer!=acme", true),
+            ("hardware", true),
+            ("!hardware", false),
+            ("!customer", true),
+            ("region=us || site=berlin", true),
+            ("region=us || site=lab && hardware", false),
+            ("(region=us || site=berlin) && hardware=pi4", true),
+            ("(region=us || site=lab) && hardware=pi4", false),
+        ] {
+            let parsed = Selector::parse(selector).unwrap();
+            assert_eq!(parsed.matches(&client), expected, "{}", selector);
+        }
+    }
+
+    #[test]
+    fn test_selector_rejects_malformed_expressions() {
+        for selector in [
+            "",
+            "region=",
+            "=eu",
+            "region=eu &&",
+            "region=eu & site=lab",
+            "(region=eu",
+            "region=eu)",
+            "region=eu site=lab",
+            "region=\"eu\"",
+            "!region=eu",
+        ] {
+            assert!(Selector::parse(selector).is_err(), "{} should be rejected", selector);
+        }
+    }
+
+    #[test]
+    fn test_selector_serializes_as_its_source() {
+        let selector: Selector = serde_json::from_str(r#"" region=eu && site!=lab ""#).unwrap();
+        assert_eq!(selector.as_str(), "region=eu && site!=lab");
+        assert_eq!(serde_json::to_string(&selector).unwrap(), r#""region=eu && site!=lab""#);
+        assert!(serde_json::from_str::<Selector>(r#""region=""#).is_err());
+    }
+}
@@ -1,41 +1,48 @@
-use crate::app_store::{AppStore, AppStoreError};
+use crate::app_store::{
+    self, AppStore, AppStoreError, AuditEntry, AuditFilter, NewAuditEntry, StoredBuild,
+    VersionMetadataUpdate,
+};
+use crate::artifacts::{self, negotiate_encodings, ArtifactStore, ContentEncoding};
 use crate::config::CONFIG;
+use crate::release_events::ReleaseEvents;
+use crate::release_scheduler::{
+    self, ReleaseScheduler, SCHEDULE_CANCELLED, SCHEDULE_IN_PROGRESS, SCHEDULE_STATUSES,
+};
+use crate::selector::{Labels, Selector};
+use crate::targets::TargetTriple;
+use crate::webhooks::{
+    WebhookDispatcher, WebhookEvent, EVENT_BUILD_AUTO_DISABLED, EVENT_CLIENT_CRASHED,
+    EVENT_CLIENT_ROLLED_BACK, EVENT_CLIENT_STARTUP_FAILED, EVENT_CLIENT_STOPPED, EVENT_TYPES,
+    EVENT_VERSION_PROMOTED,
+};
+use axum::body::{Body, Bytes};
 use axum::routing::post;
 use axum::{
     async_trait,
-    extract::{FromRef, FromRequestParts, State},
-    http::{request::Parts, StatusCode},
+    extract::{DefaultBodyLimit, FromRef, FromRequestParts, Path, Query, State},
+    http::{header, request::Parts, HeaderMap, HeaderValue, StatusCode},
+    response::sse::{Event, KeepAlive, Sse},
+    response::{IntoResponse, Response},
     routing::get,
     Json, Router,
 };
+use chrono::{DateTime, Utc};
+use futures_util::Stream;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use sqlx::postgres::PgPoolOptions;
-use sqlx::{Acquire, PgPool};
-use std::env::args;
-use std::sync::Arc;
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::io::SeekFrom;
 use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio::net::TcpListener;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_util::io::ReaderStream;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize)]
-enum Architecture {
-    #[serde(rename = "x86_64-pc-windows-gnu")]
-    X86_64Intel,
-    #[serde(rename = "x86_64-unknown-linux-gnu")]
-    X86_64Linux,
-}
-
-impl Architecture {
-    fn to_string(&self) -> &str {
-        // serde deserializes json with extra quotes.
-        match self {
-            Architecture::X86_64Intel => "x86_64-pc-windows-gnu",
-            Architecture::X86_64Linux => "x86_64-unknown-linux-gnu",
-        }
-    }
-}
-
 #[derive(Deserialize)]
 struct CreateApplication {
     name: String,
@@ -47,31 +54,448 @@ struct Application {
     id: Uuid,
     name: String,
     description: String,
+    min_supported_version: Option<String>,
+}
+
+impl From<app_store::Application> for Application {
+    fn from(app: app_store::Application) -> Self {
+        Application {
+            id: app.id,
+            name: app.name,
+            description: app.description,
+            min_supported_version: app.min_supported_version,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ApplicationVersion {
+    id: Uuid,
+    app_id: Uuid,
+    version: String,
+    latest: bool,
+    mandatory: bool,
+    release_notes: Option<String>,
+    released_at: Option<DateTime<Utc>>,
+    git_commit: Option<String>,
+    metadata: serde_json::Value,
+    rollout_percentage: Option<i32>,
+    selector: Option<String>,
+}
+
+impl From<app_store::ApplicationVersion> for ApplicationVersion {
+    fn from(version: app_store::ApplicationVersion) -> Self {
+        ApplicationVersion {
+            id: version.id,
+            app_id: version.app_id,
+            version: version.version,
+            latest: version.latest,
+            mandatory: version.mandatory,
+            release_notes: version.release_notes,
+            released_at: version.released_at,
+            git_commit: version.git_commit,
+            metadata: version.metadata,
+            rollout_percentage: version.rollout_percentage,
+            selector: version.selector,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ApplicationBuild {
+    id: Uuid,
+    app_version_id: Uuid,
+    build_version: String,
+    url: String,
+    disabled: bool,
+    sha256: Option<String>,
+}
+
+impl From<app_store::ApplicationBuild> for ApplicationBuild {
+    fn from(build: app_store::ApplicationBuild) -> Self {
+        ApplicationBuild {
+            id: build.id,
+            app_version_id: build.app_version_id,
+            build_version: build.build_version,
+            url: build.url,
+            disabled: build.disabled,
+            sha256: build.sha256,
+        }
+    }
 }
 
 #[derive(Deserialize)]
 struct CreateApplicationBuild {
     app_id: Uuid,
     version: String,
-    architecture: Architecture,
+    architecture: TargetTriple,
     latest: bool,
     url: String,
+    /// Digest of an artifact hosted elsewhere. Uploaded artifacts are digested by the server.
+    sha256: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct UploadBuildArtifact {
+    build_id: Uuid,
+    file_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CreateTarget {
+    triple: TargetTriple,
+    description: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SetTargetAlias {
+    alias: TargetTriple,
+    triple: TargetTriple,
+}
+
+#[derive(Deserialize)]
+struct SetTargetFallback {
+    triple: TargetTriple,
+    fallback: TargetTriple,
+    priority: Option<i32>,
+}
+
+#[derive(Deserialize)]
+struct PromoteVersion {
+    app_id: Uuid,
+    version: String,
+}
+
+#[derive(Deserialize)]
+struct SetVersionSelector {
+    app_id: Uuid,
+    version: String,
+    selector: Option<Selector>,
+}
+
+#[derive(Deserialize)]
+struct SetVersionMandatory {
+    app_id: Uuid,
+    version: String,
+    mandatory: bool,
+}
+
+#[derive(Deserialize)]
+struct SetVersionMetadata {
+    app_id: Uuid,
+    version: String,
+    release_notes: Option<String>,
+    released_at: Option<DateTime<Utc>>,
+    git_commit: Option<String>,
+    metadata: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct CreateReleaseSchedule {
+    app_id: Uuid,
+    version: String,
+    start_at: DateTime<Utc>,
+    ramp: Option<Vec<i32>>,
+    step_interval_minutes: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct ReleaseScheduleQuery {
+    app_id: Option<Uuid>,
+    status: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CancelReleaseSchedule {
+    schedule_id: Uuid,
+}
+
+#[derive(Serialize)]
+struct ReleaseSchedule {
+    #[serde(flatten)]
+    schedule: app_store::ReleaseSchedule,
+    steps: Vec<app_store::ReleaseScheduleStep>,
+}
+
+#[derive(Deserialize)]
+struct SetMinSupportedVersion {
+    app_id: Uuid,
+    min_supported_version: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DisableVersion {
+    app_id: Uuid,
+    version: String,
+    disabled: bool,
+}
+
+#[derive(Deserialize)]
+struct DisableBuild {
+    build_id: Uuid,
+    disabled: bool,
+}
+
+#[derive(Deserialize)]
+struct ToggleClient {
+    client_id: Uuid,
+    enabled: bool,
+}
+
+#[derive(Deserialize)]
+struct SetClientLabels {
+    client_id: Uuid,
+    labels: BTreeMap<String, Option<String>>,
+}
+
+#[derive(Deserialize)]
+struct ClientQuery {
+    app_id: Uuid,
+    selector: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Client {
+    #[serde(flatten)]
+    client: app_store::Client,
+    effective_labels: Labels,
+}
+
+impl From<app_store::Client> for Client {
+    fn from(client: app_store::Client) -> Self {
+        Client {
+            effective_labels: client.effective_labels(),
+            client,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct AuditQuery {
+    actor: Option<String>,
+    action: Option<String>,
+    app_id: Option<Uuid>,
+    target_id: Option<Uuid>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+    format: Option<String>,
+}
+
+impl AuditQuery {
+    fn to_filter(&self) -> AuditFilter {
+        AuditFilter {
+            actor: self.actor.clone(),
+            action: self.action.clone(),
+            app_id: self.app_id,
+            target_id: self.target_id,
+            since: self.since,
+            until: self.until,
+            limit: self.limit,
+            offset: self.offset,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CreateWebhookSubscription {
+    url: String,
+    secret: Option<String>,
+    event_types: Option<Vec<String>>,
+    app_id: Option<Uuid>,
+}
+
+/// Returned once when a subscription is created. The secret is not returned by any other api.
+#[derive(Serialize)]
+struct WebhookSubscriptionCreated {
+    id: Uuid,
+    url: String,
+    secret: String,
+    event_types: Vec<String>,
+    app_id: Option<Uuid>,
+    enabled: bool,
 }
 
 #[derive(Deserialize)]
+struct ToggleWebhookSubscription {
+    subscription_id: Uuid,
+    enabled: bool,
+}
+
+#[derive(Deserialize)]
+struct WebhookDeliveryQuery {
+    subscription_id: Option<Uuid>,
+    status: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(Deserialize, Clone)]
 struct ClientDetails {
     client_id: Uuid,
     app_id: Uuid,
     current_running_version: String,
-    architecture: Architecture,
+    architecture: TargetTriple,
+    /// Labels describing the client. Labels set by admins take precedence. Leaving them out keeps
+    /// the labels the client reported before.
+    #[serde(default)]
+    labels: Option<Labels>,
 }
 
-#[derive(Serialize)]
+/// A client whose start of `current_running_version` failed. Older clients only send the client
+/// details, so everything describing the failure is optional.
+#[derive(Deserialize)]
+struct ClientFailureReport {
+    #[serde(flatten)]
+    client: ClientDetails,
+    exit_code: Option<i32>,
+    signal: Option<i32>,
+    uptime_ms: Option<i64>,
+    stderr_tail: Option<String>,
+    failed_probe: Option<String>,
+    error: Option<String>,
+    host: Option<serde_json::Value>,
+}
+
+/// A client that relaunched `current_running_version` after `failed_version` failed to start.
+#[derive(Deserialize)]
+struct ClientRollbackReport {
+    #[serde(flatten)]
+    client: ClientDetails,
+    failed_version: String,
+}
+
+/// An exit of the app running on a client. `exit_code` is missing when the app was killed by
+/// `signal`.
+#[derive(Deserialize)]
+struct ClientCrashReport {
+    #[serde(flatten)]
+    client: ClientDetails,
+    exit_code: Option<i32>,
+    signal: Option<i32>,
+    crash_count: i32,
+    restarting: bool,
+    stderr_tail: Option<String>,
+}
+
+/// The app running on a client was stopped because the client itself shut down. `killed` tells
+/// whether the app had to be killed after the client's drain timeout.
+#[derive(Deserialize)]
+struct ClientStoppedReport {
+    #[serde(flatten)]
+    client: ClientDetails,
+    exit_code: Option<i32>,
+    signal: Option<i32>,
+    killed: bool,
+}
+
+#[derive(Deserialize)]
+struct ClientReportQuery {
+    app_id: Uuid,
+    client_id: Option<Uuid>,
+    limit: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct BuildFailureQuery {
+    build_id: Uuid,
+    client_id: Option<Uuid>,
+    limit: Option<i64>,
+}
+
+/// `update_required` is set whenever a newer version exists. `update_mandatory` is additionally set
+/// when the client runs a version older than `min_supported_version` or skipped a mandatory version,
+/// in which case the update should not be deferred. The release fields describe `version`.
+#[derive(Serialize, PartialEq, Debug)]
 struct LatestVersion {
     build_id: Uuid,
     version: String,
     url: String,
     update_required: bool,
+    update_mandatory: bool,
+    min_supported_version: Option<String>,
+    release_notes: Option<String>,
+    released_at: Option<DateTime<Utc>>,
+    git_commit: Option<String>,
+    metadata: serde_json::Value,
+    /// Hex sha256 digest of the build's artifact, when known.
+    sha256: Option<String>,
+    /// Patches that turn builds of earlier versions into this build.
+    patches: Vec<Patch>,
+}
+
+/// A binary patch a client running `from_version` can download instead of the whole build. See
+/// `ArtifactStore` for the format.
+#[derive(Serialize, PartialEq, Debug)]
+struct Patch {
+    from_version: String,
+    url: String,
+    sha256: String,
+    size: i64,
+}
+
+impl From<app_store::BuildPatch> for Patch {
+    fn from(patch: app_store::BuildPatch) -> Self {
+        Patch {
+            url: patch_url(patch.from_build_id, patch.to_build_id),
+            from_version: patch.from_version,
+            sha256: patch.sha256,
+            size: patch.size,
+        }
+    }
+}
+
+/// Actor recorded for changes the server makes on its own.
+pub(crate) const SYSTEM_ACTOR: &str = "system";
+/// Long enough for sha256 object names.
+const MAX_GIT_COMMIT_LENGTH: usize = 64;
+/// Crash and failure reports keep the end of the app's stderr up to this many bytes.
+const MAX_STDERR_TAIL_LENGTH: usize = 16 * 1024;
+/// Errors and failed probes of failure reports are cut off after this many bytes.
+const MAX_REPORT_ERROR_LENGTH: usize = 4 * 1024;
+/// Host descriptions of failure reports keep the fields that fit in this many bytes of json.
+const MAX_HOST_LENGTH: usize = 4 * 1024;
+const DEFAULT_DELIVERY_LIMIT: i64 = 100;
+const DEFAULT_ROLLBACK_LIMIT: i64 = 100;
+const DEFAULT_CRASH_LIMIT: i64 = 100;
+const DEFAULT_FAILURE_REPORT_LIMIT: i64 = 100;
+const LATEST_VERSION_EVENT: &str = "latest";
+const STREAM_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+/// Open update streams re-resolve on this interval even without a release change notification so
+/// that a missed notification only delays an update instead of losing it.
+const STREAM_RESYNC_INTERVAL: Duration = Duration::from_secs(60);
+
+/// State shared with route handlers.
+#[derive(Clone)]
+struct AppState {
+    pool: PgPool,
+    webhooks: WebhookDispatcher,
+    release_events: ReleaseEvents,
+    artifacts: ArtifactStore,
+}
+
+impl FromRef<AppState> for ArtifactStore {
+    fn from_ref(state: &AppState) -> Self {
+        state.artifacts.clone()
+    }
+}
+
+impl FromRef<AppState> for ReleaseEvents {
+    fn from_ref(state: &AppState) -> Self {
+        state.release_events.clone()
+    }
+}
+
+impl FromRef<AppState> for PgPool {
+    fn from_ref(state: &AppState) -> Self {
+        state.pool.clone()
+    }
+}
+
+impl FromRef<AppState> for WebhookDispatcher {
+    fn from_ref(state: &AppState) -> Self {
+        state.webhooks.clone()
+    }
 }
 
 /// Starts web server to start listening for cvm clients.
@@ -92,14 +516,61 @@ pub async fn start() {
         .await
         .expect("can't connect to database");
 
+    let webhooks = WebhookDispatcher::new(&CONFIG, pool.clone());
+    match webhooks.resume_pending().await {
+        Ok(resumed) => tracing::debug!("Resumed {} pending webhook deliveries", resumed),
+        Err(err) => tracing::error!("Failed to resume pending webhook deliveries: {}", err),
+    }
+    ReleaseScheduler::new(&CONFIG, pool.clone(), webhooks.clone()).spawn();
+
     // Register route handlers
     let app = Router::new()
         .route("/application/create", post(create_application))
+        .route("/application/build", post(publish_application_build))
         .route("/application/latest", post(get_latest_version))
+        .route("/application/latest/stream", post(stream_latest_version))
+        .route("/application/min-version", post(set_min_supported_version))
+        .route("/version/promote", post(promote_version))
+        .route("/version/mandatory", post(set_version_mandatory))
+        .route("/version/selector", post(set_version_selector))
+        .route("/version/metadata", post(set_version_metadata))
+        .route("/version/disable", post(disable_version))
+        .route("/build/disable", post(disable_build))
+        .route("/build/failures", get(get_build_failure_reports))
+        .route(
+            "/build/artifact",
+            post(upload_build_artifact)
+                .layer(DefaultBodyLimit::max(CONFIG.artifact_max_size_mb * 1024 * 1024)),
+        )
+        .route("/artifacts/:build_id/:file_name", get(download_build_artifact))
+        .route("/patches/:from_build_id/:to_build_id", get(download_build_patch))
+        .route("/client/toggle", post(toggle_client))
+        .route("/client/labels", post(set_client_labels))
+        .route("/clients", get(get_clients))
+        .route("/schedules", post(create_release_schedule).get(get_release_schedules))
+        .route("/schedules/cancel", post(cancel_release_schedule))
+        .route("/targets", post(create_target).get(get_targets))
+        .route("/targets/alias", post(set_target_alias))
+        .route("/targets/fallback", post(set_target_fallback))
+        .route("/audit", get(get_audit_log))
+        .route("/audit/export", get(export_audit_log))
+        .route("/webhooks", post(create_webhook_subscription).get(get_webhook_subscriptions))
+        .route("/webhooks/toggle", post(toggle_webhook_subscription))
+        .route("/webhooks/deliveries", get(get_webhook_deliveries))
         .route("/client/success", post(report_build_success))
         .route("/client/failure", post(report_build_failure))
+        .route("/client/rollback", post(report_client_rollback))
+        .route("/rollbacks", get(get_client_rollbacks))
+        .route("/client/crash", post(report_client_crash))
+        .route("/crashes", get(get_client_crashes))
+        .route("/client/stopped", post(report_client_stopped))
         .route("/health", get(health))
-        .with_state(pool);
+        .with_state(AppState {
+            webhooks,
+            release_events: ReleaseEvents::listen(pool.clone()),
+            artifacts: ArtifactStore::new(&CONFIG),
+            pool,
+        });
 
     // Bind to port and startup server
     let listener = TcpListener::bind("127.0.0.1:3000").await.unwrap();
@@ -119,7 +590,7 @@ where
     type Rejection = (StatusCode, String);
 
     async fn from_request_parts(_: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
-        let mut pool = PgPool::from_ref(state);
+        let pool = PgPool::from_ref(state);
         let conn_pool = pool.acquire().await.map_err(internal_error)?;
         let app_store = AppStore::from_pool_connection(&CONFIG, conn_pool)
             .await
@@ -129,15 +600,62 @@ where
     }
 }
 
-/// Administrative api for creating a new application. The corresponding api for adding a
-/// new version for the application is not yet available.
+/// Request context for route handlers whose changes must be kept together, e.g. an administrative
+/// change and its audit entry. The changes are made in one transaction, which the handler commits
+/// with `AppStore::commit`. Returning an error before rolls them back.
+struct TransactionContext(AppStore);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for TransactionContext
+where
+    PgPool: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(_: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let pool = PgPool::from_ref(state);
+        let app_store = AppStore::begin_transaction(&CONFIG, &pool)
+            .await
+            .map_err(app_store_error)?;
+        Ok(Self(app_store))
+    }
+}
+
+/// Name of the person or system performing an administrative change, taken from the
+/// `X-CVM-Actor` header. Requests without the header are recorded as "unknown".
+struct Actor(String);
+
+const ACTOR_HEADER: &str = "x-cvm-actor";
+const UNKNOWN_ACTOR: &str = "unknown";
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Actor
+where
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        let actor = parts
+            .headers
+            .get(ACTOR_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| !value.is_empty())
+            .unwrap_or(UNKNOWN_ACTOR);
+        Ok(Self(actor.to_string()))
+    }
+}
+
+/// Administrative api for creating a new application.
 /// POST:
 /// {
 ///     name: String,
 ///     description: String
 /// }
 async fn create_application(
-    RequestContext(mut app_store): RequestContext,
+    TransactionContext(mut app_store): TransactionContext,
+    Actor(actor): Actor,
     Json(params): Json<CreateApplication>,
 ) -> Result<Json<Application>, (StatusCode, String)> {
     let app = app_store
@@ -145,121 +663,2044 @@ async fn create_application(
         .await
         .map_err(app_store_error)?;
 
-    Ok(Json(Application {
-        id: app.id,
-        name: app.name,
-        description: app.description,
-    }))
+    record_audit(
+        &mut app_store,
+        NewAuditEntry {
+            actor: &actor,
+            action: "application.create",
+            target_type: "application",
+            target_id: app.id,
+            app_id: Some(app.id),
+            before: None,
+            after: Some(to_json(&app)?),
+        },
+    )
+    .await?;
+
+    app_store.commit().await.map_err(app_store_error)?;
+    Ok(Json(Application::from(app)))
 }
 
-/// Returns the latest version of an application based on the client details provided.
+/// Administrative api for publishing a build of an application version. The version is created
+/// if it does not exist yet and is promoted to latest when `latest` is set.
 /// POST:
 /// {
-///     client_id: Uuid,
 ///     app_id: Uuid,
-///     current_running_version: String,
-///     architecture: Architecture
-/// }
-///
-/// Architecture can be x86_64-pc-windows-gnu or x86_64-unknown-linux-gnu
-///
-/// Returns
-/// {
-/// build_id: Uuid,
 ///     version: String,
-///     url: String,
-///     update_required: bool,
+///     architecture: String,
+///     latest: bool,
+///     url: String
 /// }
-async fn get_latest_version(
-    RequestContext(mut app_store): RequestContext,
-    Json(params): Json<ClientDetails>,
-) -> Result<Json<LatestVersion>, (StatusCode, String)> {
-    let arch = params.architecture.to_string();
-    let app_build = app_store
-        .get_latest_application_version_build(params.app_id, arch)
+///
+/// The architecture must be a registered target triple or alias; builds are stored under the
+/// target's triple.
+async fn publish_application_build(
+    TransactionContext(mut app_store): TransactionContext,
+    State(webhooks): State<WebhookDispatcher>,
+    Actor(actor): Actor,
+    Json(params): Json<CreateApplicationBuild>,
+) -> Result<Json<ApplicationBuild>, (StatusCode, String)> {
+    semver::Version::parse(&params.version).map_err(bad_request)?;
+    let sha256 = params
+        .sha256
+        .as_deref()
+        .map(|sha256| {
+            artifacts::parse_sha256(sha256)
+                .ok_or((StatusCode::BAD_REQUEST, format!("Invalid sha256 digest: {}", sha256)))
+        })
+        .transpose()?;
+    let target = registered_target(&mut app_store, &params.architecture).await?;
+    let app_version = match app_store
+        .get_application_version(params.app_id, &params.version)
         .await
-        .map_err(app_store_error)?;
+    {
+        Ok(app_version) => app_version,
+        Err(AppStoreError::RowNotFound { .. }) => app_store
+            .create_application_version(params.app_id, &params.version, false)
+            .await
+            .map_err(app_store_error)?,
+        Err(err) => return Err(app_store_error(err)),
+    };
 
-    let app_version = app_store
-        .get_application_version_by_id(app_build.app_version_id)
+    let mut build = app_store
+        .create_application_build(app_version.id, &target.triple, &params.url)
         .await
         .map_err(app_store_error)?;
+    if let Some(sha256) = &sha256 {
+        build = app_store
+            .set_application_build_sha256(build.id, sha256, None)
+            .await
+            .map_err(app_store_error)?;
+    }
 
-    app_store
-        .update_client_version(params.client_id, &params.current_running_version)
-        .await
-        .map_err(app_store_error)?;
+    record_audit(
+        &mut app_store,
+        NewAuditEntry {
+            actor: &actor,
+            action: "build.publish",
+            target_type: "build",
+            target_id: build.id,
+            app_id: Some(params.app_id),
+            before: None,
+            after: Some(to_json(&build)?),
+        },
+    )
+    .await?;
+    announce_release_change(&mut app_store, params.app_id).await;
 
-    println!("{} {}", app_version.version, params.current_running_version);
-    let latest_version = semver::Version::parse(&app_version.version).unwrap();
-    let current_version =
-        semver::Version::parse(&params.current_running_version).map_err(internal_error)?;
-    let update_required = latest_version > current_version;
+    let event = if params.latest && !app_version.latest {
+        let (_, event) = promote(&mut app_store, &actor, app_version.id).await?;
+        Some(event)
+    } else {
+        None
+    };
 
-    Ok(Json(LatestVersion {
-        build_id: app_build.id,
-        url: app_build.url,
-        version: app_version.version,
-        update_required,
-    }))
+    app_store.commit().await.map_err(app_store_error)?;
+    if let Some(event) = event {
+        webhooks.publish_or_log(event).await;
+    }
+    Ok(Json(ApplicationBuild::from(build)))
 }
 
-/// Reports successful build/run for a client.
-/// POST:
-/// {
-///     client_id: Uuid,
-///     app_id: Uuid,
-///     current_running_version: String,
-///     architecture: Architecture
-/// }
-async fn report_build_success(
-    RequestContext(mut app_store): RequestContext,
-    Json(params): Json<ClientDetails>,
-) -> Result<Json<()>, (StatusCode, String)> {
-    let arch = params.architecture.to_string();
-    let app_build = app_store
-        .get_application_build(params.app_id, &params.current_running_version, arch, true)
+/// Administrative api for uploading the artifact of a build to the server's artifact store. The
+/// body is the artifact itself. The build is then served from this server and clients verify it
+/// against its digest. Patches from the build of the previous version, and to the build of the
+/// next version, for the same target are created when those artifacts are stored as well.
+/// POST: /build/artifact?build_id=&file_name=
+///
+/// `file_name` defaults to the last segment of the build's url. Clients name the downloaded file
+/// after it, e.g. `infinite_hello_0.2.0`.
+async fn upload_build_artifact(
+    TransactionContext(mut app_store): TransactionContext,
+    State(artifacts): State<ArtifactStore>,
+    Actor(actor): Actor,
+    Query(params): Query<UploadBuildArtifact>,
+    content: Bytes,
+) -> Result<Json<ApplicationBuild>, (StatusCode, String)> {
+    let before = app_store
+        .get_application_build_by_id(params.build_id)
         .await
-        .map_err(app_store_error)?;
+        .map_err(not_found)?;
+    let file_name = params
+        .file_name
+        .unwrap_or_else(|| before.url.rsplit('/').next().unwrap_or_default().to_string());
+    if !is_artifact_file_name(&file_name) {
+        return Err((StatusCode::BAD_REQUEST, format!("Invalid file name: {}", file_name)));
+    }
+    if content.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "The artifact is empty".to_string()));
+    }
 
+    let stored = artifacts
+        .store_build(before.id, &content)
+        .await
+        .map_err(internal_error)?;
     app_store
-        .increment_success_count_by_id(app_build.id)
+        .save_build_artifact(before.id, &file_name, stored.size)
         .await
         .map_err(app_store_error)?;
-
+    let url = format!("{}/artifacts/{}/{}", CONFIG.artifact_base_url, before.id, file_name);
+    let after = app_store
+        .set_application_build_sha256(before.id, &stored.sha256, Some(&url))
+        .await
+        .map_err(app_store_error)?;
+
+    let siblings = app_store
+        .get_stored_sibling_builds(before.id)
+        .await
+        .map_err(app_store_error)?;
+    let (previous, next) = adjacent_builds(&siblings, before.id);
+    let pairs = previous
+        .map(|previous| (previous, before.id))
+        .into_iter()
+        .chain(next.map(|next| (before.id, next)));
+    for (from_build_id, to_build_id) in pairs {
+        let patch = artifacts
+            .create_patch(from_build_id, to_build_id)
+            .await
+            .map_err(internal_error)?;
+        app_store
+            .save_build_patch(from_build_id, to_build_id, &patch.sha256, patch.size)
+            .await
+            .map_err(app_store_error)?;
+    }
+
+    let app_version = app_store
+        .get_application_version_by_id(after.app_version_id)
+        .await
+        .map_err(app_store_error)?;
+    record_audit(
+        &mut app_store,
+        NewAuditEntry {
+            actor: &actor,
+            action: "build.artifact",
+            target_type: "build",
+            target_id: after.id,
+            app_id: Some(app_version.app_id),
+            before: Some(to_json(&before)?),
+            after: Some(to_json(&after)?),
+        },
+    )
+    .await?;
+    announce_release_change(&mut app_store, app_version.app_id).await;
+
+    app_store.commit().await.map_err(app_store_error)?;
+    Ok(Json(ApplicationBuild::from(after)))
+}
+
+/// Downloads a stored build artifact, compressed with zstd or gzip when the request's
+/// `Accept-Encoding` allows it. A single `Range` of the served encoding can be requested to resume
+/// an interrupted download. The file name is only used by clients to name the file.
+/// GET: /artifacts/{build_id}/{file_name}
+async fn download_build_artifact(
+    State(artifacts): State<ArtifactStore>,
+    Path((build_id, _file_name)): Path<(Uuid, String)>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    let accept_encoding = headers
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok());
+    let (encoding, mut file, length) = artifacts
+        .open_build_encoded(build_id, &negotiate_encodings(accept_encoding))
+        .await
+        .map_err(|_| (StatusCode::NOT_FOUND, format!("No artifact stored for build {}", build_id)))?;
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| artifacts::parse_byte_range(value, length));
+
+    let mut response = match range {
+        None => Body::from_stream(ReaderStream::new(file)).into_response(),
+        Some(Ok(range)) => {
+            file.seek(SeekFrom::Start(range.start)).await.map_err(internal_error)?;
+            let mut response = Body::from_stream(ReaderStream::new(file.take(range.length()))).into_response();
+            *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+            response.headers_mut().insert(
+                header::CONTENT_RANGE,
+                header_value(format!("bytes {}-{}/{}", range.start, range.end, length))?,
+            );
+            response
+        }
+        Some(Err(())) => {
+            let mut response = StatusCode::RANGE_NOT_SATISFIABLE.into_response();
+            response
+                .headers_mut()
+                .insert(header::CONTENT_RANGE, header_value(format!("bytes */{}", length))?);
+            return Ok(response);
+        }
+    };
+    let content_length = range.and_then(Result::ok).map_or(length, |range| range.length());
+    let response_headers = response.headers_mut();
+    response_headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/octet-stream"));
+    response_headers.insert(header::CONTENT_LENGTH, HeaderValue::from(content_length));
+    response_headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    response_headers.insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+    if encoding != ContentEncoding::Identity {
+        response_headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding.as_str()));
+    }
+    Ok(response)
+}
+
+fn header_value(value: String) -> Result<HeaderValue, (StatusCode, String)> {
+    HeaderValue::from_str(&value).map_err(internal_error)
+}
+
+/// Downloads the patch from one build to another.
+/// GET: /patches/{from_build_id}/{to_build_id}
+async fn download_build_patch(
+    State(artifacts): State<ArtifactStore>,
+    Path((from_build_id, to_build_id)): Path<(Uuid, Uuid)>,
+) -> Result<Response, (StatusCode, String)> {
+    let content = artifacts
+        .read_patch(from_build_id, to_build_id)
+        .await
+        .map_err(|_| (StatusCode::NOT_FOUND, "Patch not found".to_string()))?;
+    Ok(([(header::CONTENT_TYPE, "application/octet-stream")], content).into_response())
+}
+
+fn patch_url(from_build_id: Uuid, to_build_id: Uuid) -> String {
+    format!("{}/patches/{}/{}", CONFIG.artifact_base_url, from_build_id, to_build_id)
+}
+
+fn is_artifact_file_name(file_name: &str) -> bool {
+    !file_name.is_empty()
+        && file_name.len() <= 255
+        && !file_name.starts_with('.')
+        && file_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+}
+
+/// Returns the builds of the versions just before and just after the build's version.
+fn adjacent_builds(builds: &[StoredBuild], build_id: Uuid) -> (Option<Uuid>, Option<Uuid>) {
+    let mut versions: Vec<(semver::Version, Uuid)> = builds
+        .iter()
+        .filter_map(|build| Some((semver::Version::parse(&build.version).ok()?, build.build_id)))
+        .collect();
+    versions.sort();
+    let Some(position) = versions.iter().position(|(_, id)| *id == build_id) else {
+        return (None, None);
+    };
+    let previous = position.checked_sub(1).map(|previous| versions[previous].1);
+    let next = versions.get(position + 1).map(|(_, id)| *id);
+    (previous, next)
+}
+
+/// Administrative api for making a version the latest version of its application.
+/// POST:
+/// {
+///     app_id: Uuid,
+///     version: String
+/// }
+async fn promote_version(
+    TransactionContext(mut app_store): TransactionContext,
+    State(webhooks): State<WebhookDispatcher>,
+    Actor(actor): Actor,
+    Json(params): Json<PromoteVersion>,
+) -> Result<Json<ApplicationVersion>, (StatusCode, String)> {
+    let app_version = app_store
+        .get_application_version(params.app_id, &params.version)
+        .await
+        .map_err(not_found)?;
+
+    let (promoted, event) = promote(&mut app_store, &actor, app_version.id).await?;
+    app_store.commit().await.map_err(app_store_error)?;
+    webhooks.publish_or_log(event).await;
+    Ok(Json(ApplicationVersion::from(promoted)))
+}
+
+/// Administrative api for marking a version as mandatory. Clients running an older version are told
+/// to update immediately, even when the latest version is not mandatory itself.
+/// POST:
+/// {
+///     app_id: Uuid,
+///     version: String,
+///     mandatory: bool
+/// }
+async fn set_version_mandatory(
+    TransactionContext(mut app_store): TransactionContext,
+    Actor(actor): Actor,
+    Json(params): Json<SetVersionMandatory>,
+) -> Result<Json<ApplicationVersion>, (StatusCode, String)> {
+    let before = app_store
+        .get_application_version(params.app_id, &params.version)
+        .await
+        .map_err(not_found)?;
+    let after = app_store
+        .set_application_version_mandatory(before.id, params.mandatory)
+        .await
+        .map_err(app_store_error)?;
+
+    record_audit(
+        &mut app_store,
+        NewAuditEntry {
+            actor: &actor,
+            action: if params.mandatory { "version.mandatory" } else { "version.optional" },
+            target_type: "version",
+            target_id: after.id,
+            app_id: Some(params.app_id),
+            before: Some(to_json(&before)?),
+            after: Some(to_json(&after)?),
+        },
+    )
+    .await?;
+    announce_release_change(&mut app_store, params.app_id).await;
+
+    app_store.commit().await.map_err(app_store_error)?;
+    Ok(Json(ApplicationVersion::from(after)))
+}
+
+/// Administrative api for targeting a version at the clients whose labels match a selector, e.g.
+/// `region=eu && site!=lab`. Matching clients are served the version instead of the latest version
+/// until it is promoted. `null` stops targeting the version.
+/// POST:
+/// {
+///     app_id: Uuid,
+///     version: String,
+///     selector: String
+/// }
+async fn set_version_selector(
+    TransactionContext(mut app_store): TransactionContext,
+    Actor(actor): Actor,
+    Json(params): Json<SetVersionSelector>,
+) -> Result<Json<ApplicationVersion>, (StatusCode, String)> {
+    let before = app_store
+        .get_application_version(params.app_id, &params.version)
+        .await
+        .map_err(not_found)?;
+    if before.latest && params.selector.is_some() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("{} is already served to every client", params.version),
+        ));
+    }
+    let after = app_store
+        .set_application_version_selector(before.id, params.selector.as_ref())
+        .await
+        .map_err(app_store_error)?;
+
+    record_audit(
+        &mut app_store,
+        NewAuditEntry {
+            actor: &actor,
+            action: "version.selector",
+            target_type: "version",
+            target_id: after.id,
+            app_id: Some(params.app_id),
+            before: Some(to_json(&before)?),
+            after: Some(to_json(&after)?),
+        },
+    )
+    .await?;
+    announce_release_change(&mut app_store, params.app_id).await;
+
+    app_store.commit().await.map_err(app_store_error)?;
+    Ok(Json(ApplicationVersion::from(after)))
+}
+
+/// Administrative api for describing a version. Only the fields that are set are changed;
+/// `metadata` must be a json object and replaces the current metadata. `released_at` is set
+/// automatically when the version is first promoted.
+/// POST:
+/// {
+///     app_id: Uuid,
+///     version: String,
+///     release_notes: String,
+///     released_at: DateTime,
+///     git_commit: String,
+///     metadata: { .. }
+/// }
+async fn set_version_metadata(
+    TransactionContext(mut app_store): TransactionContext,
+    Actor(actor): Actor,
+    Json(params): Json<SetVersionMetadata>,
+) -> Result<Json<ApplicationVersion>, (StatusCode, String)> {
+    if let Some(git_commit) = &params.git_commit {
+        if git_commit.is_empty()
+            || git_commit.len() > MAX_GIT_COMMIT_LENGTH
+            || !git_commit.chars().all(|c| c.is_ascii_hexdigit())
+        {
+            return Err((StatusCode::BAD_REQUEST, format!("Invalid git commit: {}", git_commit)));
+        }
+    }
+    if params.metadata.as_ref().is_some_and(|metadata| !metadata.is_object()) {
+        return Err((StatusCode::BAD_REQUEST, "metadata must be a json object".to_string()));
+    }
+
+    let before = app_store
+        .get_application_version(params.app_id, &params.version)
+        .await
+        .map_err(not_found)?;
+    let update = VersionMetadataUpdate {
+        release_notes: params.release_notes,
+        released_at: params.released_at,
+        git_commit: params.git_commit.map(|git_commit| git_commit.to_ascii_lowercase()),
+        metadata: params.metadata,
+    };
+    let after = app_store
+        .update_application_version_metadata(before.id, &update)
+        .await
+        .map_err(app_store_error)?;
+
+    record_audit(
+        &mut app_store,
+        NewAuditEntry {
+            actor: &actor,
+            action: "version.metadata",
+            target_type: "version",
+            target_id: after.id,
+            app_id: Some(params.app_id),
+            before: Some(to_json(&before)?),
+            after: Some(to_json(&after)?),
+        },
+    )
+    .await?;
+    announce_release_change(&mut app_store, params.app_id).await;
+
+    app_store.commit().await.map_err(app_store_error)?;
+    Ok(Json(ApplicationVersion::from(after)))
+}
+
+/// Administrative api for setting the oldest version of an application that clients may keep
+/// running. Clients on older versions are told to update immediately. A null version clears it.
+/// POST:
+/// {
+///     app_id: Uuid,
+///     min_supported_version: String
+/// }
+async fn set_min_supported_version(
+    TransactionContext(mut app_store): TransactionContext,
+    Actor(actor): Actor,
+    Json(params): Json<SetMinSupportedVersion>,
+) -> Result<Json<Application>, (StatusCode, String)> {
+    if let Some(version) = &params.min_supported_version {
+        semver::Version::parse(version).map_err(bad_request)?;
+    }
+    let before = app_store
+        .get_application_by_id(params.app_id)
+        .await
+        .map_err(not_found)?;
+    let after = app_store
+        .set_application_min_supported_version(params.app_id, params.min_supported_version.as_deref())
+        .await
+        .map_err(app_store_error)?;
+
+    record_audit(
+        &mut app_store,
+        NewAuditEntry {
+            actor: &actor,
+            action: "application.min_version",
+            target_type: "application",
+            target_id: after.id,
+            app_id: Some(after.id),
+            before: Some(to_json(&before)?),
+            after: Some(to_json(&after)?),
+        },
+    )
+    .await?;
+    announce_release_change(&mut app_store, params.app_id).await;
+
+    app_store.commit().await.map_err(app_store_error)?;
+    Ok(Json(Application::from(after)))
+}
+
+/// Administrative api for disabling or re-enabling every build of a version.
+/// POST:
+/// {
+///     app_id: Uuid,
+///     version: String,
+///     disabled: bool
+/// }
+async fn disable_version(
+    TransactionContext(mut app_store): TransactionContext,
+    Actor(actor): Actor,
+    Json(params): Json<DisableVersion>,
+) -> Result<Json<Vec<ApplicationBuild>>, (StatusCode, String)> {
+    let app_version = app_store
+        .get_application_version(params.app_id, &params.version)
+        .await
+        .map_err(not_found)?;
+    let before = app_store
+        .get_application_builds_by_version(app_version.id)
+        .await
+        .map_err(app_store_error)?;
+    let after = app_store
+        .set_application_version_disabled(app_version.id, params.disabled)
+        .await
+        .map_err(app_store_error)?;
+
+    record_audit(
+        &mut app_store,
+        NewAuditEntry {
+            actor: &actor,
+            action: if params.disabled { "version.disable" } else { "version.enable" },
+            target_type: "version",
+            target_id: app_version.id,
+            app_id: Some(params.app_id),
+            before: Some(to_json(&before)?),
+            after: Some(to_json(&after)?),
+        },
+    )
+    .await?;
+    announce_release_change(&mut app_store, params.app_id).await;
+
+    app_store.commit().await.map_err(app_store_error)?;
+    Ok(Json(after.into_iter().map(ApplicationBuild::from).collect()))
+}
+
+/// Administrative api for disabling or re-enabling a single build.
+/// POST:
+/// {
+///     build_id: Uuid,
+///     disabled: bool
+/// }
+async fn disable_build(
+    TransactionContext(mut app_store): TransactionContext,
+    Actor(actor): Actor,
+    Json(params): Json<DisableBuild>,
+) -> Result<Json<ApplicationBuild>, (StatusCode, String)> {
+    let before = app_store
+        .get_application_build_by_id(params.build_id)
+        .await
+        .map_err(not_found)?;
+    let app_version = app_store
+        .get_application_version_by_id(before.app_version_id)
+        .await
+        .map_err(app_store_error)?;
+    let after = app_store
+        .set_application_build_disabled(params.build_id, params.disabled)
+        .await
+        .map_err(app_store_error)?;
+
+    record_audit(
+        &mut app_store,
+        NewAuditEntry {
+            actor: &actor,
+            action: if params.disabled { "build.disable" } else { "build.enable" },
+            target_type: "build",
+            target_id: params.build_id,
+            app_id: Some(app_version.app_id),
+            before: Some(to_json(&before)?),
+            after: Some(to_json(&after)?),
+        },
+    )
+    .await?;
+    announce_release_change(&mut app_store, app_version.app_id).await;
+
+    app_store.commit().await.map_err(app_store_error)?;
+    Ok(Json(ApplicationBuild::from(after)))
+}
+
+/// Administrative api for enabling or disabling a client.
+/// POST:
+/// {
+///     client_id: Uuid,
+///     enabled: bool
+/// }
+async fn toggle_client(
+    TransactionContext(mut app_store): TransactionContext,
+    Actor(actor): Actor,
+    Json(params): Json<ToggleClient>,
+) -> Result<Json<()>, (StatusCode, String)> {
+    let before = app_store
+        .get_client_by_id(params.client_id)
+        .await
+        .map_err(not_found)?;
+    let after = app_store
+        .set_client_enabled(params.client_id, params.enabled)
+        .await
+        .map_err(app_store_error)?;
+
+    record_audit(
+        &mut app_store,
+        NewAuditEntry {
+            actor: &actor,
+            action: if params.enabled { "client.enable" } else { "client.disable" },
+            target_type: "client",
+            target_id: params.client_id,
+            app_id: Some(after.app_id),
+            before: Some(to_json(&before)?),
+            after: Some(to_json(&after)?),
+        },
+    )
+    .await?;
+
+    app_store.commit().await.map_err(app_store_error)?;
+    Ok(Json(()))
+}
+
+/// Administrative api for labelling a client. The labels are merged into the labels set before, and
+/// a `null` value removes a label. They take precedence over the labels the client reports.
+/// POST:
+/// {
+///     client_id: Uuid,
+///     labels: { "site": "berlin", "region": null }
+/// }
+async fn set_client_labels(
+    TransactionContext(mut app_store): TransactionContext,
+    Actor(actor): Actor,
+    Json(params): Json<SetClientLabels>,
+) -> Result<Json<Client>, (StatusCode, String)> {
+    let before = app_store
+        .get_client_by_id(params.client_id)
+        .await
+        .map_err(not_found)?;
+    let mut labels = before.labels.0.clone();
+    for (key, value) in &params.labels {
+        match value {
+            Some(value) => labels.insert(key, value).map_err(bad_request)?,
+            None => labels.remove(key),
+        }
+    }
+    let after = app_store
+        .set_client_labels(params.client_id, &labels)
+        .await
+        .map_err(app_store_error)?;
+
+    record_audit(
+        &mut app_store,
+        NewAuditEntry {
+            actor: &actor,
+            action: "client.labels",
+            target_type: "client",
+            target_id: params.client_id,
+            app_id: Some(after.app_id),
+            before: Some(to_json(&before)?),
+            after: Some(to_json(&after)?),
+        },
+    )
+    .await?;
+    announce_release_change(&mut app_store, after.app_id).await;
+
+    app_store.commit().await.map_err(app_store_error)?;
+    Ok(Json(Client::from(after)))
+}
+
+/// Lists the clients of an application with their labels, optionally only those matching a
+/// selector.
+/// GET: /clients?app_id=&selector=
+async fn get_clients(
+    RequestContext(mut app_store): RequestContext,
+    Query(params): Query<ClientQuery>,
+) -> Result<Json<Vec<Client>>, (StatusCode, String)> {
+    let selector = params
+        .selector
+        .as_deref()
+        .map(Selector::parse)
+        .transpose()
+        .map_err(bad_request)?;
+    let clients = app_store
+        .get_clients(params.app_id)
+        .await
+        .map_err(app_store_error)?;
+
+    Ok(Json(
+        clients
+            .into_iter()
+            .map(Client::from)
+            .filter(|client| {
+                selector
+                    .as_ref()
+                    .is_none_or(|selector| selector.matches(&client.effective_labels))
+            })
+            .collect(),
+    ))
+}
+
+/// Returns audit log entries, newest first.
+/// GET: /audit?actor=&action=&app_id=&target_id=&since=&until=&limit=&offset=
+///
+/// `since` and `until` are RFC 3339 timestamps.
+async fn get_audit_log(
+    RequestContext(mut app_store): RequestContext,
+    Query(params): Query<AuditQuery>,
+) -> Result<Json<Vec<AuditEntry>>, (StatusCode, String)> {
+    let entries = app_store
+        .get_audit_entries(&params.to_filter())
+        .await
+        .map_err(app_store_error)?;
+    Ok(Json(entries))
+}
+
+/// Exports audit log entries as a downloadable file. Accepts the same filters as `/audit` and
+/// `format=csv` (default) or `format=json`.
+async fn export_audit_log(
+    RequestContext(mut app_store): RequestContext,
+    Query(params): Query<AuditQuery>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let entries = app_store
+        .get_audit_entries(&params.to_filter())
+        .await
+        .map_err(app_store_error)?;
+
+    let (content_type, extension, body) = match params.format.as_deref() {
+        None | Some("csv") => ("text/csv", "csv", audit_entries_to_csv(&entries)),
+        Some("json") => ("application/json", "json", to_json(&entries)?.to_string()),
+        Some(other) => {
+            return Err((StatusCode::BAD_REQUEST, format!("Unsupported export format: {}", other)))
+        }
+    };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"audit_log.{}\"", extension),
+            ),
+        ],
+        body,
+    ))
+}
+
+/// Returns the latest version of an application based on the client details provided.
+/// POST:
+/// {
+///     client_id: Uuid,
+///     app_id: Uuid,
+///     current_running_version: String,
+///     architecture: String
+/// }
+///
+/// Architecture is the client's target triple, e.g. x86_64-unknown-linux-gnu, or an alias of one.
+/// When there is no build for the target, the build of its most preferred fallback target is
+/// returned.
+///
+/// Returns
+/// {
+/// build_id: Uuid,
+///     version: String,
+///     url: String,
+///     update_required: bool,
+///     update_mandatory: bool,
+///     min_supported_version: Option<String>,
+///     release_notes: Option<String>,
+///     released_at: Option<DateTime>,
+///     git_commit: Option<String>,
+///     metadata: Json,
+///     sha256: Option<String>,
+///     patches: [{ from_version: String, url: String, sha256: String, size: i64 }],
+/// }
+///
+/// The response carries an ETag derived from the resolved build and the client's running version.
+/// When the request's If-None-Match matches it, 304 Not Modified is returned without a body. The
+/// latest version is still resolved to compute the tag; only recording the client's version again
+/// is skipped.
+async fn get_latest_version(
+    RequestContext(mut app_store): RequestContext,
+    headers: HeaderMap,
+    Json(params): Json<ClientDetails>,
+) -> Result<Response, (StatusCode, String)> {
+    let latest_version = resolve_latest_version(&mut app_store, &params).await?;
+    let etag = latest_version_etag(
+        &latest_version,
+        &params.current_running_version,
+        params.labels.as_ref(),
+    );
+    if if_none_match(&headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+    }
+
+    app_store
+        .update_client_version(
+            params.client_id,
+            &params.current_running_version,
+            params.labels.as_ref(),
+        )
+        .await
+        .map_err(app_store_error)?;
+
+    Ok(([(header::ETAG, etag)], Json(latest_version)).into_response())
+}
+
+/// Strong ETag for a latest version response. The tag covers the whole response, which depends on
+/// the version the client is running, as well as the running version and reported labels
+/// themselves so that a client that changed either is recorded again.
+fn latest_version_etag(
+    latest_version: &LatestVersion,
+    current_running_version: &str,
+    reported_labels: Option<&Labels>,
+) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(serde_json::to_vec(latest_version).unwrap_or_default());
+    hasher.update(b"\0");
+    hasher.update(current_running_version.as_bytes());
+    if let Some(labels) = reported_labels {
+        hasher.update(b"\0");
+        hasher.update(serde_json::to_vec(labels).unwrap_or_default());
+    }
+    format!("\"{}\"", hex::encode(&hasher.finalize()[..16]))
+}
+
+/// Returns true when the request's If-None-Match header matches the etag.
+fn if_none_match(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// Streams the latest version of an application as server-sent events. The request body is the
+/// same as for `/application/latest`.
+///
+/// A `latest` event carrying the same json as `/application/latest` is sent as soon as the stream
+/// opens and again whenever the build resolved for the client changes. The connection does not
+/// hold a database connection while idle; it re-resolves when a release change is announced
+/// (see `ReleaseEvents`) and periodically as a fallback.
+async fn stream_latest_version(
+    State(pool): State<PgPool>,
+    State(release_events): State<ReleaseEvents>,
+    Json(params): Json<ClientDetails>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, (StatusCode, String)> {
+    // Subscribe before resolving so a release published in between is not missed.
+    let receiver = release_events.subscribe();
+    let mut app_store = AppStore::from_pg_pool(&CONFIG, &pool)
+        .await
+        .map_err(app_store_error)?;
+    app_store
+        .update_client_version(
+            params.client_id,
+            &params.current_running_version,
+            params.labels.as_ref(),
+        )
+        .await
+        .map_err(app_store_error)?;
+    let latest_version = resolve_latest_version(&mut app_store, &params).await?;
+
+    let state = LatestVersionStream {
+        pool,
+        params,
+        receiver,
+        last_sent: None,
+        pending: Some(latest_version),
+    };
+    let stream = futures_util::stream::unfold(state, next_latest_version_event);
+    Ok(Sse::new(stream).keep_alive(KeepAlive::new().interval(STREAM_KEEP_ALIVE_INTERVAL)))
+}
+
+struct LatestVersionStream {
+    pool: PgPool,
+    params: ClientDetails,
+    receiver: broadcast::Receiver<Uuid>,
+    last_sent: Option<LatestVersion>,
+    pending: Option<LatestVersion>,
+}
+
+/// Waits until the latest version resolved for the streaming client changes and returns it as an
+/// event.
+async fn next_latest_version_event(
+    mut state: LatestVersionStream,
+) -> Option<(Result<Event, Infallible>, LatestVersionStream)> {
+    loop {
+        if let Some(latest_version) = state.pending.take() {
+            let event = Event::default()
+                .event(LATEST_VERSION_EVENT)
+                .json_data(&latest_version)
+                .ok()?;
+            state.last_sent = Some(latest_version);
+            return Some((Ok(event), state));
+        }
+
+        let changed = tokio::select! {
+            received = state.receiver.recv() => match received {
+                Ok(app_id) => app_id == state.params.app_id,
+                // Some notifications were dropped, one of them may have been for this application.
+                Err(RecvError::Lagged(_)) => true,
+                Err(RecvError::Closed) => return None,
+            },
+            _ = tokio::time::sleep(STREAM_RESYNC_INTERVAL) => true,
+        };
+        if !changed {
+            continue;
+        }
+
+        let resolved = match AppStore::from_pg_pool(&CONFIG, &state.pool).await {
+            Ok(mut app_store) => resolve_latest_version(&mut app_store, &state.params).await,
+            Err(err) => Err(app_store_error(err)),
+        };
+        match resolved {
+            Ok(latest_version) if state.last_sent.as_ref() != Some(&latest_version) => {
+                state.pending = Some(latest_version);
+            }
+            Ok(_) => {}
+            Err((_, message)) => tracing::error!("Unable to resolve latest version: {}", message),
+        }
+    }
+}
+
+/// Resolves the build a client should be running.
+async fn resolve_latest_version(
+    app_store: &mut AppStore,
+    params: &ClientDetails,
+) -> Result<LatestVersion, (StatusCode, String)> {
+    let target = registered_target(app_store, &params.architecture).await?;
+    let labels = client_labels(app_store, params).await?;
+    let targeted_version_id = targeted_version(app_store, params.app_id, &labels).await?;
+    let resolved = app_store
+        .resolve_latest_build(params.app_id, &target.triple, params.client_id, targeted_version_id)
+        .await
+        .map_err(app_store_error)?;
+
+    let latest_version = semver::Version::parse(&resolved.version).map_err(internal_error)?;
+    let current_version =
+        semver::Version::parse(&params.current_running_version).map_err(internal_error)?;
+    let update_required = latest_version > current_version;
+    let patches = app_store
+        .get_build_patches_to(resolved.build_id)
+        .await
+        .map_err(app_store_error)?;
+    let update_mandatory = update_required
+        && is_update_mandatory(
+            &current_version,
+            &latest_version,
+            resolved.min_supported_version.as_deref(),
+            &resolved.mandatory_versions,
+        );
+
+    Ok(LatestVersion {
+        build_id: resolved.build_id,
+        url: resolved.url,
+        version: resolved.version,
+        update_required,
+        update_mandatory,
+        min_supported_version: resolved.min_supported_version,
+        release_notes: resolved.release_notes,
+        released_at: resolved.released_at,
+        git_commit: resolved.git_commit,
+        metadata: resolved.metadata,
+        sha256: resolved.sha256,
+        patches: patches.into_iter().map(Patch::from).collect(),
+    })
+}
+
+/// Labels the client is targeted by: the labels it reports, or last reported, overridden by the
+/// labels set by admins. Clients that are not registered only have the labels they report.
+async fn client_labels(
+    app_store: &mut AppStore,
+    params: &ClientDetails,
+) -> Result<Labels, (StatusCode, String)> {
+    let client = app_store
+        .find_client(params.client_id)
+        .await
+        .map_err(app_store_error)?;
+    Ok(match client {
+        Some(client) => params
+            .labels
+            .as_ref()
+            .unwrap_or(&client.reported_labels)
+            .merged(&client.labels),
+        None => params.labels.clone().unwrap_or_default(),
+    })
+}
+
+/// Returns the newest version whose selector matches the labels.
+async fn targeted_version(
+    app_store: &mut AppStore,
+    app_id: Uuid,
+    labels: &Labels,
+) -> Result<Option<Uuid>, (StatusCode, String)> {
+    let versions = app_store
+        .get_targeted_application_versions(app_id)
+        .await
+        .map_err(app_store_error)?;
+    Ok(versions
+        .into_iter()
+        .filter(|version| {
+            version
+                .selector
+                .as_deref()
+                .and_then(|selector| Selector::parse(selector).ok())
+                .is_some_and(|selector| selector.matches(labels))
+        })
+        .filter_map(|version| Some((semver::Version::parse(&version.version).ok()?, version.id)))
+        .max()
+        .map(|(_, id)| id))
+}
+
+/// An update is mandatory when the running version is below the minimum supported version or
+/// when a mandatory version was released after it, up to and including the latest version.
+fn is_update_mandatory(
+    current_version: &semver::Version,
+    latest_version: &semver::Version,
+    min_supported_version: Option<&str>,
+    mandatory_versions: &[String],
+) -> bool {
+    let unsupported = min_supported_version
+        .and_then(|version| semver::Version::parse(version).ok())
+        .is_some_and(|min_supported_version| *current_version < min_supported_version);
+    let skipped_mandatory = mandatory_versions
+        .iter()
+        .filter_map(|version| semver::Version::parse(version).ok())
+        .any(|version| *current_version < version && version <= *latest_version);
+    unsupported || skipped_mandatory
+}
+
+/// Reports successful build/run for a client.
+/// POST:
+/// {
+///     client_id: Uuid,
+///     app_id: Uuid,
+///     current_running_version: String,
+///     architecture: String
+/// }
+async fn report_build_success(
+    RequestContext(mut app_store): RequestContext,
+    Json(params): Json<ClientDetails>,
+) -> Result<Json<()>, (StatusCode, String)> {
+    let target = registered_target(&mut app_store, &params.architecture).await?;
+    let app_build = app_store
+        .get_application_build(params.app_id, &params.current_running_version, &target.triple, true)
+        .await
+        .map_err(app_store_error)?;
+
+    app_store
+        .increment_success_count_by_id(app_build.id)
+        .await
+        .map_err(app_store_error)?;
+
     Ok(Json(()))
 }
 
-/// Reports failure build/startup
+/// Reports failure build/startup
+/// POST:
+/// {
+///     client_id: Uuid,
+///     app_id: Uuid,
+///     current_running_version: String,
+///     architecture: String,
+///     exit_code: i32 | null,
+///     signal: i32 | null,
+///     uptime_ms: i64 | null,
+///     stderr_tail: String | null,
+///     failed_probe: String | null,
+///     error: String | null,
+///     host: Object | null
+/// }
+///
+/// The report is stored against the build, see /build/failures, in the same transaction that counts
+/// the failure. `uptime_ms` is the time from starting the build until it failed, `failed_probe` the
+/// health probe it didn't pass and `host` describes the client's machine. Overlong `stderr_tail`,
+/// `error`, `failed_probe` and `host` are cut. Subscribers of `client.startup_failed` are notified of every
+/// failure. When AUTO_DISABLE_FAILURE_THRESHOLD is set and the build reaches that many failures, the
+/// build is disabled and subscribers of `build.auto_disabled` are notified.
+async fn report_build_failure(
+    TransactionContext(mut app_store): TransactionContext,
+    State(webhooks): State<WebhookDispatcher>,
+    Json(params): Json<ClientFailureReport>,
+) -> Result<Json<app_store::BuildFailureReport>, (StatusCode, String)> {
+    let client = &params.client;
+    let target = registered_target(&mut app_store, &client.architecture).await?;
+    let app_build = app_store
+        .get_application_build(client.app_id, &client.current_running_version, &target.triple, true)
+        .await
+        .map_err(app_store_error)?;
+
+    app_store
+        .increment_failure_count_by_id(app_build.id)
+        .await
+        .map_err(app_store_error)?;
+    let host = params.host.as_ref().and_then(truncate_host);
+    let report = app_store
+        .record_build_failure_report(&app_store::NewBuildFailureReport {
+            build_id: app_build.id,
+            client_id: client.client_id,
+            app_id: client.app_id,
+            architecture: &target.triple,
+            version: &client.current_running_version,
+            exit_code: params.exit_code,
+            signal: params.signal,
+            uptime_ms: params.uptime_ms,
+            stderr_tail: params.stderr_tail.as_deref().map(stderr_tail),
+            failed_probe: params.failed_probe.as_deref().map(report_error),
+            error: params.error.as_deref().map(report_error),
+            host: host.as_ref(),
+        })
+        .await
+        .map_err(app_store_error)?;
+
+    let failed = WebhookEvent::new(
+        EVENT_CLIENT_STARTUP_FAILED,
+        client.app_id,
+        serde_json::json!({
+            "client_id": client.client_id,
+            "build_id": app_build.id,
+            "version": client.current_running_version,
+            "architecture": target.triple,
+            "report_id": report.id,
+            "exit_code": report.exit_code,
+            "signal": report.signal,
+            "failed_probe": report.failed_probe,
+            "error": report.error,
+        }),
+    );
+
+    let app_build = app_store
+        .get_application_build_by_id(app_build.id)
+        .await
+        .map_err(app_store_error)?;
+    let mut auto_disabled = None;
+    if let Some(threshold) = CONFIG.auto_disable_failure_threshold {
+        if !app_build.disabled && app_build.failed_count >= threshold {
+            auto_disabled = Some(auto_disable_build(&mut app_store, client.app_id, app_build).await?);
+        }
+    }
+
+    app_store.commit().await.map_err(app_store_error)?;
+    webhooks.publish_or_log(failed).await;
+    if let Some(event) = auto_disabled {
+        webhooks.publish_or_log(event).await;
+    }
+    Ok(Json(report))
+}
+
+/// Returns the reports of a build failing to start, newest first.
+/// GET: /build/failures?build_id=&client_id=&limit=
+async fn get_build_failure_reports(
+    RequestContext(mut app_store): RequestContext,
+    Query(params): Query<BuildFailureQuery>,
+) -> Result<Json<Vec<app_store::BuildFailureReport>>, (StatusCode, String)> {
+    let reports = app_store
+        .get_build_failure_reports(
+            params.build_id,
+            params.client_id,
+            params.limit.unwrap_or(DEFAULT_FAILURE_REPORT_LIMIT),
+        )
+        .await
+        .map_err(app_store_error)?;
+    Ok(Json(reports))
+}
+
+/// Reports that a client went back to the last version that started successfully after
+/// `failed_version` failed to start. The failure itself is reported through /client/failure.
+/// POST:
+/// {
+///     client_id: Uuid,
+///     app_id: Uuid,
+///     current_running_version: String,
+///     architecture: String,
+///     failed_version: String
+/// }
+///
+/// Subscribers of `client.rolled_back` are notified of every rollback.
+async fn report_client_rollback(
+    RequestContext(mut app_store): RequestContext,
+    State(webhooks): State<WebhookDispatcher>,
+    Json(params): Json<ClientRollbackReport>,
+) -> Result<Json<app_store::ClientRollback>, (StatusCode, String)> {
+    let client = params.client;
+    let target = registered_target(&mut app_store, &client.architecture).await?;
+    app_store
+        .update_client_version(
+            client.client_id,
+            &client.current_running_version,
+            client.labels.as_ref(),
+        )
+        .await
+        .map_err(app_store_error)?;
+    let rollback = app_store
+        .record_client_rollback(
+            client.client_id,
+            client.app_id,
+            &target.triple,
+            &params.failed_version,
+            &client.current_running_version,
+        )
+        .await
+        .map_err(app_store_error)?;
+
+    webhooks
+        .publish_or_log(WebhookEvent::new(
+            EVENT_CLIENT_ROLLED_BACK,
+            client.app_id,
+            serde_json::json!({
+                "client_id": client.client_id,
+                "failed_version": rollback.failed_version,
+                "version": rollback.version,
+                "architecture": target.triple,
+            }),
+        ))
+        .await;
+
+    Ok(Json(rollback))
+}
+
+/// Returns the rollbacks clients of an application reported, newest first.
+/// GET: /rollbacks?app_id=&client_id=&limit=
+async fn get_client_rollbacks(
+    RequestContext(mut app_store): RequestContext,
+    Query(params): Query<ClientReportQuery>,
+) -> Result<Json<Vec<app_store::ClientRollback>>, (StatusCode, String)> {
+    let rollbacks = app_store
+        .get_client_rollbacks(
+            params.app_id,
+            params.client_id,
+            params.limit.unwrap_or(DEFAULT_ROLLBACK_LIMIT),
+        )
+        .await
+        .map_err(app_store_error)?;
+    Ok(Json(rollbacks))
+}
+
+/// Reports that the app running on a client exited on its own.
 /// POST:
 /// {
 ///     client_id: Uuid,
 ///     app_id: Uuid,
 ///     current_running_version: String,
-///     architecture: Architecture
+///     architecture: String,
+///     exit_code: i32 | null,
+///     signal: i32 | null,
+///     crash_count: i32,
+///     restarting: bool,
+///     stderr_tail: String | null
 /// }
-/// TODO: Trigger a roll back on the latest version if too many clients report a failed startup of a version.
-async fn report_build_failure(
+///
+/// `crash_count` counts the exits within the client's restart window and `restarting` tells whether
+/// the client restarts the app. `stderr_tail` holds the last lines the app wrote to stderr. Subscribers of `client.crashed` are notified of every report.
+async fn report_client_crash(
     RequestContext(mut app_store): RequestContext,
-    Json(params): Json<ClientDetails>,
+    State(webhooks): State<WebhookDispatcher>,
+    Json(params): Json<ClientCrashReport>,
+) -> Result<Json<app_store::ClientCrash>, (StatusCode, String)> {
+    let target = registered_target(&mut app_store, &params.client.architecture).await?;
+    let crash = app_store
+        .record_client_crash(&app_store::NewClientCrash {
+            client_id: params.client.client_id,
+            app_id: params.client.app_id,
+            architecture: &target.triple,
+            version: &params.client.current_running_version,
+            exit_code: params.exit_code,
+            signal: params.signal,
+            crash_count: params.crash_count,
+            restarting: params.restarting,
+            stderr_tail: params.stderr_tail.as_deref().map(stderr_tail),
+        })
+        .await
+        .map_err(app_store_error)?;
+
+    webhooks
+        .publish_or_log(WebhookEvent::new(
+            EVENT_CLIENT_CRASHED,
+            crash.app_id,
+            serde_json::json!({
+                "client_id": crash.client_id,
+                "version": crash.version,
+                "architecture": crash.architecture,
+                "exit_code": crash.exit_code,
+                "signal": crash.signal,
+                "crash_count": crash.crash_count,
+                "restarting": crash.restarting,
+                "stderr_tail": crash.stderr_tail,
+            }),
+        ))
+        .await;
+
+    Ok(Json(crash))
+}
+
+/// Reports that a client shut down and stopped the app it ran.
+/// POST:
+/// {
+///     client_id: Uuid,
+///     app_id: Uuid,
+///     current_running_version: String,
+///     architecture: String,
+///     exit_code: i32 | null,
+///     signal: i32 | null,
+///     killed: bool
+/// }
+///
+/// Subscribers of `client.stopped` are notified of every report.
+async fn report_client_stopped(
+    RequestContext(mut app_store): RequestContext,
+    State(webhooks): State<WebhookDispatcher>,
+    Json(params): Json<ClientStoppedReport>,
 ) -> Result<Json<()>, (StatusCode, String)> {
-    let arch = params.architecture.to_string();
-    let app_build = app_store
-        .get_application_build(params.app_id, &params.current_running_version, arch, true)
+    let target = registered_target(&mut app_store, &params.client.architecture).await?;
+    webhooks
+        .publish_or_log(WebhookEvent::new(
+            EVENT_CLIENT_STOPPED,
+            params.client.app_id,
+            serde_json::json!({
+                "client_id": params.client.client_id,
+                "version": params.client.current_running_version,
+                "architecture": target.triple,
+                "exit_code": params.exit_code,
+                "signal": params.signal,
+                "killed": params.killed,
+            }),
+        ))
+        .await;
+
+    Ok(Json(()))
+}
+
+/// Returns the crashes clients of an application reported, newest first.
+/// GET: /crashes?app_id=&client_id=&limit=
+async fn get_client_crashes(
+    RequestContext(mut app_store): RequestContext,
+    Query(params): Query<ClientReportQuery>,
+) -> Result<Json<Vec<app_store::ClientCrash>>, (StatusCode, String)> {
+    let crashes = app_store
+        .get_client_crashes(
+            params.app_id,
+            params.client_id,
+            params.limit.unwrap_or(DEFAULT_CRASH_LIMIT),
+        )
+        .await
+        .map_err(app_store_error)?;
+    Ok(Json(crashes))
+}
+
+/// Disables a build that has failed too many startups. Returns the event that notifies webhook
+/// subscribers, which is published once the change is committed.
+async fn auto_disable_build(
+    app_store: &mut AppStore,
+    app_id: Uuid,
+    before: app_store::ApplicationBuild,
+) -> Result<WebhookEvent, (StatusCode, String)> {
+    let after = app_store
+        .set_application_build_disabled(before.id, true)
+        .await
+        .map_err(app_store_error)?;
+
+    record_audit(
+        app_store,
+        NewAuditEntry {
+            actor: SYSTEM_ACTOR,
+            action: "build.auto_disable",
+            target_type: "build",
+            target_id: after.id,
+            app_id: Some(app_id),
+            before: Some(to_json(&before)?),
+            after: Some(to_json(&after)?),
+        },
+    )
+    .await?;
+    announce_release_change(app_store, app_id).await;
+
+    Ok(WebhookEvent::new(
+        EVENT_BUILD_AUTO_DISABLED,
+        app_id,
+        serde_json::json!({
+            "build_id": after.id,
+            "app_version_id": after.app_version_id,
+            "architecture": after.build_version,
+            "failed_count": after.failed_count,
+            "success_count": after.success_count,
+        }),
+    ))
+}
+
+/// Administrative api for subscribing an endpoint to webhook events. A secret is generated when
+/// none is provided; it is only returned by this api. Leaving out `event_types` subscribes to every
+/// event and leaving out `app_id` subscribes to events of every application.
+/// POST:
+/// {
+///     url: String,
+///     secret: String,
+///     event_types: [String],
+///     app_id: Uuid
+/// }
+async fn create_webhook_subscription(
+    TransactionContext(mut app_store): TransactionContext,
+    Actor(actor): Actor,
+    Json(params): Json<CreateWebhookSubscription>,
+) -> Result<Json<WebhookSubscriptionCreated>, (StatusCode, String)> {
+    reqwest::Url::parse(&params.url).map_err(bad_request)?;
+    let event_types = params.event_types.unwrap_or_default();
+    if let Some(unknown) = event_types.iter().find(|event| !EVENT_TYPES.contains(&event.as_str())) {
+        return Err((StatusCode::BAD_REQUEST, format!("Unknown event type: {}", unknown)));
+    }
+    let secret = params
+        .secret
+        .unwrap_or_else(|| Uuid::new_v4().simple().to_string());
+
+    let subscription = app_store
+        .create_webhook_subscription(&params.url, &secret, &event_types, params.app_id)
+        .await
+        .map_err(app_store_error)?;
+
+    record_audit(
+        &mut app_store,
+        NewAuditEntry {
+            actor: &actor,
+            action: "webhook.create",
+            target_type: "webhook",
+            target_id: subscription.id,
+            app_id: subscription.app_id,
+            before: None,
+            after: Some(to_json(&subscription)?),
+        },
+    )
+    .await?;
+
+    app_store.commit().await.map_err(app_store_error)?;
+    Ok(Json(WebhookSubscriptionCreated {
+        id: subscription.id,
+        url: subscription.url,
+        secret: subscription.secret,
+        event_types: subscription.event_types,
+        app_id: subscription.app_id,
+        enabled: subscription.enabled,
+    }))
+}
+
+/// Lists webhook subscriptions without their secrets.
+async fn get_webhook_subscriptions(
+    RequestContext(mut app_store): RequestContext,
+) -> Result<Json<Vec<app_store::WebhookSubscription>>, (StatusCode, String)> {
+    let subscriptions = app_store
+        .get_webhook_subscriptions()
+        .await
+        .map_err(app_store_error)?;
+    Ok(Json(subscriptions))
+}
+
+/// Administrative api for pausing or resuming a webhook subscription.
+/// POST:
+/// {
+///     subscription_id: Uuid,
+///     enabled: bool
+/// }
+async fn toggle_webhook_subscription(
+    TransactionContext(mut app_store): TransactionContext,
+    Actor(actor): Actor,
+    Json(params): Json<ToggleWebhookSubscription>,
+) -> Result<Json<app_store::WebhookSubscription>, (StatusCode, String)> {
+    let before = app_store
+        .get_webhook_subscription_by_id(params.subscription_id)
+        .await
+        .map_err(not_found)?;
+    let after = app_store
+        .set_webhook_subscription_enabled(params.subscription_id, params.enabled)
+        .await
+        .map_err(app_store_error)?;
+
+    record_audit(
+        &mut app_store,
+        NewAuditEntry {
+            actor: &actor,
+            action: if params.enabled { "webhook.enable" } else { "webhook.disable" },
+            target_type: "webhook",
+            target_id: after.id,
+            app_id: after.app_id,
+            before: Some(to_json(&before)?),
+            after: Some(to_json(&after)?),
+        },
+    )
+    .await?;
+
+    app_store.commit().await.map_err(app_store_error)?;
+    Ok(Json(after))
+}
+
+/// Returns the webhook delivery log, newest first.
+/// GET: /webhooks/deliveries?subscription_id=&status=&limit=
+///
+/// `status` is one of pending, delivered or failed.
+async fn get_webhook_deliveries(
+    RequestContext(mut app_store): RequestContext,
+    Query(params): Query<WebhookDeliveryQuery>,
+) -> Result<Json<Vec<app_store::WebhookDelivery>>, (StatusCode, String)> {
+    let deliveries = app_store
+        .get_webhook_deliveries(
+            params.subscription_id,
+            params.status.as_deref(),
+            params.limit.unwrap_or(DEFAULT_DELIVERY_LIMIT),
+        )
+        .await
+        .map_err(app_store_error)?;
+    Ok(Json(deliveries))
+}
+
+/// Administrative api for scheduling the promotion of a version. Without a ramp the version is
+/// promoted at `start_at`. With a ramp, e.g. [5, 25, 100], the version is served to that percentage
+/// of clients at `start_at` and every `step_interval_minutes` after, and promoted at the final step,
+/// which must be 100. `start_at` may carry any utc offset.
+/// POST:
+/// {
+///     app_id: Uuid,
+///     version: String,
+///     start_at: DateTime,
+///     ramp: [i32],
+///     step_interval_minutes: i64
+/// }
+async fn create_release_schedule(
+    TransactionContext(mut app_store): TransactionContext,
+    Actor(actor): Actor,
+    Json(params): Json<CreateReleaseSchedule>,
+) -> Result<Json<ReleaseSchedule>, (StatusCode, String)> {
+    let ramp = params.ramp.unwrap_or_else(|| vec![100]);
+    let steps = release_steps(params.start_at, &ramp, params.step_interval_minutes)
+        .map_err(|message| (StatusCode::BAD_REQUEST, message))?;
+    let app_version = app_store
+        .get_application_version(params.app_id, &params.version)
+        .await
+        .map_err(not_found)?;
+    if app_version.latest {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("{} is already the latest version", params.version),
+        ));
+    }
+    let active = app_store
+        .count_active_release_schedules(params.app_id)
+        .await
+        .map_err(app_store_error)?;
+    if active > 0 {
+        return Err((
+            StatusCode::CONFLICT,
+            "The application already has a release schedule in progress".to_string(),
+        ));
+    }
+
+    let schedule = app_store
+        .create_release_schedule(params.app_id, app_version.id, &actor, &steps)
+        .await
+        .map_err(app_store_error)?;
+    let schedule = release_schedule(&mut app_store, schedule).await?;
+
+    record_audit(
+        &mut app_store,
+        NewAuditEntry {
+            actor: &actor,
+            action: "schedule.create",
+            target_type: "schedule",
+            target_id: schedule.schedule.id,
+            app_id: Some(params.app_id),
+            before: None,
+            after: Some(to_json(&schedule)?),
+        },
+    )
+    .await?;
+
+    app_store.commit().await.map_err(app_store_error)?;
+    Ok(Json(schedule))
+}
+
+/// Lists release schedules with their steps, newest first.
+/// GET: /schedules?app_id=&status=
+///
+/// `status` is one of pending, in_progress, completed, cancelled or failed.
+async fn get_release_schedules(
+    RequestContext(mut app_store): RequestContext,
+    Query(params): Query<ReleaseScheduleQuery>,
+) -> Result<Json<Vec<ReleaseSchedule>>, (StatusCode, String)> {
+    if let Some(status) = params.status.as_deref() {
+        if !SCHEDULE_STATUSES.contains(&status) {
+            return Err((StatusCode::BAD_REQUEST, format!("Unknown status: {}", status)));
+        }
+    }
+    let schedules = app_store
+        .get_release_schedules(params.app_id, params.status.as_deref())
         .await
         .map_err(app_store_error)?;
 
+    let mut views = Vec::with_capacity(schedules.len());
+    for schedule in schedules {
+        views.push(release_schedule(&mut app_store, schedule).await?);
+    }
+    Ok(Json(views))
+}
+
+/// Administrative api for cancelling a release schedule before it completes. Cancelling a schedule
+/// that is part way through its ramp ends the rollout, so every client is served the latest version
+/// again.
+/// POST:
+/// {
+///     schedule_id: Uuid
+/// }
+async fn cancel_release_schedule(
+    TransactionContext(mut app_store): TransactionContext,
+    Actor(actor): Actor,
+    Json(params): Json<CancelReleaseSchedule>,
+) -> Result<Json<ReleaseSchedule>, (StatusCode, String)> {
+    let before = app_store
+        .get_release_schedule(params.schedule_id)
+        .await
+        .map_err(not_found)?;
+    if !release_scheduler::is_active(&before.status) {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("The release schedule is already {}", before.status),
+        ));
+    }
+
+    if before.status == SCHEDULE_IN_PROGRESS {
+        set_version_rollout(&mut app_store, &actor, before.app_version_id, None).await?;
+    }
     app_store
-        .increment_failure_count_by_id(app_build.id)
+        .set_release_schedule_status(before.id, SCHEDULE_CANCELLED, None)
+        .await
+        .map_err(app_store_error)?;
+    let after = app_store
+        .get_release_schedule(before.id)
+        .await
+        .map_err(app_store_error)?;
+    let after = release_schedule(&mut app_store, after).await?;
+
+    record_audit(
+        &mut app_store,
+        NewAuditEntry {
+            actor: &actor,
+            action: "schedule.cancel",
+            target_type: "schedule",
+            target_id: before.id,
+            app_id: Some(before.app_id),
+            before: Some(to_json(&before)?),
+            after: Some(to_json(&after)?),
+        },
+    )
+    .await?;
+
+    app_store.commit().await.map_err(app_store_error)?;
+    Ok(Json(after))
+}
+
+async fn release_schedule(
+    app_store: &mut AppStore,
+    schedule: app_store::ReleaseSchedule,
+) -> Result<ReleaseSchedule, (StatusCode, String)> {
+    let steps = app_store
+        .get_release_schedule_steps(schedule.id)
+        .await
+        .map_err(app_store_error)?;
+    Ok(ReleaseSchedule { schedule, steps })
+}
+
+/// Turns a ramp of rollout percentages into the time each step runs at. The ramp must increase and
+/// end at 100.
+fn release_steps(
+    start_at: DateTime<Utc>,
+    ramp: &[i32],
+    step_interval_minutes: Option<i64>,
+) -> Result<Vec<(i32, DateTime<Utc>)>, String> {
+    if ramp.last() != Some(&100) {
+        return Err("The ramp must end at 100 percent".to_string());
+    }
+    if ramp.iter().any(|percentage| !(1..=100).contains(percentage))
+        || ramp.windows(2).any(|pair| pair[0] >= pair[1])
+    {
+        return Err("Ramp percentages must increase from 1 to 100".to_string());
+    }
+    let interval = match step_interval_minutes {
+        Some(minutes) if minutes > 0 => chrono::Duration::minutes(minutes),
+        _ if ramp.len() == 1 => chrono::Duration::zero(),
+        _ => return Err("step_interval_minutes must be positive for a ramp".to_string()),
+    };
+
+    Ok(ramp
+        .iter()
+        .enumerate()
+        .map(|(step, percentage)| (*percentage, start_at + interval * step as i32))
+        .collect())
+}
+
+/// Administrative api for registering a target triple that builds can be published for.
+/// POST:
+/// {
+///     triple: String,
+///     description: String
+/// }
+async fn create_target(
+    TransactionContext(mut app_store): TransactionContext,
+    Actor(actor): Actor,
+    Json(params): Json<CreateTarget>,
+) -> Result<Json<app_store::Target>, (StatusCode, String)> {
+    if app_store
+        .get_target(params.triple.as_str())
+        .await
+        .map_err(app_store_error)?
+        .is_some()
+    {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("Target is already registered: {}", params.triple),
+        ));
+    }
+
+    let target = app_store
+        .create_target(params.triple.as_str(), params.description.as_deref().unwrap_or_default())
+        .await
+        .map_err(app_store_error)?;
+
+    record_audit(
+        &mut app_store,
+        NewAuditEntry {
+            actor: &actor,
+            action: "target.create",
+            target_type: "target",
+            target_id: target.id,
+            app_id: None,
+            before: None,
+            after: Some(to_json(&target)?),
+        },
+    )
+    .await?;
+
+    app_store.commit().await.map_err(app_store_error)?;
+    Ok(Json(target))
+}
+
+/// Lists registered targets with their aliases and fallbacks.
+async fn get_targets(
+    RequestContext(mut app_store): RequestContext,
+) -> Result<Json<Vec<app_store::Target>>, (StatusCode, String)> {
+    let targets = app_store.get_targets().await.map_err(app_store_error)?;
+    Ok(Json(targets))
+}
+
+/// Administrative api for registering another name clients may report for a target. An existing
+/// alias is moved to the given target.
+/// POST:
+/// {
+///     alias: String,
+///     triple: String
+/// }
+async fn set_target_alias(
+    TransactionContext(mut app_store): TransactionContext,
+    Actor(actor): Actor,
+    Json(params): Json<SetTargetAlias>,
+) -> Result<Json<app_store::Target>, (StatusCode, String)> {
+    let before = registered_target(&mut app_store, &params.triple).await?;
+    if let Some(existing) = app_store
+        .get_target(params.alias.as_str())
+        .await
+        .map_err(app_store_error)?
+    {
+        if existing.triple == params.alias.as_str() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("{} is a registered target and can't be an alias", params.alias),
+            ));
+        }
+    }
+
+    let after = app_store
+        .set_target_alias(params.alias.as_str(), &before.triple)
+        .await
+        .map_err(app_store_error)?;
+    audit_target_change(&mut app_store, &actor, "target.alias", before, &after).await?;
+    app_store.commit().await.map_err(app_store_error)?;
+    Ok(Json(after))
+}
+
+/// Administrative api for letting clients of a target run the builds of another target when there
+/// is no build for their own. Fallbacks with a lower priority are preferred; the priority of an
+/// existing fallback is updated.
+/// POST:
+/// {
+///     triple: String,
+///     fallback: String,
+///     priority: i32
+/// }
+async fn set_target_fallback(
+    TransactionContext(mut app_store): TransactionContext,
+    Actor(actor): Actor,
+    Json(params): Json<SetTargetFallback>,
+) -> Result<Json<app_store::Target>, (StatusCode, String)> {
+    let before = registered_target(&mut app_store, &params.triple).await?;
+    let fallback = registered_target(&mut app_store, &params.fallback).await?;
+    if before.triple == fallback.triple {
+        return Err((StatusCode::BAD_REQUEST, "A target can't fall back to itself".to_string()));
+    }
+
+    let after = app_store
+        .set_target_fallback(&before.triple, &fallback.triple, params.priority.unwrap_or_default())
         .await
         .map_err(app_store_error)?;
+    audit_target_change(&mut app_store, &actor, "target.fallback", before, &after).await?;
+    app_store.commit().await.map_err(app_store_error)?;
+    Ok(Json(after))
+}
+
+async fn audit_target_change(
+    app_store: &mut AppStore,
+    actor: &str,
+    action: &str,
+    before: app_store::Target,
+    after: &app_store::Target,
+) -> Result<(), (StatusCode, String)> {
+    record_audit(
+        app_store,
+        NewAuditEntry {
+            actor,
+            action,
+            target_type: "target",
+            target_id: after.id,
+            app_id: None,
+            before: Some(to_json(&before)?),
+            after: Some(to_json(after)?),
+        },
+    )
+    .await
+}
 
-    Ok(Json({}))
+/// Looks up a target by triple or alias, rejecting targets that are not registered.
+async fn registered_target(
+    app_store: &mut AppStore,
+    triple: &TargetTriple,
+) -> Result<app_store::Target, (StatusCode, String)> {
+    app_store
+        .get_target(triple.as_str())
+        .await
+        .map_err(app_store_error)?
+        .ok_or_else(|| (StatusCode::BAD_REQUEST, format!("Unknown target: {}", triple)))
 }
 
 /// Health endpoint for monitoring
 async fn health() -> Result<Json<()>, (StatusCode, String)> {
-    Ok(Json({}))
+    Ok(Json(()))
+}
+
+/// Promotes a version to latest and records the previous and new latest version in the audit log.
+/// Release schedules of the application that are still active are cancelled, as their next step
+/// would roll an older version out again. Returns the event that notifies webhook subscribers,
+/// which is published once the promotion is committed.
+async fn promote(
+    app_store: &mut AppStore,
+    actor: &str,
+    version_id: Uuid,
+) -> Result<(app_store::ApplicationVersion, WebhookEvent), (StatusCode, String)> {
+    let app_version = app_store
+        .get_application_version_by_id(version_id)
+        .await
+        .map_err(app_store_error)?;
+    let before = app_store
+        .get_latest_application_version(app_version.app_id)
+        .await
+        .map_err(app_store_error)?;
+    let after = app_store
+        .promote_application_version(version_id)
+        .await
+        .map_err(app_store_error)?;
+
+    record_audit(
+        app_store,
+        NewAuditEntry {
+            actor,
+            action: "version.promote",
+            target_type: "version",
+            target_id: version_id,
+            app_id: Some(after.app_id),
+            before: before.as_ref().map(to_json).transpose()?,
+            after: Some(to_json(&after)?),
+        },
+    )
+    .await?;
+    cancel_superseded_schedules(app_store, actor, &after).await?;
+    announce_release_change(app_store, after.app_id).await;
+
+    let event = WebhookEvent::new(
+        EVENT_VERSION_PROMOTED,
+        after.app_id,
+        serde_json::json!({
+            "version_id": after.id,
+            "version": after.version,
+            "previous_version": before.map(|version| version.version),
+            "actor": actor,
+        }),
+    );
+    Ok((after, event))
+}
+
+async fn cancel_superseded_schedules(
+    app_store: &mut AppStore,
+    actor: &str,
+    promoted: &app_store::ApplicationVersion,
+) -> Result<(), (StatusCode, String)> {
+    let schedules = app_store
+        .get_release_schedules(Some(promoted.app_id), None)
+        .await
+        .map_err(app_store_error)?;
+    let reason = format!("Superseded by the promotion of {}", promoted.version);
+    for before in schedules.into_iter().filter(|schedule| release_scheduler::is_active(&schedule.status)) {
+        app_store
+            .set_release_schedule_status(before.id, SCHEDULE_CANCELLED, Some(&reason))
+            .await
+            .map_err(app_store_error)?;
+        let after = app_store
+            .get_release_schedule(before.id)
+            .await
+            .map_err(app_store_error)?;
+        record_audit(
+            app_store,
+            NewAuditEntry {
+                actor,
+                action: "schedule.cancel",
+                target_type: "schedule",
+                target_id: before.id,
+                app_id: Some(before.app_id),
+                before: Some(to_json(&before)?),
+                after: Some(to_json(&after)?),
+            },
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Promotes a version on behalf of the release scheduler. Returns the event to publish once the
+/// promotion is committed.
+pub(crate) async fn promote_scheduled_version(
+    app_store: &mut AppStore,
+    version_id: Uuid,
+) -> Result<WebhookEvent, String> {
+    promote(app_store, SYSTEM_ACTOR, version_id)
+        .await
+        .map(|(_, event)| event)
+        .map_err(|(_, message)| message)
+}
+
+/// Serves a version to a percentage of its application's clients instead of the latest version.
+/// `None` ends the rollout.
+pub(crate) async fn set_version_rollout(
+    app_store: &mut AppStore,
+    actor: &str,
+    version_id: Uuid,
+    rollout_percentage: Option<i32>,
+) -> Result<app_store::ApplicationVersion, (StatusCode, String)> {
+    let before = app_store
+        .get_application_version_by_id(version_id)
+        .await
+        .map_err(not_found)?;
+    let after = app_store
+        .set_application_version_rollout(version_id, rollout_percentage)
+        .await
+        .map_err(app_store_error)?;
+
+    record_audit(
+        app_store,
+        NewAuditEntry {
+            actor,
+            action: "version.rollout",
+            target_type: "version",
+            target_id: version_id,
+            app_id: Some(after.app_id),
+            before: Some(to_json(&before)?),
+            after: Some(to_json(&after)?),
+        },
+    )
+    .await?;
+    announce_release_change(app_store, after.app_id).await;
+    Ok(after)
+}
+
+/// Lets open update streams know that the releases of an application changed. The change itself
+/// has already been made, so a failure is logged rather than returned. Postgres holds back the
+/// notification of a change made in a transaction until the transaction is committed.
+async fn announce_release_change(app_store: &mut AppStore, app_id: Uuid) {
+    if let Err(err) = app_store.notify_release_changed(app_id).await {
+        tracing::error!("Failed to announce release change of {}: {}", app_id, err);
+    }
+}
+
+async fn record_audit(
+    app_store: &mut AppStore,
+    entry: NewAuditEntry<'_>,
+) -> Result<(), (StatusCode, String)> {
+    app_store
+        .create_audit_entry(&entry)
+        .await
+        .map_err(app_store_error)?;
+    Ok(())
+}
+
+fn audit_entries_to_csv(entries: &[AuditEntry]) -> String {
+    let mut csv = String::from("id,created_at,actor,action,target_type,target_id,app_id,before,after\n");
+    for entry in entries {
+        let fields = [
+            entry.id.to_string(),
+            entry.created_at.to_rfc3339(),
+            entry.actor.clone(),
+            entry.action.clone(),
+            entry.target_type.clone(),
+            entry.target_id.to_string(),
+            entry.app_id.map(|id| id.to_string()).unwrap_or_default(),
+            entry.before.as_ref().map(|v| v.to_string()).unwrap_or_default(),
+            entry.after.as_ref().map(|v| v.to_string()).unwrap_or_default(),
+        ];
+        let row: Vec<String> = fields.iter().map(|field| escape_csv_field(field)).collect();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+fn escape_csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<serde_json::Value, (StatusCode, String)> {
+    serde_json::to_value(value).map_err(internal_error)
 }
 
 fn internal_error<E>(err: E) -> (StatusCode, String)
@@ -269,13 +2710,200 @@ where
     (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
 }
 
+fn bad_request<E>(err: E) -> (StatusCode, String)
+where
+    E: std::error::Error,
+{
+    (StatusCode::BAD_REQUEST, err.to_string())
+}
+
+/// Keeps the end of a stderr tail that is longer than `MAX_STDERR_TAIL_LENGTH`.
+fn stderr_tail(text: &str) -> &str {
+    let mut start = text.len().saturating_sub(MAX_STDERR_TAIL_LENGTH);
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    &text[start..]
+}
+
+/// Cuts an error reported by a client off after `MAX_REPORT_ERROR_LENGTH`.
+fn report_error(text: &str) -> &str {
+    let mut end = text.len().min(MAX_REPORT_ERROR_LENGTH);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+/// Keeps the fields of a host description that fit in `MAX_HOST_LENGTH`. A description that is too
+/// long and not a json object is dropped.
+fn truncate_host(host: &serde_json::Value) -> Option<serde_json::Value> {
+    if host.to_string().len() <= MAX_HOST_LENGTH {
+        return Some(host.clone());
+    }
+    let serde_json::Value::Object(fields) = host else {
+        return None;
+    };
+    // The braces, and a separator after every field.
+    let mut length = 2;
+    let mut kept = serde_json::Map::new();
+    for (key, value) in fields {
+        let field_length = serde_json::Value::from(key.as_str()).to_string().len() + 1 + value.to_string().len() + 1;
+        if length + field_length <= MAX_HOST_LENGTH {
+            length += field_length;
+            kept.insert(key.clone(), value.clone());
+        }
+    }
+    Some(serde_json::Value::Object(kept))
+}
+
 fn app_store_error(err: AppStoreError) -> (StatusCode, String) {
     (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
 }
 
-fn serialize_architecture(arch: Architecture) -> &'static str {
-    match arch {
-        Architecture::X86_64Intel => "x86_64-pc-windows-gnu",
-        Architecture::X86_64Linux => "x86_64-unknown-linux-gnu",
+fn not_found(err: AppStoreError) -> (StatusCode, String) {
+    (StatusCode::NOT_FOUND, err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn latest_version() -> LatestVersion {
+        LatestVersion {
+            build_id: Uuid::new_v4(),
+            version: "0.2.0".to_string(),
+            url: "http://example.com/app_0.2.0".to_string(),
+            update_required: true,
+            update_mandatory: false,
+            min_supported_version: None,
+            release_notes: None,
+            released_at: None,
+            git_commit: None,
+            metadata: serde_json::json!({}),
+            sha256: None,
+            patches: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_adjacent_builds() {
+        let builds: Vec<StoredBuild> = ["0.10.0", "0.2.0", "0.9.1", "invalid"]
+            .iter()
+            .map(|version| StoredBuild { build_id: Uuid::new_v4(), version: version.to_string() })
+            .collect();
+        let id = |index: usize| builds[index].build_id;
+        assert_eq!(adjacent_builds(&builds, id(2)), (Some(id(1)), Some(id(0))));
+        assert_eq!(adjacent_builds(&builds, id(1)), (None, Some(id(2))));
+        assert_eq!(adjacent_builds(&builds, id(0)), (Some(id(2)), None));
+        assert_eq!(adjacent_builds(&builds, Uuid::new_v4()), (None, None));
+    }
+
+    #[test]
+    fn test_report_texts_are_truncated() {
+        let tail = format!("{}é\nlast line", "x".repeat(MAX_STDERR_TAIL_LENGTH));
+        assert!(stderr_tail(&tail).ends_with("é\nlast line"));
+        assert!(stderr_tail(&tail).len() <= MAX_STDERR_TAIL_LENGTH);
+        assert_eq!(stderr_tail("short"), "short");
+
+        let error = format!("{}é", "x".repeat(MAX_REPORT_ERROR_LENGTH - 1));
+        assert_eq!(report_error(&error), "x".repeat(MAX_REPORT_ERROR_LENGTH - 1));
+        assert_eq!(report_error("short"), "short");
+
+        let host = serde_json::json!({
+            "hostname": "box",
+            "notes": "x".repeat(MAX_HOST_LENGTH),
+        });
+        let kept = truncate_host(&host).unwrap();
+        assert_eq!(kept, serde_json::json!({ "hostname": "box" }));
+        assert!(kept.to_string().len() <= MAX_HOST_LENGTH);
+        assert_eq!(truncate_host(&serde_json::json!("x".repeat(MAX_HOST_LENGTH))), None);
+        assert_eq!(truncate_host(&serde_json::json!({ "hostname": "box" })), Some(serde_json::json!({ "hostname": "box" })));
+    }
+
+    #[test]
+    fn test_is_artifact_file_name() {
+        assert!(is_artifact_file_name("infinite_hello_0.2.0"));
+        assert!(!is_artifact_file_name(""));
+        assert!(!is_artifact_file_name("../infinite_hello"));
+        assert!(!is_artifact_file_name(".hidden"));
+        assert!(!is_artifact_file_name("a/b"));
+    }
+
+    #[test]
+    fn test_latest_version_etag_depends_on_update_requirements() {
+        let optional = latest_version();
+        let mandatory = LatestVersion { build_id: optional.build_id, update_mandatory: true, ..latest_version() };
+        assert_ne!(latest_version_etag(&optional, "0.1.0", None), latest_version_etag(&mandatory, "0.1.0", None));
+    }
+
+    #[test]
+    fn test_release_steps() {
+        let start_at = Utc::now();
+        assert_eq!(release_steps(start_at, &[100], None).unwrap(), vec![(100, start_at)]);
+        assert_eq!(
+            release_steps(start_at, &[5, 25, 100], Some(30)).unwrap(),
+            vec![
+                (5, start_at),
+                (25, start_at + chrono::Duration::minutes(30)),
+                (100, start_at + chrono::Duration::minutes(60)),
+            ]
+        );
+        assert!(release_steps(start_at, &[5, 25], Some(30)).is_err());
+        assert!(release_steps(start_at, &[25, 5, 100], Some(30)).is_err());
+        assert!(release_steps(start_at, &[0, 100], Some(30)).is_err());
+        assert!(release_steps(start_at, &[5, 100], None).is_err());
+        assert!(release_steps(start_at, &[], None).is_err());
+    }
+
+    #[test]
+    fn test_is_update_mandatory() {
+        let version = |version: &str| semver::Version::parse(version).unwrap();
+        let mandatory = |versions: &[&str]| versions.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+
+        assert!(!is_update_mandatory(&version("0.1.0"), &version("0.3.0"), None, &[]));
+        // Below the minimum supported version.
+        assert!(is_update_mandatory(&version("0.1.0"), &version("0.3.0"), Some("0.2.0"), &[]));
+        assert!(!is_update_mandatory(&version("0.2.0"), &version("0.3.0"), Some("0.2.0"), &[]));
+        // The latest version or a skipped version is mandatory.
+        assert!(is_update_mandatory(&version("0.1.0"), &version("0.3.0"), None, &mandatory(&["0.3.0"])));
+        assert!(is_update_mandatory(&version("0.1.0"), &version("0.3.0"), None, &mandatory(&["0.2.0"])));
+        // Mandatory versions the client already runs or that are newer than latest don't count.
+        assert!(!is_update_mandatory(&version("0.2.0"), &version("0.3.0"), None, &mandatory(&["0.1.0", "0.2.0", "0.4.0"])));
+    }
+
+    #[test]
+    fn test_latest_version_etag_depends_on_running_version() {
+        let latest = latest_version();
+        assert_eq!(latest_version_etag(&latest, "0.1.0", None), latest_version_etag(&latest, "0.1.0", None));
+        assert_ne!(latest_version_etag(&latest, "0.1.0", None), latest_version_etag(&latest, "0.2.0", None));
+    }
+
+    #[test]
+    fn test_latest_version_etag_depends_on_reported_labels() {
+        let latest = latest_version();
+        let labels: Labels = serde_json::from_str(r#"{"site": "berlin"}"#).unwrap();
+        assert_ne!(latest_version_etag(&latest, "0.1.0", None), latest_version_etag(&latest, "0.1.0", Some(&labels)));
+        assert_ne!(
+            latest_version_etag(&latest, "0.1.0", Some(&Labels::default())),
+            latest_version_etag(&latest, "0.1.0", Some(&labels))
+        );
+    }
+
+    #[test]
+    fn test_if_none_match() {
+        let etag = latest_version_etag(&latest_version(), "0.1.0", None);
+        let mut headers = HeaderMap::new();
+        assert!(!if_none_match(&headers, &etag));
+
+        headers.insert(header::IF_NONE_MATCH, HeaderValue::from_str(&format!("\"other\", W/{}", etag)).unwrap());
+        assert!(if_none_match(&headers, &etag));
+
+        headers.insert(header::IF_NONE_MATCH, HeaderValue::from_static("\"other\""));
+        assert!(!if_none_match(&headers, &etag));
+
+        headers.insert(header::IF_NONE_MATCH, HeaderValue::from_static("*"));
+        assert!(if_none_match(&headers, &etag));
     }
 }
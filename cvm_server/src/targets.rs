@@ -0,0 +1,117 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::Formatter;
+
+/// A Rust target triple such as `x86_64-unknown-linux-gnu` or `aarch64-apple-darwin`, or an alias
+/// registered for one. Only the shape is checked here; whether the target is known is decided by
+/// the `targets` table.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TargetTriple(String);
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct InvalidTargetTriple(String);
+
+impl std::fmt::Display for InvalidTargetTriple {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid target triple: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidTargetTriple {}
+
+/// Triples have an architecture followed by one to three of vendor, os and environment.
+const MIN_COMPONENTS: usize = 2;
+const MAX_COMPONENTS: usize = 4;
+const MAX_LENGTH: usize = 255;
+
+impl TargetTriple {
+    pub fn parse(value: &str) -> Result<Self, InvalidTargetTriple> {
+        let triple = value.trim().to_ascii_lowercase();
+        let components: Vec<&str> = triple.split('-').collect();
+        let valid = triple.len() <= MAX_LENGTH
+            && (MIN_COMPONENTS..=MAX_COMPONENTS).contains(&components.len())
+            && components.iter().all(|component| {
+                !component.is_empty()
+                    && component
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+            });
+
+        if valid {
+            Ok(TargetTriple(triple))
+        } else {
+            Err(InvalidTargetTriple(value.to_string()))
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for TargetTriple {
+    type Error = InvalidTargetTriple;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        TargetTriple::parse(&value)
+    }
+}
+
+impl From<TargetTriple> for String {
+    fn from(triple: TargetTriple) -> Self {
+        triple.0
+    }
+}
+
+impl std::fmt::Display for TargetTriple {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_accepts_target_triples() {
+        for triple in [
+            "x86_64-unknown-linux-gnu",
+            "x86_64-unknown-linux-musl",
+            "aarch64-apple-darwin",
+            "x86_64-pc-windows-msvc",
+            "armv7-unknown-linux-gnueabihf",
+            "wasm32-wasip1",
+            "thumbv7em-none-eabihf",
+        ] {
+            assert_eq!(TargetTriple::parse(triple).unwrap().as_str(), triple);
+        }
+    }
+
+    #[test]
+    fn test_parse_normalizes_case_and_whitespace() {
+        let triple = TargetTriple::parse(" X86_64-Unknown-Linux-GNU ").unwrap();
+        assert_eq!(triple.as_str(), "x86_64-unknown-linux-gnu");
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed_triples() {
+        for triple in [
+            "",
+            "x86_64",
+            "x86_64--linux-gnu",
+            "x86_64-unknown-linux-gnu-extra",
+            "x86_64-unknown-linux gnu",
+            "x86_64-unknown-linux-gnu;",
+        ] {
+            assert!(TargetTriple::parse(triple).is_err(), "{} should be rejected", triple);
+        }
+    }
+
+    #[test]
+    fn test_deserialize_validates() {
+        let triple: TargetTriple = serde_json::from_str("\"aarch64-unknown-linux-musl\"").unwrap();
+        assert_eq!(triple.as_str(), "aarch64-unknown-linux-musl");
+        assert!(serde_json::from_str::<TargetTriple>("\"not a triple\"").is_err());
+    }
+}
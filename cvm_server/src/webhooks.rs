@@ -0,0 +1,428 @@
+use crate::app_store::{AppStore, AppStoreError, WebhookDelivery, WebhookSubscription};
+use crate::config::Config;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use sqlx::PgPool;
+use std::time::Duration;
+use uuid::Uuid;
+
+pub const EVENT_VERSION_PROMOTED: &str = "version.promoted";
+pub const EVENT_BUILD_AUTO_DISABLED: &str = "build.auto_disabled";
+pub const EVENT_CLIENT_STARTUP_FAILED: &str = "client.startup_failed";
+pub const EVENT_CLIENT_ROLLED_BACK: &str = "client.rolled_back";
+pub const EVENT_CLIENT_CRASHED: &str = "client.crashed";
+pub const EVENT_CLIENT_STOPPED: &str = "client.stopped";
+pub const EVENT_TYPES: [&str; 6] = [
+    EVENT_VERSION_PROMOTED,
+    EVENT_BUILD_AUTO_DISABLED,
+    EVENT_CLIENT_STARTUP_FAILED,
+    EVENT_CLIENT_ROLLED_BACK,
+    EVENT_CLIENT_CRASHED,
+    EVENT_CLIENT_STOPPED,
+];
+
+pub const SIGNATURE_HEADER: &str = "x-cvm-signature";
+pub const EVENT_HEADER: &str = "x-cvm-event";
+pub const DELIVERY_HEADER: &str = "x-cvm-delivery";
+
+pub const DELIVERY_PENDING: &str = "pending";
+pub const DELIVERY_DELIVERED: &str = "delivered";
+pub const DELIVERY_FAILED: &str = "failed";
+
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Retries are never delayed by more than this, however many attempts are configured.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// Json body posted to webhook subscribers.
+#[derive(Serialize, Debug, Clone)]
+pub struct WebhookEvent {
+    pub id: Uuid,
+    pub event_type: String,
+    pub app_id: Uuid,
+    pub occurred_at: DateTime<Utc>,
+    pub data: serde_json::Value,
+}
+
+impl WebhookEvent {
+    pub fn new(event_type: &str, app_id: Uuid, data: serde_json::Value) -> Self {
+        WebhookEvent {
+            id: Uuid::new_v4(),
+            event_type: event_type.to_string(),
+            app_id,
+            occurred_at: Utc::now(),
+            data,
+        }
+    }
+}
+
+/// Sends events to the webhook subscriptions interested in them.
+///
+/// Every event results in a delivery record per subscription. Deliveries are sent in the
+/// background and retried with exponential backoff until they succeed or the configured number
+/// of attempts is exhausted; the outcome of each attempt is written to the delivery record.
+/// Deliveries that are still pending when the server stops are resumed by `resume_pending` when
+/// it starts again.
+#[derive(Clone)]
+pub struct WebhookDispatcher {
+    app_config: &'static Config,
+    pool: PgPool,
+    client: reqwest::Client,
+}
+
+impl WebhookDispatcher {
+    pub fn new(app_config: &'static Config, pool: PgPool) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(DELIVERY_TIMEOUT)
+            .build()
+            .expect("Failed to build webhook http client");
+        WebhookDispatcher { app_config, pool, client }
+    }
+
+    /// Records a delivery for every interested subscription and starts sending them. Returns the
+    /// deliveries that were created.
+    pub async fn publish(&self, event: WebhookEvent) -> Result<Vec<WebhookDelivery>, AppStoreError> {
+        let mut app_store = AppStore::from_pg_pool(self.app_config, &self.pool).await?;
+        let subscriptions = app_store
+            .get_webhook_subscriptions_for_event(&event.event_type, event.app_id)
+            .await?;
+        let payload = serde_json::to_value(&event).expect("Webhook events are serializable");
+
+        let mut deliveries = Vec::with_capacity(subscriptions.len());
+        for subscription in subscriptions {
+            let delivery = app_store
+                .create_webhook_delivery(subscription.id, event.id, &event.event_type, &payload)
+                .await?;
+            self.spawn_delivery(subscription, &delivery);
+            deliveries.push(delivery);
+        }
+        Ok(deliveries)
+    }
+
+    /// Continues sending the deliveries that were still pending when the server stopped, with the
+    /// attempts they have left. Returns how many deliveries were resumed.
+    pub async fn resume_pending(&self) -> Result<usize, AppStoreError> {
+        let mut app_store = AppStore::from_pg_pool(self.app_config, &self.pool).await?;
+        let deliveries = app_store.get_pending_webhook_deliveries().await?;
+        for delivery in &deliveries {
+            let subscription = app_store
+                .get_webhook_subscription_by_id(delivery.subscription_id)
+                .await?;
+            self.spawn_delivery(subscription, delivery);
+        }
+        Ok(deliveries.len())
+    }
+
+    fn spawn_delivery(&self, subscription: WebhookSubscription, delivery: &WebhookDelivery) {
+        let dispatcher = self.clone();
+        let body = delivery.payload.to_string().into_bytes();
+        let delivery_id = delivery.id;
+        let event_type = delivery.event_type.clone();
+        let first_attempt = delivery.attempts as u32 + 1;
+        tokio::spawn(async move {
+            dispatcher
+                .deliver(subscription, delivery_id, &event_type, body, first_attempt)
+                .await;
+        });
+    }
+
+    /// Publishes the event and logs failures instead of returning them. Used by route handlers
+    /// where a webhook problem should not fail the request that caused the event.
+    pub async fn publish_or_log(&self, event: WebhookEvent) {
+        let event_type = event.event_type.clone();
+        if let Err(err) = self.publish(event).await {
+            tracing::error!("Failed to publish {} webhook event: {}", event_type, err);
+        }
+    }
+
+    async fn deliver(
+        &self,
+        subscription: WebhookSubscription,
+        delivery_id: Uuid,
+        event_type: &str,
+        body: Vec<u8>,
+        first_attempt: u32,
+    ) {
+        let signature = sign(&subscription.secret, &body);
+        let max_attempts = self.app_config.webhook_max_attempts.max(1);
+
+        // A delivery resumed after the limit was lowered still gets its last attempt.
+        for attempt in first_attempt.min(max_attempts)..=max_attempts {
+            let result = self
+                .client
+                .post(&subscription.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header(EVENT_HEADER, event_type)
+                .header(DELIVERY_HEADER, delivery_id.to_string())
+                .header(SIGNATURE_HEADER, &signature)
+                .body(body.clone())
+                .send()
+                .await;
+
+            let (response_status, last_error) = match result {
+                Ok(response) if response.status().is_success() => {
+                    (Some(response.status().as_u16() as i32), None)
+                }
+                Ok(response) => (
+                    Some(response.status().as_u16() as i32),
+                    Some(format!("Subscriber responded with {}", response.status())),
+                ),
+                Err(err) => (None, Some(err.to_string())),
+            };
+
+            let status = match (&last_error, attempt == max_attempts) {
+                (None, _) => DELIVERY_DELIVERED,
+                (Some(_), true) => DELIVERY_FAILED,
+                (Some(_), false) => DELIVERY_PENDING,
+            };
+            self.record_attempt(delivery_id, status, attempt as i32, response_status, last_error.as_deref())
+                .await;
+
+            if status != DELIVERY_PENDING {
+                return;
+            }
+            tokio::time::sleep(retry_delay(self.app_config.webhook_retry_base_delay_ms, attempt)).await;
+        }
+    }
+
+    async fn record_attempt(
+        &self,
+        delivery_id: Uuid,
+        status: &str,
+        attempts: i32,
+        response_status: Option<i32>,
+        last_error: Option<&str>,
+    ) {
+        let result = match AppStore::from_pg_pool(self.app_config, &self.pool).await {
+            Ok(mut app_store) => {
+                app_store
+                    .update_webhook_delivery(delivery_id, status, attempts, response_status, last_error)
+                    .await
+            }
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            tracing::error!("Failed to record webhook delivery {}: {}", delivery_id, err);
+        }
+    }
+}
+
+/// Delay before the attempt after `attempt`. It doubles with every attempt, starting at the base
+/// delay, up to [`MAX_RETRY_DELAY`].
+fn retry_delay(base_delay_ms: u64, attempt: u32) -> Duration {
+    let factor = 1u64.checked_shl(attempt.saturating_sub(1)).unwrap_or(u64::MAX);
+    Duration::from_millis(base_delay_ms.saturating_mul(factor)).min(MAX_RETRY_DELAY)
+}
+
+/// Signs a webhook body with the subscription secret. Subscribers verify deliveries by computing
+/// the HMAC-SHA256 of the raw request body and comparing it with the `X-CVM-Signature` header.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_store::tests::{setup, TEST_CONFIG};
+    use axum::body::Bytes;
+    use axum::extract::State;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::post;
+    use axum::Router;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use tokio::net::TcpListener;
+
+    const SECRET: &str = "webhook-test-secret";
+
+    /// Local stand-in for a webhook subscriber. It fails the first `failures` requests and records
+    /// the headers and body of every request it receives.
+    #[derive(Clone, Default)]
+    struct StandIn {
+        failures: usize,
+        calls: Arc<AtomicUsize>,
+        received: Arc<Mutex<Vec<(HeaderMap, Bytes)>>>,
+    }
+
+    async fn receive(State(stand_in): State<StandIn>, headers: HeaderMap, body: Bytes) -> StatusCode {
+        let call = stand_in.calls.fetch_add(1, Ordering::SeqCst);
+        stand_in.received.lock().unwrap().push((headers, body));
+        if call < stand_in.failures {
+            StatusCode::INTERNAL_SERVER_ERROR
+        } else {
+            StatusCode::OK
+        }
+    }
+
+    async fn start_stand_in(stand_in: StandIn) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let app = Router::new().route("/hook", post(receive)).with_state(stand_in);
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}/hook", address)
+    }
+
+    async fn wait_for_delivery(app_store: &mut AppStore, subscription_id: Uuid) -> WebhookDelivery {
+        for _ in 0..100 {
+            let mut deliveries = app_store
+                .get_webhook_deliveries(Some(subscription_id), None, 1)
+                .await
+                .unwrap();
+            if let Some(delivery) = deliveries.pop() {
+                if delivery.status != DELIVERY_PENDING {
+                    return delivery;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("Webhook delivery did not complete");
+    }
+
+    async fn dispatcher() -> WebhookDispatcher {
+        let pool = PgPool::connect(&TEST_CONFIG.db_url).await.unwrap();
+        WebhookDispatcher::new(&TEST_CONFIG, pool)
+    }
+
+    #[test]
+    fn test_retry_delay_doubles_up_to_the_maximum() {
+        assert_eq!(retry_delay(1000, 1), Duration::from_secs(1));
+        assert_eq!(retry_delay(1000, 3), Duration::from_secs(4));
+        assert_eq!(retry_delay(1000, 13), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(1000, 65), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(u64::MAX, u32::MAX), MAX_RETRY_DELAY);
+    }
+
+    #[test]
+    fn test_sign_is_hex_hmac_sha256() {
+        // Test vector from RFC 4231 test case 2.
+        let signature = sign("Jefe", b"what do ya want for nothing?");
+        assert_eq!(
+            signature,
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_publish_delivers_signed_event() {
+        let mut app_store = setup().await.unwrap();
+        let app = app_store.create_application("abc", "abcd").await.unwrap();
+        let stand_in = StandIn::default();
+        let url = start_stand_in(stand_in.clone()).await;
+        let subscription = app_store
+            .create_webhook_subscription(&url, SECRET, &[EVENT_VERSION_PROMOTED.to_string()], Some(app.id))
+            .await
+            .unwrap();
+
+        let event = WebhookEvent::new(EVENT_VERSION_PROMOTED, app.id, serde_json::json!({ "version": "1.0.0" }));
+        let deliveries = dispatcher().await.publish(event).await.unwrap();
+        assert_eq!(deliveries.len(), 1);
+
+        let delivery = wait_for_delivery(&mut app_store, subscription.id).await;
+        assert_eq!(delivery.status, DELIVERY_DELIVERED);
+        assert_eq!(delivery.attempts, 1);
+        assert_eq!(delivery.response_status, Some(200));
+
+        let received = stand_in.received.lock().unwrap();
+        let (headers, body) = &received[0];
+        assert_eq!(headers[SIGNATURE_HEADER], sign(SECRET, body));
+        assert_eq!(headers[EVENT_HEADER], EVENT_VERSION_PROMOTED);
+        let payload: serde_json::Value = serde_json::from_slice(body).unwrap();
+        assert_eq!(payload["data"]["version"], "1.0.0");
+    }
+
+    #[tokio::test]
+    async fn test_publish_retries_failed_deliveries() {
+        let mut app_store = setup().await.unwrap();
+        let app = app_store.create_application("abc", "abcd").await.unwrap();
+        let stand_in = StandIn { failures: 1, ..Default::default() };
+        let url = start_stand_in(stand_in.clone()).await;
+        let subscription = app_store
+            .create_webhook_subscription(&url, SECRET, &[], Some(app.id))
+            .await
+            .unwrap();
+
+        let event = WebhookEvent::new(EVENT_CLIENT_STARTUP_FAILED, app.id, serde_json::json!({}));
+        dispatcher().await.publish(event).await.unwrap();
+
+        let delivery = wait_for_delivery(&mut app_store, subscription.id).await;
+        assert_eq!(delivery.status, DELIVERY_DELIVERED);
+        assert_eq!(delivery.attempts, 2);
+        assert_eq!(stand_in.calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_publish_marks_delivery_failed_after_max_attempts() {
+        let mut app_store = setup().await.unwrap();
+        let app = app_store.create_application("abc", "abcd").await.unwrap();
+        let stand_in = StandIn { failures: usize::MAX, ..Default::default() };
+        let url = start_stand_in(stand_in.clone()).await;
+        let subscription = app_store
+            .create_webhook_subscription(&url, SECRET, &[], Some(app.id))
+            .await
+            .unwrap();
+
+        let event = WebhookEvent::new(EVENT_BUILD_AUTO_DISABLED, app.id, serde_json::json!({}));
+        dispatcher().await.publish(event).await.unwrap();
+
+        let delivery = wait_for_delivery(&mut app_store, subscription.id).await;
+        assert_eq!(delivery.status, DELIVERY_FAILED);
+        assert_eq!(delivery.attempts, TEST_CONFIG.webhook_max_attempts as i32);
+        assert_eq!(delivery.response_status, Some(500));
+    }
+
+    #[tokio::test]
+    async fn test_resumes_pending_deliveries_with_the_attempts_left() {
+        let mut app_store = setup().await.unwrap();
+        let app = app_store.create_application("abc", "abcd").await.unwrap();
+        let stand_in = StandIn::default();
+        let url = start_stand_in(stand_in.clone()).await;
+        let subscription = app_store
+            .create_webhook_subscription(&url, SECRET, &[], Some(app.id))
+            .await
+            .unwrap();
+        // Left pending by a server that stopped after the first attempt failed.
+        let event = WebhookEvent::new(EVENT_CLIENT_CRASHED, app.id, serde_json::json!({}));
+        let payload = serde_json::to_value(&event).unwrap();
+        let delivery = app_store
+            .create_webhook_delivery(subscription.id, event.id, &event.event_type, &payload)
+            .await
+            .unwrap();
+        app_store
+            .update_webhook_delivery(delivery.id, DELIVERY_PENDING, 1, Some(500), Some("Subscriber responded with 500"))
+            .await
+            .unwrap();
+
+        let pending = app_store.get_pending_webhook_deliveries().await.unwrap();
+        let delivery = pending.into_iter().find(|pending| pending.id == delivery.id).unwrap();
+        dispatcher().await.spawn_delivery(subscription.clone(), &delivery);
+
+        let delivery = wait_for_delivery(&mut app_store, subscription.id).await;
+        assert_eq!(delivery.status, DELIVERY_DELIVERED);
+        assert_eq!(delivery.attempts, 2);
+        let received = stand_in.received.lock().unwrap();
+        let (headers, body) = &received[0];
+        assert_eq!(headers[SIGNATURE_HEADER], sign(SECRET, body));
+    }
+
+    #[tokio::test]
+    async fn test_publish_skips_unrelated_subscriptions() {
+        let mut app_store = setup().await.unwrap();
+        let app = app_store.create_application("abc", "abcd").await.unwrap();
+        let other_app = app_store.create_application("abc", "abcd").await.unwrap();
+        app_store
+            .create_webhook_subscription("http://127.0.0.1:9/hook", SECRET, &[EVENT_VERSION_PROMOTED.to_string()], Some(app.id))
+            .await
+            .unwrap();
+        app_store
+            .create_webhook_subscription("http://127.0.0.1:9/hook", SECRET, &[], Some(other_app.id))
+            .await
+            .unwrap();
+
+        let event = WebhookEvent::new(EVENT_BUILD_AUTO_DISABLED, app.id, serde_json::json!({}));
+        let deliveries = dispatcher().await.publish(event).await.unwrap();
+        assert!(deliveries.is_empty());
+    }
+}